@@ -0,0 +1,26 @@
+export interface IBackupMetrics {
+    backup?: IBackupProgress,
+    last_backup?: IBackupRun,
+    snapshot?: IBackupProgress,
+    last_snapshot?: IBackupRun,
+}
+
+export interface IBackupProgress {
+    kind: 'Backup' | 'Snapshot',
+    name: string,
+    started: number,
+    state: 'Copying' | 'Pushing' | 'Finished' | { Failed: string },
+    pages_total: number,
+    pages_copied: number,
+    targets: number,
+    targets_done: number,
+    bytes_total: number,
+    bytes_uploaded: number,
+}
+
+export interface IBackupRun {
+    name: string,
+    finished: number,
+    size: number,
+    duration_ms: number,
+}
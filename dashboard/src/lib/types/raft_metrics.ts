@@ -1,3 +1,5 @@
+import type {IBackupMetrics} from "$lib/types/backup_metrics";
+
 export interface IRaftMetrics {
     id: number,
     current_term: number,
@@ -11,6 +13,8 @@ export interface IRaftMetrics {
     millis_since_quorum_ack?: number,
     membership_config: IStoredMembership,
     replication: Map<number, ILogId>
+    // the backup metrics of this node are returned next to the Raft metrics
+    backup?: IBackupMetrics,
 }
 
 export interface IVote {
@@ -6,6 +6,8 @@ use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use tokio::sync::Mutex;
 
+#[cfg(feature = "sqlite")]
+use crate::Error;
 #[cfg(any(feature = "backup", feature = "dashboard"))]
 use crate::client::stream::ClientStreamReq;
 #[cfg(feature = "dashboard")]
@@ -19,11 +21,21 @@ use crate::store::state_machine::memory::notify_handler::NotifyRequest;
 #[cfg(feature = "cache")]
 use crate::store::state_machine::memory::{TypeConfigKV, kv_handler::CacheRequestHandler};
 #[cfg(feature = "sqlite")]
+use crate::query::consistency::LeaderContact;
+#[cfg(feature = "sqlite")]
 use crate::store::state_machine::sqlite::{
-    TypeConfigSqlite, state_machine::SqlitePool, writer::WriterRequest,
+    TypeConfigSqlite,
+    changes::ChangesRequest,
+    state_machine::{QueryWrite, SqlitePool},
+    write_budget,
+    writer::WriterRequest,
 };
+#[cfg(feature = "sqlite")]
+use openraft::raft::ClientWriteResponse;
 #[cfg(any(feature = "backup", feature = "dashboard"))]
 use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "sqlite")]
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -54,6 +66,8 @@ pub(crate) struct AppState {
     pub is_shutting_down: AtomicBool,
     #[cfg(feature = "backup")]
     pub backups_dir: String,
+    #[cfg(feature = "backup")]
+    pub restore: crate::restore::OnlineRestore,
     pub id: NodeId,
     #[cfg(feature = "cache")]
     pub nodes: Vec<crate::Node>,
@@ -65,6 +79,8 @@ pub(crate) struct AppState {
     pub raft_lock: Arc<Mutex<()>>,
     #[cfg(feature = "s3")]
     pub s3_config: Option<Arc<S3Config>>,
+    #[cfg(feature = "backup")]
+    pub backup_targets: Vec<Arc<dyn crate::backup_target::BackupTarget>>,
     pub secret_raft: String,
     pub secret_api: String,
     #[cfg(feature = "dashboard")]
@@ -90,10 +106,31 @@ pub struct StateRaftDB {
     pub raft: openraft::Raft<TypeConfigSqlite>,
     pub shutdown_handle: hiqlite_wal::ShutdownHandle,
     pub sql_writer: flume::Sender<WriterRequest>,
+    pub tx_changes: flume::Sender<ChangesRequest>,
     pub read_pool: SqlitePool,
     pub log_statements: bool,
+    pub query_timeout: Option<Duration>,
     pub is_raft_stopped: Arc<AtomicBool>,
     pub is_startup_finished: Arc<AtomicBool>,
+    pub leader_contact: LeaderContact,
+    pub has_write_budget: bool,
+    #[cfg(feature = "backup")]
+    pub backup_progress: Arc<crate::backup_progress::BackupTracker>,
+}
+
+#[cfg(feature = "sqlite")]
+impl StateRaftDB {
+    /// Proposes a client write. With a `write_instruction_budget`, the write is checked in a dry
+    /// run on this node first, which must be the leader.
+    pub async fn client_write(
+        &self,
+        write: QueryWrite,
+    ) -> Result<ClientWriteResponse<TypeConfigSqlite>, Error> {
+        if self.has_write_budget {
+            write_budget::check(&self.sql_writer, &write).await?;
+        }
+        Ok(self.raft.client_write(write).await?)
+    }
 }
 
 #[cfg(feature = "cache")]
@@ -111,4 +148,6 @@ pub struct StateRaftCache {
     pub shutdown_handle: Option<hiqlite_wal::ShutdownHandle>,
     #[cfg(feature = "cache")]
     pub cache_storage_disk: bool,
+    #[cfg(feature = "backup")]
+    pub state_machine: Arc<crate::store::state_machine::memory::state_machine::StateMachineMemory>,
 }
@@ -0,0 +1,730 @@
+//! Continuous archiving of the committed Raft log to S3 for point-in-time recovery.
+//!
+//! Full backups only capture the database on the `BackupConfig` schedule. In between, the leader
+//! pushes all committed writes in encrypted segments to `wal/{lineage}/{first}_{last}`. A restore
+//! can then replay these segments on top of the latest full backup.
+//!
+//! A lineage is started with the first backup of a database and stays the same on all nodes,
+//! because it is derived from the replicated `Backup` log entry. Log indexes restart after a
+//! restore, and an online restore replaces the database in the middle of the log, which is why
+//! the restored database will start a new lineage with its next backup.
+//!
+//! The entries of an online restore are never archived. A successful restore resets the lineage
+//! before the archiver reads it again, so its entries can only ever end up inside the archive of
+//! a failed one, which has not changed any data.
+
+use crate::backup::RestoreTarget;
+use crate::helpers::{deserialize, serialize};
+use crate::s3::S3Config;
+use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::store::state_machine::sqlite::state_machine::{
+    QueryWrite, SqlitePool, StateMachineData, StateMachineSqlite,
+};
+use crate::store::state_machine::sqlite::writer::{self, WriterRequest};
+use crate::{Error, NodeConfig, NodeId};
+use chrono::{DateTime, Utc};
+use cryptr::EncValue;
+use hiqlite_wal::LogStoreReader;
+use openraft::storage::{RaftLogReader, RaftLogStorage, RaftStateMachine};
+use openraft::{EntryPayload, LogId, Raft};
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+use tokio::{task, time};
+use tracing::{debug, error, info, warn};
+
+/// `_metadata` key for the lineage the local database belongs to.
+const META_LINEAGE: &str = "archive_lineage";
+/// `_metadata` key inside a backup with the lineage and log index it has been taken at.
+const META_BASE: &str = "archive_base";
+
+const PREFIX: &str = "wal/";
+const SEGMENT_MAX_ENTRIES: u64 = 1000;
+/// Backups requested by the archiver itself should never pile up.
+const BACKUP_REQUEST_INTERVAL: Duration = Duration::from_secs(600);
+
+/// A position inside an archive lineage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ArchivePosition {
+    pub lineage: String,
+    pub index: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchivedEntry {
+    log_id: LogId<NodeId>,
+    write: QueryWrite,
+}
+
+impl ArchivedEntry {
+    /// Only writes that modify the database need to be replayed. The chunks of an online
+    /// restore would duplicate a whole backup inside the archive.
+    fn from_payload(
+        log_id: LogId<NodeId>,
+        payload: EntryPayload<TypeConfigSqlite>,
+    ) -> Option<Self> {
+        match payload {
+            EntryPayload::Normal(
+                QueryWrite::Backup(_) | QueryWrite::RTT | QueryWrite::Restore(_),
+            ) => None,
+            EntryPayload::Normal(write) => Some(Self { log_id, write }),
+            EntryPayload::Blank | EntryPayload::Membership(_) => None,
+        }
+    }
+
+    fn unix_ms(&self) -> Option<i64> {
+        fn unix_ms(write: &QueryWrite) -> Option<i64> {
+            match write {
+                QueryWrite::Stamped((stamp, _)) => Some(stamp.unix_ms),
+                QueryWrite::Idempotent((_, write)) => unix_ms(write),
+                _ => None,
+            }
+        }
+        unix_ms(&self.write)
+    }
+
+    fn is_after(&self, target: &RestoreTarget) -> bool {
+        match target {
+            RestoreTarget::Latest => false,
+            RestoreTarget::LogIndex(index) => self.log_id.index > *index,
+            RestoreTarget::Timestamp(ts) => self
+                .unix_ms()
+                .is_some_and(|unix_ms| unix_ms > ts.timestamp_millis()),
+        }
+    }
+}
+
+fn segment_name(lineage: &str, first: u64, last: u64) -> String {
+    format!("{PREFIX}{lineage}/{first:020}_{last:020}")
+}
+
+fn segment_range(key: &str) -> Option<(u64, u64)> {
+    let (_, name) = key.strip_prefix(PREFIX)?.rsplit_once('/')?;
+    let (first, last) = name.split_once('_')?;
+    Some((first.parse().ok()?, last.parse().ok()?))
+}
+
+/// Sets the lineage with the first backup, if the database does not belong to one yet.
+/// This is executed by the writer and must be deterministic on all nodes.
+pub(crate) fn lineage_init(
+    conn: &rusqlite::Connection,
+    node_id: NodeId,
+    ts: i64,
+    log_index: u64,
+) -> Result<ArchivePosition, Error> {
+    if let Some(lineage) = meta_read(conn, META_LINEAGE)? {
+        return Ok(lineage);
+    }
+
+    let lineage = ArchivePosition {
+        lineage: format!("{ts}_{node_id}"),
+        index: log_index,
+    };
+    info!("Starting new log archive lineage {}", lineage.lineage);
+    meta_write(conn, META_LINEAGE, Some(&lineage))?;
+    Ok(lineage)
+}
+
+/// Marks a freshly created backup with the position it has been taken at. The backup must not
+/// keep the lineage, because a restored database starts with new log indexes.
+pub(crate) fn backup_set_base(
+    conn_bkp: &rusqlite::Connection,
+    base: &ArchivePosition,
+) -> Result<(), Error> {
+    meta_write(conn_bkp, META_LINEAGE, None)?;
+    meta_write(conn_bkp, META_BASE, Some(base))
+}
+
+pub(crate) async fn backup_read_base(path: String) -> Result<Option<ArchivePosition>, Error> {
+    task::spawn_blocking(move || {
+        let conn = rusqlite::Connection::open(path)?;
+        meta_read(&conn, META_BASE)
+    })
+    .await?
+}
+
+fn meta_read(conn: &rusqlite::Connection, key: &str) -> Result<Option<ArchivePosition>, Error> {
+    let bytes: Option<Vec<u8>> = conn
+        .query_row("SELECT data FROM _metadata WHERE key = $1", [key], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    match bytes {
+        None => Ok(None),
+        Some(bytes) => Ok(Some(deserialize(&bytes)?)),
+    }
+}
+
+fn meta_write(
+    conn: &rusqlite::Connection,
+    key: &str,
+    value: Option<&ArchivePosition>,
+) -> Result<(), Error> {
+    match value {
+        None => {
+            conn.execute("DELETE FROM _metadata WHERE key = $1", [key])?;
+        }
+        Some(value) => {
+            let bytes = serialize(value)?;
+            conn.execute(
+                "REPLACE INTO _metadata (key, data) VALUES ($1, $2)",
+                (key, bytes),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the sorted `(first, last, key)` of all segments for the given lineage.
+async fn list_segments(s3: &S3Config, lineage: &str) -> Result<Vec<(u64, u64, String)>, Error> {
+    let mut segments = Vec::new();
+    for list in s3.bucket.list(&format!("{PREFIX}{lineage}/"), None).await? {
+        for object in list.contents {
+            if let Some((first, last)) = segment_range(&object.key) {
+                segments.push((first, last, object.key));
+            }
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// An online restore replaces the database with a backup at the current log index. The backup
+/// must neither keep its base nor continue the lineage of the replaced database.
+pub(crate) fn restore_reset(conn: &rusqlite::Connection) -> Result<(), Error> {
+    meta_write(conn, META_LINEAGE, None)?;
+    meta_write(conn, META_BASE, None)
+}
+
+/// Archived segments are only useful as long as a backup they can be applied to exists.
+/// A segment is always uploaded after the backup it continues, so it can be removed as soon as
+/// it is older than the oldest backup we keep.
+pub(crate) fn is_expired_segment(key: &str, last_modified: i64, threshold: DateTime<Utc>) -> bool {
+    segment_range(key).is_some() && last_modified < threshold.timestamp()
+}
+
+/// Archives the committed Raft log to S3 while this node is the leader.
+pub(crate) struct Archiver {
+    this_node: NodeId,
+    interval: Duration,
+    s3_config: Arc<S3Config>,
+    log_reader: LogStoreReader<TypeConfigSqlite>,
+}
+
+impl Archiver {
+    pub(crate) async fn new(
+        node_config: &NodeConfig,
+        log_store: &mut hiqlite_wal::LogStore<TypeConfigSqlite>,
+    ) -> Option<Self> {
+        let interval = node_config.backup_config.archive_interval()?;
+        let Some(s3_config) = node_config.s3_config.clone() else {
+            warn!("Log archiving is configured, but there is no `S3Config` - it will not run");
+            return None;
+        };
+
+        Some(Self {
+            this_node: node_config.node_id,
+            interval,
+            s3_config,
+            log_reader: log_store.get_log_reader().await,
+        })
+    }
+
+    pub(crate) fn spawn(self, raft: Raft<TypeConfigSqlite>, read_pool: SqlitePool) {
+        task::spawn(self.run(raft, read_pool));
+    }
+
+    async fn run(mut self, raft: Raft<TypeConfigSqlite>, read_pool: SqlitePool) {
+        info!("Log archiving task started");
+
+        // the lineage and the next log index to archive
+        let mut next: Option<ArchivePosition> = None;
+        let mut last_backup_request: Option<Instant> = None;
+
+        loop {
+            time::sleep(self.interval).await;
+
+            let metrics = raft.metrics().borrow().clone();
+            if metrics.running_state.is_err() {
+                info!("Raft has been shut down - stopping log archiving");
+                break;
+            }
+            if metrics.current_leader != Some(self.this_node) {
+                // another leader may have archived in the meantime
+                next = None;
+                continue;
+            }
+
+            // This must be read after `metrics`, so that all entries up to `last_applied` are
+            // included. A successful online restore resets the lineage and can never be archived.
+            let lineage = match read_lineage(&read_pool).await {
+                Ok(lineage) => lineage,
+                Err(err) => {
+                    error!("Error reading the log archive lineage: {err}");
+                    continue;
+                }
+            };
+            let Some(lineage) = lineage else {
+                // archived entries can only ever be replayed on top of a backup
+                info!("No backup exists to start the log archive with - creating one now");
+                self.request_backup(&raft, &mut last_backup_request).await;
+                continue;
+            };
+
+            if next.as_ref().map(|n| &n.lineage) != Some(&lineage.lineage) {
+                match self.find_next(&lineage).await {
+                    Ok(pos) => next = Some(pos),
+                    Err(err) => {
+                        error!("Error listing archived log segments: {err}");
+                        continue;
+                    }
+                }
+            }
+            let pos = next.as_mut().expect("to always be set above");
+
+            if let Some(purged) = metrics.purged
+                && purged.index >= pos.index
+            {
+                error!(
+                    "Raft logs {}..={} have been purged before they could be archived - \
+                    point-in-time recovery will only be possible from the next backup",
+                    pos.index, purged.index
+                );
+                pos.index = purged.index + 1;
+                self.request_backup(&raft, &mut last_backup_request).await;
+            }
+
+            let last_applied = metrics.last_applied.map(|l| l.index).unwrap_or_default();
+            while pos.index <= last_applied {
+                let last = last_applied.min(pos.index + SEGMENT_MAX_ENTRIES - 1);
+                if let Err(err) = self.archive_segment(&pos.lineage, pos.index, last).await {
+                    error!("Error archiving raft logs {}..={last}: {err}", pos.index);
+                    break;
+                }
+                pos.index = last + 1;
+            }
+        }
+    }
+
+    async fn find_next(&self, lineage: &ArchivePosition) -> Result<ArchivePosition, Error> {
+        let segments = list_segments(&self.s3_config, &lineage.lineage).await?;
+        let last = segments
+            .iter()
+            .map(|(_, last, _)| *last)
+            .max()
+            .unwrap_or_default()
+            .max(lineage.index);
+
+        debug!(
+            "Continuing log archive lineage {} at index {}",
+            lineage.lineage,
+            last + 1
+        );
+        Ok(ArchivePosition {
+            lineage: lineage.lineage.clone(),
+            index: last + 1,
+        })
+    }
+
+    async fn archive_segment(&mut self, lineage: &str, first: u64, last: u64) -> Result<(), Error> {
+        let entries = self
+            .log_reader
+            .try_get_log_entries(first..=last)
+            .await
+            .map_err(|err| Error::Error(err.to_string().into()))?;
+        if entries.len() as u64 != last - first + 1 {
+            return Err(Error::Error(
+                format!(
+                    "expected {} log entries, got {}",
+                    last - first + 1,
+                    entries.len()
+                )
+                .into(),
+            ));
+        }
+
+        let archived = entries
+            .into_iter()
+            .filter_map(|entry| ArchivedEntry::from_payload(entry.log_id, entry.payload))
+            .collect::<Vec<_>>();
+        let bytes = serialize(&archived)?;
+        let enc = EncValue::encrypt(&bytes)?.into_bytes();
+
+        // Empty segments are pushed as well. Each segment covers all indexes in its range,
+        // which makes gaps easy to detect during a restore.
+        self.s3_config
+            .bucket
+            .put(segment_name(lineage, first, last), enc.as_ref())
+            .await?;
+        debug!("Archived raft logs {first}..={last}");
+
+        Ok(())
+    }
+
+    async fn request_backup(
+        &self,
+        raft: &Raft<TypeConfigSqlite>,
+        last_request: &mut Option<Instant>,
+    ) {
+        if last_request.is_some_and(|ts| ts.elapsed() < BACKUP_REQUEST_INTERVAL) {
+            return;
+        }
+        *last_request = Some(Instant::now());
+
+        let write = QueryWrite::Backup((self.this_node, Utc::now().timestamp()));
+        if let Err(err) = raft.client_write(write).await {
+            error!("Error creating a backup for the log archive: {err}");
+        }
+    }
+}
+
+async fn read_lineage(read_pool: &SqlitePool) -> Result<Option<ArchivePosition>, Error> {
+    let conn = read_pool.get().await?;
+    task::spawn_blocking(move || meta_read(&conn, META_LINEAGE)).await?
+}
+
+/// Replays the archived segments after the given backup position up to the `target` into the
+/// database at `path_db`, which must already be in place. Returns the last replayed log index.
+pub(crate) async fn replay(
+    node_config: &NodeConfig,
+    path_db: String,
+    s3: &S3Config,
+    base: &ArchivePosition,
+    target: &RestoreTarget,
+) -> Result<u64, Error> {
+    info!(
+        "Replaying archived raft logs for lineage {} after index {} until {:?}",
+        base.lineage, base.index, target
+    );
+
+    let segments = list_segments(s3, &base.lineage).await?;
+
+    let mut sm = StateMachineSqlite::new(
+        &node_config.data_dir,
+        &node_config.filename_db,
+        node_config.node_id,
+        false,
+        node_config.prepared_statement_cache_capacity,
+        1,
+        node_config.sql_functions.clone(),
+        // the original writes have been limited in the same way
+        node_config.write_instruction_budget,
+        Vec::new(),
+        false,
+        node_config
+            .backup_config
+            .retention_local(node_config.backup_keep_days_local),
+        false,
+        0,
+    )
+    .await
+    .map_err(|err| Error::Error(err.to_string().into()))?;
+
+    let mut source = S3Segments { s3, sm: &mut sm };
+    let res = replay_segments(&mut source, segments, base.index, target).await;
+
+    let (ack, rx) = oneshot::channel();
+    sm.write_tx
+        .send_async(WriterRequest::Shutdown(ack))
+        .await
+        .expect("sql writer to always be listening");
+    rx.await
+        .expect("to always get a shutdown ack from the sql writer");
+    drop(sm);
+    let last_replayed = res?;
+
+    // The new Raft starts with fresh logs, exactly like after a normal backup restore.
+    task::spawn_blocking(move || {
+        let conn = rusqlite::Connection::open(path_db)?;
+        writer::persist_metadata(&conn, &StateMachineData::default())?;
+        meta_write(&conn, META_BASE, None)
+    })
+    .await??;
+
+    info!("Archived raft logs replayed until index {last_replayed}");
+    Ok(last_replayed)
+}
+
+/// Fetches archived segments and applies their entries during a replay.
+trait SegmentSource {
+    async fn fetch(&mut self, key: String) -> Result<Vec<ArchivedEntry>, Error>;
+
+    async fn apply(&mut self, entries: Vec<openraft::Entry<TypeConfigSqlite>>)
+    -> Result<(), Error>;
+}
+
+struct S3Segments<'a> {
+    s3: &'a S3Config,
+    sm: &'a mut StateMachineSqlite,
+}
+
+impl SegmentSource for S3Segments<'_> {
+    async fn fetch(&mut self, key: String) -> Result<Vec<ArchivedEntry>, Error> {
+        let res = self.s3.bucket.get(&key).await?;
+        let bytes = EncValue::try_from_bytes(res.bytes().await?.to_vec())?.decrypt()?;
+        Ok(deserialize::<Vec<ArchivedEntry>>(&bytes)?)
+    }
+
+    async fn apply(
+        &mut self,
+        entries: Vec<openraft::Entry<TypeConfigSqlite>>,
+    ) -> Result<(), Error> {
+        self.sm
+            .apply(entries)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Error(err.to_string().into()))
+    }
+}
+
+/// Fetches the given `segments` in order and applies all entries after `base_index` up to the
+/// `target`. Returns the last applied log index.
+///
+/// Missing logs in between only end the replay early for `RestoreTarget::Latest`. Any other
+/// target could not be reached, and the restored database would silently miss writes.
+async fn replay_segments(
+    source: &mut impl SegmentSource,
+    segments: Vec<(u64, u64, String)>,
+    base_index: u64,
+    target: &RestoreTarget,
+) -> Result<u64, Error> {
+    let mut applied = base_index;
+    let mut last_replayed = base_index;
+    for (first, last, key) in segments {
+        if last <= applied {
+            continue;
+        }
+        if first > applied + 1 {
+            if *target != RestoreTarget::Latest {
+                return Err(Error::Error(
+                    format!(
+                        "Raft logs {}..{first} are missing in the archive - cannot replay until \
+                        {target:?}",
+                        applied + 1
+                    )
+                    .into(),
+                ));
+            }
+            warn!(
+                "Raft logs {}..{first} are missing in the archive - stopping the replay",
+                applied + 1
+            );
+            break;
+        }
+
+        let mut reached = false;
+        let mut entries = Vec::new();
+        for entry in source.fetch(key).await? {
+            if entry.log_id.index <= applied {
+                continue;
+            }
+            if entry.is_after(target) {
+                reached = true;
+                break;
+            }
+            last_replayed = entry.log_id.index;
+            entries.push(openraft::Entry {
+                log_id: entry.log_id,
+                payload: EntryPayload::Normal(entry.write),
+            });
+        }
+
+        source.apply(entries).await?;
+
+        if reached {
+            break;
+        }
+        applied = last;
+    }
+
+    Ok(last_replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::state_machine::sqlite::deterministic::WriteStamp;
+    use crate::store::state_machine::sqlite::idempotency::IdempotencyKey;
+    use crate::store::state_machine::sqlite::restore::RestoreWrite;
+    use openraft::CommittedLeaderId;
+
+    fn entry(index: u64, write: QueryWrite) -> ArchivedEntry {
+        ArchivedEntry {
+            log_id: LogId::new(CommittedLeaderId::new(1, 1), index),
+            write,
+        }
+    }
+
+    #[test]
+    fn segment_names() {
+        let name = segment_name("1700000000_1", 1, 1000);
+        assert_eq!(
+            name,
+            "wal/1700000000_1/00000000000000000001_00000000000000001000"
+        );
+        assert_eq!(segment_range(&name), Some((1, 1000)));
+        // names must sort in log order
+        assert!(segment_name("l", 999, 999) < segment_name("l", 1000, 1000));
+
+        assert_eq!(segment_range("backup_node_1_1700000000.sqlite"), None);
+        assert_eq!(segment_range("wal/lineage/invalid"), None);
+
+        let threshold = Utc::now();
+        assert!(is_expired_segment(
+            &name, // 2024-01-01T00:00:00Z
+            1704067200, threshold
+        ));
+        assert!(!is_expired_segment(
+            "backup_node_1_1700000000.sqlite",
+            // 2024-01-01T00:00:00Z
+            1704067200,
+            threshold
+        ));
+    }
+
+    #[test]
+    fn restore_targets() {
+        let stamp = WriteStamp {
+            unix_ms: 1_700_000_000_000,
+            seed: 0,
+        };
+        let stamped = entry(
+            10,
+            QueryWrite::Stamped((stamp, Box::new(QueryWrite::Batch("SELECT 1".into())))),
+        );
+        let idempotent = entry(
+            11,
+            QueryWrite::Idempotent((
+                IdempotencyKey::from_key("key"),
+                Box::new(QueryWrite::Stamped((stamp, Box::new(QueryWrite::RTT)))),
+            )),
+        );
+        let plain = entry(12, QueryWrite::Batch("SELECT 1".into()));
+
+        assert_eq!(stamped.unix_ms(), Some(stamp.unix_ms));
+        assert_eq!(idempotent.unix_ms(), Some(stamp.unix_ms));
+        assert_eq!(plain.unix_ms(), None);
+
+        assert!(!plain.is_after(&RestoreTarget::Latest));
+        assert!(!stamped.is_after(&RestoreTarget::LogIndex(10)));
+        assert!(idempotent.is_after(&RestoreTarget::LogIndex(10)));
+
+        let before = DateTime::from_timestamp_millis(stamp.unix_ms - 1).unwrap();
+        let exact = DateTime::from_timestamp_millis(stamp.unix_ms).unwrap();
+        assert!(stamped.is_after(&RestoreTarget::Timestamp(before)));
+        assert!(!stamped.is_after(&RestoreTarget::Timestamp(exact)));
+        // writes without a stamp cannot be placed in time and are always applied
+        assert!(!plain.is_after(&RestoreTarget::Timestamp(before)));
+
+        assert!(
+            ArchivedEntry::from_payload(stamped.log_id, EntryPayload::Normal(QueryWrite::RTT))
+                .is_none()
+        );
+        assert!(
+            ArchivedEntry::from_payload(
+                stamped.log_id,
+                EntryPayload::Normal(QueryWrite::Backup((1, 0)))
+            )
+            .is_none()
+        );
+        assert!(
+            ArchivedEntry::from_payload(
+                stamped.log_id,
+                EntryPayload::Normal(QueryWrite::Restore(RestoreWrite::Chunk {
+                    id: "restore".to_string(),
+                    offset: 0,
+                    data: vec![0; 1024],
+                }))
+            )
+            .is_none()
+        );
+        assert!(
+            ArchivedEntry::from_payload(stamped.log_id, EntryPayload::Normal(plain.write))
+                .is_some()
+        );
+    }
+
+    /// Segments `a`, `b` and `c` with 3 entries each, remembering all applied indexes.
+    struct TestSegments(Vec<u64>);
+
+    impl SegmentSource for TestSegments {
+        async fn fetch(&mut self, key: String) -> Result<Vec<ArchivedEntry>, Error> {
+            let range = match key.as_str() {
+                "a" => 1..=3,
+                "b" => 4..=6,
+                _ => 10..=12,
+            };
+            Ok(range
+                .map(|idx| entry(idx, QueryWrite::Batch("SELECT 1".into())))
+                .collect())
+        }
+
+        async fn apply(
+            &mut self,
+            entries: Vec<openraft::Entry<TypeConfigSqlite>>,
+        ) -> Result<(), Error> {
+            self.0.extend(entries.into_iter().map(|e| e.log_id.index));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_segments_until_target() {
+        // segments 1-3 and 4-6 are contiguous, logs 7-9 are missing
+        let segments = vec![
+            (1, 3, "a".to_string()),
+            (4, 6, "b".to_string()),
+            (10, 12, "c".to_string()),
+        ];
+        let replay = async |target: RestoreTarget| {
+            let mut source = TestSegments(Vec::new());
+            let res = replay_segments(&mut source, segments.clone(), 1, &target).await;
+            (res, source.0)
+        };
+
+        let (res, applied) = replay(RestoreTarget::LogIndex(5)).await;
+        assert_eq!(res.unwrap(), 5);
+        assert_eq!(applied, vec![2, 3, 4, 5]);
+
+        // `Latest` replays everything until the gap
+        let (res, applied) = replay(RestoreTarget::Latest).await;
+        assert_eq!(res.unwrap(), 6);
+        assert_eq!(applied, vec![2, 3, 4, 5, 6]);
+
+        // any other target behind the gap cannot be reached
+        let (res, applied) = replay(RestoreTarget::LogIndex(11)).await;
+        assert!(res.is_err());
+        assert_eq!(applied, vec![2, 3, 4, 5, 6]);
+        let ts = DateTime::from_timestamp_millis(1_700_000_000_000).unwrap();
+        let (res, _) = replay(RestoreTarget::Timestamp(ts)).await;
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn lineage_and_backup_base() -> Result<(), Error> {
+        let conn = rusqlite::Connection::open_in_memory()?;
+        conn.execute(
+            "CREATE TABLE _metadata (key TEXT NOT NULL PRIMARY KEY, data BLOB NOT NULL)",
+            (),
+        )?;
+
+        let lineage = lineage_init(&conn, 1, 1_700_000_000, 5)?;
+        assert_eq!(lineage.lineage, "1700000000_1");
+        assert_eq!(lineage.index, 5);
+        // later backups stay in the same lineage
+        assert_eq!(lineage_init(&conn, 2, 1_700_000_100, 50)?, lineage);
+
+        let base = ArchivePosition {
+            lineage: lineage.lineage.clone(),
+            index: 50,
+        };
+        backup_set_base(&conn, &base)?;
+        assert_eq!(meta_read(&conn, META_LINEAGE)?, None);
+        assert_eq!(meta_read(&conn, META_BASE)?, Some(base));
+
+        Ok(())
+    }
+}
@@ -1,31 +1,51 @@
 use crate::app_state::AppState;
-use crate::helpers::{deserialize, set_path_access};
+use crate::archive::{self, ArchivePosition};
+use crate::backup_diff::{self, BackupStore};
+use crate::backup_progress::BackupTracker;
+use crate::backup_target::{BackupObject, BackupTarget, LocalTarget};
+use crate::helpers::{deserialize, serialize, set_path_access};
 use crate::store::logs;
 use crate::store::state_machine::sqlite::state_machine::{
-    PathBackups, PathDb, PathLockFile, PathSnapshots, QueryWrite, StateMachineData,
-    StateMachineSqlite,
+    PathBackups, PathDb, PathLockFile, PathSnapshots, StateMachineData, StateMachineSqlite,
 };
-use crate::{Client, Error, NodeConfig};
-use chrono::{DateTime, Utc};
+use crate::{Client, Error, NodeConfig, init};
+use chrono::{DateTime, Datelike, Utc};
+use rusqlite::{OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt::{Display, Formatter};
 use std::ops::Sub;
 use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
-use tokio::time::Instant;
 use tokio::{fs, task, time};
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
 #[cfg(feature = "s3")]
 use crate::s3::S3Config;
 
 pub const BACKUP_DB_NAME: &str = "restore.sqlite";
+const BACKUP_PREFIX_DB: &str = "backup_node_";
+const BACKUP_PREFIX_CACHE: &str = "backup_cache_node_";
+/// `_metadata` key inside a backup with the row counts of its source database.
+const META_ROW_COUNTS: &str = "backup_row_counts";
 
 #[derive(Debug, Clone)]
 pub struct BackupConfig {
     cron_schedule: cron::Schedule,
     keep_days: u16,
+    archive_interval: Option<Duration>,
+    verify: bool,
+    differential: u16,
+    retention: Option<RetentionPolicy>,
+    retention_dry_run: bool,
+    targets: Vec<Arc<dyn BackupTarget>>,
+    #[cfg(feature = "cache")]
+    cache: bool,
 }
 
 impl Default for BackupConfig {
@@ -33,6 +53,14 @@ impl Default for BackupConfig {
         Self {
             cron_schedule: cron::Schedule::from_str("0 30 2 * * * *").unwrap(),
             keep_days: 30,
+            archive_interval: None,
+            verify: false,
+            differential: 0,
+            retention: None,
+            retention_dry_run: false,
+            targets: Vec::new(),
+            #[cfg(feature = "cache")]
+            cache: false,
         }
     }
 }
@@ -43,9 +71,132 @@ impl BackupConfig {
             cron_schedule: cron::Schedule::from_str(cron_schedule)
                 .map_err(|_| Error::Config("Invalid syntax for cron_schedule".into()))?,
             keep_days,
+            archive_interval: None,
+            verify: false,
+            differential: 0,
+            retention: None,
+            retention_dry_run: false,
+            targets: Vec::new(),
+            #[cfg(feature = "cache")]
+            cache: false,
         })
     }
 
+    /// Continuously archives all committed writes to the `S3Config` bucket in this interval.
+    /// This makes it possible to restore up to any point in time after the latest backup with
+    /// `BackupSource::PointInTime`, instead of losing everything since the last backup.
+    ///
+    /// Archiving starts with the first backup and writes that are still only in the Raft logs
+    /// once the cluster is lost cannot be restored. Keep the interval short and the logs long
+    /// enough: if they are purged before the next run, the archive can only continue with the
+    /// next backup.
+    pub fn with_archive_interval(mut self, archive_interval: Duration) -> Self {
+        self.archive_interval = Some(archive_interval);
+        self
+    }
+
+    pub(crate) fn archive_interval(&self) -> Option<Duration> {
+        self.archive_interval
+    }
+
+    /// Verifies each new backup in the background like `Client::backup_verify()`. Each node
+    /// verifies its local backup and the leader additionally the encrypted copy on each target.
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub(crate) fn verify(&self) -> bool {
+        self.verify
+    }
+
+    /// Creates up to `max_differentials` differential backups after each full backup. They only
+    /// contain the database pages that have changed since the previous backup, which saves a
+    /// lot of bandwidth and storage for big databases. The next backup afterward, or any leader
+    /// change, starts a new chain with a full backup.
+    ///
+    /// A restore of a differential backup fetches its full backup and all differentials up to
+    /// it. The retention keeps everything a kept differential depends on.
+    pub fn with_differential(mut self, max_differentials: u16) -> Self {
+        self.differential = max_differentials;
+        self
+    }
+
+    pub(crate) fn differential(&self) -> u16 {
+        self.differential
+    }
+
+    /// Replaces the flat `keep_days` with a grandfather-father-son retention, locally and on all
+    /// targets.
+    /// Of all backups, the newest one of each of the latest `daily` days, `weekly` weeks and
+    /// `monthly` months that have a backup will be kept, together with the latest backup in
+    /// any case. Periods are evaluated in UTC.
+    pub fn with_retention(mut self, daily: u16, weekly: u16, monthly: u16) -> Self {
+        self.retention = Some(RetentionPolicy::Gfs {
+            daily,
+            weekly,
+            monthly,
+        });
+        self
+    }
+
+    /// Only logs which backups would be deleted by the retention instead of deleting them.
+    pub fn with_retention_dry_run(mut self, dry_run: bool) -> Self {
+        self.retention_dry_run = dry_run;
+        self
+    }
+
+    /// Pushes an encrypted copy of each backup to this target as well, e.g. a `LocalTarget` on
+    /// a mounted network path or an `S3Config` for another region. Can be given multiple times
+    /// for redundant copies. The `S3Config` of the `NodeConfig` is always used as a target.
+    pub fn with_target(mut self, target: Arc<dyn BackupTarget>) -> Self {
+        self.targets.push(target);
+        self
+    }
+
+    /// Backs up the cache state machine with each run as well, like `Client::backup_cache()`.
+    /// Only the current cache leader creates the backup and pushes it to all targets.
+    #[cfg(feature = "cache")]
+    pub fn with_cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    #[cfg(feature = "cache")]
+    pub(crate) fn cache(&self) -> bool {
+        self.cache
+    }
+
+    /// All targets, starting with the `S3Config` of the `NodeConfig`, if it exists.
+    pub(crate) fn targets(&self, s3_config: Option<&Arc<S3Config>>) -> Vec<Arc<dyn BackupTarget>> {
+        let mut targets = Vec::with_capacity(self.targets.len() + 1);
+        if let Some(s3_config) = s3_config {
+            targets.push(s3_config.clone() as Arc<dyn BackupTarget>);
+        }
+        targets.extend(self.targets.iter().cloned());
+        targets
+    }
+
+    /// The retention for backups on the targets.
+    pub(crate) fn retention(&self) -> Retention {
+        Retention {
+            policy: self
+                .retention
+                .unwrap_or(RetentionPolicy::Days(self.keep_days)),
+            dry_run: self.retention_dry_run,
+        }
+    }
+
+    /// The retention for local backups, which keep them for `keep_days_local` without a policy.
+    pub(crate) fn retention_local(&self, keep_days_local: u16) -> Retention {
+        Retention {
+            policy: self
+                .retention
+                .unwrap_or(RetentionPolicy::Days(keep_days_local)),
+            dry_run: self.retention_dry_run,
+        }
+    }
+
     pub fn from_env() -> Self {
         let cron_str = env::var("HQL_BACKUP_CRON").unwrap_or_else(|_| "0 30 2 * * * *".to_string());
         let cron_schedule =
@@ -56,9 +207,214 @@ impl BackupConfig {
             .parse::<u16>()
             .expect("Cannot parse HQL_BACKUP_KEEP_DAYS to u16");
 
+        let archive_interval = env::var("HQL_BACKUP_ARCHIVE_INTERVAL").ok().map(|secs| {
+            let secs = secs
+                .parse::<u64>()
+                .expect("Cannot parse HQL_BACKUP_ARCHIVE_INTERVAL to u64");
+            assert!(
+                secs > 0,
+                "HQL_BACKUP_ARCHIVE_INTERVAL must be greater than 0"
+            );
+            Duration::from_secs(secs)
+        });
+
+        let keep = |key: &str| {
+            env::var(key).ok().map(|v| {
+                v.parse::<u16>()
+                    .unwrap_or_else(|_| panic!("Cannot parse {key} to u16"))
+            })
+        };
+        let (daily, weekly, monthly) = (
+            keep("HQL_BACKUP_KEEP_DAILY"),
+            keep("HQL_BACKUP_KEEP_WEEKLY"),
+            keep("HQL_BACKUP_KEEP_MONTHLY"),
+        );
+        let retention = if daily.is_some() || weekly.is_some() || monthly.is_some() {
+            Some(RetentionPolicy::Gfs {
+                daily: daily.unwrap_or_default(),
+                weekly: weekly.unwrap_or_default(),
+                monthly: monthly.unwrap_or_default(),
+            })
+        } else {
+            None
+        };
+        let retention_dry_run = env::var("HQL_BACKUP_RETENTION_DRY_RUN")
+            .map(|v| {
+                v.parse::<bool>()
+                    .expect("Cannot parse HQL_BACKUP_RETENTION_DRY_RUN to bool")
+            })
+            .unwrap_or(false);
+
+        let verify = env::var("HQL_BACKUP_VERIFY")
+            .map(|v| {
+                v.parse::<bool>()
+                    .expect("Cannot parse HQL_BACKUP_VERIFY to bool")
+            })
+            .unwrap_or(false);
+
+        let differential = env::var("HQL_BACKUP_DIFFERENTIAL")
+            .map(|v| {
+                v.parse::<u16>()
+                    .expect("Cannot parse HQL_BACKUP_DIFFERENTIAL to u16")
+            })
+            .unwrap_or(0);
+
+        let targets = env::var("HQL_BACKUP_TARGET_DIRS")
+            .map(|dirs| {
+                dirs.lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty())
+                    .map(|dir| Arc::new(LocalTarget::new(dir)) as Arc<dyn BackupTarget>)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        #[cfg(feature = "cache")]
+        let cache = env::var("HQL_BACKUP_CACHE")
+            .map(|v| {
+                v.parse::<bool>()
+                    .expect("Cannot parse HQL_BACKUP_CACHE to bool")
+            })
+            .unwrap_or(false);
+
         Self {
             cron_schedule,
             keep_days,
+            archive_interval,
+            verify,
+            differential,
+            retention,
+            retention_dry_run,
+            targets,
+            #[cfg(feature = "cache")]
+            cache,
+        }
+    }
+}
+
+/// `(timestamp, name)` of backups
+type Backups = Vec<(DateTime<Utc>, String)>;
+
+/// Decides which backups are pruned after each backup run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Retention {
+    policy: RetentionPolicy,
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RetentionPolicy {
+    /// Keeps all backups younger than the given amount of days.
+    Days(u16),
+    /// Keeps the newest backup of each of the latest days, weeks and months that have backups.
+    Gfs {
+        daily: u16,
+        weekly: u16,
+        monthly: u16,
+    },
+}
+
+impl Retention {
+    /// Splits the `(timestamp, name)` of all backups into `(keep, expired)`, newest first.
+    fn split(&self, now: DateTime<Utc>, mut backups: Backups) -> (Backups, Backups) {
+        backups.sort_by(|a, b| b.cmp(a));
+
+        let keep = match self.policy {
+            RetentionPolicy::Days(days) => {
+                let threshold = now.sub(chrono::Duration::days(days as i64));
+                backups.iter().map(|(dt, _)| *dt >= threshold).collect()
+            }
+            RetentionPolicy::Gfs {
+                daily,
+                weekly,
+                monthly,
+            } => {
+                let mut keep = vec![false; backups.len()];
+                // never delete the latest backup, whatever the policy says
+                if let Some(latest) = keep.first_mut() {
+                    *latest = true;
+                }
+                keep_per_period(&backups, &mut keep, daily, |dt| (dt.year(), dt.ordinal()));
+                keep_per_period(&backups, &mut keep, weekly, |dt| {
+                    let week = dt.iso_week();
+                    (week.year(), week.week())
+                });
+                keep_per_period(&backups, &mut keep, monthly, |dt| (dt.year(), dt.month()));
+                keep
+            }
+        };
+
+        let (keep, expired): (Vec<_>, Vec<_>) =
+            backups.into_iter().zip(keep).partition(|(_, keep)| *keep);
+        (
+            keep.into_iter().map(|(b, _)| b).collect(),
+            expired.into_iter().map(|(b, _)| b).collect(),
+        )
+    }
+
+    /// Works like `split()` for database backups, but additionally keeps the full backup and
+    /// all older differentials of the chain of each differential backup that is kept.
+    fn split_chains(&self, now: DateTime<Utc>, backups: Backups) -> (Backups, Backups) {
+        let (mut keep, mut expired) = self.split(now, backups);
+
+        let mut needed = Vec::new();
+        for (dt, name) in keep
+            .iter()
+            .filter(|(_, name)| backup_diff::is_diff_name(name))
+        {
+            let node = chain_node(name);
+            // the chain starts with the latest full backup of the same leader
+            let start = keep
+                .iter()
+                .chain(expired.iter())
+                .filter(|(dt_full, full)| {
+                    !backup_diff::is_diff_name(full) && chain_node(full) == node && dt_full <= dt
+                })
+                .map(|(dt_full, _)| *dt_full)
+                .max();
+            if let Some(start) = start {
+                needed.push((node, start, *dt));
+            }
+        }
+
+        let (chains, expired_rest): (Vec<_>, Vec<_>) = expired.drain(..).partition(|(dt, name)| {
+            let node = chain_node(name);
+            needed
+                .iter()
+                .any(|(n, start, end)| *n == node && start <= dt && dt <= end)
+        });
+        expired = expired_rest;
+        keep.extend(chains);
+        keep.sort_by(|a, b| b.cmp(a));
+
+        (keep, expired)
+    }
+}
+
+/// The leader node id inside the name of a database backup.
+fn chain_node(name: &str) -> Option<&str> {
+    name.strip_prefix(BACKUP_PREFIX_DB)?
+        .split_once('_')
+        .map(|(node, _)| node)
+}
+
+/// Marks the newest backup of each of the latest `count` periods. `backups` must be sorted
+/// newest first.
+fn keep_per_period<F>(backups: &[(DateTime<Utc>, String)], keep: &mut [bool], count: u16, period: F)
+where
+    F: Fn(&DateTime<Utc>) -> (i32, u32),
+{
+    let mut last = None;
+    let mut kept = 0;
+    for (i, (dt, _)) in backups.iter().enumerate() {
+        if kept == count {
+            break;
+        }
+        let p = period(dt);
+        if last != Some(p) {
+            keep[i] = true;
+            kept += 1;
+            last = Some(p);
         }
     }
 }
@@ -67,12 +423,69 @@ impl BackupConfig {
 pub enum BackupSource {
     S3(String),
     File(String),
+    /// Restores the latest suitable backup from S3 and replays the archived logs on top of it.
+    /// Requires `BackupConfig::with_archive_interval()`.
+    PointInTime(RestoreTarget),
+}
+
+/// How far archived logs should be replayed on top of a backup.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RestoreTarget {
+    /// Everything that has been archived.
+    Latest,
+    /// Up to and including the given Raft log index of the latest archive lineage.
+    LogIndex(u64),
+    /// All writes up to and including the given time.
+    Timestamp(DateTime<Utc>),
+}
+
+impl RestoreTarget {
+    /// Parses `latest`, `index:<log_index>` or `time:<RFC 3339 timestamp>`.
+    fn parse(value: &str) -> Option<Self> {
+        if value == "latest" {
+            return Some(Self::Latest);
+        }
+        if let Some(index) = value.strip_prefix("index:") {
+            return index.parse().ok().map(Self::LogIndex);
+        }
+        if let Some(ts) = value.strip_prefix("time:") {
+            return DateTime::parse_from_rfc3339(ts)
+                .ok()
+                .map(|dt| Self::Timestamp(dt.to_utc()));
+        }
+        None
+    }
+}
+
+impl Display for RestoreTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Latest => write!(f, "latest"),
+            Self::LogIndex(index) => write!(f, "index:{index}"),
+            Self::Timestamp(ts) => write!(f, "time:{}", ts.to_rfc3339()),
+        }
+    }
+}
+
+impl Display for BackupSource {
+    /// The same format as `HQL_BACKUP_RESTORE`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::S3(obj) => write!(f, "s3:{obj}"),
+            Self::File(file) => write!(f, "file:{file}"),
+            Self::PointInTime(target) => write!(f, "pitr:{target}"),
+        }
+    }
 }
 
 impl BackupSource {
     fn from_env() -> Option<Self> {
         let var = env::var("HQL_BACKUP_RESTORE").ok()?;
+        Self::parse(&var)
+    }
 
+    /// Parses the same format as `HQL_BACKUP_RESTORE`.
+    pub(crate) fn parse(var: &str) -> Option<Self> {
         if let Some(obj) = var.strip_prefix("s3:") {
             return Some(Self::S3(obj.to_string()));
         }
@@ -81,8 +494,20 @@ impl BackupSource {
             return Some(Self::File(file.to_string()));
         }
 
+        if let Some(target) = var.strip_prefix("pitr:") {
+            let target = RestoreTarget::parse(target);
+            if target.is_none() {
+                error!(
+                    "HQL_BACKUP_RESTORE with 'pitr:' must be followed by 'latest', \
+                    'index:<log_index>' or 'time:<RFC 3339>'. Cannot restore from backup: {}",
+                    var
+                );
+            }
+            return target.map(Self::PointInTime);
+        }
+
         error!(
-            "HQL_BACKUP_RESTORE must start with either 's3:' or 'file:'. \
+            "HQL_BACKUP_RESTORE must start with either 's3:', 'file:' or 'pitr:'. \
             Cannot restore from backup - unknown prefix: {}",
             var
         );
@@ -95,6 +520,8 @@ pub fn start_cron(
     backup_config: BackupConfig,
     #[cfg(feature = "s3")] s3_config: Option<Arc<S3Config>>,
 ) {
+    let targets = backup_config.targets(s3_config.as_ref());
+
     task::spawn(Box::pin(async move {
         info!("Backup cron task started");
 
@@ -124,7 +551,10 @@ pub fn start_cron(
             for _ in 0..retries {
                 match backup_cron_job(
                     &client,
-                    backup_config.keep_days,
+                    backup_config.retention(),
+                    #[cfg(feature = "cache")]
+                    backup_config.cache(),
+                    &targets,
                     #[cfg(feature = "s3")]
                     &s3_config,
                 )
@@ -159,31 +589,53 @@ pub fn start_cron(
 
 async fn backup_cron_job(
     client: &Client,
-    keep_days: u16,
+    retention: Retention,
+    #[cfg(feature = "cache")] cache: bool,
+    targets: &[Arc<dyn BackupTarget>],
     #[cfg(feature = "s3")] s3_config: &Option<Arc<S3Config>>,
 ) -> Result<(), Error> {
     client.backup().await?;
 
-    #[cfg(feature = "s3")]
+    // the cache content is the same on all nodes, so only its leader needs to back it up
+    #[cfg(feature = "cache")]
+    if cache
+        && client.is_leader_cache().await
+        && let Err(err) = client.backup_cache().await
     {
-        if let Some(s3_config) = s3_config {
-            // the backup task will be async in the background, but we can start cleaning up already
-            let threshold = Utc::now().sub(chrono::Duration::days(keep_days as i64));
+        error!("Error during cache backup: {err}");
+    }
 
-            let list = s3_config.bucket.list("", None).await?;
-            for bucket in list {
-                if bucket.name != s3_config.bucket.name {
-                    info!("Found non-configured bucket {} - skipping", bucket.name);
+    // the backup task will be async in the background, but we can start cleaning up already
+    for target in targets {
+        let (keep, others) = match backup_target_cleanup(target.as_ref(), retention).await {
+            Ok(res) => res,
+            Err(err) => {
+                // the other targets should still be cleaned up
+                error!("Error during backup cleanup on {}: {err}", target.name());
+                continue;
+            }
+        };
+
+        // Archived logs only exist on the `S3Config` and can only be replayed on top of a
+        // backup we keep there. The margin covers clock drift between the nodes and S3.
+        #[cfg(feature = "s3")]
+        if let Some(s3_config) = s3_config
+            && std::ptr::addr_eq(Arc::as_ptr(target), Arc::as_ptr(s3_config))
+            && let Some(oldest) = keep.iter().map(|(dt, _)| *dt).min()
+        {
+            let threshold = oldest.sub(chrono::Duration::days(1));
+            for object in others {
+                if !archive::is_expired_segment(&object.name, object.last_modified, threshold) {
                     continue;
                 }
-
-                for object in bucket.contents.iter() {
-                    if let Some(dt) = dt_from_backup_name(&object.key)
-                        && dt < threshold
-                    {
-                        info!("Deleting expired backup: {}", object.key);
-                        s3_config.bucket.delete(object.key.clone()).await?;
-                    }
+                if retention.dry_run {
+                    debug!(
+                        "Retention dry-run: would delete log archive segment {}",
+                        object.name
+                    );
+                } else {
+                    debug!("Deleting expired log archive segment: {}", object.name);
+                    s3_config.bucket.delete(object.name).await?;
                 }
             }
         }
@@ -192,16 +644,54 @@ async fn backup_cron_job(
     Ok(())
 }
 
-pub(crate) async fn backup_local_cleanup(backup_path: String, keep_days: u16) -> Result<(), Error> {
+/// Deletes all expired backups from the `target`. Returns the database backups that are kept
+/// and all objects that are no backups.
+async fn backup_target_cleanup(
+    target: &dyn BackupTarget,
+    retention: Retention,
+) -> Result<(Backups, Vec<BackupObject>), Error> {
+    let mut backups = Vec::new();
+    let mut backups_cache = Vec::new();
+    let mut others = Vec::new();
+    for object in target.list().await? {
+        match backup_name_parts(&object.name) {
+            Some((BackupKind::Db | BackupKind::Diff, dt)) => backups.push((dt, object.name)),
+            Some((BackupKind::Cache, dt)) => backups_cache.push((dt, object.name)),
+            None => others.push(object),
+        }
+    }
+
+    let now = Utc::now();
+    let (keep, mut expired) = retention.split_chains(now, backups);
+    expired.extend(retention.split(now, backups_cache).1);
+    for (_, name) in expired {
+        if retention.dry_run {
+            info!(
+                "Retention dry-run: would delete expired backup from {}: {name}",
+                target.name()
+            );
+            continue;
+        }
+        info!("Deleting expired backup from {}: {name}", target.name());
+        target.delete(&name).await?;
+        // there is no verification for each backup
+        let _ = target.delete(&verification_object(&name)).await;
+    }
+
+    Ok((keep, others))
+}
+
+pub(crate) async fn backup_local_cleanup(
+    backup_path: String,
+    retention: Retention,
+) -> Result<(), Error> {
     // 2024/01/01 00:00:00
     let ts_min = 1704063600;
 
-    let ts_threshold = Utc::now()
-        .sub(chrono::Duration::days(keep_days as i64))
-        .timestamp();
-
     let path = Path::new(&backup_path);
     let mut dir_entries = tokio::fs::read_dir(path).await?;
+    let mut backups = Vec::new();
+    let mut backups_cache = Vec::new();
 
     loop {
         let entry = match dir_entries.next_entry().await {
@@ -217,63 +707,445 @@ pub(crate) async fn backup_local_cleanup(backup_path: String, keep_days: u16) ->
         }
 
         let name = entry.file_name();
-        if let Some(s) = name.to_str() {
-            if !s.starts_with("backup_node_") && !s.ends_with(".sqlite") {
-                continue;
+        if let Some(s) = name.to_str()
+            && !s.ends_with('~')
+            && let Some((kind, dt)) = backup_name_parts(s)
+            && dt.timestamp() > ts_min
+        {
+            match kind {
+                BackupKind::Db | BackupKind::Diff => backups.push((dt, s.to_string())),
+                BackupKind::Cache => backups_cache.push((dt, s.to_string())),
             }
+        }
+    }
 
-            let stripped = s.strip_suffix(".sqlite").unwrap_or(s);
-            let (_, ts) = stripped.rsplit_once('_').unwrap_or_default();
-
-            match ts.parse::<i64>() {
-                Ok(ts) => {
-                    if ts > ts_min && ts < ts_threshold {
-                        let p = format!("{backup_path}/{s}");
-                        info!("Cleaning up local backup {s} ({p})");
-                        if let Err(err) = tokio::fs::remove_file(p).await {
-                            error!(?err, "Error removing local backup");
-                        }
-                    }
-                }
-                Err(err) => {
-                    error!(?err, "Cannot parse ts from file {s}")
-                }
-            }
+    let now = Utc::now();
+    let (_, mut expired) = retention.split_chains(now, backups);
+    expired.extend(retention.split(now, backups_cache).1);
+    for (_, name) in expired {
+        let p = format!("{backup_path}/{name}");
+        if retention.dry_run {
+            info!("Retention dry-run: would clean up local backup {name} ({p})");
+            continue;
+        }
+        info!("Cleaning up local backup {name} ({p})");
+        if let Err(err) = tokio::fs::remove_file(p).await {
+            error!(?err, "Error removing local backup");
         }
+        let _ = tokio::fs::remove_file(verification_file(&backup_path, &name)).await;
     }
 
     Ok(())
 }
 
-fn dt_from_backup_name(name: &str) -> Option<DateTime<Utc>> {
-    if let Some(backup) = name.strip_prefix("backup_node_") {
-        let (_, rest) = match backup.split_once("_") {
-            None => {
-                error!("Invalid backup filename format on S3: {}", name);
-                return None;
+/// Retries for each backup push, because targets can be temporarily unavailable.
+const PUSH_MAX_RETRIES: u64 = 10;
+
+/// Encrypts and pushes the local backup at `path` as `object` to the `target`. The backup was
+/// already acked at this point, so failed pushes are retried, to not end up as just a log line.
+/// Returns `true` if the push succeeded.
+pub(crate) async fn push_backup(
+    target: &dyn BackupTarget,
+    path: &str,
+    object: &str,
+    progress: Option<&BackupTracker>,
+) -> bool {
+    let name = target.name();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+
+        let pushed = AtomicU64::new(0);
+        let on_progress = |bytes: u64| {
+            let prev = pushed.swap(bytes, Ordering::Relaxed);
+            if let Some(progress) = progress {
+                progress.uploaded(object, bytes as i64 - prev as i64);
             }
-            Some(s) => s,
         };
-        let ts = match rest.strip_suffix(".sqlite") {
-            None => {
+        let res = target.put_with_progress(path, object, &on_progress).await;
+        if res.is_err()
+            && let Some(progress) = progress
+        {
+            // the next attempt starts from scratch
+            progress.uploaded(object, -(pushed.load(Ordering::Relaxed) as i64));
+        }
+
+        match res {
+            Ok(_) => {
+                info!("Push backup {object} to {name} has been finished");
+                return true;
+            }
+            Err(err) if attempt < PUSH_MAX_RETRIES => {
                 error!(
-                    "Invalid backup filename on S3 - '.sqlite' suffix missing: {}",
-                    name
+                    "Error pushing Backup to {name} (attempt {attempt}/{PUSH_MAX_RETRIES}): {err} - retrying"
                 );
-                return None;
+                time::sleep(Duration::from_secs(5 * attempt)).await;
             }
-            Some(ts) => ts,
-        };
-
-        match ts.parse::<i64>() {
-            Ok(ts) => DateTime::from_timestamp(ts, 0),
             Err(err) => {
-                error!("Error parsing TS from remote backup as i64: {}", err);
-                None
+                error!(
+                    "Error pushing Backup to {name} after {attempt} attempts: {err} - \
+                    the backup exists locally but not offsite"
+                );
+                return false;
+            }
+        }
+    }
+}
+
+/// Creates a backup of the cache state machine of this node inside its backups folder and
+/// pushes it to all targets in the background. Returns the name of the backup.
+#[cfg(feature = "cache")]
+pub(crate) async fn backup_cache(state: &AppState) -> Result<String, Error> {
+    let ts = Utc::now().timestamp();
+    let file = format!("{BACKUP_PREFIX_CACHE}{}_{ts}.cache", state.id);
+    let path_full = format!("{}/{file}", state.backups_dir);
+    info!("Creating cache backup into {path_full}");
+
+    let bytes = state.raft_cache.state_machine.backup_build().await?;
+
+    // same as for the database: never leave a partial file under the final backup name
+    fs::create_dir_all(&state.backups_dir).await?;
+    let path_temp = format!("{path_full}~");
+    let res = match fs::write(&path_temp, bytes).await {
+        Ok(_) => fs::rename(&path_temp, &path_full).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = res {
+        let _ = fs::remove_file(&path_temp).await;
+        return Err(err.into());
+    }
+    info!("Cache backup finished");
+
+    for target in state.backup_targets.iter() {
+        let target = target.clone();
+        let file = file.clone();
+        let path_full = path_full.clone();
+        task::spawn(async move {
+            info!(
+                "Background task for cache backup push to {} has been started",
+                target.name()
+            );
+            push_backup(target.as_ref(), &path_full, &file, None).await;
+        });
+    }
+
+    Ok(file)
+}
+
+/// The result of a backup verification.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupVerification {
+    /// Unix timestamp of the verification.
+    pub verified_at: i64,
+    /// The reason why the backup is not restorable, or `None` if it is known-good.
+    pub error: Option<String>,
+}
+
+impl BackupVerification {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Records the row counts of all tables in the source database inside the backup, so it can be
+/// verified later on without access to the source.
+pub(crate) fn backup_set_row_counts(
+    conn: &rusqlite::Connection,
+    conn_bkp: &rusqlite::Connection,
+) -> Result<(), Error> {
+    let counts = table_row_counts(conn)?;
+    conn_bkp.execute(
+        "REPLACE INTO _metadata (key, data) VALUES ($1, $2)",
+        (META_ROW_COUNTS, serialize(&counts)?),
+    )?;
+    Ok(())
+}
+
+fn table_row_counts(conn: &rusqlite::Connection) -> Result<BTreeMap<String, i64>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT name FROM sqlite_schema WHERE type = 'table' AND name NOT LIKE 'sqlite_%' \
+        AND name != '_metadata'",
+    )?;
+    let tables = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut counts = BTreeMap::new();
+    for table in tables {
+        let sql = format!("SELECT count(*) FROM \"{}\"", table.replace('"', "\"\""));
+        let count = conn.query_row(&sql, [], |row| row.get(0))?;
+        counts.insert(table, count);
+    }
+    Ok(counts)
+}
+
+/// Checks the plain SQLite backup at `path` and returns the reason if it is not restorable.
+fn verify_db(path: &str) -> Result<(), String> {
+    let conn = rusqlite::Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|err| format!("Cannot open backup: {err}"))?;
+
+    for check in ["integrity_check", "quick_check"] {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA {check}"))
+            .map_err(|err| format!("{check} failed: {err}"))?;
+        let res = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| format!("{check} failed: {err}"))?;
+        if res != ["ok"] {
+            return Err(format!("{check} failed: {}", res.join(", ")));
+        }
+    }
+
+    let expected = conn
+        .query_row(
+            "SELECT data FROM _metadata WHERE key = $1",
+            [META_ROW_COUNTS],
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+        .optional()
+        .map_err(|err| format!("Cannot read row counts: {err}"))?;
+    let Some(expected) = expected else {
+        // backups from older versions cannot be compared with their source
+        return Ok(());
+    };
+    let expected = deserialize::<BTreeMap<String, i64>>(&expected)
+        .map_err(|err| format!("Cannot decode row counts: {err}"))?;
+
+    let actual =
+        table_row_counts(&conn).map_err(|err| format!("Cannot count table rows: {err}"))?;
+    if actual != expected {
+        let mut diff = Vec::new();
+        for (table, count) in &expected {
+            let found = actual.get(table).copied().unwrap_or_default();
+            if found != *count {
+                diff.push(format!("{table}: expected {count}, found {found}"));
             }
         }
+        for table in actual.keys().filter(|t| !expected.contains_key(*t)) {
+            diff.push(format!("{table}: unexpected table"));
+        }
+        return Err(format!("Row counts do not match - {}", diff.join(", ")));
+    }
+
+    Ok(())
+}
+
+fn verification_dir(backups_dir: &str) -> String {
+    // a sibling, so nothing else ever shows up inside the backups dir
+    format!("{backups_dir}_verify")
+}
+
+fn verification_file(backups_dir: &str, name: &str) -> String {
+    format!("{}/{name}.json", verification_dir(backups_dir))
+}
+
+pub(crate) fn verification_object(name: &str) -> String {
+    format!("verify/{name}")
+}
+
+/// Verifies the local backup `name` in a temporary copy and records the result.
+pub(crate) async fn verify_local(
+    backups_dir: &str,
+    name: &str,
+) -> Result<BackupVerification, Error> {
+    let dir = verification_dir(backups_dir);
+    fs::create_dir_all(&dir).await?;
+    let path_tmp = format!("{dir}/{name}~");
+
+    let res = match backup_diff::fetch(BackupStore::Dir(backups_dir), name, &path_tmp).await {
+        Ok(_) => verify_tmp(path_tmp.clone()).await,
+        Err(err) => Err(format!("Cannot copy backup: {err}")),
+    };
+    let _ = fs::remove_file(&path_tmp).await;
+
+    let verification = verification_from(name, res);
+    fs::write(
+        verification_file(backups_dir, name),
+        serde_json::to_vec(&verification)?,
+    )
+    .await?;
+    Ok(verification)
+}
+
+/// Fetches and decrypts the backup `name` from the `target` into a temporary file, verifies it
+/// and records the result on the `target`.
+pub(crate) async fn verify_target(
+    target: &dyn BackupTarget,
+    backups_dir: &str,
+    name: &str,
+) -> Result<BackupVerification, Error> {
+    let dir = verification_dir(backups_dir);
+    fs::create_dir_all(&dir).await?;
+    // multiple targets may verify the same backup at the same time
+    let path_tmp = format!("{dir}/{}~", Uuid::now_v7());
+
+    let res = match backup_diff::fetch(BackupStore::Target(target), name, &path_tmp).await {
+        Ok(_) => verify_tmp(path_tmp.clone()).await,
+        Err(err) => Err(format!("Cannot fetch backup from {}: {err}", target.name())),
+    };
+
+    let verification = verification_from(name, res);
+    let res = async {
+        fs::write(&path_tmp, serde_json::to_vec(&verification)?).await?;
+        target.put(&path_tmp, &verification_object(name)).await
+    }
+    .await;
+    let _ = fs::remove_file(&path_tmp).await;
+    res?;
+
+    Ok(verification)
+}
+
+/// Fetches the backup for an online restore into a temporary file and verifies it.
+/// Returns the path to the temporary file.
+pub(crate) async fn fetch_verified(
+    s3_config: Option<&S3Config>,
+    backups_dir: &str,
+    src: &BackupSource,
+    id: &str,
+) -> Result<String, Error> {
+    let dir = verification_dir(backups_dir);
+    fs::create_dir_all(&dir).await?;
+    let path_tmp = format!("{dir}/restore_{id}~");
+
+    let res = async {
+        match src {
+            BackupSource::S3(obj) => {
+                let Some(s3_config) = s3_config else {
+                    return Err(Error::S3(
+                        "No `S3Config` given, cannot restore backup".to_string(),
+                    ));
+                };
+                backup_diff::fetch(BackupStore::Target(s3_config), obj, &path_tmp).await?;
+            }
+            BackupSource::File(path) => {
+                let (dir, name) = path.rsplit_once('/').unwrap_or((".", path));
+                backup_diff::fetch(BackupStore::Dir(dir), name, &path_tmp).await?;
+            }
+            BackupSource::PointInTime(_) => {
+                return Err(Error::Config(
+                    "Point-in-time recovery cannot be fetched as a single backup".into(),
+                ));
+            }
+        }
+
+        is_metadata_ok(path_tmp.clone()).await?;
+        verify_tmp(path_tmp.clone())
+            .await
+            .map_err(|err| Error::Error(format!("Backup verification failed: {err}").into()))
+    }
+    .await;
+
+    match res {
+        Ok(()) => Ok(path_tmp),
+        Err(err) => {
+            let _ = fs::remove_file(&path_tmp).await;
+            Err(err)
+        }
+    }
+}
+
+async fn verify_tmp(path: String) -> Result<(), String> {
+    task::spawn_blocking(move || verify_db(&path))
+        .await
+        .map_err(|err| format!("Verification task failed: {err}"))?
+}
+
+fn verification_from(name: &str, res: Result<(), String>) -> BackupVerification {
+    match &res {
+        Ok(_) => info!("Backup {name} has been verified successfully"),
+        Err(err) => error!("Backup {name} verification failed: {err}"),
+    }
+    BackupVerification {
+        verified_at: Utc::now().timestamp(),
+        error: res.err(),
+    }
+}
+
+pub(crate) async fn verification_local(
+    backups_dir: &str,
+    name: &str,
+) -> Option<BackupVerification> {
+    let bytes = fs::read(verification_file(backups_dir, name)).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+pub(crate) async fn verification_target(
+    target: &dyn BackupTarget,
+    backups_dir: &str,
+    name: &str,
+) -> Result<BackupVerification, Error> {
+    let dir = verification_dir(backups_dir);
+    fs::create_dir_all(&dir).await?;
+    let path_tmp = format!("{dir}/{}~", Uuid::now_v7());
+
+    let res = match target.get(&verification_object(name), &path_tmp).await {
+        Ok(_) => fs::read(&path_tmp).await.map_err(Error::from),
+        Err(err) => Err(err),
+    };
+    let _ = fs::remove_file(&path_tmp).await;
+    Ok(serde_json::from_slice(&res?)?)
+}
+
+/// The kind of a backup. Each kind is pruned by the retention independently.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BackupKind {
+    Db,
+    /// A differential database backup, which is pruned together with its chain.
+    Diff,
+    Cache,
+}
+
+/// `true` if the `name` belongs to either a database or a cache backup.
+pub(crate) fn is_backup_name(name: &str) -> bool {
+    name.starts_with(BACKUP_PREFIX_DB) || name.starts_with(BACKUP_PREFIX_CACHE)
+}
+
+fn dt_from_backup_name(name: &str) -> Option<DateTime<Utc>> {
+    match backup_name_parts(name) {
+        Some((BackupKind::Db | BackupKind::Diff, dt)) => Some(dt),
+        _ => None,
+    }
+}
+
+fn backup_name_parts(name: &str) -> Option<(BackupKind, DateTime<Utc>)> {
+    let (kind, backup, suffix) = if let Some(backup) = name.strip_prefix(BACKUP_PREFIX_DB) {
+        if backup_diff::is_diff_name(backup) {
+            (BackupKind::Diff, backup, ".diff")
+        } else {
+            (BackupKind::Db, backup, ".sqlite")
+        }
+    } else if let Some(backup) = name.strip_prefix(BACKUP_PREFIX_CACHE) {
+        (BackupKind::Cache, backup, ".cache")
     } else {
-        None
+        return None;
+    };
+
+    let (_, rest) = match backup.split_once("_") {
+        None => {
+            error!("Invalid backup filename format: {}", name);
+            return None;
+        }
+        Some(s) => s,
+    };
+    let ts = match rest.strip_suffix(suffix) {
+        None => {
+            error!(
+                "Invalid backup filename - '{}' suffix missing: {}",
+                suffix, name
+            );
+            return None;
+        }
+        Some(ts) => ts,
+    };
+
+    match ts.parse::<i64>() {
+        Ok(ts) => DateTime::from_timestamp(ts, 0).map(|dt| (kind, dt)),
+        Err(err) => {
+            error!("Error parsing TS from backup {} as i64: {}", name, err);
+            None
+        }
     }
 }
 
@@ -305,7 +1177,7 @@ pub(crate) async fn restore_backup_start(node_config: &NodeConfig) -> Result<boo
 pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Result<(), Error> {
     info!("Starting database restore from backup {:?}", src);
 
-    if let BackupSource::S3(_) = &src
+    if matches!(src, BackupSource::S3(_) | BackupSource::PointInTime(_))
         && node_config.s3_config.is_none()
     {
         return Err(Error::S3(
@@ -324,6 +1196,7 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
     fs::create_dir_all(&path_backups).await?;
     set_path_access(&path_backups, 0o700).await?;
 
+    let mut replay = None;
     let (path_backup, remove_src) = match src {
         BackupSource::S3(s3_obj) => {
             let s3_config = match &node_config.s3_config {
@@ -335,16 +1208,39 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
                 Some(c) => c,
             };
             let path_backup = format!("{path_backups}/{BACKUP_DB_NAME}");
-            s3_config.pull(&s3_obj, &path_backup).await?;
+            backup_diff::fetch(
+                BackupStore::Target(s3_config.as_ref()),
+                &s3_obj,
+                &path_backup,
+            )
+            .await?;
             (path_backup, true)
         }
         BackupSource::File(path_src) => {
             let (path, filename) = path_src.rsplit_once('/').unwrap_or(("", &path_src));
             debug!("Given backup path full: '{path_src}', after parsing: '{path}' / '{filename}'");
-            let path_backup = format!("{path_backups}/{filename}");
 
-            fs::copy(path_src, &path_backup).await?;
-            (path_backup, false)
+            if backup_diff::is_diff_name(filename) {
+                // the rebuilt database must not show up as a differential backup afterward
+                let path_backup = format!("{path_backups}/{BACKUP_DB_NAME}");
+                let dir = path_src.rsplit_once('/').map(|(dir, _)| dir).unwrap_or(".");
+                backup_diff::fetch(BackupStore::Dir(dir), filename, &path_backup).await?;
+                (path_backup, true)
+            } else {
+                let path_backup = format!("{path_backups}/{filename}");
+                fs::copy(path_src, &path_backup).await?;
+                (path_backup, false)
+            }
+        }
+        BackupSource::PointInTime(target) => {
+            let s3_config = node_config
+                .s3_config
+                .as_ref()
+                .expect("S3Config to be checked above");
+            let path_backup = format!("{path_backups}/{BACKUP_DB_NAME}");
+            let base = pull_pitr_backup(s3_config, &target, &path_backup).await?;
+            replay = Some((base, target));
+            (path_backup, true)
         }
     };
 
@@ -369,14 +1265,70 @@ pub async fn restore_backup(node_config: &NodeConfig, src: BackupSource) -> Resu
     fs::copy(&path_backup, &path_db_full).await?;
     set_path_access(&path_db_full, 0o700).await?;
 
+    if let Some((base, target)) = replay {
+        let s3_config = node_config
+            .s3_config
+            .as_ref()
+            .expect("S3Config to be checked above");
+        archive::replay(node_config, path_db_full, s3_config, &base, &target).await?;
+    }
+
     if remove_src {
-        info!("Cleaning up S3 backup from {}", path_backup);
+        info!("Cleaning up fetched backup from {}", path_backup);
         fs::remove_file(path_backup).await?;
     }
 
     Ok(())
 }
 
+/// Pulls the latest backup from S3 the archived logs can be replayed on to reach the `target`.
+async fn pull_pitr_backup(
+    s3_config: &S3Config,
+    target: &RestoreTarget,
+    path_backup: &str,
+) -> Result<ArchivePosition, Error> {
+    let mut backups = Vec::new();
+    for list in s3_config.bucket.list("", None).await? {
+        for object in list.contents {
+            if let Some(dt) = dt_from_backup_name(&object.key) {
+                backups.push((dt, object.key));
+            }
+        }
+    }
+    // newest first
+    backups.sort_by(|a, b| b.cmp(a));
+
+    let mut lineage = None;
+    for (dt, key) in backups {
+        if let RestoreTarget::Timestamp(ts) = target
+            && dt > *ts
+        {
+            continue;
+        }
+
+        backup_diff::fetch(BackupStore::Target(s3_config), &key, path_backup).await?;
+        let Some(base) = archive::backup_read_base(path_backup.to_string()).await? else {
+            warn!("Backup {key} has been created without log archiving - skipping it");
+            continue;
+        };
+
+        if let RestoreTarget::LogIndex(index) = target {
+            // log indexes restart with each restore and only make sense inside one lineage
+            let lineage = lineage.get_or_insert_with(|| base.lineage.clone());
+            if base.lineage != *lineage || base.index > *index {
+                continue;
+            }
+        }
+
+        info!("Using backup {key} as the base for point-in-time recovery");
+        return Ok(base);
+    }
+
+    Err(Error::S3(format!(
+        "No backup with log archiving found on S3 for {target:?}"
+    )))
+}
+
 async fn is_metadata_ok(path_db: String) -> Result<(), Error> {
     if env::var("HQL_BACKUP_SKIP_VALIDATION") == Ok("true".to_string()) {
         return Ok(());
@@ -405,79 +1357,351 @@ async fn is_metadata_ok(path_db: String) -> Result<(), Error> {
 #[tracing::instrument(level = "debug", skip_all)]
 #[cfg(feature = "backup")]
 pub async fn restore_backup_finish(state: &Arc<AppState>) {
-    loop {
-        match state.raft_db.raft.is_initialized().await {
-            Ok(res) => {
-                if res {
-                    break;
-                }
-            }
-            Err(err) => {
-                error!("{}", err);
-            }
+    init::build_initial_snapshot(&state.raft_db.raft, state.id).await;
+    info!("restore_backup_finish task successful");
+}
+
+/// Checks if the env var `HQL_BACKUP_RESTORE_CACHE` is set and prepares the cache restore if so.
+/// Each node cleans up its cache state machine and logs, while node `1` additionally returns
+/// the backup, which must be applied before its cache Raft starts.
+#[cfg(feature = "cache")]
+pub(crate) async fn restore_cache_start(
+    node_config: &NodeConfig,
+) -> Result<Option<Vec<u8>>, Error> {
+    let Ok(var) = env::var("HQL_BACKUP_RESTORE_CACHE") else {
+        return Ok(None);
+    };
+    let src = match BackupSource::parse(&var) {
+        Some(src @ (BackupSource::S3(_) | BackupSource::File(_))) => src,
+        _ => {
+            return Err(Error::Config(
+                "HQL_BACKUP_RESTORE_CACHE must start with either 's3:' or 'file:'".into(),
+            ));
         }
-        debug!("Waiting for Raft init");
-        time::sleep(Duration::from_millis(50)).await;
-    }
+    };
+    info!("Found cache backup restore request {:?}", src);
 
-    while state.raft_db.raft.current_leader().await.is_none() {
-        time::sleep(Duration::from_millis(50)).await;
+    debug!("Removing old cache data");
+    let _ = fs::remove_dir_all(format!("{}/state_machine_cache", node_config.data_dir)).await;
+    let _ = fs::remove_dir_all(logs::logs_dir_cache(&node_config.data_dir)).await;
+
+    if node_config.node_id != 1 {
+        warn!("Cleaned up the existing cache - starting restore cluster join");
+        return Ok(None);
     }
 
-    debug_assert!(
-        state.raft_db.raft.current_leader().await == Some(state.id),
-        "It should never happen that node 1 is not the raft leader during backup restore"
-    );
+    let bytes = match src {
+        BackupSource::S3(s3_obj) => {
+            let Some(s3_config) = &node_config.s3_config else {
+                return Err(Error::S3(
+                    "No `S3Config` given, cannot restore cache backup".to_string(),
+                ));
+            };
+            let path_backups = format!("{}/state_machine/backups", node_config.data_dir);
+            fs::create_dir_all(&path_backups).await?;
 
-    let reqs = 10;
-    for _ in 0..reqs {
-        let start = Instant::now();
-        match state.raft_db.raft.client_write(QueryWrite::RTT).await {
-            Ok(_) => {
-                info!("Raft RTT: {} micros", start.elapsed().as_micros());
-            }
-            Err(err) => {
-                error!("Raft RTT request error: {}", err);
-            }
+            let path_tmp = format!("{path_backups}/{}", Uuid::now_v7());
+            let res = s3_config.pull(&s3_obj, &path_tmp).await;
+            let bytes = match res {
+                Ok(_) => fs::read(&path_tmp).await.map_err(Error::from),
+                Err(err) => Err(err),
+            };
+            let _ = fs::remove_file(&path_tmp).await;
+            bytes?
         }
-    }
+        BackupSource::File(path) => fs::read(path).await?,
+        BackupSource::PointInTime(_) => unreachable!("rejected above"),
+    };
+
+    Ok(Some(bytes))
+}
+
+/// Builds a fresh snapshot of the restored cache and purges the logs afterward, so all other
+/// nodes must install the snapshot when they join.
+#[cfg(feature = "cache")]
+pub(crate) async fn restore_cache_finish(state: &Arc<AppState>) {
+    let raft = &state.raft_cache.raft;
 
-    let last_log;
     loop {
-        let metrics = state.raft_db.raft.metrics().borrow().clone();
-        if let Some(last_applied) = metrics.last_applied
-            && last_applied.index >= reqs
-        {
-            debug!("Found high enough last_applied log id");
-            last_log = last_applied.index;
-            break;
+        match raft.is_initialized().await {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(err) => error!("{}", err),
         }
+        debug!("Waiting for cache Raft init");
         time::sleep(Duration::from_millis(50)).await;
     }
 
-    debug!("Taking snapshot now");
-    if let Err(err) = state.raft_db.raft.trigger().snapshot().await {
-        // e.g. the raft is shutting down: do not panic the task, just bail out - the
-        // wait-for-snapshot loop below would never finish anyway
-        error!("Error triggering snapshot: {err}");
-        return;
+    while raft.current_leader().await.is_none() {
+        time::sleep(Duration::from_millis(50)).await;
     }
 
-    // while let Err(_err) = state.raft_db.raft.trigger().snapshot().await {
-    //     debug_assert!("")
-    //     time::sleep(Duration::from_millis(500)).await;
-    // }
+    let last_log = loop {
+        if let Some(last_applied) = raft.metrics().borrow().last_applied {
+            break last_applied.index;
+        }
+        time::sleep(Duration::from_millis(50)).await;
+    };
 
-    // wait until snapshot has been built
-    while state.raft_db.raft.metrics().borrow().snapshot.is_none() {
-        info!("Waiting for snapshot build to finish");
+    debug!("Taking cache snapshot now");
+    if let Err(err) = raft.trigger().snapshot().await {
+        error!("Error triggering cache snapshot: {err}");
+        return;
+    }
+
+    while raft.metrics().borrow().snapshot.map(|s| s.index) < Some(last_log) {
+        info!("Waiting for cache snapshot build to finish");
         time::sleep(Duration::from_millis(100)).await;
     }
 
-    debug!("Purging logs");
-    while let Err(err) = state.raft_db.raft.trigger().purge_log(last_log).await {
-        error!("Error during logs purge: {}", err);
+    debug!("Purging cache logs");
+    while let Err(err) = raft.trigger().purge_log(last_log).await {
+        error!("Error during cache logs purge: {}", err);
     }
 
-    info!("restore_backup_finish task successful");
+    info!("restore_cache_finish task successful");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backups(timestamps: &[&str]) -> Backups {
+        timestamps
+            .iter()
+            .map(|ts| {
+                let dt = DateTime::parse_from_rfc3339(ts).unwrap().to_utc();
+                (dt, format!("backup_node_1_{}.sqlite", dt.timestamp()))
+            })
+            .collect()
+    }
+
+    fn names(backups: &[(DateTime<Utc>, String)]) -> Vec<String> {
+        backups
+            .iter()
+            .map(|(dt, _)| dt.format("%Y-%m-%d %H:%M").to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn local_cleanup_ignores_foreign_files() {
+        let dir = env::temp_dir().join(format!(
+            "hiqlite_cleanup_test_{}_{}",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        fs::create_dir_all(&dir).await.unwrap();
+
+        // old enough to be expired by a retention of 1 day
+        let ts = Utc::now().timestamp() - 7 * 24 * 60 * 60;
+        let backup = dir.join(format!("backup_node_1_{ts}.sqlite"));
+        let foreign = dir.join(format!("app_{ts}.sqlite"));
+        fs::write(&backup, b"").await.unwrap();
+        fs::write(&foreign, b"").await.unwrap();
+
+        let retention = Retention {
+            policy: RetentionPolicy::Days(1),
+            dry_run: false,
+        };
+        backup_local_cleanup(dir.to_str().unwrap().to_string(), retention)
+            .await
+            .unwrap();
+
+        let backup_exists = fs::try_exists(&backup).await.unwrap();
+        let foreign_exists = fs::try_exists(&foreign).await.unwrap();
+        let _ = fs::remove_dir_all(&dir).await;
+        assert!(!backup_exists);
+        assert!(foreign_exists);
+    }
+
+    #[test]
+    fn retention_days() {
+        let now = DateTime::parse_from_rfc3339("2025-03-31T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let retention = Retention {
+            policy: RetentionPolicy::Days(2),
+            dry_run: false,
+        };
+        let (keep, expired) = retention.split(
+            now,
+            backups(&[
+                "2025-03-28T02:30:00Z",
+                "2025-03-30T02:30:00Z",
+                "2025-03-29T12:00:00Z",
+                "2025-03-31T02:30:00Z",
+            ]),
+        );
+        assert_eq!(
+            names(&keep),
+            ["2025-03-31 02:30", "2025-03-30 02:30", "2025-03-29 12:00"]
+        );
+        assert_eq!(names(&expired), ["2025-03-28 02:30"]);
+    }
+
+    #[test]
+    fn retention_gfs() {
+        let now = DateTime::parse_from_rfc3339("2025-03-31T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let retention = Retention {
+            policy: RetentionPolicy::Gfs {
+                daily: 3,
+                weekly: 2,
+                monthly: 3,
+            },
+            dry_run: false,
+        };
+
+        let (keep, expired) = retention.split(
+            now,
+            backups(&[
+                // 2 backups on the same day -> only the newest one is a daily backup
+                "2025-03-31T02:30:00Z",
+                "2025-03-31T10:00:00Z",
+                "2025-03-30T02:30:00Z",
+                "2025-03-29T02:30:00Z",
+                "2025-03-28T02:30:00Z",
+                // the week before, Mon - Sun
+                "2025-03-23T02:30:00Z",
+                "2025-03-17T02:30:00Z",
+                // 2 weeks before
+                "2025-03-10T02:30:00Z",
+                "2025-02-15T02:30:00Z",
+                "2025-02-01T02:30:00Z",
+                "2025-01-31T02:30:00Z",
+                "2024-12-31T02:30:00Z",
+            ]),
+        );
+
+        assert_eq!(
+            names(&keep),
+            [
+                // daily + weekly (2025-W14) + monthly
+                "2025-03-31 10:00",
+                // daily + weekly (2025-W13)
+                "2025-03-30 02:30",
+                // daily
+                "2025-03-29 02:30",
+                // monthly
+                "2025-02-15 02:30",
+                "2025-01-31 02:30",
+            ]
+        );
+        assert_eq!(
+            names(&expired),
+            [
+                "2025-03-31 02:30",
+                "2025-03-28 02:30",
+                "2025-03-23 02:30",
+                "2025-03-17 02:30",
+                "2025-03-10 02:30",
+                "2025-02-01 02:30",
+                "2024-12-31 02:30",
+            ]
+        );
+
+        // the latest backup is always kept
+        let retention = Retention {
+            policy: RetentionPolicy::Gfs {
+                daily: 0,
+                weekly: 0,
+                monthly: 0,
+            },
+            dry_run: true,
+        };
+        let (keep, expired) = retention.split(
+            now,
+            backups(&["2025-03-30T02:30:00Z", "2025-03-31T02:30:00Z"]),
+        );
+        assert_eq!(names(&keep), ["2025-03-31 02:30"]);
+        assert_eq!(names(&expired), ["2025-03-30 02:30"]);
+    }
+
+    #[test]
+    fn retention_keeps_differential_chains() {
+        let now = DateTime::parse_from_rfc3339("2025-03-31T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let retention = Retention {
+            policy: RetentionPolicy::Days(2),
+            dry_run: false,
+        };
+        let backup = |ts: &str, node: u64, suffix: &str| {
+            let dt = DateTime::parse_from_rfc3339(ts).unwrap().to_utc();
+            (
+                dt,
+                format!("backup_node_{node}_{}.{suffix}", dt.timestamp()),
+            )
+        };
+
+        let (keep, expired) = retention.split_chains(
+            now,
+            vec![
+                backup("2025-03-25T02:30:00Z", 1, "sqlite"),
+                backup("2025-03-26T02:30:00Z", 1, "diff"),
+                // a new chain after a leader change
+                backup("2025-03-27T02:30:00Z", 2, "sqlite"),
+                backup("2025-03-28T02:30:00Z", 2, "diff"),
+                backup("2025-03-29T02:30:00Z", 2, "diff"),
+                backup("2025-03-30T02:30:00Z", 2, "diff"),
+                backup("2025-03-31T02:30:00Z", 2, "diff"),
+            ],
+        );
+        // the kept differentials need their full backup and all differentials in between
+        assert_eq!(
+            names(&keep),
+            [
+                "2025-03-31 02:30",
+                "2025-03-30 02:30",
+                "2025-03-29 02:30",
+                "2025-03-28 02:30",
+                "2025-03-27 02:30",
+            ]
+        );
+        assert_eq!(names(&expired), ["2025-03-26 02:30", "2025-03-25 02:30"]);
+    }
+
+    #[test]
+    fn verify_row_counts() -> Result<(), Error> {
+        let path = env::temp_dir().join(format!(
+            "hiqlite_backup_verify_test_{}_{}.sqlite",
+            std::process::id(),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let path = path.to_str().unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory()?;
+        conn.execute_batch(
+            r#"
+CREATE TABLE _metadata (key TEXT NOT NULL PRIMARY KEY, data BLOB NOT NULL);
+CREATE TABLE "odd ""name""" (id INTEGER PRIMARY KEY);
+CREATE TABLE items (id INTEGER PRIMARY KEY);
+INSERT INTO items (id) VALUES (1), (2), (3);
+"#,
+        )?;
+        conn.execute(&format!("VACUUM INTO '{path}'"), ())?;
+
+        let conn_bkp = rusqlite::Connection::open(path)?;
+        backup_set_row_counts(&conn, &conn_bkp)?;
+        assert_eq!(verify_db(path), Ok(()));
+
+        // a backup that does not match its source anymore
+        conn_bkp.execute("DELETE FROM items WHERE id = 3", ())?;
+        conn_bkp.execute("CREATE TABLE other (id INTEGER PRIMARY KEY)", ())?;
+        let err = verify_db(path).unwrap_err();
+        assert!(err.contains("items: expected 3, found 2"), "{err}");
+        assert!(err.contains("other: unexpected table"), "{err}");
+        drop(conn_bkp);
+
+        // not a database at all
+        std::fs::write(
+            path,
+            b"definitely not an sqlite file, but long enough for a header",
+        )?;
+        assert!(verify_db(path).is_err());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,580 @@
+//! Differential backups based on SQLite page hashes.
+//!
+//! A differential chain starts with a full backup, which is a page-exact copy of the database.
+//! Each node keeps the hash of each page of the latest backup in its chain next to its backups
+//! folder. All following backups of the chain only contain the pages that have changed since
+//! the previous one, together with an index of their page numbers. A restore fetches the full
+//! backup and applies all differentials of the chain in order.
+//!
+//! A `VACUUM` rewrites the whole page layout, which is why it only runs before full backups as
+//! long as differentials are enabled.
+
+use crate::backup_progress::PAGES_PER_STEP;
+use crate::backup_target::BackupTarget;
+use crate::helpers::{deserialize, serialize};
+use crate::{Error, NodeId};
+use rusqlite::backup::StepResult;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tokio::{fs, task};
+use tracing::{debug, info, warn};
+
+const DIFF_MAGIC: &[u8; 8] = b"HQLDIFF1";
+const DIFF_SUFFIX: &str = ".diff";
+/// A chain can never be longer than the max amount of differentials plus its full backup.
+const CHAIN_MAX_LEN: usize = u16::MAX as usize + 1;
+
+/// The kind of the next database backup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NextBackup {
+    /// Differentials are disabled and each backup is a full one.
+    Plain,
+    /// Starts a new differential chain.
+    Full,
+    /// Only contains the pages that have changed since the latest backup of the chain.
+    Differential,
+    /// The chain already contains a newer backup, which happens for replayed log entries.
+    /// Creating it again would break the chain.
+    Exists,
+}
+
+/// The page hashes of the latest backup of the current chain on this node.
+#[derive(Debug, Serialize, Deserialize)]
+struct ChainState {
+    /// The leader the chain has been created with. Only this node pushes the chain to the
+    /// targets, which is why each leader change starts a new chain.
+    node_id: NodeId,
+    /// The full backup of the chain.
+    base: String,
+    /// The latest backup of the chain, either `base` or a differential.
+    latest: String,
+    latest_ts: i64,
+    differentials: u16,
+    page_size: u32,
+    image_hash: String,
+    hashes: Vec<[u8; 32]>,
+}
+
+/// The index at the start of each differential backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DiffIndex {
+    /// The full backup of the chain.
+    base: String,
+    /// The backup this differential must be applied on.
+    parent: String,
+    /// sha256 of the database the `parent` rebuilds into.
+    parent_hash: String,
+    /// sha256 of the database after this differential has been applied.
+    image_hash: String,
+    page_size: u32,
+    page_count: u32,
+    /// The changed pages, starting at `0`, in the same order as their content follows the index.
+    pages: Vec<u32>,
+}
+
+/// Where backups can be fetched from for a restore or verification.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum BackupStore<'a> {
+    /// Plain backups inside a local directory, like the backups folder of a node.
+    Dir(&'a str),
+    /// Encrypted backups on a `BackupTarget`.
+    Target(&'a dyn BackupTarget),
+}
+
+impl BackupStore<'_> {
+    async fn get(&self, name: &str, path: &str) -> Result<(), Error> {
+        match self {
+            Self::Dir(dir) => {
+                fs::copy(format!("{dir}/{name}"), path).await?;
+                Ok(())
+            }
+            Self::Target(target) => target.get(name, path).await,
+        }
+    }
+}
+
+pub(crate) fn is_diff_name(name: &str) -> bool {
+    name.ends_with(DIFF_SUFFIX)
+}
+
+fn state_path(backups_dir: &str) -> String {
+    // a sibling, so nothing else ever shows up inside the backups dir
+    format!("{backups_dir}_diff/chain")
+}
+
+fn state_read(backups_dir: &str) -> Option<ChainState> {
+    let bytes = std::fs::read(state_path(backups_dir)).ok()?;
+    match deserialize(&bytes) {
+        Ok(state) => Some(state),
+        Err(err) => {
+            warn!("Cannot read differential backup chain state: {err}");
+            None
+        }
+    }
+}
+
+fn state_write(backups_dir: &str, state: &ChainState) -> Result<(), Error> {
+    let path = state_path(backups_dir);
+    if let Some((dir, _)) = path.rsplit_once('/') {
+        std::fs::create_dir_all(dir)?;
+    }
+    let path_temp = format!("{path}~");
+    std::fs::write(&path_temp, serialize(state)?)?;
+    std::fs::rename(path_temp, path)?;
+    Ok(())
+}
+
+/// Removes the chain state, so the next backup will be a full one.
+pub(crate) fn chain_reset(backups_dir: &str) {
+    let _ = std::fs::remove_file(state_path(backups_dir));
+}
+
+/// Decides the kind of the next backup, which depends on the current chain on this node.
+pub(crate) fn next_backup(
+    backups_dir: &str,
+    node_id: NodeId,
+    ts: i64,
+    max_differentials: u16,
+) -> NextBackup {
+    if max_differentials == 0 {
+        return NextBackup::Plain;
+    }
+
+    let Some(state) = state_read(backups_dir) else {
+        return NextBackup::Full;
+    };
+    if ts <= state.latest_ts {
+        NextBackup::Exists
+    } else if state.node_id != node_id {
+        info!("The leader has changed - starting a new differential backup chain");
+        NextBackup::Full
+    } else if state.differentials >= max_differentials {
+        NextBackup::Full
+    } else if !Path::new(&format!("{backups_dir}/{}", state.latest)).exists() {
+        warn!(
+            "The latest backup {} of the differential chain does not exist anymore - starting \
+            a new chain",
+            state.latest
+        );
+        NextBackup::Full
+    } else {
+        NextBackup::Differential
+    }
+}
+
+/// Creates a page-exact copy of the database at `path`. Unlike `VACUUM INTO`, this keeps the
+/// page layout, which makes it possible to compare it with the previous backup page by page.
+/// `on_step` is called with the progress after each step.
+pub(crate) fn copy_pages<F>(
+    conn: &rusqlite::Connection,
+    path: &str,
+    mut on_step: F,
+) -> Result<(), Error>
+where
+    F: FnMut(rusqlite::backup::Progress),
+{
+    let mut conn_bkp = rusqlite::Connection::open(path)?;
+    {
+        let backup = rusqlite::backup::Backup::new(conn, &mut conn_bkp)?;
+        loop {
+            let res = backup.step(PAGES_PER_STEP)?;
+            on_step(backup.progress());
+            match res {
+                StepResult::Done => break,
+                StepResult::More => {}
+                // the writer is the only connection that modifies the database
+                res => {
+                    return Err(Error::Sqlite(
+                        format!("Unexpected result while copying pages: {res:?}").into(),
+                    ));
+                }
+            }
+        }
+    }
+    // the copy should be a single self-contained file like any other backup
+    conn_bkp.query_row("PRAGMA journal_mode = DELETE", (), |_| Ok(()))?;
+    Ok(())
+}
+
+/// Starts a new chain with the full backup `name` at `path`.
+pub(crate) fn chain_start(
+    backups_dir: &str,
+    node_id: NodeId,
+    ts: i64,
+    name: &str,
+    path: &str,
+) -> Result<(), Error> {
+    let (page_size, hashes, image_hash) = hash_pages(path)?;
+    state_write(
+        backups_dir,
+        &ChainState {
+            node_id,
+            base: name.to_string(),
+            latest: name.to_string(),
+            latest_ts: ts,
+            differentials: 0,
+            page_size,
+            image_hash,
+            hashes,
+        },
+    )
+}
+
+/// Builds the differential backup `name` from the page-exact copy at `path_image` inside the
+/// `backups_dir` and removes the copy afterward.
+pub(crate) fn create_differential(
+    backups_dir: &str,
+    ts: i64,
+    name: &str,
+    path_image: &str,
+) -> Result<(), Error> {
+    let Some(mut state) = state_read(backups_dir) else {
+        return Err(Error::Error(
+            "No differential backup chain state found".into(),
+        ));
+    };
+
+    let (page_size, hashes, image_hash) = hash_pages(path_image)?;
+    if page_size != state.page_size {
+        return Err(Error::Error(
+            format!(
+                "The page size changed from {} to {page_size} - a new full backup is needed",
+                state.page_size
+            )
+            .into(),
+        ));
+    }
+    let pages = hashes
+        .iter()
+        .enumerate()
+        .filter(|(i, hash)| state.hashes.get(*i) != Some(hash))
+        .map(|(i, _)| i as u32)
+        .collect::<Vec<_>>();
+
+    let index = DiffIndex {
+        base: state.base.clone(),
+        parent: state.latest.clone(),
+        parent_hash: state.image_hash.clone(),
+        image_hash: image_hash.clone(),
+        page_size,
+        page_count: hashes.len() as u32,
+        pages,
+    };
+
+    let path = format!("{backups_dir}/{name}");
+    // `{path}~` is usually the image itself
+    let path_temp = format!("{path}.pages~");
+    if let Err(err) = write_diff(&index, path_image, &path_temp) {
+        let _ = std::fs::remove_file(&path_temp);
+        return Err(err);
+    }
+    std::fs::rename(&path_temp, &path)?;
+    std::fs::remove_file(path_image)?;
+    info!(
+        "Differential backup {name} contains {} of {} pages",
+        index.pages.len(),
+        index.page_count
+    );
+
+    state.latest = name.to_string();
+    state.latest_ts = ts;
+    state.differentials += 1;
+    state.image_hash = image_hash;
+    state.hashes = hashes;
+    state_write(backups_dir, &state)
+}
+
+/// Fetches the backup `name` from the `store` into `path`. A differential backup will be
+/// rebuilt from its full backup and all differentials of its chain.
+pub(crate) async fn fetch(store: BackupStore<'_>, name: &str, path: &str) -> Result<(), Error> {
+    if !is_diff_name(name) {
+        return store.get(name, path).await;
+    }
+
+    let mut paths = Vec::new();
+    let res = async {
+        // follow the chain back to its full backup, newest first
+        let mut diffs = Vec::new();
+        let mut visited = HashSet::new();
+        let mut next = name.to_string();
+        while is_diff_name(&next) {
+            // a differential pointing back into its own chain would never end
+            if diffs.len() >= CHAIN_MAX_LEN || !visited.insert(next.clone()) {
+                return Err(Error::Error(
+                    format!("The differential backup chain of {name} is broken").into(),
+                ));
+            }
+
+            let path_diff = format!("{path}.{}~", diffs.len());
+            paths.push(path_diff.clone());
+            store.get(&next, &path_diff).await?;
+
+            let p = path_diff.clone();
+            let index = task::spawn_blocking(move || read_index(&p)).await??;
+            debug!("Differential backup {next} is based on {}", index.parent);
+            next = index.parent.clone();
+            diffs.push((path_diff, index));
+        }
+
+        info!(
+            "Rebuilding {name} from {next} and {} differential backups",
+            diffs.len()
+        );
+        store.get(&next, path).await?;
+
+        diffs.reverse();
+        let path = path.to_string();
+        task::spawn_blocking(move || apply(&path, &diffs)).await?
+    }
+    .await;
+
+    for path_diff in paths {
+        let _ = fs::remove_file(path_diff).await;
+    }
+    if res.is_err() {
+        let _ = fs::remove_file(path).await;
+    }
+    res
+}
+
+/// Applies the `(path, index)` of all `diffs`, oldest first, on the full backup at `path`.
+fn apply(path: &str, diffs: &[(String, DiffIndex)]) -> Result<(), Error> {
+    let Some((_, first)) = diffs.first() else {
+        return Ok(());
+    };
+    let (_, _, hash) = hash_pages(path)?;
+    if hash != first.parent_hash {
+        return Err(Error::Error(
+            format!(
+                "Full backup {} does not match its differential backups",
+                first.parent
+            )
+            .into(),
+        ));
+    }
+
+    let mut image = std::fs::OpenOptions::new().write(true).open(path)?;
+    for (path_diff, index) in diffs {
+        let page_size = index.page_size as usize;
+        image.set_len(index.page_count as u64 * page_size as u64)?;
+
+        let mut reader = BufReader::new(File::open(path_diff)?);
+        // the reader is still positioned at the start of the pages afterward
+        read_index_from(&mut reader)?;
+
+        let mut page = vec![0; page_size];
+        for pgno in &index.pages {
+            reader.read_exact(&mut page)?;
+            image.seek(SeekFrom::Start(*pgno as u64 * page_size as u64))?;
+            image.write_all(&page)?;
+        }
+    }
+    image.sync_all()?;
+    drop(image);
+
+    let (_, _, hash) = hash_pages(path)?;
+    let last = &diffs.last().unwrap().1;
+    if hash != last.image_hash {
+        return Err(Error::Error(
+            "The rebuilt backup does not match its differential backup".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn write_diff(index: &DiffIndex, path_image: &str, path: &str) -> Result<(), Error> {
+    let bytes = serialize(index)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(DIFF_MAGIC)?;
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+
+    let mut image = File::open(path_image)?;
+    let mut page = vec![0; index.page_size as usize];
+    for pgno in &index.pages {
+        image.seek(SeekFrom::Start(*pgno as u64 * index.page_size as u64))?;
+        image.read_exact(&mut page)?;
+        writer.write_all(&page)?;
+    }
+
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok(())
+}
+
+fn read_index(path: &str) -> Result<DiffIndex, Error> {
+    read_index_from(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_index_from<R: Read>(reader: &mut R) -> Result<DiffIndex, Error> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != DIFF_MAGIC {
+        return Err(Error::Error("Not a differential backup".into()));
+    }
+
+    let mut len = [0; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    // the index contains 4 bytes per page, which can never come close to this
+    if len > 1024 * 1024 * 1024 {
+        return Err(Error::Error(
+            "Invalid differential backup index length".into(),
+        ));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(deserialize(&bytes)?)
+}
+
+/// Returns the page size, the hash of each page and the hash of the whole database at `path`.
+fn hash_pages(path: &str) -> Result<(u32, Vec<[u8; 32]>, String), Error> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    // the page size is a big-endian u16 at offset 16 of the header, where `1` means 65536
+    let mut header = [0; 100];
+    file.read_exact(&mut header)?;
+    let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+        1 => 65536,
+        size => size as u32,
+    };
+    if !page_size.is_power_of_two() || page_size < 512 {
+        return Err(Error::Error(
+            format!("Invalid page size {page_size} in {path}").into(),
+        ));
+    }
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut hasher = Sha256::new();
+    let mut hashes = Vec::new();
+    let mut page = vec![0; page_size as usize];
+    loop {
+        let mut read = 0;
+        while read < page.len() {
+            match file.read(&mut page[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        if read == 0 {
+            break;
+        }
+        if read < page.len() {
+            return Err(Error::Error(
+                format!("{path} is not a multiple of its page size").into(),
+            ));
+        }
+
+        hasher.update(&page);
+        hashes.push(Sha256::digest(&page).into());
+    }
+
+    Ok((page_size, hashes, hex::encode(hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn differential_chain_rebuilds_backup() -> Result<(), Error> {
+        let dir =
+            std::env::temp_dir().join(format!("hiqlite_backup_diff_{}", uuid::Uuid::now_v7()));
+        let backups_dir = dir.join("backups");
+        std::fs::create_dir_all(&backups_dir)?;
+        let backups_dir = backups_dir.to_str().unwrap().to_string();
+        let dir = dir.to_str().unwrap().to_string();
+
+        let conn = rusqlite::Connection::open(format!("{dir}/source.db"))?;
+        conn.execute_batch(
+            r#"
+            PRAGMA journal_mode = WAL;
+            CREATE TABLE items (id INTEGER PRIMARY KEY, data TEXT NOT NULL);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+            INSERT INTO items (id, data) SELECT i, 'item ' || i FROM n;
+            "#,
+        )?;
+
+        assert_eq!(next_backup(&backups_dir, 1, 1, 0), NextBackup::Plain);
+        assert_eq!(next_backup(&backups_dir, 1, 1, 2), NextBackup::Full);
+        let full = "backup_node_1_1.sqlite";
+        let path_full = format!("{backups_dir}/{full}");
+        copy_pages(&conn, &path_full, |_| {})?;
+        chain_start(&backups_dir, 1, 1, full, &path_full)?;
+
+        // two differentials, each of them only with a few changed pages
+        let mut diffs = Vec::new();
+        for (ts, sql) in [
+            (2, "UPDATE items SET data = 'changed' WHERE id = 1000"),
+            (3, "INSERT INTO items (id, data) VALUES (2001, 'new')"),
+        ] {
+            assert_eq!(
+                next_backup(&backups_dir, 1, ts, 2),
+                NextBackup::Differential
+            );
+            conn.execute(sql, ())?;
+
+            let name = format!("backup_node_1_{ts}.diff");
+            // the same temp file the writer uses
+            let path_image = format!("{backups_dir}/{name}~");
+            copy_pages(&conn, &path_image, |_| {})?;
+            create_differential(&backups_dir, ts, &name, &path_image)?;
+            assert!(!Path::new(&path_image).exists());
+
+            let index = read_index(&format!("{backups_dir}/{name}"))?;
+            assert_eq!(index.base, full);
+            assert!(!index.pages.is_empty());
+            assert!(index.pages.len() < index.page_count as usize / 2);
+            diffs.push(name);
+        }
+        // the max amount of differentials has been reached, and a new leader always starts a
+        // new chain
+        assert_eq!(next_backup(&backups_dir, 1, 4, 2), NextBackup::Full);
+        assert_eq!(next_backup(&backups_dir, 2, 4, 3), NextBackup::Full);
+        assert_eq!(next_backup(&backups_dir, 1, 4, 3), NextBackup::Differential);
+        // replayed backup requests must never overwrite a backup of the chain
+        assert_eq!(next_backup(&backups_dir, 1, 3, 3), NextBackup::Exists);
+
+        let path_restored = format!("{dir}/restored.db");
+        fetch(BackupStore::Dir(&backups_dir), &diffs[1], &path_restored).await?;
+        let restored = rusqlite::Connection::open(&path_restored)?;
+        let (count, changed): (i64, String) = restored.query_row(
+            "SELECT COUNT(*), (SELECT data FROM items WHERE id = 1000) FROM items",
+            (),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(count, 2001);
+        assert_eq!(changed, "changed");
+        let check: String = restored.query_row("PRAGMA integrity_check", (), |row| row.get(0))?;
+        assert_eq!(check, "ok");
+        drop(restored);
+
+        // the first differential rebuilds the state at its own time
+        fetch(BackupStore::Dir(&backups_dir), &diffs[0], &path_restored).await?;
+        let restored = rusqlite::Connection::open(&path_restored)?;
+        let count: i64 = restored.query_row("SELECT COUNT(*) FROM items", (), |row| row.get(0))?;
+        assert_eq!(count, 2000);
+        drop(restored);
+
+        // a chain with a wrong full backup must never be applied
+        conn.execute("DELETE FROM items WHERE id < 100", ())?;
+        copy_pages(&conn, &path_full, |_| {})?;
+        assert!(
+            fetch(BackupStore::Dir(&backups_dir), &diffs[1], &path_restored)
+                .await
+                .is_err()
+        );
+        assert!(!Path::new(&path_restored).exists());
+
+        chain_reset(&backups_dir);
+        assert_eq!(next_backup(&backups_dir, 1, 4, 2), NextBackup::Full);
+
+        drop(conn);
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
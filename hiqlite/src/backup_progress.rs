@@ -0,0 +1,293 @@
+//! Progress and metrics of database backups and snapshots on this node.
+//!
+//! The writer copies the database page by page and reports each step, and each push to a
+//! `BackupTarget` reports the bytes it has sent. A backup is only finished, when it has been
+//! pushed to all targets.
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// The amount of pages copied with each step of the SQLite backup API.
+pub(crate) const PAGES_PER_STEP: i32 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ProgressKind {
+    Backup,
+    Snapshot,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupProgress {
+    pub kind: ProgressKind,
+    /// The name of the backup file, or the ID of the snapshot.
+    pub name: String,
+    /// Unix timestamp in milliseconds.
+    pub started: i64,
+    pub state: BackupState,
+    pub pages_total: u64,
+    pub pages_copied: u64,
+    /// The amount of targets the backup is pushed to.
+    pub targets: u32,
+    pub targets_done: u32,
+    /// The size of the backup multiplied with the amount of targets.
+    pub bytes_total: u64,
+    /// The bytes that have been pushed to all targets so far.
+    pub bytes_uploaded: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BackupState {
+    /// The database is copied into the local file.
+    Copying,
+    /// The backup is encrypted and pushed to all targets. Only the leader pushes backups.
+    Pushing,
+    Finished,
+    Failed(String),
+}
+
+impl BackupState {
+    pub fn is_done(&self) -> bool {
+        matches!(self, Self::Finished | Self::Failed(_))
+    }
+}
+
+/// A successfully finished backup or snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupRun {
+    pub name: String,
+    /// Unix timestamp in milliseconds.
+    pub finished: i64,
+    pub size: u64,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackupMetrics {
+    /// The latest backup, which might still be running.
+    pub backup: Option<BackupProgress>,
+    pub last_backup: Option<BackupRun>,
+    /// The latest snapshot, which might still be running.
+    pub snapshot: Option<BackupProgress>,
+    pub last_snapshot: Option<BackupRun>,
+}
+
+#[derive(Debug)]
+pub(crate) struct BackupTracker {
+    backup: watch::Sender<Option<BackupProgress>>,
+    snapshot: watch::Sender<Option<BackupProgress>>,
+    last_backup: Mutex<Option<BackupRun>>,
+    last_snapshot: Mutex<Option<BackupRun>>,
+}
+
+impl Default for BackupTracker {
+    fn default() -> Self {
+        Self {
+            backup: watch::channel(None).0,
+            snapshot: watch::channel(None).0,
+            last_backup: Mutex::new(None),
+            last_snapshot: Mutex::new(None),
+        }
+    }
+}
+
+impl BackupTracker {
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Option<BackupProgress>> {
+        self.backup.subscribe()
+    }
+
+    pub(crate) fn metrics(&self) -> BackupMetrics {
+        BackupMetrics {
+            backup: self.backup.borrow().clone(),
+            last_backup: self.last_backup.lock().unwrap().clone(),
+            snapshot: self.snapshot.borrow().clone(),
+            last_snapshot: self.last_snapshot.lock().unwrap().clone(),
+        }
+    }
+
+    pub(crate) fn start(&self, kind: ProgressKind, name: &str) {
+        self.channel(kind).send_replace(Some(BackupProgress {
+            kind,
+            name: name.to_string(),
+            started: Utc::now().timestamp_millis(),
+            state: BackupState::Copying,
+            pages_total: 0,
+            pages_copied: 0,
+            targets: 0,
+            targets_done: 0,
+            bytes_total: 0,
+            bytes_uploaded: 0,
+        }));
+    }
+
+    pub(crate) fn pages(&self, kind: ProgressKind, progress: rusqlite::backup::Progress) {
+        let total = progress.pagecount.max(0) as u64;
+        let remaining = progress.remaining.max(0) as u64;
+        self.update(kind, None, |p| {
+            p.pages_total = total;
+            p.pages_copied = total.saturating_sub(remaining);
+        });
+    }
+
+    /// The local copy with `size` bytes exists and will be pushed to `targets` targets.
+    pub(crate) fn copied(&self, kind: ProgressKind, size: u64, targets: usize) {
+        self.update(kind, None, |p| {
+            p.pages_copied = p.pages_total;
+            p.state = BackupState::Pushing;
+            p.targets = targets as u32;
+            p.bytes_total = size * targets as u64;
+        });
+        if targets == 0 {
+            self.finish(kind, None, size);
+        }
+    }
+
+    /// Adds `bytes` pushed bytes of the backup `name`. Negative values revert a failed attempt.
+    pub(crate) fn uploaded(&self, name: &str, bytes: i64) {
+        self.update(ProgressKind::Backup, Some(name), |p| {
+            p.bytes_uploaded = p.bytes_uploaded.saturating_add_signed(bytes);
+        });
+    }
+
+    /// The push of the backup `name` with `size` bytes to a single target has finished.
+    pub(crate) fn pushed(&self, name: &str, size: u64, err: Option<String>) {
+        let mut is_done = false;
+        self.update(ProgressKind::Backup, Some(name), |p| {
+            p.targets_done += 1;
+            if let Some(err) = err
+                && !p.state.is_done()
+            {
+                p.state = BackupState::Failed(err);
+            }
+            is_done = p.targets_done >= p.targets;
+        });
+        if is_done {
+            self.finish(ProgressKind::Backup, Some(name), size);
+        }
+    }
+
+    /// Finishes the latest backup or snapshot, if it has not failed in the meantime.
+    pub(crate) fn finish(&self, kind: ProgressKind, name: Option<&str>, size: u64) {
+        let last = match kind {
+            ProgressKind::Backup => &self.last_backup,
+            ProgressKind::Snapshot => &self.last_snapshot,
+        };
+        self.update(kind, name, |p| {
+            if p.state.is_done() {
+                return;
+            }
+            p.pages_copied = p.pages_total;
+            p.bytes_uploaded = p.bytes_total;
+            p.state = BackupState::Finished;
+
+            // must be set before subscribers are notified about the finished state
+            let now = Utc::now().timestamp_millis();
+            *last.lock().unwrap() = Some(BackupRun {
+                name: p.name.clone(),
+                finished: now,
+                size,
+                duration_ms: now.saturating_sub(p.started) as u64,
+            });
+        });
+    }
+
+    pub(crate) fn fail(&self, kind: ProgressKind, err: String) {
+        self.update(kind, None, |p| p.state = BackupState::Failed(err));
+    }
+
+    fn channel(&self, kind: ProgressKind) -> &watch::Sender<Option<BackupProgress>> {
+        match kind {
+            ProgressKind::Backup => &self.backup,
+            ProgressKind::Snapshot => &self.snapshot,
+        }
+    }
+
+    /// Updates the latest progress, but only if it has the given `name`, because pushes are still
+    /// running in the background, when the next backup starts.
+    fn update<F: FnOnce(&mut BackupProgress)>(&self, kind: ProgressKind, name: Option<&str>, f: F) {
+        self.channel(kind).send_if_modified(|p| match p.as_mut() {
+            Some(p) if name.is_none_or(|name| name == p.name) => {
+                f(p);
+                true
+            }
+            _ => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::backup::Progress;
+
+    #[test]
+    fn backup_finishes_after_all_pushes() {
+        let tracker = BackupTracker::default();
+        let mut rx = tracker.subscribe();
+        assert!(rx.borrow_and_update().is_none());
+
+        tracker.start(ProgressKind::Backup, "backup_node_1_1.sqlite");
+        tracker.pages(
+            ProgressKind::Backup,
+            Progress {
+                remaining: 30,
+                pagecount: 100,
+            },
+        );
+        let progress = rx.borrow_and_update().clone().unwrap();
+        assert_eq!(progress.state, BackupState::Copying);
+        assert_eq!((progress.pages_copied, progress.pages_total), (70, 100));
+
+        tracker.copied(ProgressKind::Backup, 1000, 2);
+        // a failed attempt is reverted before the retry
+        tracker.uploaded("backup_node_1_1.sqlite", 600);
+        tracker.uploaded("backup_node_1_1.sqlite", -600);
+        tracker.uploaded("backup_node_1_1.sqlite", 1000);
+        // updates of older backups are ignored
+        tracker.uploaded("backup_node_1_0.sqlite", 1000);
+        tracker.pushed("backup_node_1_1.sqlite", 1000, None);
+        let progress = rx.borrow_and_update().clone().unwrap();
+        assert_eq!(progress.state, BackupState::Pushing);
+        assert_eq!(
+            (progress.bytes_uploaded, progress.bytes_total),
+            (1000, 2000)
+        );
+        assert_eq!(progress.targets_done, 1);
+        assert!(tracker.metrics().last_backup.is_none());
+
+        tracker.uploaded("backup_node_1_1.sqlite", 1000);
+        tracker.pushed("backup_node_1_1.sqlite", 1000, None);
+        let metrics = tracker.metrics();
+        assert_eq!(metrics.backup.unwrap().state, BackupState::Finished);
+        let run = metrics.last_backup.unwrap();
+        assert_eq!(run.name, "backup_node_1_1.sqlite");
+        assert_eq!(run.size, 1000);
+
+        // a failed push never counts as a successful backup
+        tracker.start(ProgressKind::Backup, "backup_node_1_2.sqlite");
+        tracker.copied(ProgressKind::Backup, 1000, 1);
+        tracker.pushed(
+            "backup_node_1_2.sqlite",
+            1000,
+            Some("Push to s3 failed".to_string()),
+        );
+        let metrics = tracker.metrics();
+        assert!(matches!(
+            metrics.backup.unwrap().state,
+            BackupState::Failed(_)
+        ));
+        assert_eq!(metrics.last_backup.unwrap().name, "backup_node_1_1.sqlite");
+    }
+
+    #[test]
+    fn backup_without_targets_finishes_locally() {
+        let tracker = BackupTracker::default();
+        tracker.start(ProgressKind::Snapshot, "snapshot");
+        tracker.copied(ProgressKind::Snapshot, 1000, 0);
+        let metrics = tracker.metrics();
+        assert!(metrics.backup.is_none());
+        assert_eq!(metrics.snapshot.unwrap().state, BackupState::Finished);
+        assert_eq!(metrics.last_snapshot.unwrap().size, 1000);
+    }
+}
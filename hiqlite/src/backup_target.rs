@@ -0,0 +1,415 @@
+//! Storage targets for offsite copies of backups.
+//!
+//! Each node creates its backups inside its local backups folder. The leader then pushes an
+//! encrypted copy to each configured `BackupTarget`, which will be pruned with the same
+//! retention afterward. An `S3Config` is always the first target, if one exists.
+
+use crate::Error;
+use crate::s3::S3Config;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use cryptr::stream::reader::channel_reader::ChannelReader;
+use cryptr::stream::writer::channel_writer::{ChannelReceiver, ChannelWriter};
+use cryptr::{ChunkSizeKb, EncValue, FileReader, FileWriter, StreamReader, StreamWriter};
+use futures_util::future::BoxFuture;
+use std::fmt::Debug;
+use std::future::poll_fn;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+use tokio::{fs, task};
+use tracing::{debug, error, warn};
+
+/// An object inside a `BackupTarget`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupObject {
+    /// The name of the object, relative to the target root and with `/` as separator.
+    pub name: String,
+    /// Unix timestamp of the last modification.
+    pub last_modified: i64,
+    pub size: Option<u64>,
+}
+
+/// A storage for encrypted copies of backups, like an S3 bucket or a mounted network path.
+///
+/// Objects are always encrypted with the active `EncKeys` before they are handed over to the
+/// target. `get()` and `get_stream()` return the decrypted content.
+pub trait BackupTarget: Debug + Send + Sync {
+    /// A unique name, which identifies this target in logs and `Client` functions.
+    fn name(&self) -> String;
+
+    /// Encrypts the local file at `path` and stores it as `object`.
+    fn put<'a>(&'a self, path: &'a str, object: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// The same as `put()`, but calls `on_progress` with the amount of bytes of `path` that have
+    /// been handed over so far. By default, the whole size is reported after `put()` has
+    /// finished.
+    fn put_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        object: &'a str,
+        on_progress: &'a (dyn Fn(u64) + Send + Sync),
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            self.put(path, object).await?;
+            on_progress(fs::metadata(path).await?.len());
+            Ok(())
+        })
+    }
+
+    /// Decrypts `object` into the local file at `path`, which will be overwritten.
+    fn get<'a>(&'a self, object: &'a str, path: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Decrypts `object` in the background and streams its content.
+    fn get_stream(&self, object: String) -> Result<ChannelReceiver, Error>;
+
+    /// Lists all objects inside this target.
+    fn list(&self) -> BoxFuture<'_, Result<Vec<BackupObject>, Error>>;
+
+    /// Deletes `object`. Deleting an object that does not exist is not an error.
+    fn delete<'a>(&'a self, object: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+impl BackupTarget for S3Config {
+    fn name(&self) -> String {
+        format!(
+            "s3:{}/{}",
+            self.bucket.host.host_str().unwrap_or_default(),
+            self.bucket.name
+        )
+    }
+
+    fn put<'a>(&'a self, path: &'a str, object: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(self.push(path, object, &|_| {}))
+    }
+
+    fn put_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        object: &'a str,
+        on_progress: &'a (dyn Fn(u64) + Send + Sync),
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(self.push(path, object, on_progress))
+    }
+
+    fn get<'a>(&'a self, object: &'a str, path: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(self.pull(object, path))
+    }
+
+    fn get_stream(&self, object: String) -> Result<ChannelReceiver, Error> {
+        self.pull_channel(object)
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<BackupObject>, Error>> {
+        Box::pin(async move {
+            let mut res = Vec::with_capacity(16);
+
+            for bucket in self.bucket.list("", None).await? {
+                if bucket.name != self.bucket.name {
+                    // it's possible that the creds have access to multiple buckets
+                    continue;
+                }
+
+                for obj in bucket.contents {
+                    let s = obj.last_modified.as_str();
+                    let last_modified = if s.len() < 19 {
+                        warn!("last modified timestamp from S3 too short");
+                        0
+                    } else {
+                        NaiveDateTime::parse_from_str(&s[..19], "%Y-%m-%dT%H:%M:%S")
+                            .map_err(|err| {
+                                error!("Error parsing timestamp from S3: {}", obj.last_modified);
+                                Error::S3(format!(
+                                    "Cannot parse last_modified timestamp from S3: {err}"
+                                ))
+                            })?
+                            .and_utc()
+                            .timestamp()
+                    };
+
+                    res.push(BackupObject {
+                        name: obj.key,
+                        last_modified,
+                        size: Some(obj.size),
+                    });
+                }
+            }
+
+            Ok(res)
+        })
+    }
+
+    fn delete<'a>(&'a self, object: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            self.bucket.delete(object).await?;
+            Ok(())
+        })
+    }
+}
+
+/// A `BackupTarget` inside a local directory, which is usually a mounted network path like NFS.
+#[derive(Debug, Clone)]
+pub struct LocalTarget {
+    dir: String,
+}
+
+impl LocalTarget {
+    /// The `dir` will be created with the first backup, if it does not exist yet. It must not
+    /// be the backups folder of any node.
+    pub fn new<S: Into<String>>(dir: S) -> Self {
+        let dir: String = dir.into();
+        Self {
+            dir: dir.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn path(&self, object: &str) -> Result<String, Error> {
+        if object.is_empty()
+            || object.starts_with('/')
+            || object
+                .split('/')
+                .any(|part| part.is_empty() || part == "..")
+        {
+            return Err(Error::Config(
+                format!("Invalid backup object name: {object}").into(),
+            ));
+        }
+        Ok(format!("{}/{object}", self.dir))
+    }
+}
+
+impl BackupTarget for LocalTarget {
+    fn name(&self) -> String {
+        format!("file:{}", self.dir)
+    }
+
+    fn put<'a>(&'a self, path: &'a str, object: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        self.put_with_progress(path, object, &|_| {})
+    }
+
+    fn put_with_progress<'a>(
+        &'a self,
+        path: &'a str,
+        object: &'a str,
+        on_progress: &'a (dyn Fn(u64) + Send + Sync),
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let target = self.path(object)?;
+            if let Some(parent) = Path::new(&target).parent() {
+                fs::create_dir_all(parent).await?;
+            }
+
+            // encrypt into a temp file and move it into place, so a crash mid-push can never
+            // leave a partial object under the final name
+            let target_tmp = format!("{target}~");
+            let writer = StreamWriter::File(FileWriter {
+                path: &target_tmp,
+                overwrite_target: true,
+            });
+            if let Err(err) = encrypt_file(path, writer, on_progress).await {
+                let _ = fs::remove_file(&target_tmp).await;
+                return Err(err);
+            }
+
+            fs::rename(&target_tmp, &target).await?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, object: &'a str, path: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let source = self.path(object)?;
+            let reader = StreamReader::File(FileReader {
+                path: &source,
+                print_progress: false,
+            });
+            let writer = StreamWriter::File(FileWriter {
+                path,
+                overwrite_target: true,
+            });
+
+            EncValue::decrypt_stream(reader, writer)
+                .await
+                .map_err(|err| Error::Error(err.to_string().into()))
+        })
+    }
+
+    fn get_stream(&self, object: String) -> Result<ChannelReceiver, Error> {
+        let source = self.path(&object)?;
+        let (channel_writer, rx) = ChannelWriter::new();
+        let writer = StreamWriter::Channel(channel_writer.clone());
+
+        task::spawn(async move {
+            let reader = StreamReader::File(FileReader {
+                path: &source,
+                print_progress: false,
+            });
+
+            if let Err(err) = EncValue::decrypt_stream(reader, writer).await {
+                channel_writer.err(Some(err)).await;
+            }
+        });
+
+        Ok(rx)
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<BackupObject>, Error>> {
+        Box::pin(async move {
+            let mut res = Vec::with_capacity(16);
+            if !fs::try_exists(&self.dir).await? {
+                return Ok(res);
+            }
+
+            let mut dirs = vec![String::default()];
+            while let Some(prefix) = dirs.pop() {
+                let mut list = fs::read_dir(format!("{}/{prefix}", self.dir)).await?;
+                while let Some(entry) = list.next_entry().await? {
+                    let Some(fname) = entry.file_name().to_str().map(String::from) else {
+                        continue;
+                    };
+                    let name = format!("{prefix}{fname}");
+
+                    let meta = entry.metadata().await?;
+                    if meta.is_dir() {
+                        dirs.push(format!("{name}/"));
+                        continue;
+                    }
+                    if name.ends_with('~') {
+                        debug!("Skipping unfinished object {name}");
+                        continue;
+                    }
+
+                    let last_modified: DateTime<Utc> = meta.modified()?.into();
+                    res.push(BackupObject {
+                        name,
+                        last_modified: last_modified.timestamp(),
+                        size: Some(meta.len()),
+                    });
+                }
+            }
+
+            Ok(res)
+        })
+    }
+
+    fn delete<'a>(&'a self, object: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            match fs::remove_file(self.path(object)?).await {
+                Ok(_) => Ok(()),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+}
+
+/// Encrypts the file at `path` into `writer` and calls `on_progress` with the amount of bytes
+/// that have been read so far.
+pub(crate) async fn encrypt_file(
+    path: &str,
+    writer: StreamWriter<'_>,
+    on_progress: &(dyn Fn(u64) + Send + Sync),
+) -> Result<(), Error> {
+    let chunk_size = ChunkSizeKb::default().value_bytes() as usize;
+    let mut file = fs::File::open(path).await?;
+    let (reader, mut tx) = ChannelReader::new();
+
+    let read = async move {
+        let mut total = 0;
+        loop {
+            // the reader expects full chunks, only the last one may be smaller
+            let mut buf = vec![0; chunk_size];
+            let mut len = 0;
+            while len < chunk_size {
+                match file.read(&mut buf[len..]).await? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            if len == 0 {
+                break;
+            }
+            buf.truncate(len);
+
+            poll_fn(|cx| tx.poll_ready(cx))
+                .await
+                .and_then(|_| tx.start_send(Ok(buf)))
+                .map_err(|err| Error::Error(err.to_string().into()))?;
+            total += len as u64;
+            on_progress(total);
+
+            if len < chunk_size {
+                break;
+            }
+        }
+        Ok::<(), Error>(())
+    };
+    let encrypt = async {
+        EncValue::encrypt_stream(StreamReader::Channel(reader), writer)
+            .await
+            .map_err(|err| Error::Error(err.to_string().into()))
+    };
+
+    let (res_read, res_encrypt) = tokio::join!(read, encrypt);
+    res_read?;
+    res_encrypt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_target_object_paths() {
+        let target = LocalTarget::new("/mnt/backups/");
+        assert_eq!(target.name(), "file:/mnt/backups");
+        assert_eq!(
+            target.path("backup_node_1_1.sqlite").unwrap(),
+            "/mnt/backups/backup_node_1_1.sqlite"
+        );
+        assert_eq!(
+            target.path("verify/backup_node_1_1.sqlite").unwrap(),
+            "/mnt/backups/verify/backup_node_1_1.sqlite"
+        );
+
+        for invalid in [
+            "",
+            "/etc/passwd",
+            "../backup",
+            "verify//backup",
+            "a/../../b",
+        ] {
+            assert!(target.path(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn local_target_put_reports_progress() -> Result<(), Error> {
+        let _ = cryptr::EncKeys::generate()?.init();
+        let dir = std::env::temp_dir().join(format!("hiqlite_target_{}", uuid::Uuid::now_v7()));
+        let dir = dir.to_str().unwrap().to_string();
+        fs::create_dir_all(&dir).await?;
+        let target = LocalTarget::new(format!("{dir}/target"));
+
+        let chunk_size = ChunkSizeKb::default().value_bytes() as usize;
+        // a smaller last chunk, and only full chunks
+        for size in [chunk_size * 2 + 1000, chunk_size * 2] {
+            let path = format!("{dir}/backup");
+            let data = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            fs::write(&path, &data).await?;
+
+            let reported = std::sync::atomic::AtomicU64::new(0);
+            target
+                .put_with_progress(&path, "backup", &|bytes| {
+                    reported.store(bytes, std::sync::atomic::Ordering::Relaxed)
+                })
+                .await?;
+            assert_eq!(reported.into_inner(), size as u64);
+
+            let path_restored = format!("{dir}/restored");
+            target.get("backup", &path_restored).await?;
+            assert_eq!(fs::read(&path_restored).await?, data);
+        }
+
+        let _ = fs::remove_dir_all(&dir).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use crate::migration::{Migration, Migrations};
+use crate::store::logs;
+use crate::store::state_machine::sqlite::state_machine::StateMachineSqlite;
+use crate::store::state_machine::sqlite::writer::{self, INTERNAL_TABLES};
+use crate::{Error, NodeConfig};
+use rusqlite::OpenFlags;
+use rust_embed::RustEmbed;
+use std::path::Path;
+use tokio::{fs, task};
+use tracing::{info, warn};
+
+/// Bootstraps a new cluster from an existing SQLite database file.
+///
+/// Node `1` copies the file into place during its very first start, marks the given migrations
+/// as applied, and installs the result as the initial snapshot. All other nodes will receive
+/// the data via snapshot install when they join. As soon as node `1` has been initialized, the
+/// bootstrap will be skipped, so it is safe to keep the config in place.
+#[derive(Debug, Clone)]
+pub struct BootstrapDb {
+    path: String,
+    migrations: Vec<Migration>,
+}
+
+impl BootstrapDb {
+    /// `path` is the SQLite file to bootstrap from. It will only be read and never be modified.
+    pub fn new<S: Into<String>>(path: S) -> Self {
+        Self {
+            path: path.into(),
+            migrations: Vec::new(),
+        }
+    }
+
+    /// The migrations, which have been applied to the file already. They will be added to the
+    /// `_migrations` table, so `Client::migrate::<T>()` only applies newer ones later on.
+    pub fn with_migrations<T: RustEmbed>(mut self) -> Self {
+        self.migrations = Migrations::build::<T>();
+        self
+    }
+
+    /// Works in the same way as `with_migrations()`, but reads the migrations from a directory
+    /// with `<id>_<name>.sql` files instead of embedded ones.
+    pub fn with_migrations_dir(mut self, dir: &str) -> Result<Self, Error> {
+        self.migrations = Migrations::build_from_dir(dir)?;
+        Ok(self)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Exists from the start of a bootstrap until its initial snapshot has been built. Until then,
+/// the database only exists on node `1` and is not part of the Raft.
+fn path_pending(data_dir: &str) -> String {
+    format!("{data_dir}/bootstrap_pending")
+}
+
+/// Bootstraps the database from `NodeConfig.bootstrap_db`, if given. Returns `Ok(true)` if
+/// the file has been copied into place and the Raft must build its initial snapshot from it.
+/// This will only run on a pristine node `1`, or if a previous bootstrap has not been finished.
+pub(crate) async fn bootstrap_db_start(node_config: &NodeConfig) -> Result<bool, Error> {
+    let Some(bootstrap) = &node_config.bootstrap_db else {
+        return Ok(false);
+    };
+    if node_config.node_id != 1 {
+        return Ok(false);
+    }
+
+    let path_base = StateMachineSqlite::path_base(&node_config.data_dir);
+    let path_db = format!("{path_base}/db");
+    let path_db_full = format!("{path_db}/{}", node_config.filename_db);
+    let path_logs = logs::logs_dir_db(&node_config.data_dir);
+    let path_pending = path_pending(&node_config.data_dir);
+
+    if fs::try_exists(&path_pending).await? {
+        // Nothing can have been replicated without the initial snapshot, which makes it safe to
+        // throw away everything and start over.
+        warn!(
+            "Bootstrap from {} has not been finished - starting over",
+            bootstrap.path
+        );
+        for path in [&path_base, &path_logs] {
+            if fs::try_exists(path).await? {
+                fs::remove_dir_all(path).await?;
+            }
+        }
+    } else if fs::try_exists(&path_db_full).await? || fs::try_exists(&path_logs).await? {
+        info!(
+            "Node 1 has been initialized already - skipping bootstrap from {}",
+            bootstrap.path
+        );
+        return Ok(false);
+    }
+
+    info!("Bootstrapping the database from {}", bootstrap.path);
+    fs::create_dir_all(&path_db).await?;
+    fs::write(&path_pending, bootstrap.path.as_bytes()).await?;
+    let bootstrap = bootstrap.clone();
+    let target = path_db_full.clone();
+    let res = task::spawn_blocking(move || prepare_db(&bootstrap, &target)).await?;
+    if let Err(err) = res {
+        let _ = fs::remove_file(&path_db_full).await;
+        let _ = fs::remove_file(&path_pending).await;
+        return Err(err);
+    }
+
+    Ok(true)
+}
+
+/// Marks the bootstrap as finished, as soon as the initial snapshot exists.
+pub(crate) async fn bootstrap_db_finish(data_dir: &str) -> Result<(), Error> {
+    fs::remove_file(path_pending(data_dir)).await?;
+    info!("Bootstrap finished with the initial snapshot");
+    Ok(())
+}
+
+/// Validates the file from `bootstrap` and writes a clean copy with the `_migrations`
+/// bookkeeping into `target`.
+fn prepare_db(bootstrap: &BootstrapDb, target: &str) -> Result<(), Error> {
+    if !Path::new(&bootstrap.path).is_file() {
+        return Err(Error::Config(
+            format!("Bootstrap DB {} does not exist", bootstrap.path).into(),
+        ));
+    }
+
+    let conn = rusqlite::Connection::open_with_flags(
+        &bootstrap.path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+
+    let check: String = conn.query_row("PRAGMA integrity_check", (), |row| row.get(0))?;
+    if check != "ok" {
+        return Err(Error::Sqlite(
+            format!("Integrity check for {} failed: {check}", bootstrap.path).into(),
+        ));
+    }
+
+    for table in INTERNAL_TABLES {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = $1)",
+            [table],
+            |row| row.get(0),
+        )?;
+        if exists {
+            let hint = if table == "_metadata" {
+                " - use a backup restore for existing Hiqlite databases instead"
+            } else {
+                ""
+            };
+            return Err(Error::Config(
+                format!(
+                    "Bootstrap DB {} contains the reserved table {table}{hint}",
+                    bootstrap.path
+                )
+                .into(),
+            ));
+        }
+    }
+
+    // `VACUUM INTO` creates a consistent and compact copy, even if the source is in WAL mode
+    conn.execute("VACUUM INTO $1", [target])?;
+    drop(conn);
+
+    let mut conn = rusqlite::Connection::open(target)?;
+    writer::create_migrations_table(&conn)?;
+    let txn = conn.transaction()?;
+    {
+        let mut stmt =
+            txn.prepare("INSERT INTO _migrations (id, name, ts, hash) VALUES ($1, $2, 0, $3)")?;
+        for migration in &bootstrap.migrations {
+            stmt.execute((migration.id, &migration.name, &migration.hash))?;
+        }
+    }
+    txn.commit()?;
+
+    if bootstrap.migrations.is_empty() {
+        warn!("No migrations given for the bootstrap DB - all migrations will be applied again");
+    }
+    info!(
+        "Bootstrap DB {} is ok - marked {} migrations as applied",
+        bootstrap.path,
+        bootstrap.migrations.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prepare_db_validates_and_adds_migrations() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("hiqlite_bootstrap_{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(dir.join("migrations"))?;
+        let dir_str = dir.to_str().unwrap();
+
+        let path_src = format!("{dir_str}/src.db");
+        let conn = rusqlite::Connection::open(&path_src)?;
+        conn.execute_batch(
+            r#"
+            PRAGMA journal_mode = WAL;
+            CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+            INSERT INTO users (name) VALUES ('alice'), ('bob');
+            "#,
+        )?;
+
+        std::fs::write(
+            dir.join("migrations/1_init.sql"),
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL);",
+        )?;
+        std::fs::write(
+            dir.join("migrations/2_more.sql"),
+            "CREATE INDEX users_name ON users (name);",
+        )?;
+        let bootstrap =
+            BootstrapDb::new(&path_src).with_migrations_dir(&format!("{dir_str}/migrations"))?;
+
+        // the source is still open and has its data only inside the WAL
+        let target = format!("{dir_str}/target.db");
+        prepare_db(&bootstrap, &target)?;
+        drop(conn);
+
+        let conn = rusqlite::Connection::open(&target)?;
+        let users: i64 = conn.query_row("SELECT COUNT(*) FROM users", (), |row| row.get(0))?;
+        assert_eq!(users, 2);
+        let migrations = conn
+            .prepare("SELECT id, name, ts, hash FROM _migrations ORDER BY id")?
+            .query_map((), |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(migrations.len(), 2);
+        assert_eq!(migrations[0].0, 1);
+        assert_eq!(migrations[0].1, "init");
+        assert_eq!(migrations[0].2, 0);
+        assert_eq!(migrations[0].3, bootstrap.migrations[0].hash);
+        assert_eq!(migrations[1].1, "more");
+
+        // a Hiqlite database must be restored from a backup instead
+        let bootstrap = BootstrapDb::new(&target);
+        assert!(prepare_db(&bootstrap, &format!("{dir_str}/other.db")).is_err());
+
+        // not a database at all
+        let bootstrap = BootstrapDb::new(format!("{dir_str}/migrations/1_init.sql"));
+        assert!(prepare_db(&bootstrap, &format!("{dir_str}/other.db")).is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+        Ok(())
+    }
+}
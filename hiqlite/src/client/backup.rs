@@ -1,12 +1,24 @@
+use crate::app_state::AppState;
+use crate::backup::{self, BackupSource, BackupVerification};
+use crate::backup_progress::{BackupMetrics, BackupProgress};
+use crate::backup_target::BackupTarget;
 use crate::client::helpers::await_channel_response;
 use crate::client::stream::{ClientBackupPayload, ClientStreamReq};
+use crate::helpers::deserialize;
+use crate::http_client::build_http_client;
 use crate::network::api::ApiStreamResponsePayload;
+use crate::network::management::RestoreReq;
+use crate::network::{HEADER_NAME_SECRET, serialize_network};
+use crate::restore::{self, RestoreProgress, RestoreState};
 use crate::store::state_machine::sqlite::state_machine::QueryWrite;
 use crate::{Client, Error, Response};
-use chrono::{NaiveDateTime, Utc};
-use tokio::fs;
-use tokio::sync::oneshot;
-use tracing::{debug, error, warn};
+use chrono::Utc;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio::{fs, time};
+use tracing::{debug, error, info};
 
 use cryptr::stream::writer::channel_writer::ChannelReceiver;
 #[cfg(unix)]
@@ -17,6 +29,8 @@ pub struct BackupListing {
     pub name: String,
     pub last_modified: i64,
     pub size: Option<u64>,
+    /// The latest verification result, if the backup has been verified.
+    pub verification: Option<BackupVerification>,
 }
 
 impl Client {
@@ -67,6 +81,57 @@ impl Client {
         }
     }
 
+    /// Create an on-demand backup just like `backup()` and watch its progress on this node.
+    ///
+    /// The receiver reports the pages copied into the local backup and, if this node is the
+    /// leader, the bytes pushed to all `BackupTarget`s. The backup is done, when its `state`
+    /// `is_done()`. Until this node has applied the backup request, the receiver might still
+    /// show the previous backup.
+    #[cold]
+    pub async fn backup_with_progress(
+        &self,
+    ) -> Result<watch::Receiver<Option<BackupProgress>>, Error> {
+        let Some(state) = self.inner.state.as_ref() else {
+            return Err(Error::Config(
+                "Backup progress is not available for remote clients".into(),
+            ));
+        };
+        let mut rx = state.raft_db.backup_progress.subscribe();
+        rx.mark_unchanged();
+        self.backup().await?;
+        Ok(rx)
+    }
+
+    /// The latest backup and snapshot of this node, including their progress, if they are still
+    /// running, and the last successful ones.
+    pub fn backup_metrics(&self) -> Result<BackupMetrics, Error> {
+        if let Some(state) = self.inner.state.as_ref() {
+            Ok(state.raft_db.backup_progress.metrics())
+        } else {
+            Err(Error::Config(
+                "Backup metrics are not available for remote clients".into(),
+            ))
+        }
+    }
+
+    /// Create an on-demand backup of the cache state machine of this node, with all KV entries,
+    /// counters, TTLs and locks. Returns the name of the backup.
+    ///
+    /// The backup is created inside the local backups folder and then encrypted and pushed to
+    /// all `BackupTarget`s in the background. It can be restored during startup with
+    /// `HQL_BACKUP_RESTORE_CACHE`. Use `BackupConfig::with_cache()` to create it with each
+    /// backup cron run.
+    #[cfg(feature = "cache")]
+    #[cold]
+    pub async fn backup_cache(&self) -> Result<String, Error> {
+        let Some(state) = self.inner.state.as_ref() else {
+            return Err(Error::Config(
+                "Cache backups cannot be created from remote clients".into(),
+            ));
+        };
+        backup::backup_cache(state).await
+    }
+
     #[cold]
     async fn backup_execute(&self) -> Result<(), Error> {
         let current_leader = self.inner.leader_db.read().await.0;
@@ -103,6 +168,153 @@ impl Client {
         }
     }
 
+    /// Verifies that a backup is restorable. It is copied into a temporary file, or fetched from
+    /// the first `BackupTarget` that contains it and decrypted if it does not exist locally. The copy must pass SQLite's
+    /// `integrity_check` and `quick_check`, and its row counts must match the source database
+    /// at the time of the backup.
+    ///
+    /// The result is recorded next to the backup and reported by `backup_list_local()` or
+    /// `backup_list_target()`. An `Err` is only returned if the verification could not run, a
+    /// broken backup is reported inside the `BackupVerification`.
+    #[cold]
+    pub async fn backup_verify(&self, name: &str) -> Result<BackupVerification, Error> {
+        let Some(state) = self.inner.state.clone() else {
+            return Err(Error::Config(
+                "Backups cannot be verified for remote clients".into(),
+            ));
+        };
+        if !name.starts_with("backup_node_") || name.contains('/') {
+            return Err(Error::Config(format!("Invalid backup name: {name}").into()));
+        }
+
+        if fs::try_exists(format!("{}/{name}", state.backups_dir)).await? {
+            return backup::verify_local(&state.backups_dir, name).await;
+        }
+        for target in state.backup_targets.iter() {
+            if target.list().await?.iter().any(|obj| obj.name == name) {
+                return backup::verify_target(target.as_ref(), &state.backups_dir, name).await;
+            }
+        }
+        Err(Error::Config(
+            format!("Backup {name} does not exist").into(),
+        ))
+    }
+
+    /// Restores the given backup into the running cluster, without any restart.
+    ///
+    /// The current leader fetches the backup from S3, or from its local `BackupSource::File`,
+    /// and verifies it. It is then replicated through the Raft, and all nodes replace their
+    /// database with it at the same log index. Finally, the leader builds a new snapshot, which
+    /// will be installed by nodes that are lagging behind.
+    ///
+    /// This function returns when the restore is finished. You can watch the progress from
+    /// anywhere else with `restore_progress()`. A `BackupSource::PointInTime` is only possible
+    /// with `HQL_BACKUP_RESTORE` during startup.
+    ///
+    /// **CAUTION:** All writes that happen between the backup and its restore will be lost.
+    #[cold]
+    pub async fn restore_backup(&self, src: BackupSource) -> Result<RestoreProgress, Error> {
+        let started = self.restore_start(src).await?;
+        info!("Online restore {} started", started.id);
+
+        loop {
+            time::sleep(Duration::from_millis(500)).await;
+
+            let Some(progress) = self.restore_progress().await? else {
+                return Err(Error::LeaderChange(
+                    format!("The leader changed during the restore {}", started.id).into(),
+                ));
+            };
+            if progress.id != started.id {
+                return Err(Error::LeaderChange(
+                    format!("The leader changed during the restore {}", started.id).into(),
+                ));
+            }
+
+            match &progress.state {
+                RestoreState::Finished => return Ok(progress),
+                RestoreState::Failed(err) => {
+                    return Err(Error::Error(
+                        format!("Restore {} failed: {err}", progress.id).into(),
+                    ));
+                }
+                state => debug!(
+                    "Restore {} {:?}: {} / {} bytes replicated",
+                    progress.id, state, progress.bytes_replicated, progress.bytes_total
+                ),
+            }
+        }
+    }
+
+    /// Get the progress of the latest online restore from the current leader.
+    pub async fn restore_progress(&self) -> Result<Option<RestoreProgress>, Error> {
+        if let Some(state) = self.is_leader_db_with_state().await {
+            return Ok(state.restore.progress());
+        }
+
+        let url = self
+            .build_addr("/cluster/restore", &self.inner.leader_db)
+            .await;
+        let res = self
+            .restore_http_client()
+            .get(url)
+            .header(HEADER_NAME_SECRET, self.restore_api_secret())
+            .send()
+            .await?;
+        Self::restore_response(res).await
+    }
+
+    async fn restore_start(&self, src: BackupSource) -> Result<RestoreProgress, Error> {
+        if let Some(state) = self.is_leader_db_with_state().await {
+            return restore::start(state, src);
+        }
+
+        let url = self
+            .build_addr("/cluster/restore", &self.inner.leader_db)
+            .await;
+        let payload = RestoreReq {
+            source: src.to_string(),
+        };
+        let res = self
+            .restore_http_client()
+            .post(url)
+            .header(HEADER_NAME_SECRET, self.restore_api_secret())
+            .body(serialize_network(&payload))
+            .send()
+            .await?;
+        Self::restore_response(res).await
+    }
+
+    fn restore_http_client(&self) -> reqwest::Client {
+        self.inner
+            .client
+            .clone()
+            .unwrap_or_else(|| build_http_client(self.inner.tls_no_verify))
+    }
+
+    fn restore_api_secret(&self) -> &str {
+        match &self.inner.state {
+            Some(state) => &state.secret_api,
+            None => self
+                .inner
+                .api_secret
+                .as_deref()
+                .expect("api_secret to always exist for remote clients"),
+        }
+    }
+
+    async fn restore_response<T>(res: reqwest::Response) -> Result<T, Error>
+    where
+        T: for<'a> serde::Deserialize<'a>,
+    {
+        if res.status().is_success() {
+            let bytes = res.bytes().await?;
+            Ok(deserialize(bytes.as_ref())?)
+        } else {
+            Err(res.json::<Error>().await?)
+        }
+    }
+
     /// Get the file handle to a local backup.
     pub async fn backup_file_local(&self, filename: &str) -> Result<fs::File, Error> {
         if let Some(state) = self.inner.state.clone() {
@@ -145,7 +357,7 @@ impl Client {
 
                 let fname = entry.file_name();
                 let name = fname.to_str().unwrap_or_default().to_string();
-                if !name.starts_with("backup_node_") {
+                if !backup::is_backup_name(&name) {
                     debug!("Found non-backup file: {name}");
                     continue;
                 }
@@ -157,10 +369,12 @@ impl Client {
                 #[cfg(not(unix))]
                 let size = None;
 
+                let verification = backup::verification_local(dir, &name).await;
                 res.push(BackupListing {
                     name,
                     last_modified: last_modified.timestamp(),
                     size,
+                    verification,
                 });
             }
 
@@ -175,55 +389,101 @@ impl Client {
     /// List all existing S3 backups.
     pub async fn backup_list_s3(&self) -> Result<Vec<BackupListing>, Error> {
         if let Some(state) = self.inner.state.clone() {
-            let mut res = Vec::with_capacity(16);
-
-            if let Some(s3) = state.s3_config.as_ref() {
-                let list = s3.bucket.list("", None).await?;
-                for bucket in list {
-                    if bucket.name != s3.bucket.name {
-                        // it's possible that the creds have access to multiple buckets
-                        continue;
-                    }
-
-                    for obj in bucket.contents {
-                        if !obj.key.starts_with("backup_node_") {
-                            debug!("Found non-backup file: {}", obj.key);
-                            continue;
-                        }
-
-                        let s = obj.last_modified.as_str();
-                        let last_modified = if s.len() < 19 {
-                            warn!("last modified timestamp from S3 too short");
-                            0
-                        } else {
-                            NaiveDateTime::parse_from_str(&s[..19], "%Y-%m-%dT%H:%M:%S")
-                                .map_err(|err| {
-                                    error!(
-                                        "Error parsing timestamp from S3: {}",
-                                        obj.last_modified
-                                    );
-                                    Error::S3(format!(
-                                        "Cannot parse last_modified timestamp from S3: {err}"
-                                    ))
-                                })?
-                                .and_utc()
-                                .timestamp()
-                        };
-
-                        res.push(BackupListing {
-                            name: obj.key,
-                            last_modified,
-                            size: Some(obj.size),
-                        });
-                    }
-                }
+            if let Some(s3) = state.s3_config.clone() {
+                Self::backup_list(&state.backups_dir, s3.as_ref()).await
+            } else {
+                Ok(Vec::new())
             }
+        } else {
+            Err(Error::Config(
+                "Backups cannot be listed for remote clients".into(),
+            ))
+        }
+    }
 
-            Ok(res)
+    /// The names of all configured `BackupTarget`s, starting with the `S3Config`, if it exists.
+    pub fn backup_targets(&self) -> Result<Vec<String>, Error> {
+        if let Some(state) = self.inner.state.as_ref() {
+            Ok(state.backup_targets.iter().map(|t| t.name()).collect())
         } else {
             Err(Error::Config(
                 "Backups cannot be listed for remote clients".into(),
             ))
         }
     }
+
+    /// List all existing backups on the `BackupTarget` with the given name.
+    pub async fn backup_list_target(&self, target: &str) -> Result<Vec<BackupListing>, Error> {
+        let (state, target) = self.backup_target(target)?;
+        Self::backup_list(&state.backups_dir, target.as_ref()).await
+    }
+
+    /// Stream the decrypted backup `object` from the `BackupTarget` with the given name.
+    pub fn backup_target_stream(
+        &self,
+        target: &str,
+        object: String,
+    ) -> Result<ChannelReceiver, Error> {
+        let (_, target) = self.backup_target(target)?;
+        target.get_stream(object)
+    }
+
+    fn backup_target(&self, name: &str) -> Result<(Arc<AppState>, Arc<dyn BackupTarget>), Error> {
+        let Some(state) = self.inner.state.clone() else {
+            return Err(Error::Config(
+                "Backups cannot be listed for remote clients".into(),
+            ));
+        };
+        let Some(target) = state
+            .backup_targets
+            .iter()
+            .find(|t| t.name() == name)
+            .cloned()
+        else {
+            return Err(Error::Config(
+                format!("Backup target {name} does not exist").into(),
+            ));
+        };
+        Ok((state, target))
+    }
+
+    async fn backup_list(
+        backups_dir: &str,
+        target: &dyn BackupTarget,
+    ) -> Result<Vec<BackupListing>, Error> {
+        let list = target.list().await?;
+        let names = list
+            .iter()
+            .map(|obj| obj.name.clone())
+            .collect::<HashSet<_>>();
+
+        let mut res = Vec::with_capacity(16);
+        for obj in list {
+            if !backup::is_backup_name(&obj.name) {
+                debug!("Found non-backup file: {}", obj.name);
+                continue;
+            }
+
+            let verification = if names.contains(&backup::verification_object(&obj.name)) {
+                match backup::verification_target(target, backups_dir, &obj.name).await {
+                    Ok(v) => Some(v),
+                    Err(err) => {
+                        error!("Error reading verification for {}: {err}", obj.name);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            res.push(BackupListing {
+                name: obj.name,
+                last_modified: obj.last_modified,
+                size: obj.size,
+                verification,
+            });
+        }
+
+        Ok(res)
+    }
 }
@@ -1,8 +1,8 @@
 use crate::client::helpers::await_channel_response;
 use crate::client::stream::{ClientBatchPayload, ClientStreamReq};
-use crate::network::api::ApiStreamResponsePayload;
+use crate::network::api::{ApiStreamResponsePayload, require_log_id};
 use crate::store::state_machine::sqlite::state_machine::QueryWrite;
-use crate::{Client, Error, Response};
+use crate::{Client, Error, LogId, Response};
 use std::borrow::Cow;
 use tokio::sync::oneshot;
 
@@ -38,17 +38,37 @@ impl Client {
     where
         S: Into<Cow<'static, str>>,
     {
+        self.batch_with(sql.into(), false).await.map(|(res, _)| res)
+    }
+
+    /// Works in the same way as `batch()`, but additionally returns the Raft `LogId` the batch
+    /// has been applied with.
+    pub async fn batch_with_log_id<S>(
+        &self,
+        sql: S,
+    ) -> Result<(Vec<Result<usize, Error>>, LogId), Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        require_log_id(self.batch_with(sql.into(), true).await)
+    }
+
+    /// Returns the `LogId` of the write, if it has been applied locally or `with_log_id` is set.
+    pub(crate) async fn batch_with(
+        &self,
+        sql: Cow<'static, str>,
+        with_log_id: bool,
+    ) -> Result<(Vec<Result<usize, Error>>, Option<LogId>), Error> {
         self.rate_limit_db().await?;
 
-        let sql = sql.into();
-        match self.batch_execute(sql.clone()).await {
+        match self.batch_execute(sql.clone(), with_log_id).await {
             Ok(res) => Ok(res),
             Err(err) => {
                 if self
                     .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
                     .await
                 {
-                    self.batch_execute(sql).await
+                    self.batch_execute(sql, with_log_id).await
                 } else {
                     Err(err)
                 }
@@ -59,16 +79,16 @@ impl Client {
     async fn batch_execute(
         &self,
         sql: Cow<'static, str>,
-    ) -> Result<Vec<Result<usize, Error>>, Error> {
+        with_log_id: bool,
+    ) -> Result<(Vec<Result<usize, Error>>, Option<LogId>), Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
             let res = state
                 .raft_db
-                .raft
-                .client_write(QueryWrite::Batch(sql))
+                .client_write(QueryWrite::Batch(sql).stamped())
                 .await?;
             let resp: Response = res.data;
             match resp {
-                Response::Batch(res) => res.result,
+                Response::Batch(resp) => resp.result.map(|results| (results, Some(res.log_id))),
                 _ => unreachable!(),
             }
         } else {
@@ -78,13 +98,17 @@ impl Client {
                 .send_async(ClientStreamReq::Batch(ClientBatchPayload {
                     request_id: self.new_request_id(),
                     sql,
+                    with_log_id,
                     ack,
                 }))
                 .await
                 .map_err(|err| Error::Error(err.to_string().into()))?;
             let res = await_channel_response(rx).await??;
             match res {
-                ApiStreamResponsePayload::Batch(res) => res,
+                ApiStreamResponsePayload::Batch(res) => res.map(|results| (results, None)),
+                ApiStreamResponsePayload::BatchLogId(res) => {
+                    res.map(|(results, log_id)| (results, Some(log_id)))
+                }
                 _ => unreachable!(),
             }
         }
@@ -3,9 +3,10 @@ use crate::client::stream::{ClientKVPayload, ClientStreamReq};
 use crate::helpers::deserialize;
 use crate::network::api::ApiStreamResponsePayload;
 use crate::network::serialize_network;
+use crate::store::state_machine::memory::eviction;
 use crate::store::state_machine::memory::kv_handler::CacheRequestHandler;
 use crate::store::state_machine::memory::state_machine::{CacheRequest, CacheResponse};
-use crate::{CacheVariants, Client, Error};
+use crate::{CacheStats, CacheVariants, Client, Error};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
@@ -161,6 +162,19 @@ impl Client {
         }
     }
 
+    /// The current size, limits and eviction counters of all caches on this node.
+    ///
+    /// This function does only work for Raft members and not on remote nodes.
+    pub async fn cache_stats(&self) -> Result<Vec<CacheStats>, Error> {
+        if let Some(state) = &self.inner.state {
+            eviction::cache_stats(&state.raft_cache.tx_caches).await
+        } else {
+            Err(Error::Error(
+                "This function does only work for Raft members and not on remote nodes.".into(),
+            ))
+        }
+    }
+
     /// `Put` a value into the cache.
     /// The optional `ttl` is the lifetime of the value in seconds from *now* on.
     ///
@@ -496,7 +510,11 @@ impl Client {
         is_remote_get: bool,
     ) -> Result<CacheResponse, Error> {
         if let Some(state) = self.is_leader_cache_with_state().await {
-            let res = state.raft_cache.raft.client_write(cache_req).await?;
+            let res = state
+                .raft_cache
+                .raft
+                .client_write(cache_req.stamped())
+                .await?;
             Ok(res.data)
         } else {
             let (ack, rx) = oneshot::channel();
@@ -522,7 +540,6 @@ impl Client {
             let res = await_channel_response(rx).await??;
             match res {
                 ApiStreamResponsePayload::KV(res) => res,
-                #[cfg(any(feature = "sqlite", feature = "dlock", feature = "listen_notify_local"))]
                 _ => unreachable!(),
             }
         }
@@ -0,0 +1,220 @@
+use crate::client::helpers::await_channel_response;
+use crate::helpers::deserialize;
+use crate::network::serialize_network;
+use crate::store::state_machine::memory::kv_handler::CacheRequestHandler;
+use crate::store::state_machine::memory::state_machine::{
+    CacheBatchOp, CacheRequest, CacheResponse,
+};
+use crate::{CacheVariants, Client, Error};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use tokio::sync::oneshot;
+
+/// Multiple writes to a single cache, which are replicated with a single Raft log entry and
+/// applied atomically.
+///
+/// ```rust, notest
+/// let batch = CacheBatch::new()
+///     .put("key 1", &value_1, None)
+///     .put("key 2", &value_2, Some(60))
+///     .delete("key 3");
+/// client.cache_batch(Cache::One, batch).await?;
+/// ```
+#[derive(Debug, Default)]
+pub struct CacheBatch {
+    ops: Vec<BatchOp>,
+}
+
+#[derive(Debug)]
+enum BatchOp {
+    Put {
+        key: Cow<'static, str>,
+        value: Vec<u8>,
+        ttl: Option<i64>,
+    },
+    Delete(Cow<'static, str>),
+}
+
+impl CacheBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `Put` a value into the cache.
+    /// The optional `ttl` is the lifetime of the value in seconds from the moment the batch is
+    /// sent on.
+    pub fn put<K, V>(self, key: K, value: &V, ttl: Option<i64>) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Serialize,
+    {
+        self.put_bytes(key, serialize_network(value), ttl)
+    }
+
+    /// PUT a raw bytes value into the cache
+    pub fn put_bytes<K>(mut self, key: K, value: Vec<u8>, ttl: Option<i64>) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+    {
+        self.ops.push(BatchOp::Put {
+            key: key.into(),
+            value,
+            ttl,
+        });
+        self
+    }
+
+    /// `Delete` a value from the cache.
+    pub fn delete<K>(mut self, key: K) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+    {
+        self.ops.push(BatchOp::Delete(key.into()));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn into_ops(self) -> Vec<CacheBatchOp> {
+        let now = Utc::now().timestamp_micros();
+        self.ops
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Put { key, value, ttl } => CacheBatchOp::Put {
+                    key,
+                    value,
+                    expires: ttl
+                        .map(|seconds| now.saturating_add(seconds.saturating_mul(1_000_000))),
+                },
+                BatchOp::Delete(key) => CacheBatchOp::Delete { key },
+            })
+            .collect()
+    }
+}
+
+impl Client {
+    /// GET multiple values from the cache. The result contains one entry for each key in the
+    /// same order.
+    pub async fn mget<C, I, K, V>(&self, cache: C, keys: I) -> Result<Vec<Option<V>>, Error>
+    where
+        C: CacheVariants,
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+        V: for<'a> Deserialize<'a>,
+    {
+        let values = self.mget_bytes(cache, keys).await?;
+        let mut res = Vec::with_capacity(values.len());
+        for value in values {
+            if let Some(v) = value {
+                res.push(Some(deserialize(&v)?));
+            } else {
+                res.push(None);
+            }
+        }
+        Ok(res)
+    }
+
+    /// GET multiple raw bytes values from the cache.
+    ///
+    /// Works in the same way as `.mget()` without any value mapping.
+    pub async fn mget_bytes<C, I, K>(
+        &self,
+        cache: C,
+        keys: I,
+    ) -> Result<Vec<Option<Vec<u8>>>, Error>
+    where
+        C: CacheVariants,
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        let keys = keys.into_iter().map(Into::into).collect::<Vec<String>>();
+
+        if let Some(state) = &self.inner.state {
+            let (ack, rx) = oneshot::channel();
+            state
+                .raft_cache
+                .tx_caches
+                .get(cache.hiqlite_cache_index())
+                .unwrap()
+                .send(CacheRequestHandler::GetMany((keys, ack)))
+                .expect("kv handler to always be running");
+            let values = await_channel_response(rx).await?;
+            Ok(values)
+        } else {
+            let res = self
+                .cache_req_retry(
+                    CacheRequest::GetMany {
+                        cache_idx: cache.hiqlite_cache_index(),
+                        keys,
+                    },
+                    true,
+                )
+                .await?;
+            match res {
+                CacheResponse::Values(values) => Ok(values),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// `Put` multiple values with their optional `ttl` in seconds into the cache with a single
+    /// Raft log entry.
+    pub async fn mput<C, I, K, V>(&self, cache: C, entries: I) -> Result<(), Error>
+    where
+        C: CacheVariants,
+        I: IntoIterator<Item = (K, V, Option<i64>)>,
+        K: Into<Cow<'static, str>>,
+        V: Serialize,
+    {
+        let batch = entries
+            .into_iter()
+            .fold(CacheBatch::new(), |batch, (key, value, ttl)| {
+                batch.put(key, &value, ttl)
+            });
+        self.cache_batch(cache, batch).await
+    }
+
+    /// `Delete` multiple values from the cache with a single Raft log entry.
+    pub async fn mdelete<C, I, K>(&self, cache: C, keys: I) -> Result<(), Error>
+    where
+        C: CacheVariants,
+        I: IntoIterator<Item = K>,
+        K: Into<Cow<'static, str>>,
+    {
+        let batch = keys
+            .into_iter()
+            .fold(CacheBatch::new(), |batch, key| batch.delete(key));
+        self.cache_batch(cache, batch).await
+    }
+
+    /// Applies all writes of the `CacheBatch` atomically with a single Raft log entry. The ops
+    /// are applied in order, so a later op for the same key wins.
+    pub async fn cache_batch<C>(&self, cache: C, batch: CacheBatch) -> Result<(), Error>
+    where
+        C: CacheVariants,
+    {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        self.rate_limit_cache().await?;
+
+        self.cache_req_retry(
+            CacheRequest::Batch {
+                cache_idx: cache.hiqlite_cache_index(),
+                ops: batch.into_ops(),
+            },
+            false,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use crate::client::helpers::await_channel_response;
+use crate::helpers::deserialize;
+use crate::store::state_machine::memory::kv_handler::CacheRequestHandler;
+use crate::store::state_machine::memory::state_machine::{CacheRequest, CacheResponse};
+use crate::{CacheVariants, Client, Error};
+use serde::Deserialize;
+use tokio::sync::oneshot;
+
+/// A single page of a `Client::scan_prefix()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachePage<V> {
+    /// The entries in key order.
+    pub entries: Vec<(String, V)>,
+    /// The last key of this page, if more entries exist. Pass it into the next
+    /// `Client::scan_prefix()` to continue after it.
+    pub cursor: Option<String>,
+}
+
+impl Client {
+    /// SCAN all entries with keys starting with the given `prefix` in key order.
+    ///
+    /// Returns up to `limit` entries after the `cursor`, which is `None` for the first page. As
+    /// long as the returned `CachePage.cursor` is `Some(_)`, more entries can be fetched with it.
+    /// The `limit` must be greater than `0`.
+    ///
+    /// ```rust, notest
+    /// let mut cursor = None;
+    /// loop {
+    ///     let page: CachePage<Session> = client
+    ///         .scan_prefix(Cache::One, "session:user_1:", 100, cursor)
+    ///         .await?;
+    ///     for (key, session) in page.entries {
+    ///         // ...
+    ///     }
+    ///     if page.cursor.is_none() {
+    ///         break;
+    ///     }
+    ///     cursor = page.cursor;
+    /// }
+    /// ```
+    pub async fn scan_prefix<C, P, V>(
+        &self,
+        cache: C,
+        prefix: P,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<CachePage<V>, Error>
+    where
+        C: CacheVariants,
+        P: Into<String>,
+        V: for<'a> Deserialize<'a>,
+    {
+        let page = self.scan_prefix_bytes(cache, prefix, limit, cursor).await?;
+        let mut entries = Vec::with_capacity(page.entries.len());
+        for (k, v) in page.entries {
+            entries.push((k, deserialize(&v)?));
+        }
+        Ok(CachePage {
+            entries,
+            cursor: page.cursor,
+        })
+    }
+
+    /// SCAN all raw bytes entries with keys starting with the given `prefix`.
+    ///
+    /// Works in the same way as `.scan_prefix()` without any value mapping.
+    pub async fn scan_prefix_bytes<C, P>(
+        &self,
+        cache: C,
+        prefix: P,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> Result<CachePage<Vec<u8>>, Error>
+    where
+        C: CacheVariants,
+        P: Into<String>,
+    {
+        if limit == 0 {
+            return Err(Error::BadRequest(
+                "scan_prefix() limit must be greater than 0".into(),
+            ));
+        }
+
+        let (entries, cursor) = if let Some(state) = &self.inner.state {
+            let (ack, rx) = oneshot::channel();
+            state
+                .raft_cache
+                .tx_caches
+                .get(cache.hiqlite_cache_index())
+                .unwrap()
+                .send(CacheRequestHandler::ScanPrefix((
+                    prefix.into(),
+                    limit,
+                    cursor,
+                    ack,
+                )))
+                .expect("kv handler to always be running");
+            await_channel_response(rx).await?
+        } else {
+            let res = self
+                .cache_req_retry(
+                    CacheRequest::ScanPrefix {
+                        cache_idx: cache.hiqlite_cache_index(),
+                        prefix: prefix.into(),
+                        limit,
+                        cursor,
+                    },
+                    true,
+                )
+                .await?;
+            match res {
+                CacheResponse::Page(page) => page,
+                _ => unreachable!(),
+            }
+        };
+
+        Ok(CachePage { entries, cursor })
+    }
+
+    /// All keys starting with the given `prefix` in key order. An empty `prefix` returns all
+    /// keys of the cache.
+    pub async fn keys<C, P>(&self, cache: C, prefix: P) -> Result<Vec<String>, Error>
+    where
+        C: CacheVariants,
+        P: Into<String>,
+    {
+        if let Some(state) = &self.inner.state {
+            let (ack, rx) = oneshot::channel();
+            state
+                .raft_cache
+                .tx_caches
+                .get(cache.hiqlite_cache_index())
+                .unwrap()
+                .send(CacheRequestHandler::Keys((prefix.into(), ack)))
+                .expect("kv handler to always be running");
+            let keys = await_channel_response(rx).await?;
+            Ok(keys)
+        } else {
+            let res = self
+                .cache_req_retry(
+                    CacheRequest::Keys {
+                        cache_idx: cache.hiqlite_cache_index(),
+                        prefix: prefix.into(),
+                    },
+                    true,
+                )
+                .await?;
+            match res {
+                CacheResponse::Keys(keys) => Ok(keys),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// `Delete` all values with keys starting with the given `prefix` with a single Raft log
+    /// entry. Returns the amount of deleted values.
+    ///
+    /// ```rust, notest
+    /// // invalidate all sessions of a single user
+    /// client.delete_prefix(Cache::One, "session:user_1:").await?;
+    /// ```
+    pub async fn delete_prefix<C, P>(&self, cache: C, prefix: P) -> Result<usize, Error>
+    where
+        C: CacheVariants,
+        P: Into<String>,
+    {
+        self.rate_limit_cache().await?;
+
+        let res = self
+            .cache_req_retry(
+                CacheRequest::DeletePrefix {
+                    cache_idx: cache.hiqlite_cache_index(),
+                    prefix: prefix.into(),
+                },
+                false,
+            )
+            .await?;
+        match res {
+            CacheResponse::Deleted(count) => Ok(count),
+            _ => unreachable!(),
+        }
+    }
+}
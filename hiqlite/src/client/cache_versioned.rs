@@ -0,0 +1,211 @@
+use crate::client::helpers::await_channel_response;
+use crate::helpers::deserialize;
+use crate::network::serialize_network;
+use crate::store::state_machine::memory::kv_handler::CacheRequestHandler;
+use crate::store::state_machine::memory::state_machine::{CacheRequest, CacheResponse};
+use crate::{CacheVariants, Client, Error};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use tokio::sync::oneshot;
+
+impl Client {
+    /// GET a value from the cache together with its current version.
+    ///
+    /// The version is the Raft log index of the last write for this key. It increases with each
+    /// write and is the same on all nodes, which makes it usable for optimistic concurrency with
+    /// `.put_if_version()` and `.delete_if_version()`.
+    ///
+    /// ```rust, notest
+    /// loop {
+    ///     let Some((mut config, version)) = client
+    ///         .get_versioned::<_, _, Config>(Cache::One, "config")
+    ///         .await?
+    ///     else {
+    ///         break;
+    ///     };
+    ///     config.counter += 1;
+    ///     if client
+    ///         .put_if_version(Cache::One, "config", &config, version, None)
+    ///         .await?
+    ///         .is_some()
+    ///     {
+    ///         break;
+    ///     }
+    ///     // someone else updated the config in the meantime -> try again
+    /// }
+    /// ```
+    pub async fn get_versioned<C, K, V>(&self, cache: C, key: K) -> Result<Option<(V, u64)>, Error>
+    where
+        C: CacheVariants,
+        K: Into<String>,
+        V: for<'a> Deserialize<'a>,
+    {
+        match self.get_versioned_bytes(cache, key).await? {
+            Some((v, version)) => Ok(Some((deserialize(&v)?, version))),
+            None => Ok(None),
+        }
+    }
+
+    /// GET a raw bytes value from the cache together with its current version.
+    ///
+    /// Works in the same way as `.get_versioned()` without any value mapping.
+    pub async fn get_versioned_bytes<C, K>(
+        &self,
+        cache: C,
+        key: K,
+    ) -> Result<Option<(Vec<u8>, u64)>, Error>
+    where
+        C: CacheVariants,
+        K: Into<String>,
+    {
+        if let Some(state) = &self.inner.state {
+            let (ack, rx) = oneshot::channel();
+            state
+                .raft_cache
+                .tx_caches
+                .get(cache.hiqlite_cache_index())
+                .unwrap()
+                .send(CacheRequestHandler::GetVersioned((key.into(), ack)))
+                .expect("kv handler to always be running");
+            let value = await_channel_response(rx).await?;
+            Ok(value)
+        } else {
+            let res = self
+                .cache_req_retry(
+                    CacheRequest::GetVersioned {
+                        cache_idx: cache.hiqlite_cache_index(),
+                        key: key.into(),
+                    },
+                    true,
+                )
+                .await?;
+            match res {
+                CacheResponse::Versioned(value) => Ok(value),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// `Put` a value into the cache, only if the current version of the key matches the
+    /// `expected_version` from `.get_versioned()`.
+    /// The optional `ttl` is the lifetime of the value in seconds from *now* on.
+    ///
+    /// Returns the new version if the value has been written, and `None` if the key has been
+    /// modified or deleted in the meantime.
+    pub async fn put_if_version<C, K, V>(
+        &self,
+        cache: C,
+        key: K,
+        value: &V,
+        expected_version: u64,
+        ttl: Option<i64>,
+    ) -> Result<Option<u64>, Error>
+    where
+        C: CacheVariants,
+        K: Into<Cow<'static, str>>,
+        V: Serialize,
+    {
+        self.put_conditional(
+            cache,
+            key.into(),
+            serialize_network(value),
+            Some(expected_version),
+            ttl,
+        )
+        .await
+    }
+
+    /// `Put` a value into the cache, only if the key does not exist yet.
+    /// The optional `ttl` is the lifetime of the value in seconds from *now* on.
+    ///
+    /// Returns the version of the new value if it has been written, and `None` if the key
+    /// already existed. A key, which has expired at the time the leader has received this
+    /// write, does not exist anymore, even if the TTL handler has not removed it yet.
+    pub async fn put_if_absent<C, K, V>(
+        &self,
+        cache: C,
+        key: K,
+        value: &V,
+        ttl: Option<i64>,
+    ) -> Result<Option<u64>, Error>
+    where
+        C: CacheVariants,
+        K: Into<Cow<'static, str>>,
+        V: Serialize,
+    {
+        self.put_conditional(cache, key.into(), serialize_network(value), None, ttl)
+            .await
+    }
+
+    /// `Delete` a value from the cache, only if the current version of the key matches the
+    /// `expected_version` from `.get_versioned()`.
+    ///
+    /// Returns `true` if the value has been deleted.
+    pub async fn delete_if_version<C, K>(
+        &self,
+        cache: C,
+        key: K,
+        expected_version: u64,
+    ) -> Result<bool, Error>
+    where
+        C: CacheVariants,
+        K: Into<Cow<'static, str>>,
+    {
+        self.rate_limit_cache().await?;
+
+        let res = self
+            .cache_req_retry(
+                CacheRequest::DeleteIfVersion {
+                    cache_idx: cache.hiqlite_cache_index(),
+                    key: key.into(),
+                    expected: expected_version,
+                    // stamped by the leader
+                    now: 0,
+                },
+                false,
+            )
+            .await?;
+        match res {
+            CacheResponse::Deleted(count) => Ok(count > 0),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn put_conditional<C>(
+        &self,
+        cache: C,
+        key: Cow<'static, str>,
+        value: Vec<u8>,
+        expected: Option<u64>,
+        ttl: Option<i64>,
+    ) -> Result<Option<u64>, Error>
+    where
+        C: CacheVariants,
+    {
+        self.rate_limit_cache().await?;
+
+        let res = self
+            .cache_req_retry(
+                CacheRequest::PutIfVersion {
+                    cache_idx: cache.hiqlite_cache_index(),
+                    key,
+                    value,
+                    expires: ttl.map(|seconds| {
+                        Utc::now()
+                            .timestamp_micros()
+                            .saturating_add(seconds.saturating_mul(1_000_000))
+                    }),
+                    expected,
+                    // stamped by the leader
+                    now: 0,
+                },
+                false,
+            )
+            .await?;
+        match res {
+            CacheResponse::Version(version) => Ok(version),
+            _ => unreachable!(),
+        }
+    }
+}
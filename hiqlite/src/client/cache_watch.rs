@@ -0,0 +1,168 @@
+use crate::client::helpers::{StreamReceiver, await_channel_response};
+use crate::client::stream::{ClientStreamReq, ClientWatchPayload};
+use crate::helpers::event_channel;
+use crate::network::api::ApiStreamResponsePayload;
+use crate::store::state_machine::memory::watch::{self, WatchEvent, WatchKey};
+use crate::{CacheVariants, Client, Error};
+use futures_util::Stream;
+use futures_util::stream;
+use tokio::sync::oneshot;
+
+impl Client {
+    /// Watches all keys starting with `prefix` in the given cache. Pass an empty prefix to watch
+    /// the whole cache. Use `watch_key()` to watch a single key only.
+    ///
+    /// Events are emitted in the same order in which entries are applied to the cache. Each one
+    /// carries the Raft log index of the write that caused it. Deletes from multi-key operations
+    /// like `.delete_prefix()` or `.clear_cache()` emit a single `WatchEvent::Delete` for each
+    /// removed key. Expired entries emit a `WatchEvent::Expire`.
+    ///
+    /// A local client receives events from its own state machine. A remote client gets them
+    /// from the node it is connected to via the existing WebSocket connection. Each watch buffers
+    /// up to 1024 events. If you fall behind further, or after a connection loss or leader
+    /// change, the stream returns an error and ends. You can then continue with `watch_from()`
+    /// and the `log_index()` of the last event you have seen.
+    ///
+    /// ```rust, notest
+    /// use futures_util::StreamExt;
+    ///
+    /// let mut events = client.watch(Cache::One, "session:user_1:").await?;
+    /// while let Some(event) = events.next().await {
+    ///     match event? {
+    ///         WatchEvent::Put { key, .. } => {
+    ///             // refresh local state
+    ///         }
+    ///         WatchEvent::Delete { key, .. } | WatchEvent::Expire { key, .. } => {
+    ///             // invalidate local state
+    ///         }
+    ///         WatchEvent::SnapshotInstalled { .. } => {
+    ///             // full re-sync
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub async fn watch<C, P>(
+        &self,
+        cache: C,
+        prefix: P,
+    ) -> Result<impl Stream<Item = Result<WatchEvent, Error>> + Send + Unpin + 'static, Error>
+    where
+        C: CacheVariants,
+        P: Into<String>,
+    {
+        self.watch_stream(cache, WatchKey::Prefix(prefix.into()), None)
+            .await
+    }
+
+    /// Works in the same way as `watch()`, but only for this exact key.
+    pub async fn watch_key<C, K>(
+        &self,
+        cache: C,
+        key: K,
+    ) -> Result<impl Stream<Item = Result<WatchEvent, Error>> + Send + Unpin + 'static, Error>
+    where
+        C: CacheVariants,
+        K: Into<String>,
+    {
+        self.watch_stream(cache, WatchKey::Exact(key.into()), None)
+            .await
+    }
+
+    /// Resumes a watch from `watch()` or `watch_key()`. All events with a log index greater or
+    /// equal to `log_index` are replayed first, followed by the live ones. Multiple events may
+    /// share the same log index, so pass the index of the last event you have seen and skip the
+    /// ones you have processed already.
+    ///
+    /// Each cache keeps its last 1024 events while it is watched, and for 5 more minutes after
+    /// the last watch has ended. If it cannot replay all events since `log_index`, for instance
+    /// because you connected to another node or it has been restarted, this returns an error and
+    /// you need to re-read the watched keys, just like after a `WatchEvent::SnapshotInstalled`.
+    pub async fn watch_from<C>(
+        &self,
+        cache: C,
+        key: WatchKey,
+        log_index: u64,
+    ) -> Result<impl Stream<Item = Result<WatchEvent, Error>> + Send + Unpin + 'static, Error>
+    where
+        C: CacheVariants,
+    {
+        self.watch_stream(cache, key, Some(log_index)).await
+    }
+
+    async fn watch_stream<C>(
+        &self,
+        cache: C,
+        key: WatchKey,
+        resume_from: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<WatchEvent, Error>> + Send + Unpin + 'static, Error>
+    where
+        C: CacheVariants,
+    {
+        let rx = self
+            .watch_rx(cache.hiqlite_cache_index(), key, resume_from)
+            .await?;
+
+        Ok(Box::pin(stream::unfold(rx, |mut rx| async move {
+            rx.next().await.map(|res| (res, rx))
+        })))
+    }
+
+    pub(crate) async fn watch_rx(
+        &self,
+        cache_idx: usize,
+        key: WatchKey,
+        resume_from: Option<u64>,
+    ) -> Result<WatchReceiver, Error> {
+        if let Some(state) = &self.inner.state {
+            let rx = watch::subscribe(
+                state.raft_cache.tx_caches.get(cache_idx).unwrap(),
+                key,
+                resume_from,
+            )
+            .await?;
+            Ok(watch_receiver(rx, None))
+        } else {
+            let (tx, rx) = event_channel();
+            let (ack, ack_rx) = oneshot::channel();
+            let request_id = self.new_request_id();
+
+            self.inner
+                .tx_client_cache
+                .send_async(ClientStreamReq::Watch(ClientWatchPayload {
+                    request_id,
+                    cache_idx,
+                    key,
+                    resume_from,
+                    tx,
+                    ack,
+                }))
+                .await
+                .map_err(|err| Error::Error(err.to_string().into()))?;
+
+            // from here on, dropping the receiver will cancel the watch
+            let rx = watch_receiver(rx, Some((request_id, self.inner.tx_client_cache.clone())));
+
+            let res = await_channel_response(ack_rx).await??;
+            match res {
+                ApiStreamResponsePayload::Watch(res) => res.map(|_| rx),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Events of a cache watch, either coming from the local state machine or from a remote node.
+pub(crate) type WatchReceiver = StreamReceiver<WatchEvent>;
+
+fn watch_receiver(
+    rx: flume::Receiver<Result<WatchEvent, Error>>,
+    remote: Option<(usize, flume::Sender<ClientStreamReq>)>,
+) -> WatchReceiver {
+    StreamReceiver::new(
+        rx,
+        remote,
+        ClientStreamReq::WatchCancel,
+        Result::is_err,
+        "Cache watch closed unexpectedly",
+    )
+}
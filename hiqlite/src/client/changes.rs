@@ -0,0 +1,150 @@
+use crate::client::helpers::{StreamReceiver, await_channel_response};
+use crate::client::stream::{ClientStreamReq, ClientSubscribeChangesPayload};
+use crate::helpers::event_channel;
+use crate::network::api::ApiStreamResponsePayload;
+use crate::store::state_machine::sqlite::changes::{self, ChangeEvent};
+use crate::{Client, Error};
+use futures_util::Stream;
+use futures_util::stream;
+use tokio::sync::oneshot;
+
+impl Client {
+    /// Subscribes to row changes of the given tables. Pass an empty list to receive changes for
+    /// all tables.
+    ///
+    /// Changes are captured with SQLite hooks on the writer connection, after the transaction
+    /// has been committed. Each `ChangeEvent::Rows` contains all changed rows of a single Raft
+    /// log entry together with its log index. The values of inserted and updated rows are read
+    /// by the writer at the end of that entry, so they never contain changes from later ones.
+    ///
+    /// A local client receives the changes from its own state machine. A remote client gets them
+    /// from the node it is connected to via the existing WebSocket connection. After a connection
+    /// loss or leader change, or if you do not keep up with the events, the stream returns an
+    /// error and ends. You can then continue with `subscribe_changes_from()` and the
+    /// `log_index()` after the last event you have seen.
+    ///
+    /// ```rust, notest
+    /// use futures_util::StreamExt;
+    ///
+    /// let mut changes = client.subscribe_changes(["users"]).await?;
+    /// while let Some(event) = changes.next().await {
+    ///     match event? {
+    ///         ChangeEvent::Rows { log_index, changes } => {
+    ///             // update your search index
+    ///         }
+    ///         ChangeEvent::SnapshotInstalled { .. } => {
+    ///             // full re-sync
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub async fn subscribe_changes<I, S>(
+        &self,
+        tables: I,
+    ) -> Result<impl Stream<Item = Result<ChangeEvent, Error>> + Send + Unpin + 'static, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let rx = self
+            .subscribe_changes_rx(tables.into_iter().map(Into::into).collect(), None)
+            .await?;
+
+        Ok(Box::pin(stream::unfold(rx, |mut rx| async move {
+            rx.next().await.map(|res| (res, rx))
+        })))
+    }
+
+    /// Resumes a subscription from `subscribe_changes()`. All events with a log index greater or
+    /// equal to `log_index` are replayed first, followed by the live ones.
+    ///
+    /// Each node keeps the events of the last 1024 log entries for the tables that had a
+    /// subscriber during the last 5 minutes. If it cannot replay all changes since `log_index`,
+    /// for instance because you connected to another node or it has been restarted, this returns
+    /// an error and you need to re-sync, just like after a `ChangeEvent::SnapshotInstalled`.
+    ///
+    /// ```rust, notest
+    /// let mut changes = match client.subscribe_changes_from(["users"], last_index + 1).await {
+    ///     Ok(changes) => changes,
+    ///     Err(_) => {
+    ///         // full re-sync
+    ///         client.subscribe_changes(["users"]).await?
+    ///     }
+    /// };
+    /// ```
+    pub async fn subscribe_changes_from<I, S>(
+        &self,
+        tables: I,
+        log_index: u64,
+    ) -> Result<impl Stream<Item = Result<ChangeEvent, Error>> + Send + Unpin + 'static, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let rx = self
+            .subscribe_changes_rx(
+                tables.into_iter().map(Into::into).collect(),
+                Some(log_index),
+            )
+            .await?;
+
+        Ok(Box::pin(stream::unfold(rx, |mut rx| async move {
+            rx.next().await.map(|res| (res, rx))
+        })))
+    }
+
+    pub(crate) async fn subscribe_changes_rx(
+        &self,
+        tables: Vec<String>,
+        resume_from: Option<u64>,
+    ) -> Result<ChangesReceiver, Error> {
+        if let Some(state) = &self.inner.state {
+            let rx = changes::subscribe(&state.raft_db.tx_changes, tables, resume_from).await?;
+            Ok(changes_receiver(rx, None))
+        } else {
+            let (tx, rx) = event_channel();
+            let (ack, ack_rx) = oneshot::channel();
+            let request_id = self.new_request_id();
+
+            self.inner
+                .tx_client_db
+                .send_async(ClientStreamReq::SubscribeChanges(
+                    ClientSubscribeChangesPayload {
+                        request_id,
+                        tables,
+                        resume_from,
+                        tx,
+                        ack,
+                    },
+                ))
+                .await
+                .map_err(|err| Error::Error(err.to_string().into()))?;
+
+            // from here on, dropping the receiver will cancel the subscription
+            let rx = changes_receiver(rx, Some((request_id, self.inner.tx_client_db.clone())));
+
+            let res = await_channel_response(ack_rx).await??;
+            match res {
+                ApiStreamResponsePayload::SubscribeChanges(res) => res.map(|_| rx),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Change events of a subscription, either coming from the local state machine or from a
+/// remote node.
+pub(crate) type ChangesReceiver = StreamReceiver<ChangeEvent>;
+
+fn changes_receiver(
+    rx: flume::Receiver<Result<ChangeEvent, Error>>,
+    remote: Option<(usize, flume::Sender<ClientStreamReq>)>,
+) -> ChangesReceiver {
+    StreamReceiver::new(
+        rx,
+        remote,
+        ClientStreamReq::SubscribeChangesCancel,
+        Result::is_err,
+        "Change subscription closed unexpectedly",
+    )
+}
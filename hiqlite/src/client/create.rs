@@ -21,7 +21,7 @@ impl Client {
     pub(crate) async fn new_local(
         state: Arc<AppState>,
         tls_config: Option<Arc<rustls::ClientConfig>>,
-        #[cfg(feature = "cache")] tls_no_verify: bool,
+        #[cfg(any(feature = "cache", feature = "backup"))] tls_no_verify: bool,
         #[cfg(feature = "sqlite")] tx_client_db: flume::Sender<ClientStreamReq>,
         #[cfg(feature = "sqlite")] rx_client_db: flume::Receiver<ClientStreamReq>,
         tx_shutdown: watch::Sender<bool>,
@@ -61,7 +61,7 @@ impl Client {
             #[cfg(feature = "sqlite")]
             tx_client_db,
             tls_config,
-            #[cfg(feature = "cache")]
+            #[cfg(any(feature = "cache", feature = "backup"))]
             tls_no_verify,
             api_secret: None,
             request_id: AtomicUsize::new(0),
@@ -82,6 +82,8 @@ impl Client {
 
         let slf = Self {
             inner: Arc::new(db_client),
+            #[cfg(feature = "sqlite")]
+            query_timeout: None,
         };
 
         slf.find_set_active_leader().await;
@@ -190,7 +192,7 @@ impl Client {
             #[cfg(feature = "sqlite")]
             tx_client_db,
             tls_config,
-            #[cfg(feature = "cache")]
+            #[cfg(any(feature = "cache", feature = "backup"))]
             tls_no_verify,
             api_secret: Some(api_secret),
             request_id: AtomicUsize::new(0),
@@ -211,6 +213,8 @@ impl Client {
 
         let slf = Self {
             inner: Arc::new(db_client),
+            #[cfg(feature = "sqlite")]
+            query_timeout: None,
         };
 
         // It should be enough to check for DB proxy here. When running, the forward to leader
@@ -1,9 +1,9 @@
 use crate::client::helpers::await_channel_response;
-use crate::client::stream::{ClientExecutePayload, ClientStreamReq};
-use crate::network::api::ApiStreamResponsePayload;
+use crate::client::stream::{ClientExecuteManyPayload, ClientExecutePayload, ClientStreamReq};
+use crate::network::api::{ApiStreamResponsePayload, require_log_id};
 use crate::query::rows::RowOwned;
-use crate::store::state_machine::sqlite::state_machine::{Query, QueryWrite};
-use crate::{Client, Error, Params, Response};
+use crate::store::state_machine::sqlite::state_machine::{Query, QueryMany, QueryWrite};
+use crate::{Client, Error, IdempotencyKey, LogId, Params, Response};
 use std::borrow::Cow;
 use tokio::sync::oneshot;
 
@@ -23,21 +23,97 @@ impl Client {
     where
         S: Into<Cow<'static, str>>,
     {
-        self.rate_limit_db().await?;
+        let sql = Query {
+            sql: sql.into(),
+            params,
+        };
+        self.execute_with_key(sql, None, false)
+            .await
+            .map(|(rows_affected, _)| rows_affected)
+    }
+
+    /// Works in the same way as `execute()`, but additionally returns the Raft `LogId` the write
+    /// has been applied with. You can use it with e.g. `query_as_after()` to read your own
+    /// writes from a local replica that might still lag behind the leader.
+    ///
+    /// ```rust, notest
+    /// let (_, log_id) = client
+    ///     .execute_with_log_id("INSERT INTO test (id) VALUES ($1)", params!("id1"))
+    ///     .await?;
+    /// let res: Vec<Test> = client
+    ///     .query_as_after(&log_id, "SELECT * FROM test", params!())
+    ///     .await?;
+    /// ```
+    pub async fn execute_with_log_id<S>(
+        &self,
+        sql: S,
+        params: Params,
+    ) -> Result<(usize, LogId), Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let sql = Query {
+            sql: sql.into(),
+            params,
+        };
+        require_log_id(self.execute_with_key(sql, None, true).await)
+    }
 
+    /// Works in the same way as `execute()`, but makes the write idempotent with the given key.
+    ///
+    /// If a write with the same key has been applied already, e.g. because a previous attempt
+    /// was committed right before a leader change, you will receive the original result instead
+    /// of the query being executed a second time. Keys are remembered for 1 hour. Re-use the
+    /// same key when you retry a write on your own after an error.
+    ///
+    /// ```rust, notest
+    /// let key = IdempotencyKey::new();
+    /// let rows_affected = client
+    ///     .execute_idempotent(
+    ///         key.clone(),
+    ///         "UPDATE counter SET value = value + 1 WHERE id = $1",
+    ///         params!(1),
+    ///     )
+    ///     .await?;
+    /// ```
+    pub async fn execute_idempotent<S>(
+        &self,
+        key: IdempotencyKey,
+        sql: S,
+        params: Params,
+    ) -> Result<usize, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
         let sql = Query {
             sql: sql.into(),
             params,
         };
+        self.execute_with_key(sql, Some(key), false)
+            .await
+            .map(|(rows_affected, _)| rows_affected)
+    }
 
-        match self.execute_req(sql.clone()).await {
+    /// Returns the `LogId` of the write, if it has been applied locally or `with_log_id` is set.
+    pub(crate) async fn execute_with_key(
+        &self,
+        sql: Query,
+        idempotency_key: Option<IdempotencyKey>,
+        with_log_id: bool,
+    ) -> Result<(usize, Option<LogId>), Error> {
+        self.rate_limit_db().await?;
+
+        match self
+            .execute_req(sql.clone(), idempotency_key.clone(), with_log_id)
+            .await
+        {
             Ok(res) => Ok(res),
             Err(err) => {
                 if self
                     .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
                     .await
                 {
-                    self.execute_req(sql).await
+                    self.execute_req(sql, idempotency_key, with_log_id).await
                 } else {
                     Err(err)
                 }
@@ -46,16 +122,24 @@ impl Client {
     }
 
     #[inline(always)]
-    async fn execute_req(&self, sql: Query) -> Result<usize, Error> {
+    async fn execute_req(
+        &self,
+        sql: Query,
+        idempotency_key: Option<IdempotencyKey>,
+        with_log_id: bool,
+    ) -> Result<(usize, Option<LogId>), Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
             let res = state
                 .raft_db
-                .raft
-                .client_write(QueryWrite::Execute(sql))
+                .client_write(
+                    QueryWrite::Execute(sql)
+                        .with_idempotency_key(idempotency_key)
+                        .stamped(),
+                )
                 .await?;
             let resp: Response = res.data;
             match resp {
-                Response::Execute(res) => res.result,
+                Response::Execute(resp) => resp.result.map(|rows| (rows, Some(res.log_id))),
                 _ => unreachable!(),
             }
         } else {
@@ -65,13 +149,105 @@ impl Client {
                 .send_async(ClientStreamReq::Execute(ClientExecutePayload {
                     request_id: self.new_request_id(),
                     sql,
+                    idempotency_key,
+                    with_log_id,
+                    ack,
+                }))
+                .await
+                .map_err(|err| Error::Error(err.to_string().into()))?;
+            let res = await_channel_response(rx).await??;
+            match res {
+                ApiStreamResponsePayload::Execute(res) => res.map(|rows| (rows, None)),
+                ApiStreamResponsePayload::ExecuteLogId(res) => {
+                    res.map(|(rows, log_id)| (rows, Some(log_id)))
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Executes the same statement once for each of the given `Params` inside a single
+    /// transaction. Returns the affected rows for each set of `Params` in the same order.
+    ///
+    /// The SQL is only sent once and the statement is only prepared once, which makes this a lot
+    /// more efficient for bulk loading than a `txn()` with the same query repeated over and over.
+    /// The whole transaction will be rolled back if a single execution fails.
+    ///
+    /// ```rust, notest
+    /// let params = (1..=10_000)
+    ///     .map(|id| params!(id, format!("description {id}")))
+    ///     .collect::<Vec<_>>();
+    /// let res = client
+    ///     .execute_many("INSERT INTO test (id, description) VALUES ($1, $2)", params)
+    ///     .await?;
+    /// assert_eq!(res.len(), 10_000);
+    /// ```
+    pub async fn execute_many<S>(&self, sql: S, params: Vec<Params>) -> Result<Vec<usize>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.execute_many_with_log_id(sql, params)
+            .await
+            .map(|(res, _)| res)
+    }
+
+    /// Works in the same way as `execute_many()`, but additionally returns the Raft `LogId` the
+    /// write has been applied with.
+    pub async fn execute_many_with_log_id<S>(
+        &self,
+        sql: S,
+        params: Vec<Params>,
+    ) -> Result<(Vec<usize>, LogId), Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.rate_limit_db().await?;
+
+        let query = QueryMany {
+            sql: sql.into(),
+            params,
+        };
+
+        match self.execute_many_req(query.clone()).await {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                if self
+                    .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
+                    .await
+                {
+                    self.execute_many_req(query).await
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    async fn execute_many_req(&self, query: QueryMany) -> Result<(Vec<usize>, LogId), Error> {
+        if let Some(state) = self.is_leader_db_with_state().await {
+            let res = state
+                .raft_db
+                .client_write(QueryWrite::ExecuteMany(query).stamped())
+                .await?;
+            let resp: Response = res.data;
+            match resp {
+                Response::ExecuteMany(resp) => resp.map(|rows| (rows, res.log_id)),
+                _ => unreachable!(),
+            }
+        } else {
+            let (ack, rx) = oneshot::channel();
+            self.inner
+                .tx_client_db
+                .send_async(ClientStreamReq::ExecuteMany(ClientExecuteManyPayload {
+                    request_id: self.new_request_id(),
+                    query,
                     ack,
                 }))
                 .await
                 .map_err(|err| Error::Error(err.to_string().into()))?;
             let res = await_channel_response(rx).await??;
             match res {
-                ApiStreamResponsePayload::Execute(res) => res,
+                ApiStreamResponsePayload::ExecuteMany(res) => res,
                 _ => unreachable!(),
             }
         }
@@ -132,6 +308,33 @@ impl Client {
         sql: S,
         params: Params,
     ) -> Result<Vec<Result<crate::Row<'_>, Error>>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.execute_returning_rows(sql, params, false)
+            .await
+            .map(|(rows, _)| rows)
+    }
+
+    /// Works in the same way as `execute_returning()`, but additionally returns the Raft `LogId`
+    /// the write has been applied with.
+    pub async fn execute_returning_with_log_id<S>(
+        &self,
+        sql: S,
+        params: Params,
+    ) -> Result<(Vec<Result<crate::Row<'_>, Error>>, LogId), Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        require_log_id(self.execute_returning_rows(sql, params, true).await)
+    }
+
+    async fn execute_returning_rows<S>(
+        &self,
+        sql: S,
+        params: Params,
+        with_log_id: bool,
+    ) -> Result<(Vec<Result<crate::Row<'_>, Error>>, Option<LogId>), Error>
     where
         S: Into<Cow<'static, str>>,
     {
@@ -142,14 +345,14 @@ impl Client {
             params,
         };
 
-        let rows = match self.execute_returning_req(sql.clone()).await {
+        let (rows, log_id) = match self.execute_returning_req(sql.clone(), with_log_id).await {
             Ok(res) => res,
             Err(err) => {
                 if self
                     .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
                     .await
                 {
-                    self.execute_returning_req(sql).await?
+                    self.execute_returning_req(sql, with_log_id).await?
                 } else {
                     return Err(err);
                 }
@@ -160,7 +363,7 @@ impl Client {
         for row in rows {
             res.push(row.map(crate::Row::Owned))
         }
-        Ok(res)
+        Ok((res, log_id))
     }
 
     /// Execute a query on the database that includes a `RETURNING` statement.
@@ -192,16 +395,18 @@ impl Client {
     pub(crate) async fn execute_returning_req(
         &self,
         sql: Query,
-    ) -> Result<Vec<Result<RowOwned, Error>>, Error> {
+        with_log_id: bool,
+    ) -> Result<(Vec<Result<RowOwned, Error>>, Option<LogId>), Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
             let res = state
                 .raft_db
-                .raft
-                .client_write(QueryWrite::ExecuteReturning(sql))
+                .client_write(QueryWrite::ExecuteReturning(sql).stamped())
                 .await?;
             let resp: Response = res.data;
             match resp {
-                Response::ExecuteReturning(res) => res.result,
+                Response::ExecuteReturning(resp) => {
+                    resp.result.map(|rows| (rows, Some(res.log_id)))
+                }
                 _ => unreachable!(),
             }
         } else {
@@ -211,13 +416,18 @@ impl Client {
                 .send_async(ClientStreamReq::ExecuteReturning(ClientExecutePayload {
                     request_id: self.new_request_id(),
                     sql,
+                    idempotency_key: None,
+                    with_log_id,
                     ack,
                 }))
                 .await
                 .map_err(|err| Error::Error(err.to_string().into()))?;
             let res = await_channel_response(rx).await??;
             match res {
-                ApiStreamResponsePayload::ExecuteReturning(res) => res,
+                ApiStreamResponsePayload::ExecuteReturning(res) => res.map(|rows| (rows, None)),
+                ApiStreamResponsePayload::ExecuteReturningLogId(res) => {
+                    res.map(|(rows, log_id)| (rows, Some(log_id)))
+                }
                 _ => unreachable!(),
             }
         }
@@ -0,0 +1,229 @@
+use crate::export::{
+    self, COLUMNS_QUERY, CsvParser, ExportFormat, ExportSummary, ExportWriter,
+    IMPORT_TXN_MAX_BYTES, IMPORT_TXN_MAX_STATEMENTS, ImportSummary, SCHEMA_QUERY, SchemaObject,
+    SqlSplitter, TableColumn,
+};
+use crate::store::state_machine::sqlite::state_machine::Query;
+use crate::{Client, Error, Param, Params};
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::info;
+
+impl Client {
+    /// Creates a logical export of the database inside `dir`, which will be created if it does
+    /// not exist yet.
+    ///
+    /// - `ExportFormat::Sql` writes a single `dump.sql` with the schema and `INSERT` statements,
+    ///   which can be imported into any SQLite database or with `import()` into another cluster
+    /// - `ExportFormat::Csv` and `ExportFormat::JsonLines` write one file per table, which is
+    ///   mostly useful for analytics tooling
+    ///
+    /// An empty `tables` exports all tables. Hiqlite's internal tables are never exported.
+    /// All rows are read from a single consistent snapshot and streamed into the files, so even
+    /// huge databases do not need to fit into memory. This works for local and remote clients,
+    /// while the files are always written on the machine of the client.
+    ///
+    /// ```rust, notest
+    /// let summary = client.export("/tmp/export", ExportFormat::Sql, &[]).await?;
+    /// let summary = client.export("/tmp/export", ExportFormat::Csv, &["users", "orders"]).await?;
+    /// ```
+    pub async fn export(
+        &self,
+        dir: &str,
+        format: ExportFormat,
+        tables: &[&str],
+    ) -> Result<ExportSummary, Error> {
+        let schema: Vec<SchemaObject> = self.query_map(SCHEMA_QUERY, Vec::new()).await?;
+        let columns: Vec<TableColumn> = self.query_map(COLUMNS_QUERY, Vec::new()).await?;
+        let export_tables = export::export_tables(&schema, columns, tables)?;
+
+        let mut chunks = self
+            .query_stream_chunks(Query {
+                sql: export::snapshot_query(&export_tables).into(),
+                params: Vec::new(),
+            })
+            .await?;
+        let mut writer =
+            ExportWriter::new(dir, format, &schema, &export_tables, tables.is_empty()).await?;
+
+        let res = async {
+            let mut snapshot_schema = Vec::with_capacity(schema.len());
+            while let Some(rows) = chunks.next().await? {
+                for row in rows {
+                    let (idx, values) = export::split_row(row)?;
+                    if idx < 0 {
+                        snapshot_schema.push(SchemaObject::from_values(values)?);
+                    } else {
+                        writer.write_row(idx as usize, values).await?;
+                    }
+                }
+            }
+            export::check_schema(&schema, snapshot_schema)
+        }
+        .await;
+
+        match res {
+            Ok(()) => {
+                let summary = writer.finish(&schema).await?;
+                info!(
+                    "Exported {} tables into {:?}",
+                    summary.tables.len(),
+                    summary.files
+                );
+                Ok(summary)
+            }
+            Err(err) => {
+                writer.abort().await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Imports a single file from a logical `export()` through the Raft.
+    ///
+    /// For `ExportFormat::Csv` and `ExportFormat::JsonLines`, the table name is taken from the
+    /// file name and the table must exist already. The import is split into multiple smaller
+    /// transactions to not block the cluster for too long. This means, that the import is NOT
+    /// atomic. If it fails in between, the already imported transactions will stay.
+    ///
+    /// ```rust, notest
+    /// client.import("/tmp/export/dump.sql", ExportFormat::Sql).await?;
+    /// client.import("/tmp/export/users.jsonl", ExportFormat::JsonLines).await?;
+    /// ```
+    pub async fn import(&self, path: &str, format: ExportFormat) -> Result<ImportSummary, Error> {
+        let mut lines = BufReader::new(fs::File::open(path).await?).lines();
+        let mut batch = ImportBatch::default();
+
+        match format {
+            ExportFormat::Sql => {
+                let mut splitter = SqlSplitter::default();
+                while let Some(mut line) = lines.next_line().await? {
+                    line.push('\n');
+                    for stmt in splitter.push(&line) {
+                        batch.push(self, stmt, Vec::new()).await?;
+                    }
+                }
+                if let Some(stmt) = splitter.finish() {
+                    batch.push(self, stmt, Vec::new()).await?;
+                }
+            }
+
+            ExportFormat::Csv => {
+                let table = export::table_from_path(path, format)?;
+                let mut parser = CsvParser::default();
+                let mut sql = None;
+                let mut columns = 0;
+
+                while let Some(line) = lines.next_line().await? {
+                    if line.is_empty() && !parser.is_open() {
+                        continue;
+                    }
+                    let Some(record) = parser.push_line(&line)? else {
+                        continue;
+                    };
+
+                    let Some(sql) = &sql else {
+                        let header = record
+                            .into_iter()
+                            .map(|col| {
+                                col.ok_or_else(|| {
+                                    Error::QueryParams("Empty column name in CSV header".into())
+                                })
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        columns = header.len();
+                        sql = Some(export::insert_sql(&table, &header));
+                        continue;
+                    };
+
+                    if record.len() != columns {
+                        return Err(Error::QueryParams(
+                            format!(
+                                "CSV record with {} fields for {} columns",
+                                record.len(),
+                                columns
+                            )
+                            .into(),
+                        ));
+                    }
+                    let params = record
+                        .into_iter()
+                        .map(|field| field.map(Param::Text).unwrap_or(Param::Null))
+                        .collect();
+                    batch.push(self, sql.clone(), params).await?;
+                }
+
+                if parser.is_open() {
+                    return Err(Error::QueryParams(
+                        "Invalid CSV: unterminated quoted field".into(),
+                    ));
+                }
+            }
+
+            ExportFormat::JsonLines => {
+                let table = export::table_from_path(path, format)?;
+                while let Some(line) = lines.next_line().await? {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    let obj: serde_json::Map<String, serde_json::Value> =
+                        serde_json::from_str(&line)?;
+                    let mut columns = Vec::with_capacity(obj.len());
+                    let mut params = Vec::with_capacity(obj.len());
+                    for (col, value) in obj {
+                        columns.push(col);
+                        params.push(export::param_from_json(value));
+                    }
+                    batch
+                        .push(self, export::insert_sql(&table, &columns), params)
+                        .await?;
+                }
+            }
+        }
+
+        batch.flush(self).await?;
+        info!(
+            "Imported {} with {} statements in {} transactions",
+            path, batch.summary.statements, batch.summary.transactions
+        );
+        Ok(batch.summary)
+    }
+}
+
+/// Collects statements for an import until the next transaction is big enough.
+#[derive(Default)]
+struct ImportBatch {
+    queries: Vec<(String, Params)>,
+    bytes: usize,
+    summary: ImportSummary,
+}
+
+impl ImportBatch {
+    async fn push(&mut self, client: &Client, sql: String, params: Params) -> Result<(), Error> {
+        self.bytes += export::statement_size(&sql, &params);
+        self.queries.push((sql, params));
+
+        if self.queries.len() >= IMPORT_TXN_MAX_STATEMENTS || self.bytes >= IMPORT_TXN_MAX_BYTES {
+            self.flush(client).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self, client: &Client) -> Result<(), Error> {
+        if self.queries.is_empty() {
+            return Ok(());
+        }
+
+        let queries = std::mem::take(&mut self.queries);
+        let statements = queries.len();
+        self.bytes = 0;
+
+        for res in client.txn(queries).await? {
+            res?;
+        }
+        self.summary.transactions += 1;
+        self.summary.statements += statements;
+        Ok(())
+    }
+}
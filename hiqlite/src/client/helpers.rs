@@ -27,6 +27,82 @@ pub(crate) async fn await_channel_response<T>(rx: oneshot::Receiver<T>) -> Resul
     }
 }
 
+/// The receiving end of a streamed response, either from a local handler or from a remote node.
+/// It ends after the first item for which `is_last` returns `true`. A remote stream, which has
+/// not ended yet, is cancelled on drop to free up resources on the server.
+pub(crate) struct StreamReceiver<T> {
+    rx: flume::Receiver<Result<T, Error>>,
+    remote: Option<(usize, flume::Sender<ClientStreamReq>)>,
+    cancel: fn(usize) -> ClientStreamReq,
+    is_last: fn(&Result<T, Error>) -> bool,
+    closed_msg: &'static str,
+    finished: bool,
+}
+
+impl<T> StreamReceiver<T> {
+    pub(crate) fn new(
+        rx: flume::Receiver<Result<T, Error>>,
+        remote: Option<(usize, flume::Sender<ClientStreamReq>)>,
+        cancel: fn(usize) -> ClientStreamReq,
+        is_last: fn(&Result<T, Error>) -> bool,
+        closed_msg: &'static str,
+    ) -> Self {
+        Self {
+            rx,
+            remote,
+            cancel,
+            is_last,
+            closed_msg,
+            finished: false,
+        }
+    }
+
+    /// `(request_id, tx)` for a remote stream.
+    #[cfg(feature = "sqlite")]
+    #[inline]
+    pub(crate) fn remote(&self) -> Option<&(usize, flume::Sender<ClientStreamReq>)> {
+        self.remote.as_ref()
+    }
+
+    /// Returns the next item, or `None` once the stream has ended.
+    pub(crate) async fn next(&mut self) -> Option<Result<T, Error>> {
+        if self.finished {
+            return None;
+        }
+
+        let res = self
+            .rx
+            .recv_async()
+            .await
+            .unwrap_or_else(|_| Err(Error::Connect(self.closed_msg.to_string())));
+        if (self.is_last)(&res) {
+            self.finished = true;
+        }
+
+        Some(res)
+    }
+}
+
+impl<T> Drop for StreamReceiver<T> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        // A local handler notices the dropped receiver on its own.
+        if let Some((request_id, tx)) = self.remote.take() {
+            let req = (self.cancel)(request_id);
+            if let Err(flume::TrySendError::Full(req)) = tx.try_send(req)
+                && let Ok(handle) = tokio::runtime::Handle::try_current()
+            {
+                handle.spawn(async move {
+                    let _ = tx.send_async(req).await;
+                });
+            }
+        }
+    }
+}
+
 impl Client {
     #[inline(always)]
     pub(crate) async fn build_addr(
@@ -92,7 +92,7 @@ impl Client {
             let res = state
                 .raft_db
                 .raft
-                .client_write(QueryWrite::Migration(migrations))
+                .client_write(QueryWrite::Migration(migrations).stamped())
                 .await?;
             let resp: Response = res.data;
             match resp {
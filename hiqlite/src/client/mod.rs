@@ -2,6 +2,8 @@ use crate::app_state::AppState;
 use crate::{Error, NodeId};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU32, AtomicUsize};
+#[cfg(feature = "sqlite")]
+use std::time::Duration;
 use stream::ClientStreamReq;
 use tokio::sync::{RwLock, oneshot, watch};
 
@@ -11,11 +13,23 @@ mod backup;
 mod batch;
 #[cfg(feature = "cache")]
 mod cache;
+#[cfg(feature = "cache")]
+pub mod cache_batch;
+#[cfg(feature = "cache")]
+pub mod cache_scan;
+#[cfg(feature = "cache")]
+mod cache_versioned;
+#[cfg(feature = "cache")]
+mod cache_watch;
+#[cfg(feature = "sqlite")]
+mod changes;
 mod create;
 #[cfg(feature = "dlock")]
 pub mod dlock;
 #[cfg(feature = "sqlite")]
 mod execute;
+#[cfg(feature = "sqlite")]
+mod export;
 mod helpers;
 #[cfg(feature = "listen_notify_local")]
 mod listen_notify;
@@ -24,6 +38,8 @@ mod mgmt;
 mod migrate;
 #[cfg(feature = "sqlite")]
 mod query;
+#[cfg(feature = "sqlite")]
+mod query_stream;
 mod rate_limit;
 #[cfg(feature = "shutdown-handle")]
 mod shutdown_handle;
@@ -39,6 +55,9 @@ mod transaction;
 #[derive(Clone)]
 pub struct Client {
     pub(crate) inner: Arc<DbClient>,
+    /// Overwrites the `NodeConfig::query_timeout`, see `Client::with_query_timeout()`
+    #[cfg(feature = "sqlite")]
+    pub(crate) query_timeout: Option<Duration>,
 }
 
 pub(crate) struct DbClient {
@@ -54,7 +73,7 @@ pub(crate) struct DbClient {
     #[cfg(feature = "sqlite")]
     pub(crate) tx_client_db: flume::Sender<ClientStreamReq>,
     pub(crate) tls_config: Option<Arc<rustls::ClientConfig>>,
-    #[cfg(feature = "cache")]
+    #[cfg(any(feature = "cache", feature = "backup"))]
     pub(crate) tls_no_verify: bool,
     pub(crate) api_secret: Option<String>,
     pub(crate) request_id: AtomicUsize,
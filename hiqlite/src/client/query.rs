@@ -3,12 +3,64 @@ use crate::client::stream::{ClientQueryPayload, ClientStreamReq};
 use crate::network::api::ApiStreamResponsePayload;
 use crate::query::rows::RowOwned;
 use crate::store::state_machine::sqlite::state_machine::Query;
-use crate::{Client, Error, Params, query};
+use crate::app_state::AppState;
+use crate::query::consistency::{ReadConsistency, is_within_staleness};
+use crate::query::de;
+use crate::query::timeout;
+use crate::{Client, Error, LogId, Params, query};
 use serde::de::DeserializeOwned;
 use std::borrow::Cow;
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 
+enum ReadRoute {
+    Local,
+    Leader,
+    Linearizable,
+}
+
+/// The max time to wait for the local replica to catch up with a given `LogId` before reading.
+const AWAIT_APPLIED_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl Client {
+    /// Returns a new `Client`, which uses the given timeout for all its read queries instead of
+    /// the `NodeConfig::query_timeout`. It shares everything else with the original client and
+    /// is cheap to create.
+    ///
+    /// Once the timeout is reached, the running statement will be aborted and the query returns
+    /// an `Error::Timeout`. For remote clients and `query_consistent()`, the deadline is sent
+    /// along with the query, so that the remote node stops working on it as well. For streaming
+    /// queries, the timeout applies to reading each single chunk, because the runtime of the
+    /// whole stream depends on the consumer.
+    ///
+    /// ```rust, notest
+    /// let rows = client
+    ///     .with_query_timeout(Duration::from_secs(1))
+    ///     .query_raw("SELECT * FROM big_table", params!())
+    ///     .await?;
+    /// ```
+    pub fn with_query_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            query_timeout: Some(timeout),
+        }
+    }
+
+    /// Returns the timeout for read queries, either the one of this client or the configured one.
+    pub(crate) fn effective_query_timeout(&self) -> Option<Duration> {
+        self.query_timeout.or_else(|| {
+            self.inner
+                .state
+                .as_ref()
+                .and_then(|state| state.raft_db.query_timeout)
+        })
+    }
+
+    /// Returns the deadline for a query started right now.
+    fn query_deadline(&self) -> Option<Instant> {
+        timeout::deadline(self.effective_query_timeout())
+    }
+
     /// Execute a consistent query. This query will run on the leader node only and pause Raft
     /// replication at a point, where all "current" logs have been applied to at least a quorum
     /// of all nodes. This means whatever result this query returns, at least hals of the nodes + 1
@@ -97,7 +149,7 @@ impl Client {
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_map(state, stmt, params).await
+            query::query_map(state, stmt, params, self.query_deadline()).await
         } else {
             Ok(self
                 .query_remote(stmt, params, false)
@@ -123,7 +175,7 @@ impl Client {
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_map_one(state, stmt, params).await
+            query::query_map_one(state, stmt, params, self.query_deadline()).await
         } else {
             let mut rows = self.query_remote(stmt, params, false).await?;
             if rows.is_empty() {
@@ -150,7 +202,7 @@ impl Client {
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_map_optional(state, stmt, params).await
+            query::query_map_optional(state, stmt, params, self.query_deadline()).await
         } else {
             let mut rows = self.query_remote(stmt, params, false).await?;
             if rows.is_empty() {
@@ -180,7 +232,7 @@ impl Client {
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_as(state, stmt, params).await
+            query::query_as(state, stmt, params, self.query_deadline()).await
         } else {
             Err(Error::Config(
                 "`query_as()` only works for local clients, you need to use \
@@ -199,7 +251,7 @@ impl Client {
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_as_one(state, stmt, params).await
+            query::query_as_one(state, stmt, params, self.query_deadline()).await
         } else {
             Err(Error::Config(
                 "`query_as()` only works for local clients, you need to use \
@@ -218,7 +270,7 @@ impl Client {
         S: Into<Cow<'static, str>>,
     {
         if let Some(state) = &self.inner.state {
-            query::query_as_optional(state, stmt, params).await
+            query::query_as_optional(state, stmt, params, self.query_deadline()).await
         } else {
             Err(Error::Config(
                 "`query_as_optional()` only works for local clients, you need to use \
@@ -228,6 +280,67 @@ impl Client {
         }
     }
 
+    /// Works in the same way as `query_as()`, but waits until the local replica has applied at
+    /// least the given `LogId` before reading. Use it with the `LogId` returned from e.g.
+    /// `execute_with_log_id()` to read your own writes without the network overhead of
+    /// `query_consistent()`.
+    ///
+    /// Returns `Error::Timeout` if the local replica did not catch up in time.
+    ///
+    /// ```rust, notest
+    /// let (_, log_id) = client
+    ///     .execute_with_log_id("INSERT INTO test (id) VALUES ($1)", params!("id1"))
+    ///     .await?;
+    /// let res: Vec<Entity> = client
+    ///     .query_as_after(&log_id, "SELECT * FROM test", params!())
+    ///     .await?;
+    /// ```
+    ///
+    /// **Note:**
+    /// This works for local clients only, not for `hiqlite::Client::remote()`.
+    pub async fn query_as_after<T, S>(
+        &self,
+        log_id: &LogId,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        if let Some(state) = &self.inner.state {
+            await_applied(state, log_id).await?;
+            query::query_as(state, stmt, params, self.query_deadline()).await
+        } else {
+            Err(Error::Config(
+                "`query_as_after()` only works for local clients, you need to use \
+                `query_map_after()` for remote"
+                    .into(),
+            ))
+        }
+    }
+
+    /// Works in the same way as `query_map()`, but waits until the local replica has applied at
+    /// least the given `LogId` before reading.
+    ///
+    /// Returns `Error::Timeout` if the local replica did not catch up in time. Remote clients
+    /// always read from the leader, which has applied every `LogId` it returned already.
+    pub async fn query_map_after<T, S>(
+        &self,
+        log_id: &LogId,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: for<'a, 'r> From<&'a mut crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        if let Some(state) = &self.inner.state {
+            await_applied(state, log_id).await?;
+        }
+        self.query_map(stmt, params).await
+    }
+
     /// A raw query will return the bare `Row` without doing any deserialization or mapping.
     /// This can be useful if you just need to know if a query succeeds, or if you need to manually
     /// work with the result without being able to convert it into a type.
@@ -241,6 +354,7 @@ impl Client {
                 state.raft_db.read_pool.clone(),
                 stmt,
                 params,
+                self.query_deadline(),
             )
             .await?;
             Ok(rows.into_iter().map(crate::Row::Owned).collect())
@@ -249,17 +363,129 @@ impl Client {
         }
     }
 
-    /// A raw query will return the bare `Row` without doing any deserialization or mapping.
+    /// Works in the same way as `query_map()`, but with an explicit `ReadConsistency`.
     ///
-    /// This version will return exactly one `Row`.
-    /// Throws an error if the returned rows are not exactly one.
-    pub async fn query_raw_one<S>(&self, stmt: S, params: Params) -> Result<crate::Row<'_>, Error>
+    /// ```rust, notest
+    /// let res: Vec<MyStruct> = client
+    ///     .query_map_with(
+    ///         ReadConsistency::BoundedStaleness(Staleness::Time(Duration::from_secs(1))),
+    ///         "SELECT * FROM test",
+    ///         params!(),
+    ///     )
+    ///     .await?;
+    /// ```
+    pub async fn query_map_with<T, S>(
+        &self,
+        consistency: ReadConsistency,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: for<'a, 'r> From<&'a mut crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        match self.read_route(&consistency).await {
+            ReadRoute::Local => self.query_map(stmt, params).await,
+            route => Ok(self
+                .query_leader(route, stmt, params)
+                .await?
+                .into_iter()
+                .map(|mut row| T::from(&mut row))
+                .collect()),
+        }
+    }
+
+    /// Works in the same way as `query_map_one()`, but with an explicit `ReadConsistency`.
+    pub async fn query_map_one_with<T, S>(
+        &self,
+        consistency: ReadConsistency,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
+    where
+        T: for<'r> From<&'r mut crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        match self.read_route(&consistency).await {
+            ReadRoute::Local => self.query_map_one(stmt, params).await,
+            route => {
+                let mut rows = self.query_leader(route, stmt, params).await?;
+                if rows.is_empty() {
+                    Err(Error::QueryReturnedNoRows("No rows returned".into()))
+                } else if rows.len() > 1 {
+                    Err(Error::Sqlite(
+                        format!("cannot map {} rows into one", rows.len()).into(),
+                    ))
+                } else {
+                    Ok(T::from(&mut rows.swap_remove(0)))
+                }
+            }
+        }
+    }
+
+    /// Works in the same way as `query_map_optional()`, but with an explicit `ReadConsistency`.
+    pub async fn query_map_optional_with<T, S>(
+        &self,
+        consistency: ReadConsistency,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: for<'r> From<&'r mut crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        match self.read_route(&consistency).await {
+            ReadRoute::Local => self.query_map_optional(stmt, params).await,
+            route => {
+                let mut rows = self.query_leader(route, stmt, params).await?;
+                if rows.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(T::from(&mut rows.swap_remove(0))))
+                }
+            }
+        }
+    }
+
+    /// Works in the same way as `query_as()`, but with an explicit `ReadConsistency`.
+    ///
+    /// Rows read on another node are deserialized from their owned values. Unlike `query_as()`,
+    /// this works for `hiqlite::Client::remote()` as well.
+    pub async fn query_as_with<T, S>(
+        &self,
+        consistency: ReadConsistency,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        match self.read_route(&consistency).await {
+            ReadRoute::Local if self.inner.state.is_some() => self.query_as(stmt, params).await,
+            route => self
+                .query_leader(route, stmt, params)
+                .await?
+                .into_iter()
+                .map(row_as)
+                .collect(),
+        }
+    }
+
+    /// Works in the same way as `query_as_one()`, but with an explicit `ReadConsistency`.
+    pub async fn query_as_one_with<T, S>(
+        &self,
+        consistency: ReadConsistency,
+        stmt: S,
+        params: Params,
+    ) -> Result<T, Error>
     where
+        T: DeserializeOwned + Send + 'static,
         S: Into<Cow<'static, str>>,
     {
-        let mut rows = self.query_raw(stmt, params).await?;
+        let mut rows: Vec<T> = self.query_as_with(consistency, stmt, params).await?;
         if rows.is_empty() {
-            Err(Error::Sqlite("No rows returned".into()))
+            Err(Error::QueryReturnedNoRows("no rows returned".into()))
         } else if rows.len() > 1 {
             Err(Error::Sqlite(
                 format!("cannot map {} rows into one", rows.len()).into(),
@@ -269,6 +495,104 @@ impl Client {
         }
     }
 
+    /// Works in the same way as `query_as_optional()`, but with an explicit `ReadConsistency`.
+    pub async fn query_as_optional_with<T, S>(
+        &self,
+        consistency: ReadConsistency,
+        stmt: S,
+        params: Params,
+    ) -> Result<Option<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        let mut rows: Vec<T> = self.query_as_with(consistency, stmt, params).await?;
+        if rows.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(rows.swap_remove(0)))
+        }
+    }
+
+    /// Works in the same way as `query_raw()`, but with an explicit `ReadConsistency`.
+    pub async fn query_raw_with<S>(
+        &self,
+        consistency: ReadConsistency,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<crate::Row<'_>>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        match self.read_route(&consistency).await {
+            ReadRoute::Local => self.query_raw(stmt, params).await,
+            route => self.query_leader(route, stmt, params).await,
+        }
+    }
+
+    /// Works in the same way as `query_raw_one()`, but with an explicit `ReadConsistency`.
+    pub async fn query_raw_one_with<S>(
+        &self,
+        consistency: ReadConsistency,
+        stmt: S,
+        params: Params,
+    ) -> Result<crate::Row<'_>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let rows = self.query_raw_with(consistency, stmt, params).await?;
+        exactly_one_row(rows)
+    }
+
+    /// Executes a read, which cannot be served by the local replica, on the leader.
+    async fn query_leader<S>(
+        &self,
+        route: ReadRoute,
+        stmt: S,
+        params: Params,
+    ) -> Result<Vec<crate::Row<'_>>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        match route {
+            ReadRoute::Linearizable => self.query_consistent(stmt, params).await,
+            ReadRoute::Local | ReadRoute::Leader => self.query_remote(stmt, params, false).await,
+        }
+    }
+
+    /// Decides where a read with the given consistency must be executed.
+    async fn read_route(&self, consistency: &ReadConsistency) -> ReadRoute {
+        match consistency {
+            ReadConsistency::Local => ReadRoute::Local,
+            ReadConsistency::BoundedStaleness(staleness) => match &self.inner.state {
+                Some(state) if is_within_staleness(state, staleness) => ReadRoute::Local,
+                // remote clients are always connected to the leader
+                None => ReadRoute::Local,
+                Some(_) => ReadRoute::Leader,
+            },
+            ReadConsistency::Leader => {
+                if self.inner.state.is_none() || self.is_leader_db_with_state().await.is_some() {
+                    ReadRoute::Local
+                } else {
+                    ReadRoute::Leader
+                }
+            }
+            ReadConsistency::Linearizable => ReadRoute::Linearizable,
+        }
+    }
+
+    /// A raw query will return the bare `Row` without doing any deserialization or mapping.
+    ///
+    /// This version will return exactly one `Row`.
+    /// Throws an error if the returned rows are not exactly one.
+    pub async fn query_raw_one<S>(&self, stmt: S, params: Params) -> Result<crate::Row<'_>, Error>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let rows = self.query_raw(stmt, params).await?;
+        exactly_one_row(rows)
+    }
+
     /// Executes a query on remote host and returns raw rows.
     pub(crate) async fn query_remote<S>(
         &self,
@@ -283,15 +607,19 @@ impl Client {
             sql: stmt.into(),
             params,
         };
+        let deadline = self.query_deadline();
 
-        let res = match self.query_remote_req(query.clone(), consistent).await {
+        let res = match self
+            .query_remote_req(query.clone(), consistent, deadline)
+            .await
+        {
             Ok(res) => Ok(res),
             Err(err) => {
                 if self
                     .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
                     .await
                 {
-                    self.query_remote_req(query, consistent).await
+                    self.query_remote_req(query, consistent, deadline).await
                 } else {
                     return Err(err);
                 }
@@ -308,20 +636,24 @@ impl Client {
         &self,
         query: Query,
         consistent: bool,
+        deadline: Option<Instant>,
     ) -> Result<Vec<RowOwned>, Error> {
         let (ack, rx) = oneshot::channel();
+        let timeout_ms = timeout::remaining_millis(deadline);
 
         let payload = if consistent {
             ClientStreamReq::QueryConsistent(ClientQueryPayload {
                 request_id: self.new_request_id(),
                 ack,
                 query,
+                timeout_ms,
             })
         } else {
             ClientStreamReq::Query(ClientQueryPayload {
                 request_id: self.new_request_id(),
                 ack,
                 query,
+                timeout_ms,
             })
         };
 
@@ -330,7 +662,8 @@ impl Client {
             .send_async(payload)
             .await
             .map_err(|err| Error::Error(err.to_string().into()))?;
-        let res = await_channel_response(rx).await??;
+        // the remote node stops working on the query at the same deadline
+        let res = timeout::until_deadline(deadline, await_channel_response(rx)).await??;
         match res {
             ApiStreamResponsePayload::Query(res) => {
                 assert!(!consistent);
@@ -344,3 +677,34 @@ impl Client {
         }
     }
 }
+
+/// Waits until the local state machine has applied at least the given `log_id`.
+async fn await_applied(state: &AppState, log_id: &LogId) -> Result<(), Error> {
+    state
+        .raft_db
+        .raft
+        .wait(Some(AWAIT_APPLIED_TIMEOUT))
+        .applied_index_at_least(Some(log_id.index), "read after write")
+        .await?;
+    Ok(())
+}
+
+fn exactly_one_row(mut rows: Vec<crate::Row<'_>>) -> Result<crate::Row<'_>, Error> {
+    if rows.is_empty() {
+        Err(Error::Sqlite("No rows returned".into()))
+    } else if rows.len() > 1 {
+        Err(Error::Sqlite(
+            format!("cannot map {} rows into one", rows.len()).into(),
+        ))
+    } else {
+        Ok(rows.swap_remove(0))
+    }
+}
+
+fn row_as<T: DeserializeOwned>(row: crate::Row<'_>) -> Result<T, Error> {
+    match row {
+        crate::Row::Owned(row) => de::from_row(row),
+        // rows from another node are always owned
+        crate::Row::Borrowed(_) => unreachable!(),
+    }
+}
@@ -0,0 +1,159 @@
+use crate::client::helpers::StreamReceiver;
+use crate::client::stream::{ClientQueryStreamPayload, ClientStreamReq};
+use crate::query::QueryStreamChunk;
+use crate::query::rows::RowOwned;
+use crate::store::state_machine::sqlite::state_machine::Query;
+use crate::{Client, Error, Params, query};
+use futures_util::Stream;
+use futures_util::stream;
+use std::borrow::Cow;
+
+impl Client {
+    /// Streams the result of a query row by row instead of collecting everything into a `Vec<_>`.
+    ///
+    /// This is the right tool for huge result sets, exports or reports where you do not want to
+    /// hold the whole result in memory at once. Rows are read in chunks from a pooled read
+    /// connection, which is held until the stream is either finished or dropped. When the
+    /// consumer is slow, reading pauses after one buffered chunk.
+    ///
+    /// On a remote client, the chunks are sent over the existing WebSocket connection and the
+    /// server only reads the next chunk once the client asked for it. A stream cannot be resumed
+    /// after a connection loss or leader change and will return an error in that case.
+    ///
+    /// The query timeout applies to reading each single chunk instead of the whole stream. If
+    /// reading a chunk takes longer, the stream returns an `Error::Timeout` and ends.
+    ///
+    /// ```rust, notest
+    /// use futures_util::StreamExt;
+    ///
+    /// let mut rows = client.query_stream("SELECT * FROM test", params!()).await?;
+    /// while let Some(row) = rows.next().await {
+    ///     let mut row = row?;
+    ///     let id: i64 = row.get("id");
+    /// }
+    /// ```
+    pub async fn query_stream<S>(
+        &self,
+        stmt: S,
+        params: Params,
+    ) -> Result<
+        impl Stream<Item = Result<crate::Row<'static>, Error>> + Send + Unpin + 'static,
+        Error,
+    >
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let chunks = self
+            .query_stream_chunks(Query {
+                sql: stmt.into(),
+                params,
+            })
+            .await?;
+
+        Ok(Box::pin(stream::unfold(
+            (chunks, Vec::new().into_iter()),
+            |(mut chunks, mut rows)| async move {
+                loop {
+                    if let Some(row) = rows.next() {
+                        return Some((Ok(crate::Row::Owned(row)), (chunks, rows)));
+                    }
+                    match chunks.next().await {
+                        Ok(Some(chunk)) => rows = chunk.into_iter(),
+                        Ok(None) => return None,
+                        Err(err) => return Some((Err(err), (chunks, rows))),
+                    }
+                }
+            },
+        )))
+    }
+
+    /// Works in the same way as `query_stream()`, but maps each row into `T` by using its
+    /// `From<&mut Row>` impl.
+    pub async fn query_stream_map<T, S>(
+        &self,
+        stmt: S,
+        params: Params,
+    ) -> Result<impl Stream<Item = Result<T, Error>> + Send + Unpin + 'static, Error>
+    where
+        T: for<'a, 'r> From<&'a mut crate::Row<'r>> + Send + 'static,
+        S: Into<Cow<'static, str>>,
+    {
+        let rows = self.query_stream(stmt, params).await?;
+        Ok(futures_util::StreamExt::map(rows, |row| {
+            row.map(|mut row| T::from(&mut row))
+        }))
+    }
+
+    pub(crate) async fn query_stream_chunks(&self, query: Query) -> Result<QueryChunks, Error> {
+        if let Some(state) = &self.inner.state {
+            let rx = query::query_stream_local(
+                state.raft_db.log_statements,
+                state.raft_db.read_pool.clone(),
+                query.sql,
+                query.params,
+                self.effective_query_timeout(),
+            )
+            .await?;
+
+            Ok(QueryChunks::new(rx, None))
+        } else {
+            // The amount of buffered chunks is limited by the `QueryStreamNext` requests.
+            let (tx, rx) = flume::unbounded();
+            let request_id = self.new_request_id();
+
+            self.inner
+                .tx_client_db
+                .send_async(ClientStreamReq::QueryStream(ClientQueryStreamPayload {
+                    request_id,
+                    query,
+                    chunk_timeout_ms: self.effective_query_timeout().map(|t| t.as_millis() as u64),
+                    tx,
+                }))
+                .await
+                .map_err(|err| Error::Error(err.to_string().into()))?;
+
+            Ok(QueryChunks::new(
+                rx,
+                Some((request_id, self.inner.tx_client_db.clone())),
+            ))
+        }
+    }
+}
+
+/// Chunked rows of a streaming query, either coming from a local read connection or from a
+/// remote node.
+pub(crate) struct QueryChunks(StreamReceiver<Option<Vec<RowOwned>>>);
+
+impl QueryChunks {
+    fn new(
+        rx: flume::Receiver<QueryStreamChunk>,
+        remote: Option<(usize, flume::Sender<ClientStreamReq>)>,
+    ) -> Self {
+        Self(StreamReceiver::new(
+            rx,
+            remote,
+            ClientStreamReq::QueryStreamCancel,
+            |res| !matches!(res, Ok(Some(_))),
+            "Query stream closed unexpectedly",
+        ))
+    }
+
+    /// Returns the next chunk, or `Ok(None)` if the stream has ended.
+    pub(crate) async fn next(&mut self) -> QueryStreamChunk {
+        let Some(res) = self.0.next().await else {
+            return Ok(None);
+        };
+
+        if let Ok(Some(_)) = &res
+            && let Some((request_id, tx)) = self.0.remote()
+        {
+            // request the next chunk while the current one is being consumed
+            // -> if this fails, the next `recv` will return an error anyway
+            let _ = tx
+                .send_async(ClientStreamReq::QueryStreamNext(*request_id))
+                .await;
+        }
+
+        res
+    }
+}
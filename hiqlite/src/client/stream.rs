@@ -17,12 +17,27 @@ use tokio::task::JoinHandle;
 use tokio::{select, task, time};
 use tracing::{debug, error, info};
 
+#[cfg(any(feature = "sqlite", feature = "cache"))]
+use crate::helpers::send_event;
 #[cfg(any(feature = "sqlite", feature = "cache"))]
 use crate::network::api::{ApiStreamRequest, ApiStreamRequestPayload};
 #[cfg(feature = "cache")]
-use crate::store::state_machine::memory::state_machine::CacheRequest;
+use crate::store::state_machine::memory::{
+    state_machine::CacheRequest,
+    watch::{WatchEvent, WatchKey},
+};
+#[cfg(any(feature = "sqlite", feature = "cache"))]
+use tracing::warn;
 #[cfg(feature = "sqlite")]
-use crate::{migration::Migration, store::state_machine::sqlite::state_machine::Query};
+use crate::{
+    migration::Migration,
+    query::QueryStreamChunk,
+    store::state_machine::sqlite::{
+        changes::ChangeEvent,
+        idempotency::IdempotencyKey,
+        state_machine::{Query, QueryMany},
+    },
+};
 
 #[derive(Debug)]
 pub(crate) enum ClientStreamReq {
@@ -41,6 +56,18 @@ pub(crate) enum ClientStreamReq {
     Batch(ClientBatchPayload),
     #[cfg(feature = "sqlite")]
     Migrate(ClientMigratePayload),
+    #[cfg(feature = "sqlite")]
+    QueryStream(ClientQueryStreamPayload),
+    #[cfg(feature = "sqlite")]
+    QueryStreamNext(usize),
+    #[cfg(feature = "sqlite")]
+    QueryStreamCancel(usize),
+    #[cfg(feature = "sqlite")]
+    ExecuteMany(ClientExecuteManyPayload),
+    #[cfg(feature = "sqlite")]
+    SubscribeChanges(ClientSubscribeChangesPayload),
+    #[cfg(feature = "sqlite")]
+    SubscribeChangesCancel(usize),
 
     #[cfg(feature = "backup")]
     Backup(ClientBackupPayload),
@@ -49,6 +76,10 @@ pub(crate) enum ClientStreamReq {
     KV(ClientKVPayload),
     #[cfg(feature = "cache")]
     KVGet(ClientKVPayload),
+    #[cfg(feature = "cache")]
+    Watch(ClientWatchPayload),
+    #[cfg(feature = "cache")]
+    WatchCancel(usize),
 
     #[cfg(feature = "dlock")]
     LockAwait(ClientKVPayload),
@@ -71,6 +102,9 @@ pub(crate) enum ClientStreamReq {
 pub struct ClientExecutePayload {
     pub request_id: usize,
     pub sql: Query,
+    pub idempotency_key: Option<IdempotencyKey>,
+    /// Ask for the `LogId` of the write, which older servers cannot answer
+    pub with_log_id: bool,
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
@@ -79,6 +113,9 @@ pub struct ClientExecutePayload {
 pub struct ClientTransactionPayload {
     pub request_id: usize,
     pub queries: Vec<Query>,
+    pub idempotency_key: Option<IdempotencyKey>,
+    /// Ask for the `LogId` of the write, which older servers cannot answer
+    pub with_log_id: bool,
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
@@ -87,6 +124,16 @@ pub struct ClientTransactionPayload {
 pub struct ClientQueryPayload {
     pub request_id: usize,
     pub query: Query,
+    /// The remaining time until the deadline of the query
+    pub timeout_ms: Option<u64>,
+    pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct ClientExecuteManyPayload {
+    pub request_id: usize,
+    pub query: QueryMany,
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
@@ -95,6 +142,8 @@ pub struct ClientQueryPayload {
 pub struct ClientBatchPayload {
     pub request_id: usize,
     pub sql: std::borrow::Cow<'static, str>,
+    /// Ask for the `LogId` of the write, which older servers cannot answer
+    pub with_log_id: bool,
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
@@ -106,6 +155,25 @@ pub struct ClientMigratePayload {
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct ClientQueryStreamPayload {
+    pub request_id: usize,
+    pub query: Query,
+    pub chunk_timeout_ms: Option<u64>,
+    pub tx: flume::Sender<QueryStreamChunk>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct ClientSubscribeChangesPayload {
+    pub request_id: usize,
+    pub tables: Vec<String>,
+    pub resume_from: Option<u64>,
+    pub tx: flume::Sender<Result<ChangeEvent, Error>>,
+    pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
+}
+
 #[cfg(feature = "backup")]
 #[derive(Debug)]
 pub struct ClientBackupPayload {
@@ -123,6 +191,17 @@ pub struct ClientKVPayload {
     pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
 }
 
+#[cfg(feature = "cache")]
+#[derive(Debug)]
+pub struct ClientWatchPayload {
+    pub request_id: usize,
+    pub cache_idx: usize,
+    pub key: WatchKey,
+    pub resume_from: Option<u64>,
+    pub tx: flume::Sender<Result<WatchEvent, Error>>,
+    pub ack: oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
+}
+
 #[derive(Debug)]
 enum WritePayload {
     Payload(Vec<u8>),
@@ -162,6 +241,18 @@ async fn client_stream(
         usize,
         oneshot::Sender<Result<ApiStreamResponsePayload, Error>>,
     > = HashMap::new();
+    // Streaming queries cannot be resumed after a connection loss, which is why they are
+    // tracked separately and never moved into the `in_flight_buf`.
+    #[cfg(feature = "sqlite")]
+    let mut in_flight_streams: HashMap<usize, flume::Sender<QueryStreamChunk>> = HashMap::new();
+    // The same is true for change subscriptions.
+    #[cfg(feature = "sqlite")]
+    let mut in_flight_changes: HashMap<usize, flume::Sender<Result<ChangeEvent, Error>>> =
+        HashMap::new();
+    // ... and for cache watches.
+    #[cfg(feature = "cache")]
+    let mut in_flight_watches: HashMap<usize, flume::Sender<Result<WatchEvent, Error>>> =
+        HashMap::new();
 
     let mut shutdown = false;
 
@@ -233,12 +324,16 @@ async fn client_stream(
                 ClientStreamReq::Execute(ClientExecutePayload {
                     request_id,
                     sql,
+                    idempotency_key,
+                    with_log_id,
                     ack,
                 }) => {
-                    let req = ApiStreamRequest {
-                        request_id,
-                        payload: ApiStreamRequestPayload::Execute(sql),
+                    let payload = match idempotency_key {
+                        None if with_log_id => ApiStreamRequestPayload::ExecuteLogId(sql),
+                        None => ApiStreamRequestPayload::Execute(sql),
+                        Some(key) => ApiStreamRequestPayload::ExecuteIdempotent((key, sql)),
                     };
+                    let req = ApiStreamRequest { request_id, payload };
                     Some((
                         WritePayload::Payload(serialize_network(&req)),
                         req.request_id,
@@ -250,11 +345,19 @@ async fn client_stream(
                 ClientStreamReq::ExecuteReturning(ClientExecutePayload {
                     request_id,
                     sql,
+                    // only `execute()` and `txn()` can be made idempotent
+                    idempotency_key: _,
+                    with_log_id,
                     ack,
                 }) => {
+                    let payload = if with_log_id {
+                        ApiStreamRequestPayload::ExecuteReturningLogId(sql)
+                    } else {
+                        ApiStreamRequestPayload::ExecuteReturning(sql)
+                    };
                     let req = ApiStreamRequest {
                         request_id,
-                        payload: ApiStreamRequestPayload::ExecuteReturning(sql),
+                        payload,
                     };
                     Some((
                         WritePayload::Payload(serialize_network(&req)),
@@ -267,11 +370,18 @@ async fn client_stream(
                 ClientStreamReq::Transaction(ClientTransactionPayload {
                     request_id,
                     queries,
+                    idempotency_key,
+                    with_log_id,
                     ack,
                 }) => {
+                    let payload = match idempotency_key {
+                        None if with_log_id => ApiStreamRequestPayload::TransactionLogId(queries),
+                        None => ApiStreamRequestPayload::Transaction(queries),
+                        Some(key) => ApiStreamRequestPayload::TransactionIdempotent((key, queries)),
+                    };
                     let req = ApiStreamRequest {
                         request_id,
-                        payload: ApiStreamRequestPayload::Transaction(queries),
+                        payload,
                     };
                     Some((
                         WritePayload::Payload(serialize_network(&req)),
@@ -284,11 +394,16 @@ async fn client_stream(
                 ClientStreamReq::Query(ClientQueryPayload {
                     request_id,
                     query,
+                    timeout_ms,
                     ack,
                 }) => {
+                    let payload = match timeout_ms {
+                        None => ApiStreamRequestPayload::Query(query),
+                        Some(ms) => ApiStreamRequestPayload::QueryTimeout((query, ms)),
+                    };
                     let req = ApiStreamRequest {
                         request_id,
-                        payload: ApiStreamRequestPayload::Query(query),
+                        payload,
                     };
                     Some((
                         WritePayload::Payload(serialize_network(&req)),
@@ -301,12 +416,14 @@ async fn client_stream(
                 ClientStreamReq::QueryConsistent(ClientQueryPayload {
                     request_id,
                     query,
+                    timeout_ms,
                     ack,
                 }) => {
-                    let req = ApiStreamRequest {
-                        request_id,
-                        payload: ApiStreamRequestPayload::QueryConsistent(query),
+                    let payload = match timeout_ms {
+                        None => ApiStreamRequestPayload::QueryConsistent(query),
+                        Some(ms) => ApiStreamRequestPayload::QueryConsistentTimeout((query, ms)),
                     };
+                    let req = ApiStreamRequest { request_id, payload };
                     Some((
                         WritePayload::Payload(serialize_network(&req)),
                         request_id,
@@ -318,11 +435,34 @@ async fn client_stream(
                 ClientStreamReq::Batch(ClientBatchPayload {
                     request_id,
                     sql,
+                    with_log_id,
+                    ack,
+                }) => {
+                    let payload = if with_log_id {
+                        ApiStreamRequestPayload::BatchLogId(sql)
+                    } else {
+                        ApiStreamRequestPayload::Batch(sql)
+                    };
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload,
+                    };
+                    Some((
+                        WritePayload::Payload(serialize_network(&req)),
+                        request_id,
+                        ack,
+                    ))
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::ExecuteMany(ClientExecuteManyPayload {
+                    request_id,
+                    query,
                     ack,
                 }) => {
                     let req = ApiStreamRequest {
                         request_id,
-                        payload: ApiStreamRequestPayload::Batch(sql),
+                        payload: ApiStreamRequestPayload::ExecuteMany(query),
                     };
                     Some((
                         WritePayload::Payload(serialize_network(&req)),
@@ -348,6 +488,114 @@ async fn client_stream(
                     ))
                 }
 
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::QueryStream(ClientQueryStreamPayload {
+                    request_id,
+                    query,
+                    chunk_timeout_ms,
+                    tx,
+                }) => {
+                    let payload = match chunk_timeout_ms {
+                        None => ApiStreamRequestPayload::QueryStream(query),
+                        Some(ms) => ApiStreamRequestPayload::QueryStreamTimeout((query, ms)),
+                    };
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload,
+                    };
+                    if let Err(err) = tx_write
+                        .send_async(WritePayload::Payload(serialize_network(&req)))
+                        .await
+                    {
+                        error!("Error sending query stream request to writer: {}", err);
+                        let _ =
+                            tx.send(Err(Error::Connect("Connection to Raft leader lost".into())));
+                        break;
+                    }
+                    in_flight_streams.insert(request_id, tx);
+                    None
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::QueryStreamNext(request_id) => {
+                    // the stream may have been closed in the meantime after a connection loss
+                    if in_flight_streams.contains_key(&request_id) {
+                        let req = ApiStreamRequest {
+                            request_id,
+                            payload: ApiStreamRequestPayload::QueryStreamNext,
+                        };
+                        if let Err(err) = tx_write
+                            .send_async(WritePayload::Payload(serialize_network(&req)))
+                            .await
+                        {
+                            error!("Error sending query stream request to writer: {}", err);
+                            break;
+                        }
+                    }
+                    None
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::QueryStreamCancel(request_id) => {
+                    if in_flight_streams.remove(&request_id).is_some() {
+                        let req = ApiStreamRequest {
+                            request_id,
+                            payload: ApiStreamRequestPayload::QueryStreamCancel,
+                        };
+                        if let Err(err) = tx_write
+                            .send_async(WritePayload::Payload(serialize_network(&req)))
+                            .await
+                        {
+                            error!("Error sending query stream request to writer: {}", err);
+                            break;
+                        }
+                    }
+                    None
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::SubscribeChanges(ClientSubscribeChangesPayload {
+                    request_id,
+                    tables,
+                    resume_from,
+                    tx,
+                    ack,
+                }) => {
+                    // the `ack` resolves once the subscription is active on the server,
+                    // all following events are forwarded to `tx`
+                    in_flight_changes.insert(request_id, tx);
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload: ApiStreamRequestPayload::SubscribeChanges((tables, resume_from)),
+                    };
+                    Some((
+                        WritePayload::Payload(serialize_network(&req)),
+                        request_id,
+                        ack,
+                    ))
+                }
+
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::SubscribeChangesCancel(request_id) => {
+                    if in_flight_changes.remove(&request_id).is_some() {
+                        let req = ApiStreamRequest {
+                            request_id,
+                            payload: ApiStreamRequestPayload::SubscribeChangesCancel,
+                        };
+                        if let Err(err) = tx_write
+                            .send_async(WritePayload::Payload(serialize_network(&req)))
+                            .await
+                        {
+                            error!(
+                                "Error sending change subscription cancel to writer: {}",
+                                err
+                            );
+                            break;
+                        }
+                    }
+                    None
+                }
+
                 #[cfg(feature = "backup")]
                 ClientStreamReq::Backup(ClientBackupPayload {
                     request_id,
@@ -400,6 +648,47 @@ async fn client_stream(
                     ))
                 }
 
+                #[cfg(feature = "cache")]
+                ClientStreamReq::Watch(ClientWatchPayload {
+                    request_id,
+                    cache_idx,
+                    key,
+                    resume_from,
+                    tx,
+                    ack,
+                }) => {
+                    // the `ack` resolves once the watch is active on the server,
+                    // all following events are forwarded to `tx`
+                    in_flight_watches.insert(request_id, tx);
+                    let req = ApiStreamRequest {
+                        request_id,
+                        payload: ApiStreamRequestPayload::Watch((cache_idx, key, resume_from)),
+                    };
+                    Some((
+                        WritePayload::Payload(serialize_network(&req)),
+                        request_id,
+                        ack,
+                    ))
+                }
+
+                #[cfg(feature = "cache")]
+                ClientStreamReq::WatchCancel(request_id) => {
+                    if in_flight_watches.remove(&request_id).is_some() {
+                        let req = ApiStreamRequest {
+                            request_id,
+                            payload: ApiStreamRequestPayload::WatchCancel,
+                        };
+                        if let Err(err) = tx_write
+                            .send_async(WritePayload::Payload(serialize_network(&req)))
+                            .await
+                        {
+                            error!("Error sending cache watch cancel to writer: {}", err);
+                            break;
+                        }
+                    }
+                    None
+                }
+
                 #[cfg(feature = "dlock")]
                 ClientStreamReq::LockAwait(ClientKVPayload {
                     request_id,
@@ -453,6 +742,22 @@ async fn client_stream(
                 }
 
                 ClientStreamReq::StreamResponse(resp) => {
+                    #[cfg(feature = "sqlite")]
+                    let Some(resp) = try_forward_stream_chunk(&mut in_flight_streams, resp) else {
+                        continue;
+                    };
+                    #[cfg(feature = "sqlite")]
+                    let Some(resp) =
+                        try_forward_change_event(&mut in_flight_changes, &tx_write, resp).await
+                    else {
+                        continue;
+                    };
+                    #[cfg(feature = "cache")]
+                    let Some(resp) =
+                        try_forward_watch_event(&mut in_flight_watches, &tx_write, resp).await
+                    else {
+                        continue;
+                    };
                     try_forward_response(
                         &mut in_flight,
                         &mut in_flight_buf,
@@ -535,9 +840,45 @@ async fn client_stream(
                     unreachable!("we should never receive ClientStreamReq::Batch from WS reader")
                 }
                 #[cfg(feature = "sqlite")]
+                ClientStreamReq::ExecuteMany(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::ExecuteMany from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
                 ClientStreamReq::Migrate(_) => {
                     unreachable!("we should never receive ClientStreamReq::Migrate from WS reader")
                 }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::QueryStream(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::QueryStream from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::QueryStreamNext(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::QueryStreamNext from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::QueryStreamCancel(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::QueryStreamCancel from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::SubscribeChanges(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::SubscribeChanges from WS reader"
+                    )
+                }
+                #[cfg(feature = "sqlite")]
+                ClientStreamReq::SubscribeChangesCancel(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::SubscribeChangesCancel from WS reader"
+                    )
+                }
                 #[cfg(feature = "backup")]
                 ClientStreamReq::Backup(_) => {
                     unreachable!("we should never receive ClientStreamReq::Backup from WS reader")
@@ -550,6 +891,16 @@ async fn client_stream(
                 ClientStreamReq::KVGet(_) => {
                     unreachable!("we should never receive ClientStreamReq::KVGet from WS reader")
                 }
+                #[cfg(feature = "cache")]
+                ClientStreamReq::Watch(_) => {
+                    unreachable!("we should never receive ClientStreamReq::Watch from WS reader")
+                }
+                #[cfg(feature = "cache")]
+                ClientStreamReq::WatchCancel(_) => {
+                    unreachable!(
+                        "we should never receive ClientStreamReq::WatchCancel from WS reader"
+                    )
+                }
                 #[cfg(feature = "dlock")]
                 ClientStreamReq::LockAwait(_) => {
                     unreachable!(
@@ -567,6 +918,10 @@ async fn client_stream(
                     update_leader(&leader, node_id, node).await;
                 }
                 ClientStreamReq::StreamResponse(resp) => {
+                    #[cfg(feature = "sqlite")]
+                    let Some(resp) = try_forward_stream_chunk(&mut in_flight_streams, resp) else {
+                        continue;
+                    };
                     try_forward_response(&mut in_flight, &mut in_flight_buf, false, resp).await;
                 }
                 ClientStreamReq::CleanupBuffer => {
@@ -575,6 +930,25 @@ async fn client_stream(
             }
         }
 
+        #[cfg(feature = "sqlite")]
+        for (_, tx) in in_flight_streams.drain() {
+            let _ = tx.send(Err(Error::Connect(
+                "Connection lost during query stream".into(),
+            )));
+        }
+        #[cfg(feature = "sqlite")]
+        for (_, tx) in in_flight_changes.drain() {
+            let _ = tx.send(Err(Error::Connect(
+                "Connection lost during change subscription".into(),
+            )));
+        }
+        #[cfg(feature = "cache")]
+        for (_, tx) in in_flight_watches.drain() {
+            let _ = tx.send(Err(Error::Connect(
+                "Connection lost during cache watch".into(),
+            )));
+        }
+
         if shutdown {
             debug!("Shutting down Client stream receiver");
             break;
@@ -628,6 +1002,120 @@ async fn try_forward_response(
     }
 }
 
+/// Forwards a chunk for a streaming query, or returns the response if it is not one.
+#[cfg(feature = "sqlite")]
+#[inline(always)]
+fn try_forward_stream_chunk(
+    in_flight_streams: &mut HashMap<usize, flume::Sender<QueryStreamChunk>>,
+    response: ApiStreamResponse,
+) -> Option<ApiStreamResponse> {
+    let ApiStreamResponse { request_id, result } = response;
+    match result {
+        ApiStreamResponsePayload::QueryStream(res) => {
+            let is_last = !matches!(res, Ok(Some(_)));
+            let tx = if is_last {
+                in_flight_streams.remove(&request_id)
+            } else {
+                in_flight_streams.get(&request_id).cloned()
+            };
+
+            match tx {
+                // the stream was cancelled while a chunk was still on its way
+                None => debug!("no receiver for query stream {request_id} - ignoring chunk"),
+                Some(tx) => {
+                    if tx.send(res).is_err() {
+                        debug!("query stream {request_id} receiver has been dropped");
+                    }
+                }
+            }
+            None
+        }
+        result => Some(ApiStreamResponse { request_id, result }),
+    }
+}
+
+/// Forwards an event for a change subscription, or returns the response if it is not one.
+/// A lagging subscription is cancelled on the server.
+#[cfg(feature = "sqlite")]
+#[inline(always)]
+async fn try_forward_change_event(
+    in_flight_changes: &mut HashMap<usize, flume::Sender<Result<ChangeEvent, Error>>>,
+    tx_write: &flume::Sender<WritePayload>,
+    response: ApiStreamResponse,
+) -> Option<ApiStreamResponse> {
+    let ApiStreamResponse { request_id, result } = response;
+    match result {
+        ApiStreamResponsePayload::Changes(res) => {
+            let tx = if res.is_err() {
+                in_flight_changes.remove(&request_id)
+            } else {
+                in_flight_changes.get(&request_id).cloned()
+            };
+
+            match tx {
+                // the subscription was cancelled while an event was still on its way
+                None => debug!("no receiver for change subscription {request_id} - ignoring"),
+                Some(tx) => {
+                    if !send_event(&tx, res, "Change subscriber") && !tx.is_disconnected() {
+                        warn!("Cancelling lagging change subscription {request_id}");
+                        in_flight_changes.remove(&request_id);
+                        let req = ApiStreamRequest {
+                            request_id,
+                            payload: ApiStreamRequestPayload::SubscribeChangesCancel,
+                        };
+                        let _ = tx_write
+                            .send_async(WritePayload::Payload(serialize_network(&req)))
+                            .await;
+                    }
+                }
+            }
+            None
+        }
+        result => Some(ApiStreamResponse { request_id, result }),
+    }
+}
+
+/// Forwards an event for a cache watch, or returns the response if it is not one.
+/// A lagging watch is cancelled on the server.
+#[cfg(feature = "cache")]
+#[inline(always)]
+async fn try_forward_watch_event(
+    in_flight_watches: &mut HashMap<usize, flume::Sender<Result<WatchEvent, Error>>>,
+    tx_write: &flume::Sender<WritePayload>,
+    response: ApiStreamResponse,
+) -> Option<ApiStreamResponse> {
+    let ApiStreamResponse { request_id, result } = response;
+    match result {
+        ApiStreamResponsePayload::WatchEvents(res) => {
+            let tx = if res.is_err() {
+                in_flight_watches.remove(&request_id)
+            } else {
+                in_flight_watches.get(&request_id).cloned()
+            };
+
+            match tx {
+                // the watch was cancelled while an event was still on its way
+                None => debug!("no receiver for cache watch {request_id} - ignoring"),
+                Some(tx) => {
+                    if !send_event(&tx, res, "Cache watcher") && !tx.is_disconnected() {
+                        warn!("Cancelling lagging cache watch {request_id}");
+                        in_flight_watches.remove(&request_id);
+                        let req = ApiStreamRequest {
+                            request_id,
+                            payload: ApiStreamRequestPayload::WatchCancel,
+                        };
+                        let _ = tx_write
+                            .send_async(WritePayload::Payload(serialize_network(&req)))
+                            .await;
+                    }
+                }
+            }
+            None
+        }
+        result => Some(ApiStreamResponse { request_id, result }),
+    }
+}
+
 async fn update_leader(
     leader: &Arc<RwLock<(NodeId, String)>>,
     node_id: Option<u64>,
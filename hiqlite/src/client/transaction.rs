@@ -1,8 +1,8 @@
 use crate::client::helpers::await_channel_response;
 use crate::client::stream::{ClientStreamReq, ClientTransactionPayload};
-use crate::network::api::ApiStreamResponsePayload;
+use crate::network::api::{ApiStreamResponsePayload, require_log_id};
 use crate::store::state_machine::sqlite::state_machine::{Query, QueryWrite};
-use crate::{Client, Error, Params, Response};
+use crate::{Client, Error, IdempotencyKey, LogId, Params, Response};
 use std::borrow::Cow;
 use tokio::sync::oneshot;
 
@@ -37,8 +37,28 @@ impl Client {
         Q: IntoIterator<Item = (C, Params)>,
         C: Into<Cow<'static, str>>,
     {
-        self.rate_limit_db().await?;
+        let queries: Vec<Query> = sql
+            .into_iter()
+            .map(|(q, params)| Query {
+                sql: q.into(),
+                params,
+            })
+            .collect();
+        self.txn_with_key(queries, None, false)
+            .await
+            .map(|(res, _)| res)
+    }
 
+    /// Works in the same way as `txn()`, but additionally returns the Raft `LogId` the
+    /// transaction has been applied with.
+    pub async fn txn_with_log_id<C, Q>(
+        &self,
+        sql: Q,
+    ) -> Result<(Vec<Result<usize, Error>>, LogId), Error>
+    where
+        Q: IntoIterator<Item = (C, Params)>,
+        C: Into<Cow<'static, str>>,
+    {
         let queries: Vec<Query> = sql
             .into_iter()
             .map(|(q, params)| Query {
@@ -46,15 +66,57 @@ impl Client {
                 params,
             })
             .collect();
+        require_log_id(self.txn_with_key(queries, None, true).await)
+    }
 
-        match self.txn_execute(queries.clone()).await {
+    /// Works in the same way as `txn()`, but makes the transaction idempotent with the given
+    /// key.
+    ///
+    /// If a write with the same key has been applied already, e.g. because a previous attempt
+    /// was committed right before a leader change, you will receive the original result instead
+    /// of the transaction being executed a second time. Keys are remembered for 1 hour.
+    pub async fn txn_idempotent<C, Q>(
+        &self,
+        key: IdempotencyKey,
+        sql: Q,
+    ) -> Result<Vec<Result<usize, Error>>, Error>
+    where
+        Q: IntoIterator<Item = (C, Params)>,
+        C: Into<Cow<'static, str>>,
+    {
+        let queries: Vec<Query> = sql
+            .into_iter()
+            .map(|(q, params)| Query {
+                sql: q.into(),
+                params,
+            })
+            .collect();
+        self.txn_with_key(queries, Some(key), false)
+            .await
+            .map(|(res, _)| res)
+    }
+
+    /// Returns the `LogId` of the write, if it has been applied locally or `with_log_id` is set.
+    pub(crate) async fn txn_with_key(
+        &self,
+        queries: Vec<Query>,
+        idempotency_key: Option<IdempotencyKey>,
+        with_log_id: bool,
+    ) -> Result<(Vec<Result<usize, Error>>, Option<LogId>), Error> {
+        self.rate_limit_db().await?;
+
+        match self
+            .txn_execute(queries.clone(), idempotency_key.clone(), with_log_id)
+            .await
+        {
             Ok(res) => Ok(res),
             Err(err) => {
                 if self
                     .was_leader_update_error(&err, &self.inner.leader_db, &self.inner.tx_client_db)
                     .await
                 {
-                    self.txn_execute(queries).await
+                    self.txn_execute(queries, idempotency_key, with_log_id)
+                        .await
                 } else {
                     Err(err)
                 }
@@ -66,16 +128,21 @@ impl Client {
     pub(crate) async fn txn_execute(
         &self,
         queries: Vec<Query>,
-    ) -> Result<Vec<Result<usize, Error>>, Error> {
+        idempotency_key: Option<IdempotencyKey>,
+        with_log_id: bool,
+    ) -> Result<(Vec<Result<usize, Error>>, Option<LogId>), Error> {
         if let Some(state) = self.is_leader_db_with_state().await {
             let res = state
                 .raft_db
-                .raft
-                .client_write(QueryWrite::Transaction(queries))
+                .client_write(
+                    QueryWrite::Transaction(queries)
+                        .with_idempotency_key(idempotency_key)
+                        .stamped(),
+                )
                 .await?;
             let resp: Response = res.data;
             match resp {
-                Response::Transaction(res) => res,
+                Response::Transaction(resp) => resp.map(|results| (results, Some(res.log_id))),
                 _ => unreachable!(),
             }
         } else {
@@ -85,13 +152,18 @@ impl Client {
                 .send_async(ClientStreamReq::Transaction(ClientTransactionPayload {
                     request_id: self.new_request_id(),
                     queries,
+                    idempotency_key,
+                    with_log_id,
                     ack,
                 }))
                 .await
                 .map_err(|err| Error::Error(err.to_string().into()))?;
             let res = await_channel_response(rx).await??;
             match res {
-                ApiStreamResponsePayload::Transaction(res) => res,
+                ApiStreamResponsePayload::Transaction(res) => res.map(|results| (results, None)),
+                ApiStreamResponsePayload::TransactionLogId(res) => {
+                    res.map(|(results, log_id)| (results, Some(log_id)))
+                }
                 _ => unreachable!(),
             }
         }
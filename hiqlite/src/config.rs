@@ -7,10 +7,20 @@ use tracing::{debug, warn};
 
 #[cfg(feature = "backup")]
 use crate::backup;
+#[cfg(feature = "sqlite")]
+use std::time::Duration;
 
 #[cfg(feature = "dashboard")]
 use crate::dashboard::DashboardState;
 
+#[cfg(feature = "cache")]
+use crate::{CacheLimit, CacheLimits, EvictionPolicy};
+
+#[cfg(feature = "sqlite")]
+use crate::bootstrap::BootstrapDb;
+#[cfg(feature = "sqlite")]
+use crate::functions::SqlFunctions;
+
 pub use openraft::Config as RaftConfig;
 
 #[derive(Debug)]
@@ -73,6 +83,29 @@ pub struct NodeConfig {
     ///
     /// default: 4
     pub read_pool_size: usize,
+    /// The default timeout for read queries. Once it is reached, the running statement will be
+    /// aborted and the query returns an `Error::Timeout`. This makes sure that a single bad query
+    /// cannot hold a connection from the `read_pool` forever. It applies to local queries and to
+    /// queries this node executes for remote clients. It can be overwritten for each client with
+    /// `Client::with_query_timeout()`.
+    ///
+    /// default: None
+    #[cfg(feature = "sqlite")]
+    pub query_timeout: Option<Duration>,
+    /// The maximum amount of SQLite VM instructions a single statement of a write may execute.
+    /// All writes are applied by a single writer thread, and one expensive statement, like an
+    /// `UPDATE` without a matching index, would otherwise block the apply of all following Raft
+    /// logs.
+    ///
+    /// The count depends on the query plan of each node, which is why it is never checked while
+    /// a Raft log is applied. Instead, the leader runs each write in a dry run first and rejects
+    /// it with an error before it is proposed. This executes each write twice on the leader. A
+    /// write can still become more expensive between the check and the apply, so use it as a
+    /// safety net against runaway statements, not as a tight limit.
+    ///
+    /// default: None
+    #[cfg(feature = "sqlite")]
+    pub write_instruction_budget: Option<u32>,
     /// When Raft logs should be synced to disk.
     ///
     /// - `Immediate` fsyncs the WAL before openraft is told the append finished, so an
@@ -157,10 +190,26 @@ pub struct NodeConfig {
     /// you can set rate-limits.
     #[cfg(feature = "cache")]
     pub rate_limit_cache: Option<RateLimitConfig>,
+    /// Memory limits for the caches. When a cache exceeds its limit, the leader evicts entries
+    /// and replicates the eviction to all other nodes. feature `cache`
+    ///
+    /// default: unbounded
+    #[cfg(feature = "cache")]
+    pub cache_limits: CacheLimits,
     /// To guarantee the stability of your Raft cluster no matter how high requests might spike,
     /// you can set rate-limits.
     #[cfg(feature = "sqlite")]
     pub rate_limit_db: Option<RateLimitConfig>,
+    /// Custom SQL functions, which will be installed on the writer and all read connections.
+    /// They must be registered in the same way on each node.
+    #[cfg(feature = "sqlite")]
+    pub sql_functions: SqlFunctions,
+    /// Bootstraps a new cluster from an existing SQLite file. It is only used by a pristine
+    /// node `1` and ignored as soon as the cluster has been initialized.
+    ///
+    /// default: None
+    #[cfg(feature = "sqlite")]
+    pub bootstrap_db: Option<BootstrapDb>,
 }
 
 impl Default for NodeConfig {
@@ -175,6 +224,10 @@ impl Default for NodeConfig {
             log_statements: false,
             prepared_statement_cache_capacity: 1024,
             read_pool_size: 4,
+            #[cfg(feature = "sqlite")]
+            query_timeout: None,
+            #[cfg(feature = "sqlite")]
+            write_instruction_budget: None,
             wal_sync: hiqlite_wal::LogSync::ImmediateAsync,
             wal_size: 2 * 1024 * 1024,
             #[cfg(feature = "cache")]
@@ -200,8 +253,14 @@ impl Default for NodeConfig {
             learner_only: false,
             #[cfg(feature = "cache")]
             rate_limit_cache: None,
+            #[cfg(feature = "cache")]
+            cache_limits: CacheLimits::default(),
             #[cfg(feature = "sqlite")]
             rate_limit_db: None,
+            #[cfg(feature = "sqlite")]
+            sql_functions: SqlFunctions::default(),
+            #[cfg(feature = "sqlite")]
+            bootstrap_db: None,
         }
     }
 }
@@ -301,6 +360,33 @@ impl NodeConfig {
             }
         };
 
+        #[cfg(feature = "cache")]
+        let cache_limits = {
+            let max_entries = env::var("HQL_CACHE_MAX_ENTRIES").ok().map(|v| {
+                v.parse::<u64>()
+                    .expect("Cannot parse HQL_CACHE_MAX_ENTRIES as u64")
+            });
+            let max_bytes = env::var("HQL_CACHE_MAX_BYTES").ok().map(|v| {
+                v.parse::<u64>()
+                    .expect("Cannot parse HQL_CACHE_MAX_BYTES as u64")
+            });
+            let policy = env::var("HQL_CACHE_EVICTION")
+                .ok()
+                .map(|v| {
+                    EvictionPolicy::try_from(v.as_str()).expect("Cannot parse HQL_CACHE_EVICTION")
+                })
+                .unwrap_or_default();
+            if max_entries.is_some() || max_bytes.is_some() {
+                CacheLimits::default().with_default(CacheLimit {
+                    max_entries,
+                    max_bytes,
+                    policy,
+                })
+            } else {
+                CacheLimits::default()
+            }
+        };
+
         #[cfg(feature = "sqlite")]
         let rate_limit_db = {
             if let Some(rps) = env::var("HQL_RL_DB_RPS")
@@ -321,6 +407,18 @@ impl NodeConfig {
             }
         };
 
+        #[cfg(feature = "sqlite")]
+        let bootstrap_db = env::var("HQL_BOOTSTRAP_DB").ok().map(|path| {
+            let bootstrap = BootstrapDb::new(path);
+            if let Ok(dir) = env::var("HQL_BOOTSTRAP_MIGRATIONS") {
+                bootstrap
+                    .with_migrations_dir(&dir)
+                    .expect("Cannot read migrations from HQL_BOOTSTRAP_MIGRATIONS")
+            } else {
+                bootstrap
+            }
+        });
+
         let slf = Self {
             node_id,
             nodes: Node::parse_from_env("HQL_NODES"),
@@ -343,6 +441,18 @@ impl NodeConfig {
                 .unwrap_or("4")
                 .parse()
                 .expect("Cannot parse HQL_READ_POOL_SIZE as usize"),
+            #[cfg(feature = "sqlite")]
+            query_timeout: env::var("HQL_QUERY_TIMEOUT_MS").ok().map(|v| {
+                Duration::from_millis(
+                    v.parse::<u64>()
+                        .expect("Cannot parse HQL_QUERY_TIMEOUT_MS as u64"),
+                )
+            }),
+            #[cfg(feature = "sqlite")]
+            write_instruction_budget: env::var("HQL_WRITE_INSTRUCTION_BUDGET").ok().map(|v| {
+                v.parse::<u32>()
+                    .expect("Cannot parse HQL_WRITE_INSTRUCTION_BUDGET as u32")
+            }),
             wal_sync: hiqlite_wal::LogSync::ImmediateAsync,
             wal_size: 2 * 1024 * 1024,
             #[cfg(feature = "cache")]
@@ -372,8 +482,14 @@ impl NodeConfig {
             backup_keep_days_local,
             #[cfg(feature = "cache")]
             rate_limit_cache,
+            #[cfg(feature = "cache")]
+            cache_limits,
             #[cfg(feature = "sqlite")]
             rate_limit_db,
+            #[cfg(feature = "sqlite")]
+            sql_functions: SqlFunctions::default(),
+            #[cfg(feature = "sqlite")]
+            bootstrap_db,
         };
 
         slf.is_valid()
@@ -464,6 +580,18 @@ impl NodeConfig {
             ));
         }
 
+        #[cfg(feature = "sqlite")]
+        if let Some(budget) = self.write_instruction_budget
+            && (budget == 0 || budget > i32::MAX as u32)
+        {
+            return Err(Error::Config(
+                format!("'write_instruction_budget' must be in 1..={}", i32::MAX).into(),
+            ));
+        }
+
+        #[cfg(feature = "sqlite")]
+        self.sql_functions.validate()?;
+
         if self.log_statements {
             warn!(
                 r#"
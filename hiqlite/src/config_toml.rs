@@ -152,6 +152,16 @@ impl NodeConfig {
                 as usize;
         let read_pool_size =
             t_u16(&mut map, t_name, "read_pool_size", "HQL_READ_POOL_SIZE")?.unwrap_or(4) as usize;
+        #[cfg(feature = "sqlite")]
+        let query_timeout = t_u64(&mut map, t_name, "query_timeout_ms", "HQL_QUERY_TIMEOUT_MS")?
+            .map(std::time::Duration::from_millis);
+        #[cfg(feature = "sqlite")]
+        let write_instruction_budget = t_u32(
+            &mut map,
+            t_name,
+            "write_instruction_budget",
+            "HQL_WRITE_INSTRUCTION_BUDGET",
+        )?;
 
         let wal_sync = if let Some(v) = t_str(&mut map, t_name, "log_sync", "HQL_LOG_SYNC")? {
             let Ok(sync) = LogSync::try_from(v.as_str()) else {
@@ -261,9 +271,90 @@ impl NodeConfig {
             )?
             .unwrap_or(30);
 
-            let backup_config =
+            let backup_verify =
+                t_bool(&mut map, t_name, "backup_verify", "HQL_BACKUP_VERIFY")?.unwrap_or(false);
+            let backup_differential = t_u16(
+                &mut map,
+                t_name,
+                "backup_differential",
+                "HQL_BACKUP_DIFFERENTIAL",
+            )?
+            .unwrap_or(0);
+            let backup_keep_daily = t_u16(
+                &mut map,
+                t_name,
+                "backup_keep_daily",
+                "HQL_BACKUP_KEEP_DAILY",
+            )?;
+            let backup_keep_weekly = t_u16(
+                &mut map,
+                t_name,
+                "backup_keep_weekly",
+                "HQL_BACKUP_KEEP_WEEKLY",
+            )?;
+            let backup_keep_monthly = t_u16(
+                &mut map,
+                t_name,
+                "backup_keep_monthly",
+                "HQL_BACKUP_KEEP_MONTHLY",
+            )?;
+            let backup_retention_dry_run = t_bool(
+                &mut map,
+                t_name,
+                "backup_retention_dry_run",
+                "HQL_BACKUP_RETENTION_DRY_RUN",
+            )?
+            .unwrap_or(false);
+            let backup_archive_interval = t_u64(
+                &mut map,
+                t_name,
+                "backup_archive_interval",
+                "HQL_BACKUP_ARCHIVE_INTERVAL",
+            )?;
+            let backup_target_dirs = t_str_vec(
+                &mut map,
+                t_name,
+                "backup_target_dirs",
+                "HQL_BACKUP_TARGET_DIRS",
+            )?;
+            #[cfg(feature = "cache")]
+            let backup_cache =
+                t_bool(&mut map, t_name, "backup_cache", "HQL_BACKUP_CACHE")?.unwrap_or(false);
+
+            let mut backup_config =
                 crate::backup::BackupConfig::new(backup_cron.as_ref(), backup_keep_days)
-                    .map_err(|err| Error::config(format!("Error building BackupConfig: {err}")))?;
+                    .map_err(|err| Error::config(format!("Error building BackupConfig: {err}")))?
+                    .with_verify(backup_verify)
+                    .with_differential(backup_differential)
+                    .with_retention_dry_run(backup_retention_dry_run);
+            #[cfg(feature = "cache")]
+            {
+                backup_config = backup_config.with_cache(backup_cache);
+            }
+            if backup_keep_daily.is_some()
+                || backup_keep_weekly.is_some()
+                || backup_keep_monthly.is_some()
+            {
+                backup_config = backup_config.with_retention(
+                    backup_keep_daily.unwrap_or_default(),
+                    backup_keep_weekly.unwrap_or_default(),
+                    backup_keep_monthly.unwrap_or_default(),
+                );
+            }
+            if let Some(secs) = backup_archive_interval {
+                if secs == 0 {
+                    return Err(Error::config(
+                        "'backup_archive_interval' must be greater than 0",
+                    ));
+                }
+                backup_config =
+                    backup_config.with_archive_interval(std::time::Duration::from_secs(secs));
+            }
+            for dir in backup_target_dirs.unwrap_or_default() {
+                backup_config = backup_config.with_target(std::sync::Arc::new(
+                    crate::backup_target::LocalTarget::new(dir),
+                ));
+            }
             (backup_config, backup_keep_days_local)
         };
 
@@ -364,6 +455,30 @@ impl NodeConfig {
             }
         };
 
+        #[cfg(feature = "cache")]
+        let cache_limits = {
+            let max_entries = t_u64(
+                &mut map,
+                t_name,
+                "cache_max_entries",
+                "HQL_CACHE_MAX_ENTRIES",
+            )?;
+            let max_bytes = t_u64(&mut map, t_name, "cache_max_bytes", "HQL_CACHE_MAX_BYTES")?;
+            let policy = match t_str(&mut map, t_name, "cache_eviction", "HQL_CACHE_EVICTION")? {
+                Some(policy) => crate::EvictionPolicy::try_from(policy.as_str())?,
+                None => crate::EvictionPolicy::default(),
+            };
+            if max_entries.is_some() || max_bytes.is_some() {
+                crate::CacheLimits::default().with_default(crate::CacheLimit {
+                    max_entries,
+                    max_bytes,
+                    policy,
+                })
+            } else {
+                crate::CacheLimits::default()
+            }
+        };
+
         #[cfg(feature = "sqlite")]
         let rate_limit_db = {
             if let Some(rps) = t_u32(&mut map, t_name, "rate_limit_db_rps", "HQL_RL_DB_RPS")? {
@@ -377,6 +492,23 @@ impl NodeConfig {
             }
         };
 
+        #[cfg(feature = "sqlite")]
+        let bootstrap_db = {
+            let migrations = t_str(
+                &mut map,
+                t_name,
+                "bootstrap_migrations",
+                "HQL_BOOTSTRAP_MIGRATIONS",
+            )?;
+            match t_str(&mut map, t_name, "bootstrap_db", "HQL_BOOTSTRAP_DB")? {
+                Some(path) if let Some(dir) = migrations => {
+                    Some(crate::BootstrapDb::new(path).with_migrations_dir(&dir)?)
+                }
+                Some(path) => Some(crate::BootstrapDb::new(path)),
+                None => None,
+            }
+        };
+
         check_empty(map, table_name)?;
 
         Ok(NodeConfig {
@@ -389,6 +521,10 @@ impl NodeConfig {
             log_statements,
             prepared_statement_cache_capacity,
             read_pool_size,
+            #[cfg(feature = "sqlite")]
+            query_timeout,
+            #[cfg(feature = "sqlite")]
+            write_instruction_budget,
             wal_sync,
             wal_size,
             #[cfg(feature = "cache")]
@@ -414,8 +550,14 @@ impl NodeConfig {
             learner_only,
             #[cfg(feature = "cache")]
             rate_limit_cache,
+            #[cfg(feature = "cache")]
+            cache_limits,
             #[cfg(feature = "sqlite")]
             rate_limit_db,
+            #[cfg(feature = "sqlite")]
+            sql_functions: Default::default(),
+            #[cfg(feature = "sqlite")]
+            bootstrap_db,
         })
     }
 }
@@ -432,7 +574,21 @@ fn check_empty(table: toml::Table, tbl_name: &str) -> Result<(), Error> {
                 "backup_cron",
                 "backup_keep_days",
                 "backup_keep_days_local",
+                "backup_archive_interval",
+                "backup_verify",
+                "backup_differential",
+                "backup_keep_daily",
+                "backup_keep_weekly",
+                "backup_keep_monthly",
+                "backup_retention_dry_run",
+                "backup_target_dirs",
+                "backup_cache",
+                "bootstrap_db",
+                "bootstrap_migrations",
                 "cache_storage_disk",
+                "cache_max_entries",
+                "cache_max_bytes",
+                "cache_eviction",
                 "s3_url",
                 "s3_bucket",
                 "s3_region",
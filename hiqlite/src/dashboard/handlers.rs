@@ -12,7 +12,7 @@ use axum::response::Response;
 use axum::{body, Form, Json};
 use hyper::StatusCode;
 use openraft::RaftMetrics;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use spow::pow::Pow;
 
 pub async fn redirect_to_index() -> Response {
@@ -115,9 +115,29 @@ pub async fn post_query(
     Ok(Json(res))
 }
 
-pub async fn get_metrics(state: AppStateExt, _: Session) -> Json<RaftMetrics<u64, Node>> {
-    let metrics = state.raft_db.raft.metrics().borrow().clone();
-    Json(metrics)
+/// The Raft metrics with the backup, snapshot and cache metrics of this node next to them.
+#[derive(Debug, Serialize)]
+pub struct Metrics {
+    #[serde(flatten)]
+    raft: RaftMetrics<u64, Node>,
+    #[cfg(feature = "backup")]
+    backup: crate::BackupMetrics,
+    #[cfg(feature = "cache")]
+    caches: Vec<crate::CacheStats>,
+}
+
+pub async fn get_metrics(state: AppStateExt, _: Session) -> Result<Json<Metrics>, Error> {
+    let raft = state.raft_db.raft.metrics().borrow().clone();
+    Ok(Json(Metrics {
+        raft,
+        #[cfg(feature = "backup")]
+        backup: state.raft_db.backup_progress.metrics(),
+        #[cfg(feature = "cache")]
+        caches: crate::store::state_machine::memory::eviction::cache_stats(
+            &state.raft_cache.tx_caches,
+        )
+        .await?,
+    }))
 }
 
 #[cfg(test)]
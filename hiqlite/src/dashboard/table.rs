@@ -50,6 +50,7 @@ impl Table {
             state,
             "SELECT type,name,tbl_name,sql FROM sqlite_master",
             Params::new(),
+            None,
         )
         .await?;
 
@@ -64,6 +65,7 @@ impl Table {
             state,
             "SELECT type,name,tbl_name,sql FROM sqlite_master WHERE type = $1",
             vec![Param::Text(filter.as_str().to_string())],
+            None,
         )
         .await?;
 
@@ -6,6 +6,7 @@ use axum::response::IntoResponse;
 use bincode::error::{DecodeError, EncodeError};
 use fastwebsockets::WebSocketError;
 use openraft::error::{CheckIsLeaderError, ClientWriteError, Fatal, RaftError};
+use openraft::metrics::WaitError;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::convert::Infallible;
@@ -247,6 +248,16 @@ impl From<RaftError<u64, CheckIsLeaderError<u64, Node>>> for Error {
     }
 }
 
+impl From<WaitError> for Error {
+    fn from(value: WaitError) -> Self {
+        trace!("WaitError: {value}");
+        match value {
+            WaitError::Timeout(_, _) => Self::Timeout(value.to_string()),
+            WaitError::ShuttingDown => Self::Error(value.to_string().into()),
+        }
+    }
+}
+
 impl From<Fatal<u64>> for Error {
     fn from(value: Fatal<u64>) -> Self {
         trace!("RaftErrorFatal: {value}");
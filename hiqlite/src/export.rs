@@ -0,0 +1,1024 @@
+//! Logical exports of the database and their import through the Raft.
+//!
+//! All rows are read with a single `UNION ALL` statement, which makes sure that the whole
+//! export comes from the same consistent read snapshot, for local and remote clients alike.
+//! Each row of that statement starts with the index of its table, while `-1` marks a row of
+//! the schema, which is used to detect schema changes during the export.
+
+use crate::query::rows::{RowOwned, ValueOwned};
+use crate::store::state_machine::sqlite::writer::INTERNAL_TABLES;
+use crate::{Error, Param, Params, Row};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write as _;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tracing::warn;
+
+/// The file name of a `ExportFormat::Sql` export inside the target directory.
+pub(crate) const SQL_DUMP_FILE: &str = "dump.sql";
+/// SQLite's default `SQLITE_MAX_COMPOUND_SELECT`, which limits the tables of a single export.
+const MAX_COMPOUND_SELECT: usize = 500;
+
+/// An import is split into transactions with at most this many statements ...
+pub(crate) const IMPORT_TXN_MAX_STATEMENTS: usize = 1000;
+/// ... or roughly this many bytes of SQL and parameters.
+pub(crate) const IMPORT_TXN_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+pub(crate) const SCHEMA_QUERY: &str = "SELECT type, name, tbl_name, sql FROM sqlite_master \
+    WHERE name NOT LIKE 'sqlite_%' ORDER BY rowid";
+pub(crate) const COLUMNS_QUERY: &str = "SELECT m.name AS tbl, p.name AS col \
+    FROM sqlite_master m JOIN pragma_table_info(m.name) p \
+    WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite_%' ORDER BY m.rowid, p.cid";
+
+/// The format of a logical export with `Client::export()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// A single `dump.sql` with the schema and one `INSERT` statement per row.
+    Sql,
+    /// One `<table>.csv` per table with a header row. `NULL` is an empty field, while an empty
+    /// string is always quoted. BLOBs are written as hex strings and cannot be imported again.
+    Csv,
+    /// One `<table>.jsonl` per table with one JSON object per row. BLOBs are written as hex
+    /// strings and cannot be imported again.
+    JsonLines,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Sql => "sql",
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        }
+    }
+}
+
+/// The result of a successful `Client::export()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportSummary {
+    /// `(table, rows)` of each exported table.
+    pub tables: Vec<(String, u64)>,
+    /// The paths of all written files.
+    pub files: Vec<String>,
+}
+
+/// The result of a successful `Client::import()`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportSummary {
+    /// The amount of transactions the import has been split into.
+    pub transactions: usize,
+    /// The amount of executed statements.
+    pub statements: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
+pub(crate) struct SchemaObject {
+    typ: String,
+    name: String,
+    tbl_name: String,
+    sql: Option<String>,
+}
+
+impl From<&mut Row<'_>> for SchemaObject {
+    fn from(row: &mut Row<'_>) -> Self {
+        Self {
+            typ: row.get("type"),
+            name: row.get("name"),
+            tbl_name: row.get("tbl_name"),
+            sql: row.get("sql"),
+        }
+    }
+}
+
+impl SchemaObject {
+    fn is_internal(&self) -> bool {
+        INTERNAL_TABLES.contains(&self.tbl_name.as_str())
+    }
+
+    /// Parses a schema row of the snapshot query.
+    pub(crate) fn from_values(values: impl Iterator<Item = ValueOwned>) -> Result<Self, Error> {
+        let mut values = values.map(|v| match v {
+            ValueOwned::Text(s) => Ok(Some(s)),
+            ValueOwned::Null => Ok(None),
+            v => Err(Error::Error(
+                format!("Unexpected schema value during export: {v:?}").into(),
+            )),
+        });
+        let mut next = || {
+            values.next().unwrap_or(Ok(None)).and_then(|v| {
+                v.ok_or_else(|| Error::Error("Missing schema value during export".into()))
+            })
+        };
+        Ok(Self {
+            typ: next()?,
+            name: next()?,
+            tbl_name: next()?,
+            sql: values.next().transpose()?.flatten(),
+        })
+    }
+}
+
+pub(crate) struct TableColumn {
+    tbl: String,
+    col: String,
+}
+
+impl From<&mut Row<'_>> for TableColumn {
+    fn from(row: &mut Row<'_>) -> Self {
+        Self {
+            tbl: row.get("tbl"),
+            col: row.get("col"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct ExportTable {
+    name: String,
+    columns: Vec<String>,
+}
+
+/// Selects the tables for the export in schema order. An empty `filter` selects all tables.
+pub(crate) fn export_tables(
+    schema: &[SchemaObject],
+    columns: Vec<TableColumn>,
+    filter: &[&str],
+) -> Result<Vec<ExportTable>, Error> {
+    for name in filter {
+        if !schema
+            .iter()
+            .any(|o| o.typ == "table" && o.name == *name && !o.is_internal())
+        {
+            return Err(Error::Config(format!("Table {name} does not exist").into()));
+        }
+    }
+
+    let mut tables = Vec::new();
+    for obj in schema {
+        if obj.typ != "table"
+            || obj.is_internal()
+            || (!filter.is_empty() && !filter.contains(&obj.name.as_str()))
+        {
+            continue;
+        }
+        if obj
+            .sql
+            .as_deref()
+            .is_some_and(|sql| sql.starts_with("CREATE VIRTUAL TABLE"))
+        {
+            warn!("Skipping virtual table {} during export", obj.name);
+            continue;
+        }
+
+        tables.push(ExportTable {
+            name: obj.name.clone(),
+            columns: columns
+                .iter()
+                .filter(|c| c.tbl == obj.name)
+                .map(|c| c.col.clone())
+                .collect(),
+        });
+    }
+
+    if tables.len() >= MAX_COMPOUND_SELECT {
+        return Err(Error::Config(
+            format!(
+                "A single export can contain at most {} tables",
+                MAX_COMPOUND_SELECT - 1
+            )
+            .into(),
+        ));
+    }
+    Ok(tables)
+}
+
+/// Builds the statement, which reads the schema and all rows of all `tables` at once.
+/// Each column is an expression to get the values with their original types, independently
+/// of the declared column type.
+pub(crate) fn snapshot_query(tables: &[ExportTable]) -> String {
+    let width = tables
+        .iter()
+        .map(|t| t.columns.len())
+        .max()
+        .unwrap_or_default()
+        .max(4);
+
+    let pad = |sql: &mut String, cols: usize| {
+        for _ in cols..width {
+            sql.push_str(", NULL");
+        }
+    };
+
+    let mut sql = String::from("SELECT -1, +type, +name, +tbl_name, +sql");
+    pad(&mut sql, 4);
+    sql.push_str(" FROM sqlite_master WHERE name NOT LIKE 'sqlite_%'");
+
+    for (idx, table) in tables.iter().enumerate() {
+        write!(sql, " UNION ALL SELECT {idx}").unwrap();
+        for col in &table.columns {
+            write!(sql, ", +{}", quote_ident(col)).unwrap();
+        }
+        pad(&mut sql, table.columns.len());
+        write!(sql, " FROM {}", quote_ident(&table.name)).unwrap();
+    }
+
+    sql
+}
+
+pub(crate) fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Adds `IF NOT EXISTS` to a `CREATE` statement from the schema, so a dump can be imported into
+/// a database, which already contains (parts of) the schema.
+fn if_not_exists(sql: &str) -> Cow<'_, str> {
+    for prefix in [
+        "CREATE TABLE ",
+        "CREATE UNIQUE INDEX ",
+        "CREATE INDEX ",
+        "CREATE VIEW ",
+        "CREATE TRIGGER ",
+    ] {
+        if let Some(rest) = sql.strip_prefix(prefix) {
+            return Cow::Owned(format!("{prefix}IF NOT EXISTS {rest}"));
+        }
+    }
+    Cow::Borrowed(sql)
+}
+
+fn sql_literal(value: &ValueOwned, out: &mut String) {
+    match value {
+        ValueOwned::Null => out.push_str("NULL"),
+        ValueOwned::Integer(i) => write!(out, "{i}").unwrap(),
+        ValueOwned::Real(r) => {
+            if r.is_infinite() {
+                out.push_str(if *r > 0.0 { "1e999" } else { "-1e999" });
+            } else {
+                // `{:?}` always contains a `.` or an exponent, so it stays a REAL
+                write!(out, "{r:?}").unwrap();
+            }
+        }
+        ValueOwned::Text(s) => {
+            out.push('\'');
+            out.push_str(&s.replace('\'', "''"));
+            out.push('\'');
+        }
+        ValueOwned::Blob(b) => {
+            out.push_str("X'");
+            out.push_str(&hex::encode(b));
+            out.push('\'');
+        }
+    }
+}
+
+fn csv_field(value: &ValueOwned, out: &mut String) {
+    match value {
+        ValueOwned::Null => {}
+        ValueOwned::Integer(i) => write!(out, "{i}").unwrap(),
+        ValueOwned::Real(r) => write!(out, "{r}").unwrap(),
+        ValueOwned::Text(s) => csv_text(s, out),
+        ValueOwned::Blob(b) => out.push_str(&hex::encode(b)),
+    }
+}
+
+fn csv_text(s: &str, out: &mut String) {
+    if s.is_empty() || s.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&s.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(s);
+    }
+}
+
+fn json_value(value: ValueOwned) -> serde_json::Value {
+    match value {
+        ValueOwned::Null => serde_json::Value::Null,
+        ValueOwned::Integer(i) => serde_json::Value::from(i),
+        ValueOwned::Real(r) => serde_json::Value::from(r),
+        ValueOwned::Text(s) => serde_json::Value::String(s),
+        ValueOwned::Blob(b) => serde_json::Value::String(hex::encode(b)),
+    }
+}
+
+/// The file name for the `table`. Everything except ASCII alphanumerics, `-` and `_` is
+/// percent-encoded to get a valid file name for any table.
+pub(crate) fn file_name(table: &str, format: ExportFormat) -> String {
+    let mut name = String::with_capacity(table.len() + 6);
+    for b in table.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            name.push(b as char);
+        } else {
+            write!(name, "%{b:02X}").unwrap();
+        }
+    }
+    format!("{name}.{}", format.extension())
+}
+
+/// The reverse of `file_name()`.
+pub(crate) fn table_from_path(path: &str, format: ExportFormat) -> Result<String, Error> {
+    let invalid = || Error::Config(format!("Invalid file name for an import: {path}").into());
+
+    let file = Path::new(path)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .ok_or_else(invalid)?;
+    let encoded = file
+        .strip_suffix(format.extension())
+        .and_then(|f| f.strip_suffix('.'))
+        .filter(|f| !f.is_empty())
+        .ok_or_else(invalid)?;
+
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut iter = encoded.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [
+                iter.next().ok_or_else(invalid)?,
+                iter.next().ok_or_else(invalid)?,
+            ];
+            let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+enum Sink {
+    Sql(BufWriter<fs::File>),
+    Tables(Vec<BufWriter<fs::File>>),
+}
+
+/// Writes the rows of an export into temp files, which are only moved into place once the
+/// whole export has finished.
+pub(crate) struct ExportWriter<'a> {
+    format: ExportFormat,
+    tables: &'a [ExportTable],
+    /// The `INSERT INTO ... VALUES ` prefix for each table.
+    inserts: Vec<String>,
+    rows: Vec<u64>,
+    files: Vec<String>,
+    /// Views can reference any table and are only included in a full export.
+    with_views: bool,
+    sink: Sink,
+    buf: String,
+}
+
+impl<'a> ExportWriter<'a> {
+    pub(crate) async fn new(
+        dir: &str,
+        format: ExportFormat,
+        schema: &[SchemaObject],
+        tables: &'a [ExportTable],
+        with_views: bool,
+    ) -> Result<Self, Error> {
+        fs::create_dir_all(dir).await?;
+        let dir = dir.trim_end_matches('/');
+
+        let mut slf = Self {
+            format,
+            tables,
+            inserts: Vec::new(),
+            rows: vec![0; tables.len()],
+            files: Vec::new(),
+            with_views,
+            sink: Sink::Tables(Vec::new()),
+            buf: String::with_capacity(256),
+        };
+
+        if format == ExportFormat::Sql {
+            let path = format!("{dir}/{SQL_DUMP_FILE}");
+            let mut writer = BufWriter::new(fs::File::create(format!("{path}~")).await?);
+            slf.files.push(path);
+
+            writer.write_all(b"-- Hiqlite logical export\n\n").await?;
+            for table in tables {
+                if let Some(sql) = schema
+                    .iter()
+                    .find(|o| o.typ == "table" && o.name == table.name)
+                    .and_then(|o| o.sql.as_deref())
+                {
+                    writer.write_all(if_not_exists(sql).as_bytes()).await?;
+                    writer.write_all(b";\n").await?;
+                }
+
+                let cols = table
+                    .columns
+                    .iter()
+                    .map(|c| quote_ident(c))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                slf.inserts.push(format!(
+                    "INSERT INTO {} ({cols}) VALUES (",
+                    quote_ident(&table.name)
+                ));
+            }
+            writer.write_all(b"\n").await?;
+
+            slf.sink = Sink::Sql(writer);
+        } else {
+            let mut writers = Vec::with_capacity(tables.len());
+            for table in tables {
+                let path = format!("{dir}/{}", file_name(&table.name, format));
+                let mut writer = BufWriter::new(fs::File::create(format!("{path}~")).await?);
+                slf.files.push(path);
+
+                if format == ExportFormat::Csv {
+                    let mut header = String::new();
+                    for (i, col) in table.columns.iter().enumerate() {
+                        if i > 0 {
+                            header.push(',');
+                        }
+                        csv_text(col, &mut header);
+                    }
+                    header.push('\n');
+                    writer.write_all(header.as_bytes()).await?;
+                }
+                writers.push(writer);
+            }
+            slf.sink = Sink::Tables(writers);
+        }
+
+        Ok(slf)
+    }
+
+    pub(crate) async fn write_row(
+        &mut self,
+        idx: usize,
+        values: impl Iterator<Item = ValueOwned>,
+    ) -> Result<(), Error> {
+        let Some(table) = self.tables.get(idx) else {
+            return Err(Error::Error(
+                format!("Unexpected table index {idx} during export").into(),
+            ));
+        };
+        let values = values.take(table.columns.len());
+        self.buf.clear();
+
+        let writer = match (&mut self.sink, self.format) {
+            (Sink::Sql(writer), _) => {
+                self.buf.push_str(&self.inserts[idx]);
+                for (i, value) in values.enumerate() {
+                    if i > 0 {
+                        self.buf.push_str(", ");
+                    }
+                    sql_literal(&value, &mut self.buf);
+                }
+                self.buf.push_str(");\n");
+                writer
+            }
+            (Sink::Tables(writers), ExportFormat::Csv) => {
+                for (i, value) in values.enumerate() {
+                    if i > 0 {
+                        self.buf.push(',');
+                    }
+                    csv_field(&value, &mut self.buf);
+                }
+                self.buf.push('\n');
+                &mut writers[idx]
+            }
+            (Sink::Tables(writers), _) => {
+                let obj = table
+                    .columns
+                    .iter()
+                    .cloned()
+                    .zip(values.map(json_value))
+                    .collect::<serde_json::Map<_, _>>();
+                self.buf.push_str(&serde_json::to_string(&obj)?);
+                self.buf.push('\n');
+                &mut writers[idx]
+            }
+        };
+
+        writer.write_all(self.buf.as_bytes()).await?;
+        self.rows[idx] += 1;
+        Ok(())
+    }
+
+    /// Flushes all files and moves them into place.
+    pub(crate) async fn finish(mut self, schema: &[SchemaObject]) -> Result<ExportSummary, Error> {
+        match &mut self.sink {
+            Sink::Sql(writer) => {
+                // indexes, triggers and views are created after the data, which is faster and
+                // does not fire triggers during the import
+                let names = self
+                    .tables
+                    .iter()
+                    .map(|t| t.name.as_str())
+                    .collect::<HashSet<_>>();
+                writer.write_all(b"\n").await?;
+                for obj in schema {
+                    let included = match obj.typ.as_str() {
+                        "index" | "trigger" => names.contains(obj.tbl_name.as_str()),
+                        "view" => self.with_views,
+                        _ => false,
+                    };
+                    if included && let Some(sql) = &obj.sql {
+                        writer.write_all(if_not_exists(sql).as_bytes()).await?;
+                        writer.write_all(b";\n").await?;
+                    }
+                }
+                writer.flush().await?;
+            }
+            Sink::Tables(writers) => {
+                for writer in writers {
+                    writer.flush().await?;
+                }
+            }
+        }
+
+        for path in &self.files {
+            fs::rename(format!("{path}~"), path).await?;
+        }
+
+        Ok(ExportSummary {
+            tables: self
+                .tables
+                .iter()
+                .map(|t| t.name.clone())
+                .zip(self.rows)
+                .collect(),
+            files: self.files,
+        })
+    }
+
+    /// Removes all temp files after a failed export.
+    pub(crate) async fn abort(self) {
+        drop(self.sink);
+        for path in &self.files {
+            let _ = fs::remove_file(format!("{path}~")).await;
+        }
+    }
+}
+
+/// Splits a row of the snapshot query into its table index and values.
+pub(crate) fn split_row(row: RowOwned) -> Result<(i64, impl Iterator<Item = ValueOwned>), Error> {
+    let mut values = row.columns.into_iter().map(|c| c.value);
+    match values.next() {
+        Some(ValueOwned::Integer(idx)) => Ok((idx, values)),
+        v => Err(Error::Error(
+            format!("Unexpected table index during export: {v:?}").into(),
+        )),
+    }
+}
+
+/// Makes sure the schema inside the read snapshot is the same the export has been built for.
+pub(crate) fn check_schema(
+    expected: &[SchemaObject],
+    snapshot: Vec<SchemaObject>,
+) -> Result<(), Error> {
+    let expected = expected.iter().collect::<BTreeSet<_>>();
+    let snapshot = snapshot.iter().collect::<BTreeSet<_>>();
+    if expected == snapshot {
+        Ok(())
+    } else {
+        Err(Error::Error(
+            "The schema has changed during the export - please try again".into(),
+        ))
+    }
+}
+
+/// Splits SQL text into single statements without their trailing `;`. The text can be pushed
+/// in pieces and statements may span multiple pieces.
+#[derive(Debug, Default)]
+pub(crate) struct SqlSplitter {
+    stmt: String,
+    /// The beginning of the statement without comments in uppercase, to find triggers.
+    head: String,
+    quote: Option<char>,
+    line_comment: bool,
+    block_comment: bool,
+    prev: Option<char>,
+}
+
+impl SqlSplitter {
+    pub(crate) fn push(&mut self, text: &str) -> Vec<String> {
+        let mut res = Vec::new();
+
+        for c in text.chars() {
+            let prev = self.prev.replace(c);
+
+            if self.line_comment {
+                self.stmt.push(c);
+                if c == '\n' {
+                    self.line_comment = false;
+                }
+                continue;
+            }
+            if self.block_comment {
+                self.stmt.push(c);
+                if prev == Some('*') && c == '/' {
+                    self.block_comment = false;
+                    // `*/` must not start another comment
+                    self.prev = None;
+                }
+                continue;
+            }
+            if let Some(quote) = self.quote {
+                self.stmt.push(c);
+                if c == quote {
+                    // a doubled quote simply closes and re-opens the quote
+                    self.quote = None;
+                }
+                continue;
+            }
+
+            match c {
+                '\'' | '"' | '`' => self.quote = Some(c),
+                '[' => self.quote = Some(']'),
+                '-' if prev == Some('-') => {
+                    self.line_comment = true;
+                    self.head.pop();
+                }
+                '*' if prev == Some('/') => {
+                    self.block_comment = true;
+                    self.head.pop();
+                }
+                ';' if !self.is_open_trigger() => {
+                    let stmt = std::mem::take(&mut self.stmt);
+                    if !self.head.trim().is_empty() {
+                        res.push(stmt.trim().to_string());
+                    }
+                    self.head.clear();
+                    self.prev = None;
+                    continue;
+                }
+                _ => {}
+            }
+
+            self.stmt.push(c);
+            if !self.line_comment && !self.block_comment && self.head.len() < 64 {
+                self.head.push(c.to_ascii_uppercase());
+            }
+        }
+
+        res
+    }
+
+    /// Returns the last statement, if it has no trailing `;`.
+    pub(crate) fn finish(self) -> Option<String> {
+        if self.head.trim().is_empty() {
+            None
+        } else {
+            Some(self.stmt.trim().to_string())
+        }
+    }
+
+    /// A trigger body contains `;`, and only an `END` before a `;` finishes the statement.
+    fn is_open_trigger(&self) -> bool {
+        let head = self.head.split_whitespace().take(3).collect::<Vec<_>>();
+        let is_trigger = head.first() == Some(&"CREATE")
+            && (head.get(1) == Some(&"TRIGGER")
+                || (matches!(head.get(1), Some(&"TEMP") | Some(&"TEMPORARY"))
+                    && head.get(2) == Some(&"TRIGGER")));
+        if !is_trigger {
+            return false;
+        }
+
+        let stmt = self.stmt.trim_end();
+        let ends_with_end = stmt.len() >= 3
+            && stmt.is_char_boundary(stmt.len() - 3)
+            && stmt[stmt.len() - 3..].eq_ignore_ascii_case("END")
+            && !stmt[..stmt.len() - 3]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_');
+        !ends_with_end
+    }
+}
+
+/// Parses CSV records line by line, where a quoted field can span multiple lines.
+#[derive(Debug, Default)]
+pub(crate) struct CsvParser {
+    fields: Vec<Option<String>>,
+    field: String,
+    quoted: bool,
+    in_quotes: bool,
+}
+
+impl CsvParser {
+    /// Returns the record, if the `line` completes one. An unquoted empty field is `None`.
+    pub(crate) fn push_line(&mut self, line: &str) -> Result<Option<Vec<Option<String>>>, Error> {
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if self.in_quotes {
+                if c == '"' {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        self.field.push('"');
+                    } else {
+                        self.in_quotes = false;
+                    }
+                } else {
+                    self.field.push(c);
+                }
+                continue;
+            }
+
+            match c {
+                ',' => self.end_field(),
+                '"' if self.field.is_empty() && !self.quoted => {
+                    self.quoted = true;
+                    self.in_quotes = true;
+                }
+                '"' => {
+                    return Err(Error::QueryParams(
+                        "Invalid CSV: unexpected quote inside a field".into(),
+                    ));
+                }
+                _ if self.quoted => {
+                    return Err(Error::QueryParams(
+                        "Invalid CSV: unexpected character after a quoted field".into(),
+                    ));
+                }
+                c => self.field.push(c),
+            }
+        }
+
+        if self.in_quotes {
+            self.field.push('\n');
+            return Ok(None);
+        }
+        self.end_field();
+        Ok(Some(std::mem::take(&mut self.fields)))
+    }
+
+    /// `true` if a quoted field is still open.
+    pub(crate) fn is_open(&self) -> bool {
+        self.in_quotes
+    }
+
+    fn end_field(&mut self) {
+        let field = std::mem::take(&mut self.field);
+        if field.is_empty() && !self.quoted {
+            self.fields.push(None);
+        } else {
+            self.fields.push(Some(field));
+        }
+        self.quoted = false;
+    }
+}
+
+pub(crate) fn insert_sql(table: &str, columns: &[String]) -> String {
+    let cols = columns
+        .iter()
+        .map(|c| quote_ident(c))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let params = (1..=columns.len())
+        .map(|i| format!("${i}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "INSERT INTO {} ({cols}) VALUES ({params})",
+        quote_ident(table)
+    )
+}
+
+pub(crate) fn param_from_json(value: serde_json::Value) -> Param {
+    match value {
+        serde_json::Value::Null => Param::Null,
+        serde_json::Value::Bool(b) => Param::Integer(b as i64),
+        serde_json::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Param::Integer(i)
+            } else {
+                Param::Real(n.as_f64().unwrap_or_default())
+            }
+        }
+        serde_json::Value::String(s) => Param::Text(s),
+        v => Param::Text(v.to_string()),
+    }
+}
+
+/// The approximate size of a statement inside a transaction.
+pub(crate) fn statement_size(sql: &str, params: &Params) -> usize {
+    sql.len()
+        + params
+            .iter()
+            .map(|p| match p {
+                Param::Text(s) => s.len(),
+                Param::Blob(b) => b.len(),
+                _ => 8,
+            })
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_query_reads_all_tables_at_once() -> Result<(), Error> {
+        let conn = rusqlite::Connection::open_in_memory()?;
+        conn.execute_batch(
+            r#"
+            CREATE TABLE _metadata (key TEXT NOT NULL PRIMARY KEY, data BLOB NOT NULL);
+            CREATE TABLE a (id INTEGER PRIMARY KEY, name TEXT, score REAL, data BLOB);
+            CREATE TABLE "odd ""name""" (id INTEGER PRIMARY KEY, "x y" TEXT);
+            CREATE INDEX a_name ON a (name);
+            INSERT INTO a VALUES (1, 'it''s', 1.0, X'00FF'), (2, NULL, NULL, NULL);
+            INSERT INTO "odd ""name""" VALUES (1, '42');
+            "#,
+        )?;
+
+        let schema = conn
+            .prepare(SCHEMA_QUERY)?
+            .query_map([], |row| {
+                Ok(SchemaObject {
+                    typ: row.get(0)?,
+                    name: row.get(1)?,
+                    tbl_name: row.get(2)?,
+                    sql: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let columns = conn
+            .prepare(COLUMNS_QUERY)?
+            .query_map([], |row| {
+                Ok(TableColumn {
+                    tbl: row.get(0)?,
+                    col: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tables = export_tables(&schema, columns, &[])?;
+        assert_eq!(
+            tables,
+            vec![
+                ExportTable {
+                    name: "a".to_string(),
+                    columns: vec!["id", "name", "score", "data"]
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                },
+                ExportTable {
+                    name: "odd \"name\"".to_string(),
+                    columns: vec!["id".to_string(), "x y".to_string()],
+                },
+            ]
+        );
+        assert!(export_tables(&schema, Vec::new(), &["_metadata"]).is_err());
+
+        let mut stmt = conn.prepare(&snapshot_query(&tables))?;
+        let rows = stmt
+            .query_map([], |row| {
+                (0..5)
+                    .map(|i| row.get::<_, rusqlite::types::Value>(i))
+                    .collect::<Result<Vec<_>, _>>()
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        use rusqlite::types::Value;
+        let data = rows
+            .into_iter()
+            .filter(|r| r[0] != Value::Integer(-1))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            data,
+            vec![
+                vec![
+                    Value::Integer(0),
+                    Value::Integer(1),
+                    Value::Text("it's".to_string()),
+                    Value::Real(1.0),
+                    Value::Blob(vec![0, 255]),
+                ],
+                vec![
+                    Value::Integer(0),
+                    Value::Integer(2),
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                ],
+                // the values keep their type, even though `id` is an INTEGER in the first table
+                vec![
+                    Value::Integer(1),
+                    Value::Integer(1),
+                    Value::Text("42".to_string()),
+                    Value::Null,
+                    Value::Null,
+                ],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn values_and_names() {
+        let mut out = String::new();
+        for v in [
+            ValueOwned::Null,
+            ValueOwned::Integer(-3),
+            ValueOwned::Real(1.0),
+            ValueOwned::Real(f64::INFINITY),
+            ValueOwned::Text("a'b".to_string()),
+            ValueOwned::Blob(vec![1, 171]),
+        ] {
+            sql_literal(&v, &mut out);
+            out.push('|');
+        }
+        assert_eq!(out, "NULL|-3|1.0|1e999|'a''b'|X'01ab'|");
+
+        let mut out = String::new();
+        for v in [
+            ValueOwned::Null,
+            ValueOwned::Text(String::new()),
+            ValueOwned::Text("a,\"b\"".to_string()),
+            ValueOwned::Text("plain".to_string()),
+        ] {
+            csv_field(&v, &mut out);
+            out.push(',');
+        }
+        assert_eq!(out, ",\"\",\"a,\"\"b\"\"\",plain,");
+
+        assert_eq!(
+            if_not_exists("CREATE TABLE t (id INT)"),
+            "CREATE TABLE IF NOT EXISTS t (id INT)"
+        );
+        assert_eq!(
+            if_not_exists("CREATE UNIQUE INDEX i ON t (id)"),
+            "CREATE UNIQUE INDEX IF NOT EXISTS i ON t (id)"
+        );
+
+        let name = file_name("my table/ä", ExportFormat::Csv);
+        assert_eq!(name, "my%20table%2F%C3%A4.csv");
+        assert_eq!(
+            table_from_path(&format!("/tmp/{name}"), ExportFormat::Csv).unwrap(),
+            "my table/ä"
+        );
+        assert!(table_from_path("/tmp/t.jsonl", ExportFormat::Csv).is_err());
+        assert!(table_from_path("/tmp/%4.csv", ExportFormat::Csv).is_err());
+
+        assert_eq!(
+            insert_sql("t", &["a".to_string(), "b c".to_string()]),
+            r#"INSERT INTO "t" ("a", "b c") VALUES ($1, $2)"#
+        );
+    }
+
+    #[test]
+    fn sql_splitter() {
+        let mut splitter = SqlSplitter::default();
+        let mut stmts = splitter.push(
+            "-- a comment; with a semicolon\nCREATE TABLE t (id INT, v TEXT);\n\
+            INSERT INTO t VALUES (1, 'a;b'), (2, 'it''s');\n/* block; */\n\
+            CREATE TRIGGER tr AFTER INSERT ON t BEGIN\n  DELETE FROM t WHERE id = 0;\n  \
+            UPDATE t SET v = 'end;' WHERE id = 1;\nEND;\nINSERT INTO \"x;\" VALUES (",
+        );
+        stmts.extend(splitter.push("3);\nSELECT 1"));
+        assert_eq!(
+            stmts,
+            vec![
+                "-- a comment; with a semicolon\nCREATE TABLE t (id INT, v TEXT)",
+                "INSERT INTO t VALUES (1, 'a;b'), (2, 'it''s')",
+                "/* block; */\nCREATE TRIGGER tr AFTER INSERT ON t BEGIN\n  DELETE FROM t WHERE \
+                id = 0;\n  UPDATE t SET v = 'end;' WHERE id = 1;\nEND",
+                "INSERT INTO \"x;\" VALUES (3)",
+            ]
+        );
+        assert_eq!(splitter.finish().as_deref(), Some("SELECT 1"));
+
+        let mut splitter = SqlSplitter::default();
+        assert!(splitter.push("-- only a comment\n;\n").is_empty());
+        assert_eq!(splitter.finish(), None);
+
+        // every statement must be valid on its own
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        for stmt in &stmts[..2] {
+            conn.execute(stmt, []).unwrap();
+        }
+        conn.execute(&stmts[2], []).unwrap();
+    }
+
+    #[test]
+    fn csv_parser() {
+        let mut parser = CsvParser::default();
+        assert_eq!(
+            parser.push_line("1,,\"\",plain,\"a,\"\"b\"\"\"").unwrap(),
+            Some(vec![
+                Some("1".to_string()),
+                None,
+                Some(String::new()),
+                Some("plain".to_string()),
+                Some("a,\"b\"".to_string()),
+            ])
+        );
+
+        assert_eq!(parser.push_line("2,\"multi").unwrap(), None);
+        assert!(parser.is_open());
+        assert_eq!(
+            parser.push_line("line\"").unwrap(),
+            Some(vec![Some("2".to_string()), Some("multi\nline".to_string())])
+        );
+
+        assert!(CsvParser::default().push_line("a\"b").is_err());
+        assert!(CsvParser::default().push_line("\"a\"b").is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use crate::store::state_machine::sqlite::deterministic::STAMPED_FNS;
+use rusqlite::functions::FunctionFlags;
+use std::fmt::{Debug, Formatter};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::Arc;
+
+pub use rusqlite::functions::{Aggregate, Context, SqlFnOutput};
+pub use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+pub use rusqlite::{Error, Result};
+
+type Installer =
+    Arc<dyn Fn(&rusqlite::Connection, FunctionFlags) -> rusqlite::Result<()> + Send + Sync>;
+
+/// Custom SQL functions, which will be installed on the writer connection and each connection
+/// of the read pool on every node.
+///
+/// Every node applies the Raft log on its own. If a function that is used inside a write
+/// returns different values on different nodes, the cluster state diverges. This is why each
+/// function must be registered with an explicit `deterministic` flag:
+///
+/// - `deterministic == true` functions are available for reads and writes. They must return the
+///   same output for the same input on every node, every time.
+/// - `deterministic == false` functions are only available on read connections. Using one of
+///   them in any write will return an error, which is the same on each node.
+///
+/// ```rust, notest
+/// use hiqlite::functions::SqlFunctions;
+///
+/// let sql_functions = SqlFunctions::default()
+///     .scalar("slugify", 1, true, |ctx| {
+///         let s = ctx.get::<String>(0)?;
+///         Ok(s.to_lowercase().replace(' ', "-"))
+///     })
+///     .scalar("node_hostname", 0, false, |_| Ok(hostname()));
+///
+/// let config = NodeConfig {
+///     sql_functions,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Default)]
+pub struct SqlFunctions {
+    fns: Vec<SqlFunction>,
+}
+
+#[derive(Clone)]
+struct SqlFunction {
+    name: String,
+    n_arg: i32,
+    deterministic: bool,
+    install: Installer,
+}
+
+impl Debug for SqlFunctions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(
+                self.fns
+                    .iter()
+                    .map(|func| (&func.name, func.n_arg, func.deterministic)),
+            )
+            .finish()
+    }
+}
+
+impl SqlFunctions {
+    /// Registers a scalar function. Use `n_arg == -1` for a variable amount of arguments.
+    pub fn scalar<N, F, T>(mut self, name: N, n_arg: i32, deterministic: bool, func: F) -> Self
+    where
+        N: Into<String>,
+        F: Fn(&Context<'_>) -> Result<T> + Send + Sync + 'static,
+        T: SqlFnOutput + 'static,
+    {
+        let name = name.into();
+        let func = Arc::new(func);
+        let fn_name = name.clone();
+
+        self.fns.push(SqlFunction {
+            name,
+            n_arg,
+            deterministic,
+            install: Arc::new(move |conn, flags| {
+                let func = func.clone();
+                conn.create_scalar_function(fn_name.as_str(), n_arg, flags, move |ctx| func(ctx))
+            }),
+        });
+        self
+    }
+
+    /// Registers an aggregate function. Use `n_arg == -1` for a variable amount of arguments.
+    pub fn aggregate<N, D, A, T>(
+        mut self,
+        name: N,
+        n_arg: i32,
+        deterministic: bool,
+        aggr: D,
+    ) -> Self
+    where
+        N: Into<String>,
+        D: Aggregate<A, T> + Send + Sync + 'static,
+        A: RefUnwindSafe + UnwindSafe + 'static,
+        T: SqlFnOutput + 'static,
+    {
+        let name = name.into();
+        let aggr = Arc::new(aggr);
+        let fn_name = name.clone();
+
+        self.fns.push(SqlFunction {
+            name,
+            n_arg,
+            deterministic,
+            install: Arc::new(move |conn, flags| {
+                conn.create_aggregate_function(
+                    fn_name.as_str(),
+                    n_arg,
+                    flags,
+                    SharedAggregate(aggr.clone()),
+                )
+            }),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fns.is_empty()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), crate::Error> {
+        for (i, func) in self.fns.iter().enumerate() {
+            if func.deterministic
+                && STAMPED_FNS
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&func.name))
+            {
+                return Err(crate::Error::Config(
+                    format!(
+                        "custom SQL function '{}' would overwrite a built-in function that is \
+                        stamped by the leader and cannot be registered as deterministic",
+                        func.name
+                    )
+                    .into(),
+                ));
+            }
+
+            if self.fns[..i].iter().any(|other| {
+                other.n_arg == func.n_arg && other.name.eq_ignore_ascii_case(&func.name)
+            }) {
+                return Err(crate::Error::Config(
+                    format!(
+                        "custom SQL function '{}' with {} args is registered more than once",
+                        func.name, func.n_arg
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Installs all functions on the given connection. Non-deterministic functions will be
+    /// replaced with an erroring guard on the writer connection.
+    pub(crate) fn install(&self, conn: &rusqlite::Connection, read_only: bool) -> Result<()> {
+        for func in &self.fns {
+            if func.deterministic {
+                (func.install)(
+                    conn,
+                    FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                )?;
+            } else if read_only {
+                (func.install)(conn, FunctionFlags::SQLITE_UTF8)?;
+            } else {
+                let name = func.name.clone();
+                conn.create_scalar_function(
+                    func.name.as_str(),
+                    func.n_arg,
+                    FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                    move |_| -> Result<String> {
+                        Err(Error::UserFunctionError(
+                            format!(
+                                "`{name}()` is non-deterministic and must never be used for \
+                                writing in a Raft cluster"
+                            )
+                            .into(),
+                        ))
+                    },
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `rusqlite` takes ownership of each aggregate, while we need to install the same one on
+/// multiple connections.
+struct SharedAggregate<D>(Arc<D>);
+
+impl<D, A, T> Aggregate<A, T> for SharedAggregate<D>
+where
+    D: Aggregate<A, T>,
+    A: RefUnwindSafe + UnwindSafe,
+    T: SqlFnOutput,
+{
+    fn init(&self, ctx: &mut Context<'_>) -> Result<A> {
+        self.0.init(ctx)
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut A) -> Result<()> {
+        self.0.step(ctx, acc)
+    }
+
+    fn finalize(&self, ctx: &mut Context<'_>, acc: Option<A>) -> Result<T> {
+        self.0.finalize(ctx, acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Product;
+
+    impl Aggregate<i64, i64> for Product {
+        fn init(&self, _: &mut Context<'_>) -> Result<i64> {
+            Ok(1)
+        }
+
+        fn step(&self, ctx: &mut Context<'_>, acc: &mut i64) -> Result<()> {
+            *acc *= ctx.get::<i64>(0)?;
+            Ok(())
+        }
+
+        fn finalize(&self, _: &mut Context<'_>, acc: Option<i64>) -> Result<i64> {
+            Ok(acc.unwrap_or(1))
+        }
+    }
+
+    fn functions() -> SqlFunctions {
+        SqlFunctions::default()
+            .scalar("double", 1, true, |ctx| Ok(ctx.get::<i64>(0)? * 2))
+            .scalar("node_local", 0, false, |_| Ok("local"))
+            .aggregate("product", 1, true, Product)
+    }
+
+    #[test]
+    fn custom_functions_installed_on_all_connections() {
+        let functions = functions();
+        functions.validate().unwrap();
+
+        for read_only in [true, false] {
+            let conn = rusqlite::Connection::open_in_memory().unwrap();
+            functions.install(&conn, read_only).unwrap();
+
+            let res: i64 = conn
+                .query_row("SELECT double(21)", (), |r| r.get(0))
+                .unwrap();
+            assert_eq!(res, 42);
+
+            let res: i64 = conn
+                .query_row(
+                    "SELECT product(x) FROM (SELECT 2 AS x UNION ALL SELECT 3 UNION ALL SELECT 7)",
+                    (),
+                    |r| r.get(0),
+                )
+                .unwrap();
+            assert_eq!(res, 42);
+
+            let res = conn.query_row("SELECT node_local()", (), |r| r.get::<_, String>(0));
+            if read_only {
+                assert_eq!(res.unwrap(), "local");
+            } else {
+                assert!(res.is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn custom_functions_validation() {
+        let res = SqlFunctions::default()
+            .scalar("RANDOM", 0, true, |_| Ok(4))
+            .validate();
+        assert!(res.is_err());
+
+        // overwriting a forbidden fn on read connections only is fine
+        let res = SqlFunctions::default()
+            .scalar("random", 0, false, |_| Ok(4))
+            .validate();
+        assert!(res.is_ok());
+
+        let res = functions()
+            .scalar("Double", 1, false, |ctx| ctx.get::<i64>(0))
+            .validate();
+        assert!(res.is_err());
+    }
+}
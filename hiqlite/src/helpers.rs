@@ -21,6 +21,40 @@ pub fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError>
     bincode::serde::decode_from_slice::<T, _>(bytes, bincode::config::legacy()).map(|(res, _)| res)
 }
 
+/// The capacity of event channels for change subscriptions and cache watches. A subscriber, which
+/// falls behind by more events, will be dropped with an error.
+#[cfg(any(feature = "sqlite", feature = "cache"))]
+pub(crate) const EVENT_CHANNEL_CAP: usize = 1024;
+
+/// Creates a bounded channel for subscriber events with one additional slot, which is kept free
+/// for the final error.
+#[cfg(any(feature = "sqlite", feature = "cache"))]
+#[allow(clippy::type_complexity)]
+pub(crate) fn event_channel<T>() -> (
+    flume::Sender<Result<T, Error>>,
+    flume::Receiver<Result<T, Error>>,
+) {
+    flume::bounded(EVENT_CHANNEL_CAP + 1)
+}
+
+/// Sends an event into a channel from `event_channel()` without ever blocking. Returns `false`, if
+/// the receiver has gone away or is lagging behind. In the latter case, it receives an error as
+/// its last message and the sender must be dropped.
+#[cfg(any(feature = "sqlite", feature = "cache"))]
+pub(crate) fn send_event<T>(
+    tx: &flume::Sender<Result<T, Error>>,
+    event: Result<T, Error>,
+    name: &str,
+) -> bool {
+    if tx.len() >= EVENT_CHANNEL_CAP {
+        let _ = tx.try_send(Err(Error::Error(
+            format!("{name} is lagging behind by more than {EVENT_CHANNEL_CAP} events").into(),
+        )));
+        return false;
+    }
+    tx.try_send(event).is_ok()
+}
+
 pub async fn is_raft_initialized(
     state: &Arc<AppState>,
     raft_type: &RaftType,
@@ -12,6 +12,10 @@ use tracing::{debug, error, warn};
 
 #[cfg(feature = "sqlite")]
 use crate::store::state_machine::sqlite::TypeConfigSqlite;
+#[cfg(feature = "sqlite")]
+use crate::store::state_machine::sqlite::state_machine::QueryWrite;
+#[cfg(feature = "sqlite")]
+use std::time::Instant;
 
 #[cfg(feature = "cache")]
 use crate::store::state_machine::memory::TypeConfigKV;
@@ -69,6 +73,10 @@ pub async fn check_execute_reset(base_path: &str) -> Result<bool, Error> {
 }
 
 /// Initializes a fresh node 1, if it has not been set up yet.
+///
+/// If the database has been `bootstrapped` from an existing SQLite file, its content is not
+/// part of any Raft log. It will be installed as the initial snapshot in this case, so that
+/// all other nodes will catch up via snapshot install.
 #[cfg(feature = "sqlite")]
 pub async fn init_pristine_node_1_db(
     raft: &openraft::Raft<TypeConfigSqlite>,
@@ -77,6 +85,7 @@ pub async fn init_pristine_node_1_db(
     secret_api: &str,
     tls: bool,
     tls_no_verify: bool,
+    bootstrapped: bool,
 ) -> Result<(), Error> {
     if node_id == 1 {
         let this_node = get_this_node(node_id, nodes);
@@ -88,6 +97,11 @@ pub async fn init_pristine_node_1_db(
 
         if should_node_1_skip_init(&RaftType::Sqlite, nodes, secret_api, tls, tls_no_verify).await?
         {
+            if bootstrapped {
+                return Err(Error::Config(
+                    "Found an existing cluster on remotes - cannot bootstrap the database".into(),
+                ));
+            }
             info!("node 1 (DB) should skip its own init - found existing cluster on remotes");
             return Ok(());
         }
@@ -96,11 +110,98 @@ pub async fn init_pristine_node_1_db(
         let mut nodes_set = BTreeMap::new();
         nodes_set.insert(this_node.id, this_node);
         raft.initialize(nodes_set).await?;
+
+        if bootstrapped {
+            info!("Installing the bootstrapped database as the initial snapshot");
+            build_initial_snapshot(raft, node_id).await;
+        }
     }
 
     Ok(())
 }
 
+/// Builds a snapshot from the current database and purges all logs it contains. Afterward,
+/// each joining node will receive the whole database via snapshot install.
+///
+/// Must only be called on a single-member Raft before any other node can join.
+#[tracing::instrument(level = "debug", skip_all)]
+#[cfg(feature = "sqlite")]
+pub(crate) async fn build_initial_snapshot(
+    raft: &openraft::Raft<TypeConfigSqlite>,
+    this_node: NodeId,
+) {
+    loop {
+        match raft.is_initialized().await {
+            Ok(res) => {
+                if res {
+                    break;
+                }
+            }
+            Err(err) => {
+                error!("{}", err);
+            }
+        }
+        debug!("Waiting for Raft init");
+        time::sleep(Duration::from_millis(50)).await;
+    }
+
+    while raft.current_leader().await.is_none() {
+        time::sleep(Duration::from_millis(50)).await;
+    }
+
+    debug_assert!(
+        raft.current_leader().await == Some(this_node),
+        "It should never happen that node 1 is not the raft leader for the initial snapshot"
+    );
+
+    // `max_in_snapshot_log_to_keep` logs will survive the purge, and they must not contain
+    // the very first one, or joining nodes would replicate logs instead of the snapshot.
+    let reqs = 10;
+    for _ in 0..reqs {
+        let start = Instant::now();
+        match raft.client_write(QueryWrite::RTT).await {
+            Ok(_) => {
+                info!("Raft RTT: {} micros", start.elapsed().as_micros());
+            }
+            Err(err) => {
+                error!("Raft RTT request error: {}", err);
+            }
+        }
+    }
+
+    let last_log;
+    loop {
+        let metrics = raft.metrics().borrow().clone();
+        if let Some(last_applied) = metrics.last_applied
+            && last_applied.index >= reqs
+        {
+            debug!("Found high enough last_applied log id");
+            last_log = last_applied.index;
+            break;
+        }
+        time::sleep(Duration::from_millis(50)).await;
+    }
+
+    debug!("Taking snapshot now");
+    if let Err(err) = raft.trigger().snapshot().await {
+        // e.g. the raft is shutting down: do not panic the task, just bail out - the
+        // wait-for-snapshot loop below would never finish anyway
+        error!("Error triggering snapshot: {err}");
+        return;
+    }
+
+    // wait until snapshot has been built
+    while raft.metrics().borrow().snapshot.is_none() {
+        info!("Waiting for snapshot build to finish");
+        time::sleep(Duration::from_millis(100)).await;
+    }
+
+    debug!("Purging logs");
+    while let Err(err) = raft.trigger().purge_log(last_log).await {
+        error!("Error during logs purge: {}", err);
+    }
+}
+
 // TODO this duplication is not pretty but getting the types correct is pretty hard
 /// Initializes a fresh node 1, if it has not been set up yet.
 #[cfg(feature = "cache")]
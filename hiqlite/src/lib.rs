@@ -30,16 +30,39 @@ pub use config::{NodeConfig, RaftConfig, RateLimitConfig};
 pub use query::cust_types::VecText;
 
 #[cfg(feature = "sqlite")]
-pub use crate::query::rows::Row;
+pub use crate::query::{
+    consistency::{ReadConsistency, Staleness},
+    rows::Row,
+};
 #[cfg(feature = "sqlite")]
 pub use crate::store::state_machine::sqlite::{
+    changes::{ChangeEvent, ChangeOp, RowChange},
+    idempotency::IdempotencyKey,
     param::Param,
     state_machine::Params,
     transaction_variable::{StmtColumn, StmtIndex},
 };
+#[cfg(feature = "backup")]
+pub use crate::{
+    backup::{BackupSource, RestoreTarget},
+    backup_progress::{BackupMetrics, BackupProgress, BackupRun, BackupState, ProgressKind},
+    backup_target::{BackupObject, BackupTarget, LocalTarget},
+    restore::{RestoreProgress, RestoreState},
+};
+#[cfg(feature = "cache")]
+pub use client::{cache_batch::CacheBatch, cache_scan::CachePage};
+#[cfg(feature = "cache")]
+pub use crate::store::state_machine::memory::{
+    eviction::{CacheLimit, CacheLimits, CacheStats, EvictionPolicy},
+    watch::{WatchEvent, WatchKey},
+};
 #[cfg(feature = "dlock")]
 pub use client::dlock::Lock;
 #[cfg(feature = "sqlite")]
+pub use bootstrap::BootstrapDb;
+#[cfg(feature = "sqlite")]
+pub use export::{ExportFormat, ExportSummary, ImportSummary};
+#[cfg(feature = "sqlite")]
 pub use migration::AppliedMigration;
 
 #[cfg(any(feature = "sqlite", feature = "cache"))]
@@ -63,10 +86,24 @@ mod start;
 #[cfg(any(feature = "sqlite", feature = "cache"))]
 mod store;
 
+#[cfg(feature = "backup")]
+mod archive;
 #[cfg(feature = "backup")]
 mod backup;
+#[cfg(feature = "backup")]
+mod backup_diff;
+#[cfg(feature = "backup")]
+mod backup_progress;
+#[cfg(feature = "backup")]
+mod backup_target;
+#[cfg(feature = "sqlite")]
+mod bootstrap;
 #[cfg(feature = "dashboard")]
 mod dashboard;
+#[cfg(feature = "backup")]
+mod restore;
+#[cfg(feature = "sqlite")]
+mod export;
 #[cfg(feature = "sqlite")]
 mod migration;
 #[cfg(feature = "sqlite")]
@@ -74,6 +111,10 @@ mod query;
 #[cfg(any(feature = "sqlite", feature = "cache"))]
 mod split_brain_check;
 
+/// Custom SQL functions, which can be registered via the `NodeConfig`.
+#[cfg(feature = "sqlite")]
+pub mod functions;
+
 #[cfg(feature = "macros")]
 pub mod macros;
 
@@ -94,6 +135,11 @@ pub mod tls;
 
 type NodeId = u64;
 
+/// The Raft log id a write has been applied with. It can be used with the `*_after()` queries
+/// to make sure the local replica has caught up with your own writes before reading.
+#[cfg(feature = "sqlite")]
+pub type LogId = openraft::LogId<NodeId>;
+
 pub trait CacheVariants {
     /// Returns the Enum Variants index, strictly matching the output of `hiqlite_cache_variants()`.
     fn hiqlite_cache_index(&self) -> usize;
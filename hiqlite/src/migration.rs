@@ -1,5 +1,7 @@
+use crate::Error;
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 
 pub struct Migrations;
@@ -63,6 +65,62 @@ impl Migrations {
 
         res
     }
+
+    /// Works in the same way as `build()`, but reads the migrations from a directory with
+    /// `<id>_<name>.sql` files at runtime.
+    pub fn build_from_dir(dir: &str) -> Result<Vec<Migration>, Error> {
+        let mut res = Vec::new();
+
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                return Err(Error::Config(
+                    format!("Invalid migration file name: {file_name:?}").into(),
+                ));
+            };
+            let Some(stripped) = file_name.strip_suffix(".sql") else {
+                continue;
+            };
+            let Some((id, name)) = stripped
+                .split_once('_')
+                .and_then(|(id, name)| id.parse::<u32>().ok().map(|id| (id, name)))
+            else {
+                return Err(Error::Config(
+                    format!(
+                        "Migration file names must start with `<integer>_<migration_name>`: \
+                        {file_name}"
+                    )
+                    .into(),
+                ));
+            };
+
+            let content = std::fs::read(entry.path())?;
+            res.push(Migration {
+                id,
+                name: name.to_string(),
+                hash: hex::encode(Sha256::digest(&content)),
+                content,
+            });
+        }
+
+        res.sort_by_key(|m| m.id);
+        for (i, migration) in res.iter().enumerate() {
+            if migration.id != i as u32 + 1 {
+                return Err(Error::Config(
+                    format!(
+                        "Migrations in {dir} must start at index 1 without gaps, found {} at \
+                        position {}",
+                        migration.id,
+                        i + 1
+                    )
+                    .into(),
+                ));
+            }
+        }
+
+        Ok(res)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
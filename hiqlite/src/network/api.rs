@@ -21,6 +21,7 @@ use tracing::{debug, error, info, warn};
 use crate::store::state_machine::memory::{
     kv_handler::CacheRequestHandler,
     state_machine::{CacheRequest, CacheResponse},
+    watch::{self, WatchEvent, WatchKey},
 };
 
 #[cfg(feature = "dlock")]
@@ -30,10 +31,20 @@ use crate::store::state_machine::memory::dlock_handler::{
 
 #[cfg(feature = "sqlite")]
 use crate::{
+    LogId,
     migration::Migration,
-    query::{query_consistent_local, query_owned_local, rows::RowOwned},
-    store::state_machine::sqlite::state_machine::{Query, QueryWrite},
+    query::{
+        QueryStreamChunk, query_consistent_local, query_owned_local, query_stream_local,
+        rows::RowOwned, timeout,
+    },
+    store::state_machine::sqlite::{
+        changes::{self, ChangeEvent},
+        idempotency::IdempotencyKey,
+        state_machine::{Query, QueryMany, QueryWrite},
+    },
 };
+#[cfg(any(feature = "sqlite", feature = "cache"))]
+use std::collections::HashMap;
 
 #[cfg(feature = "listen_notify")]
 use crate::store::state_machine::memory::notify_handler::NotifyRequest;
@@ -309,6 +320,69 @@ mod tests {
         assert!(ensure_ready_member(3, false, ServerState::Shutdown, &membership, "test").is_err());
     }
 
+    /// The write responses in the format of clients, which do not know about the `LogId` yet.
+    #[cfg(feature = "sqlite")]
+    #[derive(Debug, serde::Deserialize)]
+    #[allow(dead_code)]
+    enum ResponsePayloadWithoutLogId {
+        Execute(Result<usize, crate::Error>),
+        ExecuteReturning(
+            Result<Vec<Result<crate::query::rows::RowOwned, crate::Error>>, crate::Error>,
+        ),
+        Transaction(Result<Vec<Result<usize, crate::Error>>, crate::Error>),
+        Query(Result<Vec<crate::query::rows::RowOwned>, crate::Error>),
+        QueryConsistent(Result<Vec<crate::query::rows::RowOwned>, crate::Error>),
+        Batch(Result<Vec<Result<usize, crate::Error>>, crate::Error>),
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn write_responses_keep_their_format_for_older_clients() {
+        use super::ApiStreamResponsePayload;
+        use crate::helpers::deserialize;
+        use crate::network::serialize_network;
+        use openraft::{CommittedLeaderId, LogId};
+
+        let log_id = Some(LogId::new(CommittedLeaderId::new(1, 1), 13));
+
+        let bytes = serialize_network(&ApiStreamResponsePayload::execute(Ok((3, log_id)), false));
+        let res = deserialize::<ResponsePayloadWithoutLogId>(&bytes).unwrap();
+        assert!(
+            matches!(res, ResponsePayloadWithoutLogId::Execute(Ok(3))),
+            "{res:?}"
+        );
+
+        let bytes = serialize_network(&ApiStreamResponsePayload::transaction(
+            Ok((vec![Ok(1), Ok(2)], log_id)),
+            false,
+        ));
+        let res = deserialize::<ResponsePayloadWithoutLogId>(&bytes).unwrap();
+        match res {
+            ResponsePayloadWithoutLogId::Transaction(Ok(res)) => assert_eq!(res.len(), 2),
+            res => panic!("unexpected response: {res:?}"),
+        }
+
+        let bytes = serialize_network(&ApiStreamResponsePayload::batch(
+            Ok((vec![Ok(1)], log_id)),
+            false,
+        ));
+        let res = deserialize::<ResponsePayloadWithoutLogId>(&bytes).unwrap();
+        assert!(
+            matches!(res, ResponsePayloadWithoutLogId::Batch(Ok(_))),
+            "{res:?}"
+        );
+
+        // the `LogId` is only included when it has been asked for
+        match ApiStreamResponsePayload::execute(Ok((3, log_id)), true) {
+            ApiStreamResponsePayload::ExecuteLogId(Ok((3, id))) => assert_eq!(Some(id), log_id),
+            res => panic!("unexpected response: {res:?}"),
+        }
+        assert!(matches!(
+            ApiStreamResponsePayload::execute(Ok((3, None)), true),
+            ApiStreamResponsePayload::ExecuteLogId(Err(_))
+        ));
+    }
+
     fn membership_with_voters_and_learners<const VOTERS: usize, const MEMBERS: usize>(
         voters: [u64; VOTERS],
         members: [u64; MEMBERS],
@@ -434,6 +508,64 @@ pub(crate) enum ApiStreamRequestPayload {
     LockAwait(CacheRequest),
     #[cfg(feature = "listen_notify_local")]
     Notify(CacheRequest),
+    #[cfg(feature = "sqlite")]
+    QueryStream(Query),
+    #[cfg(feature = "sqlite")]
+    QueryStreamNext,
+    #[cfg(feature = "sqlite")]
+    QueryStreamCancel,
+    #[cfg(feature = "sqlite")]
+    ExecuteIdempotent((IdempotencyKey, Query)),
+    #[cfg(feature = "sqlite")]
+    TransactionIdempotent((IdempotencyKey, Vec<Query>)),
+    #[cfg(feature = "sqlite")]
+    ExecuteMany(QueryMany),
+    /// `(tables, resume from log index)`
+    #[cfg(feature = "sqlite")]
+    SubscribeChanges((Vec<String>, Option<u64>)),
+    #[cfg(feature = "sqlite")]
+    SubscribeChangesCancel,
+    /// A query with the remaining millis until its deadline
+    #[cfg(feature = "sqlite")]
+    QueryTimeout((Query, u64)),
+    /// A consistent query with the remaining millis until its deadline
+    #[cfg(feature = "sqlite")]
+    QueryConsistentTimeout((Query, u64)),
+    /// A streaming query with the millis reading each chunk may take
+    #[cfg(feature = "sqlite")]
+    QueryStreamTimeout((Query, u64)),
+    /// `(cache_idx, watched keys, resume from log index)`
+    #[cfg(feature = "cache")]
+    Watch((usize, WatchKey, Option<u64>)),
+    #[cfg(feature = "cache")]
+    WatchCancel,
+    // Work like the requests without the suffix, but ask for the `LogId` of the write in the
+    // response, which older servers do not know about.
+    #[cfg(feature = "sqlite")]
+    ExecuteLogId(Query),
+    #[cfg(feature = "sqlite")]
+    ExecuteReturningLogId(Query),
+    #[cfg(feature = "sqlite")]
+    TransactionLogId(Vec<Query>),
+    #[cfg(feature = "sqlite")]
+    BatchLogId(std::borrow::Cow<'static, str>),
+}
+
+#[cfg(feature = "sqlite")]
+impl ApiStreamRequestPayload {
+    /// If the response must contain the `LogId` of the write. Idempotent writes are newer than
+    /// the `LogId` in responses and always contain it.
+    pub(crate) fn wants_log_id(&self) -> bool {
+        matches!(
+            self,
+            Self::ExecuteLogId(_)
+                | Self::ExecuteReturningLogId(_)
+                | Self::TransactionLogId(_)
+                | Self::BatchLogId(_)
+                | Self::ExecuteIdempotent(_)
+                | Self::TransactionIdempotent(_)
+        )
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -470,6 +602,88 @@ pub(crate) enum ApiStreamResponsePayload {
 
     #[cfg(feature = "listen_notify_local")]
     Notify(Result<(), Error>),
+
+    /// `Ok(None)` marks the end of the stream
+    #[cfg(feature = "sqlite")]
+    QueryStream(QueryStreamChunk),
+    #[cfg(feature = "sqlite")]
+    ExecuteMany(Result<(Vec<usize>, LogId), Error>),
+    #[cfg(feature = "sqlite")]
+    SubscribeChanges(Result<(), Error>),
+    /// An `Err(_)` ends the subscription
+    #[cfg(feature = "sqlite")]
+    Changes(Result<ChangeEvent, Error>),
+    #[cfg(feature = "cache")]
+    Watch(Result<(), Error>),
+    /// An `Err(_)` ends the watch
+    #[cfg(feature = "cache")]
+    WatchEvents(Result<WatchEvent, Error>),
+    // The `*LogId` variants answer writes with the `LogId` they have been applied with. They are
+    // only sent for the matching requests, so that older clients keep receiving the formats above.
+    #[cfg(feature = "sqlite")]
+    ExecuteLogId(Result<(usize, LogId), Error>),
+    #[cfg(feature = "sqlite")]
+    ExecuteReturningLogId(Result<(Vec<Result<RowOwned, Error>>, LogId), Error>),
+    #[cfg(feature = "sqlite")]
+    TransactionLogId(Result<(Vec<Result<usize, Error>>, LogId), Error>),
+    #[cfg(feature = "sqlite")]
+    BatchLogId(Result<(Vec<Result<usize, Error>>, LogId), Error>),
+}
+
+/// Returns the `LogId` of a write, which must be included in the response when it was asked for.
+#[cfg(feature = "sqlite")]
+pub(crate) fn require_log_id<T>(
+    res: Result<(T, Option<LogId>), Error>,
+) -> Result<(T, LogId), Error> {
+    let (res, log_id) = res?;
+    let log_id = log_id.ok_or_else(|| Error::Error("Write response without a LogId".into()))?;
+    Ok((res, log_id))
+}
+
+/// The responses for writes, either with or without the `LogId`, depending on the request.
+#[cfg(feature = "sqlite")]
+#[allow(clippy::type_complexity)]
+impl ApiStreamResponsePayload {
+    pub(crate) fn execute(res: Result<(usize, Option<LogId>), Error>, with_log_id: bool) -> Self {
+        if with_log_id {
+            Self::ExecuteLogId(require_log_id(res))
+        } else {
+            Self::Execute(res.map(|(res, _)| res))
+        }
+    }
+
+    pub(crate) fn execute_returning(
+        res: Result<(Vec<Result<RowOwned, Error>>, Option<LogId>), Error>,
+        with_log_id: bool,
+    ) -> Self {
+        if with_log_id {
+            Self::ExecuteReturningLogId(require_log_id(res))
+        } else {
+            Self::ExecuteReturning(res.map(|(res, _)| res))
+        }
+    }
+
+    pub(crate) fn transaction(
+        res: Result<(Vec<Result<usize, Error>>, Option<LogId>), Error>,
+        with_log_id: bool,
+    ) -> Self {
+        if with_log_id {
+            Self::TransactionLogId(require_log_id(res))
+        } else {
+            Self::Transaction(res.map(|(res, _)| res))
+        }
+    }
+
+    pub(crate) fn batch(
+        res: Result<(Vec<Result<usize, Error>>, Option<LogId>), Error>,
+        with_log_id: bool,
+    ) -> Self {
+        if with_log_id {
+            Self::BatchLogId(require_log_id(res))
+        } else {
+            Self::Batch(res.map(|(res, _)| res))
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -501,6 +715,16 @@ async fn handle_socket_concurrent(
     // IMPORTANT: the reader is NOT CANCEL SAFE in v0.8!
     let mut read = FragmentCollectorRead::new(rx);
 
+    // open query streams for this connection, waiting for the client to request the next chunk
+    #[cfg(feature = "sqlite")]
+    let mut query_streams: HashMap<usize, flume::Sender<()>> = HashMap::new();
+    // open change subscriptions for this connection, dropping the sender cancels them
+    #[cfg(feature = "sqlite")]
+    let mut change_subscriptions: HashMap<usize, flume::Sender<()>> = HashMap::new();
+    // open cache watches for this connection, dropping the sender cancels them
+    #[cfg(feature = "cache")]
+    let mut watches: HashMap<usize, flume::Sender<()>> = HashMap::new();
+
     let handle_write = task::spawn(async move {
         while let Ok(req) = rx_write.recv_async().await {
             match req {
@@ -562,88 +786,295 @@ async fn handle_socket_concurrent(
             }
         };
 
+        #[cfg(feature = "sqlite")]
+        match req.payload {
+            ApiStreamRequestPayload::QueryStream(_)
+            | ApiStreamRequestPayload::QueryStreamTimeout(_) => {
+                let (Query { sql, params }, chunk_timeout) = match req.payload {
+                    ApiStreamRequestPayload::QueryStream(query) => (query, None),
+                    ApiStreamRequestPayload::QueryStreamTimeout((query, ms)) => {
+                        (query, Some(Duration::from_millis(ms)))
+                    }
+                    _ => unreachable!(),
+                };
+                query_streams.retain(|_, tx| !tx.is_disconnected());
+
+                let (tx_next, rx_next) = flume::bounded(1);
+                query_streams.insert(req.request_id, tx_next);
+
+                let tx_write = tx_write.clone();
+                let res = query_stream_local(
+                    state.raft_db.log_statements,
+                    state.raft_db.read_pool.clone(),
+                    sql,
+                    params,
+                    chunk_timeout,
+                );
+                task::spawn(async move {
+                    match res.await {
+                        Ok(rx) => stream_query_chunks(req.request_id, rx, rx_next, tx_write).await,
+                        Err(err) => {
+                            let resp = ApiStreamResponse {
+                                request_id: req.request_id,
+                                result: ApiStreamResponsePayload::QueryStream(Err(err)),
+                            };
+                            let _ = tx_write.send_async(WsWriteMsg::Payload(resp)).await;
+                        }
+                    }
+                });
+                continue;
+            }
+            ApiStreamRequestPayload::QueryStreamNext => {
+                if let Some(tx) = query_streams.get(&req.request_id) {
+                    // there can only ever be one open request for the next chunk
+                    let _ = tx.try_send(());
+                }
+                continue;
+            }
+            ApiStreamRequestPayload::QueryStreamCancel => {
+                // dropping the sender will end the stream task
+                query_streams.remove(&req.request_id);
+                continue;
+            }
+            ApiStreamRequestPayload::SubscribeChanges((tables, resume_from)) => {
+                change_subscriptions.retain(|_, tx| !tx.is_disconnected());
+
+                let (res, rx_events) = match changes::subscribe(
+                    &state.raft_db.tx_changes,
+                    tables,
+                    resume_from,
+                )
+                .await
+                {
+                    Ok(rx) => (Ok(()), Some(rx)),
+                    Err(err) => (Err(err), None),
+                };
+                let ack = ApiStreamResponse {
+                    request_id: req.request_id,
+                    result: ApiStreamResponsePayload::SubscribeChanges(res),
+                };
+                if tx_write.send_async(WsWriteMsg::Payload(ack)).await.is_err() {
+                    break;
+                }
+                let Some(rx_events) = rx_events else {
+                    continue;
+                };
+
+                let (tx_cancel, rx_cancel) = flume::bounded(1);
+                change_subscriptions.insert(req.request_id, tx_cancel);
+                task::spawn(stream_change_events(
+                    req.request_id,
+                    rx_events,
+                    rx_cancel,
+                    tx_write.clone(),
+                ));
+                continue;
+            }
+            ApiStreamRequestPayload::SubscribeChangesCancel => {
+                change_subscriptions.remove(&req.request_id);
+                continue;
+            }
+            _ => {}
+        }
+
+        #[cfg(feature = "cache")]
+        match req.payload {
+            ApiStreamRequestPayload::Watch((cache_idx, key, resume_from)) => {
+                watches.retain(|_, tx| !tx.is_disconnected());
+
+                let tx_cache = state.raft_cache.tx_caches.get(cache_idx).unwrap();
+                let (res, rx_events) = match watch::subscribe(tx_cache, key, resume_from).await {
+                    Ok(rx) => (Ok(()), Some(rx)),
+                    Err(err) => (Err(err), None),
+                };
+                let ack = ApiStreamResponse {
+                    request_id: req.request_id,
+                    result: ApiStreamResponsePayload::Watch(res),
+                };
+                if tx_write.send_async(WsWriteMsg::Payload(ack)).await.is_err() {
+                    break;
+                }
+                let Some(rx_events) = rx_events else {
+                    continue;
+                };
+
+                let (tx_cancel, rx_cancel) = flume::bounded(1);
+                watches.insert(req.request_id, tx_cancel);
+                task::spawn(stream_watch_events(
+                    req.request_id,
+                    rx_events,
+                    rx_cancel,
+                    tx_write.clone(),
+                ));
+                continue;
+            }
+            ApiStreamRequestPayload::WatchCancel => {
+                watches.remove(&req.request_id);
+                continue;
+            }
+            _ => {}
+        }
+
         let state = state.clone();
         let tx_write = tx_write.clone();
         task::spawn(async move {
             let request_id = req.request_id;
+            #[cfg(feature = "sqlite")]
+            let with_log_id = req.payload.wants_log_id();
 
             let res = match req.payload {
                 #[cfg(feature = "sqlite")]
-                ApiStreamRequestPayload::Execute(sql) => {
-                    match state
+                ApiStreamRequestPayload::Execute(sql)
+                | ApiStreamRequestPayload::ExecuteLogId(sql) => {
+                    let res = match state
                         .raft_db
-                        .raft
-                        .client_write(QueryWrite::Execute(sql))
+                        .client_write(QueryWrite::Execute(sql).stamped())
                         .await
                     {
                         Ok(resp) => {
-                            let resp: crate::Response = resp.data;
-                            let res = match resp {
-                                crate::Response::Execute(res) => res.result,
+                            let log_id = resp.log_id;
+                            match resp.data {
+                                crate::Response::Execute(res) => {
+                                    res.result.map(|res| (res, Some(log_id)))
+                                }
                                 _ => unreachable!(),
-                            };
-                            ApiStreamResponse {
-                                request_id,
-                                result: ApiStreamResponsePayload::Execute(res),
                             }
                         }
-                        Err(err) => ApiStreamResponse {
-                            request_id,
-                            result: ApiStreamResponsePayload::Execute(Err(Error::from(err))),
-                        },
+                        Err(err) => Err(err),
+                    };
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::execute(res, with_log_id),
                     }
                 }
 
                 #[cfg(feature = "sqlite")]
-                ApiStreamRequestPayload::ExecuteReturning(sql) => {
-                    match state
+                ApiStreamRequestPayload::ExecuteReturning(sql)
+                | ApiStreamRequestPayload::ExecuteReturningLogId(sql) => {
+                    let res = match state
                         .raft_db
-                        .raft
-                        .client_write(QueryWrite::ExecuteReturning(sql))
+                        .client_write(QueryWrite::ExecuteReturning(sql).stamped())
                         .await
                     {
                         Ok(resp) => {
-                            let resp: crate::Response = resp.data;
-                            let res = match resp {
-                                crate::Response::ExecuteReturning(res) => res.result,
+                            let log_id = resp.log_id;
+                            match resp.data {
+                                crate::Response::ExecuteReturning(res) => {
+                                    res.result.map(|res| (res, Some(log_id)))
+                                }
                                 _ => unreachable!(),
-                            };
-                            ApiStreamResponse {
-                                request_id,
-                                result: ApiStreamResponsePayload::ExecuteReturning(res),
                             }
                         }
-                        Err(err) => ApiStreamResponse {
-                            request_id,
-                            result: ApiStreamResponsePayload::ExecuteReturning(Err(Error::from(
-                                err,
-                            ))),
-                        },
+                        Err(err) => Err(err),
+                    };
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::execute_returning(res, with_log_id),
                     }
                 }
 
                 #[cfg(feature = "sqlite")]
-                ApiStreamRequestPayload::Transaction(queries) => {
+                ApiStreamRequestPayload::Transaction(queries)
+                | ApiStreamRequestPayload::TransactionLogId(queries) => {
+                    let res = match state
+                        .raft_db
+                        .client_write(QueryWrite::Transaction(queries).stamped())
+                        .await
+                    {
+                        Ok(resp) => {
+                            let log_id = resp.log_id;
+                            match resp.data {
+                                crate::Response::Transaction(res) => {
+                                    res.map(|res| (res, Some(log_id)))
+                                }
+                                _ => unreachable!(),
+                            }
+                        }
+                        Err(err) => Err(err),
+                    };
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::transaction(res, with_log_id),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::ExecuteIdempotent((key, sql)) => {
+                    let res = match state
+                        .raft_db
+                        .client_write(
+                            QueryWrite::Execute(sql)
+                                .with_idempotency_key(Some(key))
+                                .stamped(),
+                        )
+                        .await
+                    {
+                        Ok(resp) => {
+                            let log_id = resp.log_id;
+                            match resp.data {
+                                crate::Response::Execute(res) => {
+                                    res.result.map(|res| (res, Some(log_id)))
+                                }
+                                _ => unreachable!(),
+                            }
+                        }
+                        Err(err) => Err(err),
+                    };
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::execute(res, with_log_id),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::TransactionIdempotent((key, queries)) => {
+                    let res = match state
+                        .raft_db
+                        .client_write(
+                            QueryWrite::Transaction(queries)
+                                .with_idempotency_key(Some(key))
+                                .stamped(),
+                        )
+                        .await
+                    {
+                        Ok(resp) => {
+                            let log_id = resp.log_id;
+                            match resp.data {
+                                crate::Response::Transaction(res) => {
+                                    res.map(|res| (res, Some(log_id)))
+                                }
+                                _ => unreachable!(),
+                            }
+                        }
+                        Err(err) => Err(err),
+                    };
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::transaction(res, with_log_id),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::ExecuteMany(query) => {
                     match state
                         .raft_db
-                        .raft
-                        .client_write(QueryWrite::Transaction(queries))
+                        .client_write(QueryWrite::ExecuteMany(query).stamped())
                         .await
                     {
                         Ok(resp) => {
-                            let resp: crate::Response = resp.data;
-                            let res = match resp {
-                                crate::Response::Transaction(res) => res,
+                            let log_id = resp.log_id;
+                            let res = match resp.data {
+                                crate::Response::ExecuteMany(res) => res.map(|res| (res, log_id)),
                                 _ => unreachable!(),
                             };
                             ApiStreamResponse {
                                 request_id,
-                                result: ApiStreamResponsePayload::Transaction(res),
+                                result: ApiStreamResponsePayload::ExecuteMany(res),
                             }
                         }
                         Err(err) => ApiStreamResponse {
                             request_id,
-                            result: ApiStreamResponsePayload::Transaction(Err(Error::from(err))),
+                            result: ApiStreamResponsePayload::ExecuteMany(Err(err)),
                         },
                     }
                 }
@@ -656,6 +1087,7 @@ async fn handle_socket_concurrent(
                         state.raft_db.read_pool.clone(),
                         sql,
                         params,
+                        timeout::deadline(state.raft_db.query_timeout),
                     )
                     .await;
 
@@ -666,28 +1098,44 @@ async fn handle_socket_concurrent(
                 }
 
                 #[cfg(feature = "sqlite")]
-                ApiStreamRequestPayload::Batch(sql) => {
-                    match state
+                ApiStreamRequestPayload::QueryConsistentTimeout((Query { sql, params }, ms)) => {
+                    let res = query_consistent_local(
+                        &state.raft_db.raft,
+                        state.raft_db.log_statements,
+                        state.raft_db.read_pool.clone(),
+                        sql,
+                        params,
+                        timeout::deadline(Some(Duration::from_millis(ms))),
+                    )
+                    .await;
+
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::QueryConsistent(res),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::Batch(sql) | ApiStreamRequestPayload::BatchLogId(sql) => {
+                    let res = match state
                         .raft_db
-                        .raft
-                        .client_write(QueryWrite::Batch(sql))
+                        .client_write(QueryWrite::Batch(sql).stamped())
                         .await
                     {
                         Ok(resp) => {
-                            let resp: crate::Response = resp.data;
-                            let res = match resp {
-                                crate::Response::Batch(res) => res,
+                            let log_id = resp.log_id;
+                            match resp.data {
+                                crate::Response::Batch(res) => {
+                                    res.result.map(|res| (res, Some(log_id)))
+                                }
                                 _ => unreachable!(),
-                            };
-                            ApiStreamResponse {
-                                request_id,
-                                result: ApiStreamResponsePayload::Batch(res.result),
                             }
                         }
-                        Err(err) => ApiStreamResponse {
-                            request_id,
-                            result: ApiStreamResponsePayload::Batch(Err(Error::from(err))),
-                        },
+                        Err(err) => Err(err),
+                    };
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::batch(res, with_log_id),
                     }
                 }
 
@@ -696,7 +1144,7 @@ async fn handle_socket_concurrent(
                     match state
                         .raft_db
                         .raft
-                        .client_write(QueryWrite::Migration(migrations))
+                        .client_write(QueryWrite::Migration(migrations).stamped())
                         .await
                     {
                         Ok(resp) => {
@@ -750,6 +1198,24 @@ async fn handle_socket_concurrent(
                         state.raft_db.read_pool.clone(),
                         sql,
                         params,
+                        timeout::deadline(state.raft_db.query_timeout),
+                    )
+                    .await;
+
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::Query(res),
+                    }
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::QueryTimeout((Query { sql, params }, ms)) => {
+                    let res = query_owned_local(
+                        state.raft_db.log_statements,
+                        state.raft_db.read_pool.clone(),
+                        sql,
+                        params,
+                        timeout::deadline(Some(Duration::from_millis(ms))),
                     )
                     .await;
 
@@ -761,7 +1227,12 @@ async fn handle_socket_concurrent(
 
                 #[cfg(feature = "cache")]
                 ApiStreamRequestPayload::KV(cache_req) => {
-                    match state.raft_cache.raft.client_write(cache_req).await {
+                    match state
+                        .raft_cache
+                        .raft
+                        .client_write(cache_req.stamped())
+                        .await
+                    {
                         Ok(resp) => {
                             let resp: CacheResponse = resp.data;
                             ApiStreamResponse {
@@ -778,23 +1249,79 @@ async fn handle_socket_concurrent(
 
                 #[cfg(feature = "cache")]
                 ApiStreamRequestPayload::KVGet(cache_req) => {
-                    let (cache_idx, key) = match cache_req {
-                        CacheRequest::Get { cache_idx, key } => (cache_idx, key),
+                    let resp = match cache_req {
+                        CacheRequest::Get { cache_idx, key } => {
+                            let (ack, rx) = tokio::sync::oneshot::channel();
+                            state
+                                .raft_cache
+                                .tx_caches
+                                .get(cache_idx)
+                                .unwrap()
+                                .send(CacheRequestHandler::Get((key, ack)))
+                                .expect("kv handler to always be running");
+                            let value = rx.await.expect("to always get an answer from kv handler");
+                            CacheResponse::Value(value)
+                        }
+                        CacheRequest::GetMany { cache_idx, keys } => {
+                            let (ack, rx) = tokio::sync::oneshot::channel();
+                            state
+                                .raft_cache
+                                .tx_caches
+                                .get(cache_idx)
+                                .unwrap()
+                                .send(CacheRequestHandler::GetMany((keys, ack)))
+                                .expect("kv handler to always be running");
+                            let values = rx.await.expect("to always get an answer from kv handler");
+                            CacheResponse::Values(values)
+                        }
+                        CacheRequest::ScanPrefix {
+                            cache_idx,
+                            prefix,
+                            limit,
+                            cursor,
+                        } => {
+                            let (ack, rx) = tokio::sync::oneshot::channel();
+                            state
+                                .raft_cache
+                                .tx_caches
+                                .get(cache_idx)
+                                .unwrap()
+                                .send(CacheRequestHandler::ScanPrefix((
+                                    prefix, limit, cursor, ack,
+                                )))
+                                .expect("kv handler to always be running");
+                            let page = rx.await.expect("to always get an answer from kv handler");
+                            CacheResponse::Page(page)
+                        }
+                        CacheRequest::Keys { cache_idx, prefix } => {
+                            let (ack, rx) = tokio::sync::oneshot::channel();
+                            state
+                                .raft_cache
+                                .tx_caches
+                                .get(cache_idx)
+                                .unwrap()
+                                .send(CacheRequestHandler::Keys((prefix, ack)))
+                                .expect("kv handler to always be running");
+                            let keys = rx.await.expect("to always get an answer from kv handler");
+                            CacheResponse::Keys(keys)
+                        }
+                        CacheRequest::GetVersioned { cache_idx, key } => {
+                            let (ack, rx) = tokio::sync::oneshot::channel();
+                            state
+                                .raft_cache
+                                .tx_caches
+                                .get(cache_idx)
+                                .unwrap()
+                                .send(CacheRequestHandler::GetVersioned((key, ack)))
+                                .expect("kv handler to always be running");
+                            let value = rx.await.expect("to always get an answer from kv handler");
+                            CacheResponse::Versioned(value)
+                        }
                         _ => unreachable!(),
                     };
-
-                    let (ack, rx) = tokio::sync::oneshot::channel();
-                    state
-                        .raft_cache
-                        .tx_caches
-                        .get(cache_idx)
-                        .unwrap()
-                        .send(CacheRequestHandler::Get((key, ack)))
-                        .expect("kv handler to always be running");
-                    let value = rx.await.expect("to always get an answer from kv handler");
                     ApiStreamResponse {
                         request_id,
-                        result: ApiStreamResponsePayload::KV(Ok(CacheResponse::Value(value))),
+                        result: ApiStreamResponsePayload::KV(Ok(resp)),
                     }
                 }
 
@@ -844,6 +1371,25 @@ async fn handle_socket_concurrent(
                         },
                     }
                 }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::QueryStream(_)
+                | ApiStreamRequestPayload::QueryStreamTimeout(_)
+                | ApiStreamRequestPayload::QueryStreamNext
+                | ApiStreamRequestPayload::QueryStreamCancel => {
+                    unreachable!("query streams are handled in the WebSocket reader loop")
+                }
+
+                #[cfg(feature = "sqlite")]
+                ApiStreamRequestPayload::SubscribeChanges(_)
+                | ApiStreamRequestPayload::SubscribeChangesCancel => {
+                    unreachable!("change subscriptions are handled in the WebSocket reader loop")
+                }
+
+                #[cfg(feature = "cache")]
+                ApiStreamRequestPayload::Watch(_) | ApiStreamRequestPayload::WatchCancel => {
+                    unreachable!("cache watches are handled in the WebSocket reader loop")
+                }
             };
 
             if let Err(err) = tx_write.send_async(WsWriteMsg::Payload(res)).await {
@@ -864,3 +1410,111 @@ async fn handle_socket_concurrent(
 
     Ok(())
 }
+
+/// Sends the chunks of a streaming query to the client. After each chunk, it waits until the
+/// client requested the next one, which keeps the memory usage bounded for slow consumers.
+/// The stream is cancelled as soon as `rx_next` is disconnected.
+#[cfg(feature = "sqlite")]
+pub(crate) async fn stream_query_chunks(
+    request_id: usize,
+    rx_chunks: flume::Receiver<QueryStreamChunk>,
+    rx_next: flume::Receiver<()>,
+    tx_write: flume::Sender<WsWriteMsg>,
+) {
+    loop {
+        let res = rx_chunks.recv_async().await.unwrap_or_else(|_| {
+            Err(Error::Error(
+                "Query stream producer exited unexpectedly".into(),
+            ))
+        });
+        let is_last = !matches!(res, Ok(Some(_)));
+
+        let resp = ApiStreamResponse {
+            request_id,
+            result: ApiStreamResponsePayload::QueryStream(res),
+        };
+        if tx_write
+            .send_async(WsWriteMsg::Payload(resp))
+            .await
+            .is_err()
+            || is_last
+        {
+            break;
+        }
+
+        if rx_next.recv_async().await.is_err() {
+            debug!("Query stream {request_id} has been cancelled");
+            break;
+        }
+    }
+}
+
+/// Sends all events of a change subscription to the client, until either the subscription has
+/// ended with an error, the connection has been closed, or `rx_cancel` is disconnected.
+#[cfg(feature = "sqlite")]
+pub(crate) async fn stream_change_events(
+    request_id: usize,
+    rx_events: flume::Receiver<Result<ChangeEvent, Error>>,
+    rx_cancel: flume::Receiver<()>,
+    tx_write: flume::Sender<WsWriteMsg>,
+) {
+    loop {
+        let res = tokio::select! {
+            res = rx_events.recv_async() => res.unwrap_or_else(|_| {
+                Err(Error::Error("Change subscription ended unexpectedly".into()))
+            }),
+            _ = rx_cancel.recv_async() => {
+                debug!("Change subscription {request_id} has been cancelled");
+                break;
+            }
+        };
+        let is_last = res.is_err();
+
+        let resp = ApiStreamResponse {
+            request_id,
+            result: ApiStreamResponsePayload::Changes(res),
+        };
+        if tx_write
+            .send_async(WsWriteMsg::Payload(resp))
+            .await
+            .is_err()
+            || is_last
+        {
+            break;
+        }
+    }
+}
+
+#[cfg(feature = "cache")]
+pub(crate) async fn stream_watch_events(
+    request_id: usize,
+    rx_events: flume::Receiver<Result<WatchEvent, Error>>,
+    rx_cancel: flume::Receiver<()>,
+    tx_write: flume::Sender<WsWriteMsg>,
+) {
+    loop {
+        let res = tokio::select! {
+            res = rx_events.recv_async() => res.unwrap_or_else(|_| {
+                Err(Error::Error("Cache watch ended unexpectedly".into()))
+            }),
+            _ = rx_cancel.recv_async() => {
+                debug!("Cache watch {request_id} has been cancelled");
+                break;
+            }
+        };
+        let is_last = res.is_err();
+
+        let resp = ApiStreamResponse {
+            request_id,
+            result: ApiStreamResponsePayload::WatchEvents(res),
+        };
+        if tx_write
+            .send_async(WsWriteMsg::Payload(resp))
+            .await
+            .is_err()
+            || is_last
+        {
+            break;
+        }
+    }
+}
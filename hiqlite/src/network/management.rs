@@ -2,6 +2,8 @@ use crate::NodeId;
 use crate::app_state::{AppState, RaftType};
 use crate::network::{AppStateExt, Error, fmt_ok, get_payload, validate_secret};
 use crate::{Node, helpers};
+#[cfg(feature = "backup")]
+use crate::{backup::BackupSource, restore};
 use axum::body;
 use axum::body::Body;
 use axum::extract::Path;
@@ -28,6 +30,13 @@ pub struct ClusterLeaveReq {
     pub stay_as_learner: bool,
 }
 
+#[cfg(feature = "backup")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RestoreReq {
+    /// The backup in the same format as `HQL_BACKUP_RESTORE`.
+    pub source: String,
+}
+
 #[tracing::instrument(skip_all)]
 pub(crate) async fn add_learner(
     state: AppStateExt,
@@ -165,6 +174,43 @@ pub(crate) async fn become_member(
     }
 }
 
+/// Starts an online restore on the leader. The progress can be polled with `get_restore()`.
+#[cfg(feature = "backup")]
+#[tracing::instrument(skip_all)]
+pub(crate) async fn post_restore(
+    state: AppStateExt,
+    headers: HeaderMap,
+    body: body::Bytes,
+) -> Result<Response, Error> {
+    validate_secret(&state, &headers)?;
+
+    let raft_type = RaftType::Sqlite;
+    if helpers::is_raft_stopped(&state, &raft_type)
+        || !helpers::is_raft_initialized(&state, &raft_type).await?
+    {
+        return Err(Error::Config("Raft node has not been initialized".into()));
+    }
+    are_we_leader(&state, &raft_type).await?;
+
+    let RestoreReq { source } = get_payload(&headers, body)?;
+    let Some(src) = BackupSource::parse(&source) else {
+        return Err(Error::Config(
+            format!("Invalid backup source: {source}").into(),
+        ));
+    };
+    info!("Online restore requested from {src:?}");
+
+    let progress = restore::start(&state.0, src)?;
+    fmt_ok(headers, progress)
+}
+
+/// Returns the progress of the latest online restore this node has coordinated.
+#[cfg(feature = "backup")]
+pub(crate) async fn get_restore(state: AppStateExt, headers: HeaderMap) -> Result<Response, Error> {
+    validate_secret(&state, &headers)?;
+    fmt_ok(headers, state.restore.progress())
+}
+
 async fn are_we_leader(state: &AppStateExt, raft_type: &RaftType) -> Result<(), Error> {
     if let Some(leader_id) = helpers::get_raft_leader(state, raft_type).await {
         if leader_id == state.id {
@@ -218,11 +218,18 @@ async fn handle_socket(
         let (request_id, payload) = match req {
             #[cfg(feature = "sqlite")]
             RaftStreamRequest::AppendDB((request_id, req)) => {
+                let leader_commit = req.leader_commit.map(|id| id.index);
                 let res = state.raft_db.raft.append_entries(req).await;
-                if let Err(RaftError::Fatal(Fatal::Stopped)) = &res {
-                    debug!("Raft DB stopped - exiting");
-                    state.raft_db.is_raft_stopped.store(true, Ordering::Relaxed);
-                    break;
+                match &res {
+                    Ok(AppendEntriesResponse::Success) => {
+                        state.raft_db.leader_contact.update(leader_commit);
+                    }
+                    Err(RaftError::Fatal(Fatal::Stopped)) => {
+                        debug!("Raft DB stopped - exiting");
+                        state.raft_db.is_raft_stopped.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    _ => {}
                 }
                 (request_id, RaftStreamResponsePayload::AppendDB(res))
             }
@@ -0,0 +1,90 @@
+use crate::app_state::AppState;
+use chrono::Utc;
+use openraft::ServerState;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The consistency level for read queries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadConsistency {
+    /// Read from the local replica without any checks. This is the fastest option, but the
+    /// local replica may lag behind the leader.
+    #[default]
+    Local,
+    /// Read from the local replica as long as it does not lag behind the leader more than the
+    /// given bound. Otherwise, the read will be served by the leader.
+    BoundedStaleness(Staleness),
+    /// Always read from the current leader. Leadership is not confirmed before the read, which
+    /// means a deposed leader may still serve slightly stale data during a network partition.
+    Leader,
+    /// Read from the leader after it confirmed its leadership with a quorum of nodes via
+    /// `ensure_linearizable()`. This is the most expensive, but strongest option.
+    Linearizable,
+}
+
+/// The max staleness for `ReadConsistency::BoundedStaleness`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Staleness {
+    /// The max time since the local replica was known to be up to date with the leader.
+    Time(Duration),
+    /// The max amount of committed logs the local replica may not have applied yet, compared
+    /// to the last commit index it has received from the leader.
+    LogLag(u64),
+}
+
+/// Tracks the last successful contact with the Raft DB leader on a follower, which includes
+/// heartbeats.
+#[derive(Debug, Default)]
+pub(crate) struct LeaderContact {
+    ts_millis: AtomicI64,
+    commit_index: AtomicU64,
+}
+
+impl LeaderContact {
+    pub(crate) fn update(&self, commit_index: Option<u64>) {
+        self.commit_index
+            .fetch_max(commit_index.unwrap_or(0), Ordering::Relaxed);
+        self.ts_millis
+            .store(Utc::now().timestamp_millis(), Ordering::Release);
+    }
+}
+
+/// Checks if the local replica is fresh enough to serve a read with the given `staleness`.
+pub(crate) fn is_within_staleness(state: &AppState, staleness: &Staleness) -> bool {
+    let metrics = state.raft_db.raft.metrics();
+    let metrics = metrics.borrow();
+
+    if metrics.state == ServerState::Leader {
+        return match staleness {
+            // The leader is only up to date, as long as it has heard from a quorum recently.
+            // Otherwise, it might have been deposed already inside a network partition.
+            Staleness::Time(max) => metrics
+                .millis_since_quorum_ack
+                .map(|ms| u128::from(ms) <= max.as_millis())
+                .unwrap_or(false),
+            Staleness::LogLag(_) => true,
+        };
+    }
+
+    let last_contact = state.raft_db.leader_contact.ts_millis.load(Ordering::Acquire);
+    if last_contact == 0 {
+        // we have never heard from a leader since startup
+        return false;
+    }
+    let leader_commit = state
+        .raft_db
+        .leader_contact
+        .commit_index
+        .load(Ordering::Relaxed);
+    let applied = metrics.last_applied.map(|id| id.index).unwrap_or(0);
+
+    match staleness {
+        Staleness::Time(max) => {
+            let since_contact = Utc::now().timestamp_millis().saturating_sub(last_contact);
+            // We only know that we were up to date with the leader at the time of the last
+            // contact, if we have applied everything it had committed at that point.
+            applied >= leader_commit && since_contact.max(0) as u128 <= max.as_millis()
+        }
+        Staleness::LogLag(max) => leader_commit.saturating_sub(applied) <= *max,
+    }
+}
@@ -0,0 +1,145 @@
+use crate::Error;
+use crate::query::rows::{RowOwned, ValueOwned};
+use serde::de::value::{Error as DeError, MapDeserializer, SeqDeserializer};
+use serde::de::{DeserializeOwned, IntoDeserializer, Visitor};
+use serde::{Deserializer, forward_to_deserialize_any};
+
+/// Deserializes an owned row into `T` in the same way as `serde_rusqlite` does for local rows,
+/// so that `query_as()` and friends give the same results for rows read on another node.
+pub(crate) fn from_row<T: DeserializeOwned>(row: RowOwned) -> Result<T, Error> {
+    let columns = row
+        .columns
+        .into_iter()
+        .map(|col| (col.name, ValueDeserializer(col.value)));
+    T::deserialize(MapDeserializer::<_, DeError>::new(columns))
+        .map_err(|err| Error::Sqlite(err.to_string().into()))
+}
+
+struct ValueDeserializer(ValueOwned);
+
+impl<'de> IntoDeserializer<'de, DeError> for ValueDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Null => visitor.visit_unit(),
+            ValueOwned::Integer(i) => visitor.visit_i64(i),
+            ValueOwned::Real(r) => visitor.visit_f64(r),
+            ValueOwned::Text(s) => visitor.visit_string(s),
+            ValueOwned::Blob(b) => visitor.visit_byte_buf(b),
+        }
+    }
+
+    // SQLite has no boolean type and stores them as integers
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Integer(i) => visitor.visit_bool(i != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    // a `Vec<u8>` expects a sequence instead of bytes
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Blob(b) => visitor.visit_seq(SeqDeserializer::new(b.into_iter())),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            ValueOwned::Text(s) => visitor.visit_enum(s.into_deserializer()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::rows::ColumnOwned;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Entity {
+        id: i64,
+        name: String,
+        score: f64,
+        active: bool,
+        description: Option<String>,
+        data: Option<Vec<u8>>,
+    }
+
+    fn col(name: &str, value: ValueOwned) -> ColumnOwned {
+        ColumnOwned {
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    #[test]
+    fn deserializes_owned_rows() {
+        let row = RowOwned {
+            columns: vec![
+                col("id", ValueOwned::Integer(13)),
+                col("name", ValueOwned::Text("Some Name".to_string())),
+                col("score", ValueOwned::Real(1.5)),
+                col("active", ValueOwned::Integer(1)),
+                col("description", ValueOwned::Null),
+                col("data", ValueOwned::Blob(vec![1, 2, 3])),
+            ],
+        };
+
+        let entity: Entity = from_row(row).unwrap();
+        assert_eq!(
+            entity,
+            Entity {
+                id: 13,
+                name: "Some Name".to_string(),
+                score: 1.5,
+                active: true,
+                description: None,
+                data: Some(vec![1, 2, 3]),
+            }
+        );
+
+        let row = RowOwned {
+            columns: vec![col("id", ValueOwned::Text("13".to_string()))],
+        };
+        assert!(from_row::<Entity>(row).is_err());
+    }
+}
@@ -7,14 +7,19 @@ use openraft::Raft;
 use serde::de::DeserializeOwned;
 use std::borrow::Cow;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 use tokio::task;
 use tracing::info;
 
 #[cfg(debug_assertions)]
 use crate::store::state_machine::sqlite::writer;
 
+pub mod consistency;
 pub mod cust_types;
+pub(crate) mod de;
 pub mod rows;
+pub mod timeout;
 
 pub(crate) async fn query_consistent_local<S>(
     raft: &Raft<TypeConfigSqlite>,
@@ -22,12 +27,17 @@ pub(crate) async fn query_consistent_local<S>(
     read_pool: SqlitePool,
     stmt: S,
     params: Params,
+    deadline: Option<Instant>,
 ) -> Result<Vec<RowOwned>, Error>
 where
     S: Into<Cow<'static, str>>,
 {
-    let _ = raft.ensure_linearizable().await?;
-    query_owned_local(log_statements, read_pool, stmt, params).await
+    timeout::until_deadline(deadline, async {
+        let _ = raft.ensure_linearizable().await?;
+        Ok(())
+    })
+    .await?;
+    query_owned_local(log_statements, read_pool, stmt, params, deadline).await
 }
 
 pub(crate) async fn query_owned_local<S>(
@@ -35,6 +45,7 @@ pub(crate) async fn query_owned_local<S>(
     read_pool: SqlitePool,
     sql: S,
     params: Params,
+    deadline: Option<Instant>,
 ) -> Result<Vec<RowOwned>, Error>
 where
     S: Into<Cow<'static, str>>,
@@ -44,45 +55,155 @@ where
         info!("query_owned_local:\n{}\n{:?}", sql, params)
     }
 
-    let conn = read_pool.get().await?;
+    let conn = timeout::get_conn(&read_pool, deadline).await?;
 
     task::spawn_blocking(move || {
-        let mut stmt = conn.prepare_cached(sql.as_ref())?;
-        let columns = ColumnOwned::mapping_cols_from_stmt(stmt.columns())?;
+        timeout::run_with_deadline(&conn, deadline, || {
+            let mut stmt = conn.prepare_cached(sql.as_ref())?;
+            let columns = ColumnOwned::mapping_cols_from_stmt(stmt.columns())?;
 
-        #[cfg(debug_assertions)]
-        writer::check_stmt_params_count(&stmt, &params, &sql);
+            #[cfg(debug_assertions)]
+            writer::check_stmt_params_count(&stmt, &params, &sql);
 
-        let mut idx = 1;
-        #[allow(clippy::explicit_counter_loop)]
-        for param in params {
-            stmt.raw_bind_parameter(idx, param.into_sql())?;
-            idx += 1;
-        }
+            let mut idx = 1;
+            #[allow(clippy::explicit_counter_loop)]
+            for param in params {
+                stmt.raw_bind_parameter(idx, param.into_sql())?;
+                idx += 1;
+            }
 
-        let mut rows = stmt.raw_query();
-        let mut rows_owned = Vec::new();
-        loop {
-            match rows.next() {
-                Ok(Some(row)) => rows_owned.push(RowOwned::from_row_column(row, &columns)),
-                Ok(None) => break,
-                Err(err) => {
-                    // never silently return a truncated result
-                    return Err(Error::Sqlite(err.to_string().into()));
+            let mut rows = stmt.raw_query();
+            let mut rows_owned = Vec::new();
+            loop {
+                match rows.next() {
+                    Ok(Some(row)) => rows_owned.push(RowOwned::from_row_column(row, &columns)),
+                    Ok(None) => break,
+                    Err(err) => {
+                        // never silently return a truncated result
+                        return Err(Error::Sqlite(err.to_string().into()));
+                    }
                 }
             }
-        }
 
-        Ok::<Vec<RowOwned>, Error>(rows_owned)
+            Ok::<Vec<RowOwned>, Error>(rows_owned)
+        })
     })
     .await?
 }
 
+/// The amount of rows that will be sent as one chunk when streaming query results.
+pub(crate) const QUERY_STREAM_CHUNK_ROWS: usize = 256;
+
+/// A single chunk of a streaming query. `Ok(None)` marks the end of the stream.
+pub(crate) type QueryStreamChunk = Result<Option<Vec<RowOwned>>, Error>;
+
+/// Streams the result of a local query in chunks of `QUERY_STREAM_CHUNK_ROWS`.
+///
+/// The pooled connection is held by a blocking task until the whole result has been read, or
+/// until the receiver is dropped. The channel is bounded, so that at most one chunk is buffered
+/// in memory ahead of the consumer. `Ok(None)` marks the end of the stream.
+///
+/// The `chunk_timeout` applies to reading each single chunk, because the runtime of the whole
+/// stream depends on the consumer. Time spent waiting for the consumer does not count.
+pub(crate) async fn query_stream_local<S>(
+    log_statements: bool,
+    read_pool: SqlitePool,
+    sql: S,
+    params: Params,
+    chunk_timeout: Option<Duration>,
+) -> Result<flume::Receiver<QueryStreamChunk>, Error>
+where
+    S: Into<Cow<'static, str>>,
+{
+    let sql: Cow<'static, str> = sql.into();
+    if log_statements {
+        info!("query_stream_local:\n{}\n{:?}", sql, params)
+    }
+
+    let conn = timeout::get_conn(&read_pool, timeout::deadline(chunk_timeout)).await?;
+    let (tx, rx) = flume::bounded(1);
+
+    task::spawn_blocking(move || {
+        // re-armed with a new deadline before each chunk
+        let mut timed_out = None;
+        let mut next_chunk_deadline = || match chunk_timeout {
+            None => Ok(()),
+            Some(t) => {
+                timed_out = Some(timeout::interrupt_at(&conn, Instant::now() + t)?);
+                Ok::<(), Error>(())
+            }
+        };
+
+        let res = (|| {
+            next_chunk_deadline()?;
+            let mut stmt = conn.prepare_cached(sql.as_ref())?;
+            let columns = ColumnOwned::mapping_cols_from_stmt(stmt.columns())?;
+
+            #[cfg(debug_assertions)]
+            writer::check_stmt_params_count(&stmt, &params, &sql);
+
+            let mut idx = 1;
+            #[allow(clippy::explicit_counter_loop)]
+            for param in params {
+                stmt.raw_bind_parameter(idx, param.into_sql())?;
+                idx += 1;
+            }
+
+            let mut rows = stmt.raw_query();
+            let mut chunk = Vec::with_capacity(QUERY_STREAM_CHUNK_ROWS);
+            loop {
+                match rows.next() {
+                    Ok(Some(row)) => {
+                        chunk.push(RowOwned::from_row_column(row, &columns));
+                        if chunk.len() == QUERY_STREAM_CHUNK_ROWS {
+                            let next = Vec::with_capacity(QUERY_STREAM_CHUNK_ROWS);
+                            if tx
+                                .send(Ok(Some(std::mem::replace(&mut chunk, next))))
+                                .is_err()
+                            {
+                                // the consumer is gone -> release the connection early
+                                return Ok(());
+                            }
+                            next_chunk_deadline()?;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(err) => return Err(Error::Sqlite(err.to_string().into())),
+                }
+            }
+
+            if !chunk.is_empty() && tx.send(Ok(Some(chunk))).is_err() {
+                return Ok(());
+            }
+            let _ = tx.send(Ok(None));
+
+            Ok::<(), Error>(())
+        })();
+
+        let res = match timed_out {
+            None => res,
+            Some(timed_out) => {
+                let _ = timeout::remove_interrupt(&conn);
+                match res {
+                    Err(_) if timed_out.load(Ordering::Relaxed) => Err(timeout::err_timeout()),
+                    res => res,
+                }
+            }
+        };
+        if let Err(err) = res {
+            let _ = tx.send(Err(err));
+        }
+    });
+
+    Ok(rx)
+}
+
 #[inline(always)]
 pub(crate) async fn query_map<T, S>(
     state: &Arc<AppState>,
     sql: S,
     params: Params,
+    deadline: Option<Instant>,
 ) -> Result<Vec<T>, Error>
 where
     T: for<'r> From<&'r mut rows::Row<'r>> + Send + 'static,
@@ -93,30 +214,32 @@ where
         info!("query_map_typed:\n{}\n{:?}", sql, params)
     }
 
-    let conn = state.raft_db.read_pool.get().await?;
+    let conn = timeout::get_conn(&state.raft_db.read_pool, deadline).await?;
     task::spawn_blocking(move || {
-        let mut stmt = conn.prepare_cached(sql.as_ref())?;
+        timeout::run_with_deadline(&conn, deadline, || {
+            let mut stmt = conn.prepare_cached(sql.as_ref())?;
 
-        #[cfg(debug_assertions)]
-        writer::check_stmt_params_count(&stmt, &params, &sql);
+            #[cfg(debug_assertions)]
+            writer::check_stmt_params_count(&stmt, &params, &sql);
 
-        let mut idx = 1;
-        #[allow(clippy::explicit_counter_loop)]
-        for param in params {
-            stmt.raw_bind_parameter(idx, param.into_sql())?;
-            idx += 1;
-        }
+            let mut idx = 1;
+            #[allow(clippy::explicit_counter_loop)]
+            for param in params {
+                stmt.raw_bind_parameter(idx, param.into_sql())?;
+                idx += 1;
+            }
 
-        let mut rows = stmt.raw_query();
-        let mut res = Vec::new();
-        loop {
-            match rows.next() {
-                Ok(Some(row)) => res.push(T::from(&mut rows::Row::Borrowed(row))),
-                Ok(None) => break,
-                Err(err) => return Err(Error::Sqlite(err.to_string().into())),
+            let mut rows = stmt.raw_query();
+            let mut res = Vec::new();
+            loop {
+                match rows.next() {
+                    Ok(Some(row)) => res.push(T::from(&mut rows::Row::Borrowed(row))),
+                    Ok(None) => break,
+                    Err(err) => return Err(Error::Sqlite(err.to_string().into())),
+                }
             }
-        }
-        Ok::<Vec<T>, Error>(res)
+            Ok::<Vec<T>, Error>(res)
+        })
     })
     .await?
 }
@@ -126,12 +249,13 @@ pub(crate) async fn query_map_one<T, S>(
     state: &Arc<AppState>,
     sql: S,
     params: Params,
+    deadline: Option<Instant>,
 ) -> Result<T, Error>
 where
     T: for<'r> From<&'r mut rows::Row<'r>> + Send + 'static,
     S: Into<Cow<'static, str>>,
 {
-    let mut rows: Vec<T> = query_map(state, sql, params).await?;
+    let mut rows: Vec<T> = query_map(state, sql, params, deadline).await?;
     if rows.is_empty() {
         Err(Error::QueryReturnedNoRows("no rows returned".into()))
     } else if rows.len() > 1 {
@@ -148,12 +272,13 @@ pub(crate) async fn query_map_optional<T, S>(
     state: &Arc<AppState>,
     sql: S,
     params: Params,
+    deadline: Option<Instant>,
 ) -> Result<Option<T>, Error>
 where
     T: for<'r> From<&'r mut rows::Row<'r>> + Send + 'static,
     S: Into<Cow<'static, str>>,
 {
-    let mut rows: Vec<T> = query_map(state, sql, params).await?;
+    let mut rows: Vec<T> = query_map(state, sql, params, deadline).await?;
     if rows.is_empty() {
         Ok(None)
     } else {
@@ -166,6 +291,7 @@ pub(crate) async fn query_as<T, S>(
     state: &Arc<AppState>,
     sql: S,
     params: Params,
+    deadline: Option<Instant>,
 ) -> Result<Vec<T>, Error>
 where
     T: DeserializeOwned + Send + 'static,
@@ -176,30 +302,32 @@ where
         info!("query_as:\n{}\n{:?}", sql, params)
     }
 
-    let conn = state.raft_db.read_pool.get().await?;
+    let conn = timeout::get_conn(&state.raft_db.read_pool, deadline).await?;
     task::spawn_blocking(move || {
-        let mut stmt = conn.prepare_cached(sql.as_ref())?;
+        timeout::run_with_deadline(&conn, deadline, || {
+            let mut stmt = conn.prepare_cached(sql.as_ref())?;
 
-        #[cfg(debug_assertions)]
-        writer::check_stmt_params_count(&stmt, &params, &sql);
+            #[cfg(debug_assertions)]
+            writer::check_stmt_params_count(&stmt, &params, &sql);
 
-        let mut idx = 1;
-        #[allow(clippy::explicit_counter_loop)]
-        for param in params {
-            stmt.raw_bind_parameter(idx, param.into_sql())?;
-            idx += 1;
-        }
+            let mut idx = 1;
+            #[allow(clippy::explicit_counter_loop)]
+            for param in params {
+                stmt.raw_bind_parameter(idx, param.into_sql())?;
+                idx += 1;
+            }
 
-        let mut rows = serde_rusqlite::from_rows::<T>(stmt.raw_query());
-        let mut res = Vec::new();
-        loop {
-            match rows.next() {
-                Some(Ok(ty)) => res.push(ty),
-                Some(Err(err)) => return Err(Error::Sqlite(err.to_string().into())),
-                None => break,
+            let mut rows = serde_rusqlite::from_rows::<T>(stmt.raw_query());
+            let mut res = Vec::new();
+            loop {
+                match rows.next() {
+                    Some(Ok(ty)) => res.push(ty),
+                    Some(Err(err)) => return Err(Error::Sqlite(err.to_string().into())),
+                    None => break,
+                }
             }
-        }
-        Ok::<Vec<T>, Error>(res)
+            Ok::<Vec<T>, Error>(res)
+        })
     })
     .await?
 }
@@ -209,12 +337,13 @@ pub(crate) async fn query_as_one<T, S>(
     state: &Arc<AppState>,
     sql: S,
     params: Params,
+    deadline: Option<Instant>,
 ) -> Result<T, Error>
 where
     T: DeserializeOwned + Send + 'static,
     S: Into<Cow<'static, str>>,
 {
-    let mut rows: Vec<T> = query_as(state, sql, params).await?;
+    let mut rows: Vec<T> = query_as(state, sql, params, deadline).await?;
     if rows.is_empty() {
         Err(Error::QueryReturnedNoRows("no rows returned".into()))
     } else if rows.len() > 1 {
@@ -231,12 +360,13 @@ pub(crate) async fn query_as_optional<T, S>(
     state: &Arc<AppState>,
     sql: S,
     params: Params,
+    deadline: Option<Instant>,
 ) -> Result<Option<T>, Error>
 where
     T: DeserializeOwned + Send + 'static,
     S: Into<Cow<'static, str>>,
 {
-    let mut rows: Vec<T> = query_as(state, sql, params).await?;
+    let mut rows: Vec<T> = query_as(state, sql, params, deadline).await?;
     if rows.is_empty() {
         Ok(None)
     } else {
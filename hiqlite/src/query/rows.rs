@@ -39,7 +39,7 @@ impl Row<'_> {
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RowOwned {
     pub(crate) columns: Vec<ColumnOwned>,
 }
@@ -202,7 +202,7 @@ impl ColumnType {
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ColumnOwned {
     // TODO find a way to include all the column names only once at the very top level and
     // somehow get a reference of them into a `From<_>` impl, probably with a new Trait.
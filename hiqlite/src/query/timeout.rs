@@ -0,0 +1,137 @@
+use crate::Error;
+use crate::store::state_machine::sqlite::state_machine::SqlitePool;
+use deadpool::unmanaged::Object;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::time;
+
+/// The amount of SQLite VM instructions between two deadline checks.
+const PROGRESS_HANDLER_OPS: i32 = 1_000;
+
+/// Returns the deadline for a query started right now with the given timeout.
+#[inline]
+pub(crate) fn deadline(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.map(|t| Instant::now() + t)
+}
+
+/// Returns the remaining time until the deadline, which can be sent to a remote node.
+#[inline]
+pub(crate) fn remaining_millis(deadline: Option<Instant>) -> Option<u64> {
+    deadline.map(|d| d.saturating_duration_since(Instant::now()).as_millis() as u64)
+}
+
+pub(crate) fn err_timeout() -> Error {
+    Error::Timeout("Query did not finish before its deadline".to_string())
+}
+
+/// Waits until the given future has finished, or returns an `Error::Timeout` once the deadline
+/// has been reached.
+pub(crate) async fn until_deadline<F, T>(deadline: Option<Instant>, fut: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    match deadline {
+        None => fut.await,
+        Some(deadline) => time::timeout_at(deadline.into(), fut)
+            .await
+            .unwrap_or_else(|_| Err(err_timeout())),
+    }
+}
+
+/// Gets a connection from the read pool, but waits at most until the deadline.
+pub(crate) async fn get_conn(
+    read_pool: &SqlitePool,
+    deadline: Option<Instant>,
+) -> Result<Object<rusqlite::Connection>, Error> {
+    until_deadline(deadline, async { Ok(read_pool.get().await?) }).await
+}
+
+/// Executes `f` with a progress handler on the given connection, which interrupts the running
+/// statement once the deadline has been reached. The handler is removed afterward in any case,
+/// because pooled connections are re-used for other queries.
+pub(crate) fn run_with_deadline<F, T>(
+    conn: &rusqlite::Connection,
+    deadline: Option<Instant>,
+    f: F,
+) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error>,
+{
+    let Some(deadline) = deadline else {
+        return f();
+    };
+    if Instant::now() >= deadline {
+        return Err(err_timeout());
+    }
+
+    let timed_out = interrupt_at(conn, deadline)?;
+    let res = f();
+    remove_interrupt(conn)?;
+
+    match res {
+        Err(_) if timed_out.load(Ordering::Relaxed) => Err(err_timeout()),
+        res => res,
+    }
+}
+
+/// Installs a progress handler on the given connection, which interrupts the running statement
+/// once the deadline has been reached. The returned flag is set, if it did. A new deadline
+/// replaces the old one, which makes it possible to use a new one for each chunk of a stream.
+pub(crate) fn interrupt_at(
+    conn: &rusqlite::Connection,
+    deadline: Instant,
+) -> Result<Arc<AtomicBool>, Error> {
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let timed_out_handler = timed_out.clone();
+    conn.progress_handler(
+        PROGRESS_HANDLER_OPS,
+        Some(move || {
+            if Instant::now() >= deadline {
+                timed_out_handler.store(true, Ordering::Relaxed);
+                true
+            } else {
+                false
+            }
+        }),
+    )?;
+    Ok(timed_out)
+}
+
+/// Removes the progress handler from `interrupt_at()`.
+pub(crate) fn remove_interrupt(conn: &rusqlite::Connection) -> Result<(), Error> {
+    conn.progress_handler(0, None::<fn() -> bool>)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQL_SLOW: &str = r#"
+WITH RECURSIVE cnt(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM cnt)
+SELECT count(*) FROM cnt"#;
+
+    #[test]
+    fn deadline_interrupts_statements() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+
+        let start = Instant::now();
+        let res = run_with_deadline(&conn, deadline(Some(Duration::from_millis(50))), || {
+            Ok(conn.query_row(SQL_SLOW, (), |row| row.get::<_, i64>(0))?)
+        });
+        assert!(matches!(res, Err(Error::Timeout(_))), "{res:?}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // the handler must be gone for the next query on the same connection
+        let res = run_with_deadline(&conn, None, || {
+            Ok(conn.query_row("SELECT 1", (), |row| row.get::<_, i64>(0))?)
+        });
+        assert_eq!(res.unwrap(), 1);
+
+        let res = run_with_deadline(&conn, deadline(Some(Duration::from_secs(10))), || {
+            Ok(conn.query_row("SELECT 2", (), |row| row.get::<_, i64>(0))?)
+        });
+        assert_eq!(res.unwrap(), 2);
+    }
+}
@@ -0,0 +1,246 @@
+//! Online restores of a backup into a running cluster.
+//!
+//! The leader fetches and verifies the backup and replicates it in chunks through the Raft log,
+//! see `store::state_machine::sqlite::restore`. Afterward, it builds a new snapshot and purges
+//! the logs, so nodes that fall behind install the restored database as a snapshot instead of
+//! replaying all chunks.
+
+use crate::Error;
+use crate::app_state::AppState;
+use crate::backup::{self, BackupSource};
+use crate::store::state_machine::sqlite::restore::RestoreWrite;
+use crate::store::state_machine::sqlite::state_machine::{QueryWrite, Response};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::{fs, task, time};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Upper limit for a single chunk. `max_payload_entries` chunks must fit into a single
+/// replication message.
+const CHUNK_SIZE_MAX: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreProgress {
+    pub id: String,
+    /// The restored backup in the same format as `HQL_BACKUP_RESTORE`.
+    pub source: String,
+    pub started: i64,
+    pub state: RestoreState,
+    /// The size of the backup, as soon as it has been fetched.
+    pub bytes_total: u64,
+    pub bytes_replicated: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RestoreState {
+    /// The leader fetches and verifies the backup.
+    Fetching,
+    /// The backup is replicated to all nodes.
+    Replicating,
+    /// All nodes replace their database with the backup.
+    Applying,
+    /// The leader builds a new snapshot and purges the logs.
+    Snapshotting,
+    Finished,
+    Failed(String),
+}
+
+impl RestoreState {
+    pub fn is_done(&self) -> bool {
+        matches!(self, Self::Finished | Self::Failed(_))
+    }
+}
+
+pub(crate) struct OnlineRestore {
+    chunk_size: usize,
+    progress: Mutex<Option<RestoreProgress>>,
+}
+
+impl OnlineRestore {
+    pub(crate) fn new(wal_size: u32) -> Self {
+        Self {
+            // each chunk is a single log entry, which must fit into a WAL file
+            chunk_size: (wal_size as usize / 4).min(CHUNK_SIZE_MAX),
+            progress: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn progress(&self) -> Option<RestoreProgress> {
+        self.progress.lock().unwrap().clone()
+    }
+
+    fn update<F: FnOnce(&mut RestoreProgress)>(&self, f: F) {
+        if let Some(progress) = self.progress.lock().unwrap().as_mut() {
+            f(progress);
+        }
+    }
+}
+
+/// Starts a restore of `src` in the background. Must only be called on the leader.
+pub(crate) fn start(state: &Arc<AppState>, src: BackupSource) -> Result<RestoreProgress, Error> {
+    if matches!(src, BackupSource::PointInTime(_)) {
+        return Err(Error::Config(
+            "Point-in-time recovery is only possible with `HQL_BACKUP_RESTORE` during startup"
+                .into(),
+        ));
+    }
+    if matches!(src, BackupSource::S3(_)) && state.s3_config.is_none() {
+        return Err(Error::S3(
+            "No `S3Config` given, cannot restore backup".to_string(),
+        ));
+    }
+
+    let progress = RestoreProgress {
+        id: Uuid::now_v7().to_string(),
+        source: src.to_string(),
+        started: Utc::now().timestamp(),
+        state: RestoreState::Fetching,
+        bytes_total: 0,
+        bytes_replicated: 0,
+    };
+    {
+        let mut lock = state.restore.progress.lock().unwrap();
+        if let Some(current) = lock.as_ref()
+            && !current.state.is_done()
+        {
+            return Err(Error::Error(
+                format!("Restore {} is still running", current.id).into(),
+            ));
+        }
+        *lock = Some(progress.clone());
+    }
+
+    task::spawn(run(state.clone(), src, progress.id.clone()));
+    Ok(progress)
+}
+
+async fn run(state: Arc<AppState>, src: BackupSource, id: String) {
+    info!("Starting online restore {id} from {src:?}");
+
+    // membership changes in the middle of a restore would only slow it down
+    let lock = state.raft_lock.lock().await;
+    let res = restore(&state, &src, &id).await;
+    drop(lock);
+
+    match res {
+        Ok(()) => {
+            info!("Online restore {id} finished successfully");
+            state.restore.update(|p| p.state = RestoreState::Finished);
+        }
+        Err(err) => {
+            error!("Online restore {id} failed: {err}");
+            state
+                .restore
+                .update(|p| p.state = RestoreState::Failed(err.to_string()));
+        }
+    }
+}
+
+async fn restore(state: &Arc<AppState>, src: &BackupSource, id: &str) -> Result<(), Error> {
+    let path =
+        backup::fetch_verified(state.s3_config.as_deref(), &state.backups_dir, src, id).await?;
+    let res = replicate(state, &path, id).await;
+    let _ = fs::remove_file(&path).await;
+    let index = res?;
+
+    state
+        .restore
+        .update(|p| p.state = RestoreState::Snapshotting);
+    snapshot_purge(state, index).await
+}
+
+/// Replicates the backup at `path` and applies it on all nodes. Returns the log index of the
+/// restore.
+async fn replicate(state: &AppState, path: &str, id: &str) -> Result<u64, Error> {
+    let mut file = fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    state.restore.update(|p| {
+        p.state = RestoreState::Replicating;
+        p.bytes_total = size;
+    });
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; state.restore.chunk_size];
+    let mut offset = 0;
+    loop {
+        let len = read_chunk(&mut file, &mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+
+        let write = RestoreWrite::Chunk {
+            id: id.to_string(),
+            offset,
+            data: buf[..len].to_vec(),
+        };
+        if let Err(err) = write_restore(state, write).await {
+            let abort = RestoreWrite::Abort { id: id.to_string() };
+            if let Err(err) = write_restore(state, abort).await {
+                warn!("Cannot drop the staged backup of restore {id}: {err}");
+            }
+            return Err(err);
+        }
+
+        offset += len as u64;
+        state.restore.update(|p| p.bytes_replicated = offset);
+    }
+
+    state.restore.update(|p| p.state = RestoreState::Applying);
+    let write = RestoreWrite::Apply {
+        id: id.to_string(),
+        size: offset,
+        sha256: hasher.finalize().to_vec(),
+    };
+    write_restore(state, write).await
+}
+
+/// Fills the whole `buf`, unless the end of the file has been reached.
+async fn read_chunk(file: &mut fs::File, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut len = 0;
+    while len < buf.len() {
+        let read = file.read(&mut buf[len..]).await?;
+        if read == 0 {
+            break;
+        }
+        len += read;
+    }
+    Ok(len)
+}
+
+async fn write_restore(state: &AppState, write: RestoreWrite) -> Result<u64, Error> {
+    let res = state
+        .raft_db
+        .raft
+        .client_write(QueryWrite::Restore(write))
+        .await?;
+    match res.data {
+        Response::Restore(Ok(())) => Ok(res.log_id.index),
+        Response::Restore(Err(err)) => Err(err),
+        _ => unreachable!(),
+    }
+}
+
+/// Builds a snapshot, which contains the restored database, and purges all logs up to the
+/// restore. The chunks are not needed anymore afterward.
+async fn snapshot_purge(state: &AppState, index: u64) -> Result<(), Error> {
+    let raft = &state.raft_db.raft;
+    raft.trigger().snapshot().await?;
+
+    loop {
+        let metrics = raft.metrics().borrow().clone();
+        metrics.running_state?;
+        if metrics.snapshot.is_some_and(|s| s.index >= index) {
+            break;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+
+    raft.trigger().purge_log(index).await?;
+    Ok(())
+}
@@ -2,7 +2,7 @@ use crate::Error;
 pub use cryptr::stream::s3::*;
 use cryptr::stream::writer::channel_writer::{ChannelReceiver, ChannelWriter};
 pub use cryptr::EncKeys;
-use cryptr::{EncValue, FileReader, FileWriter, S3Reader, S3Writer, StreamReader, StreamWriter};
+use cryptr::{EncValue, FileWriter, S3Reader, S3Writer, StreamReader, StreamWriter};
 use std::env;
 use std::sync::Arc;
 use tokio::task;
@@ -75,17 +75,18 @@ impl S3Config {
         }
     }
 
-    pub(crate) async fn push(&self, path: &str, object: &str) -> Result<(), Error> {
-        let reader = StreamReader::File(FileReader {
-            path,
-            print_progress: false,
-        });
+    pub(crate) async fn push(
+        &self,
+        path: &str,
+        object: &str,
+        on_progress: &(dyn Fn(u64) + Send + Sync),
+    ) -> Result<(), Error> {
         let writer = StreamWriter::S3(S3Writer {
             bucket: &self.bucket,
             object,
         });
 
-        EncValue::encrypt_stream(reader, writer)
+        crate::backup_target::encrypt_file(path, writer, on_progress)
             .await
             .map_err(|err| Error::S3(err.to_string()))
     }
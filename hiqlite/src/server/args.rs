@@ -12,6 +12,12 @@ pub enum Args {
 
     /// Generate a new default config with safe values for testing
     GenerateConfig(ArgsGenerate),
+
+    /// Create a logical export of a running cluster as SQL dump, CSV or JSON Lines
+    Export(ArgsExport),
+
+    /// Import files from a logical export into a running cluster
+    Import(ArgsImport),
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -28,6 +34,15 @@ pub struct ArgsConfig {
     #[clap(long)]
     pub log_statements: Option<bool>,
 
+    /// Bootstrap a new cluster from this existing SQLite file. Only used by a pristine node 1.
+    #[clap(long)]
+    pub bootstrap_db: Option<String>,
+
+    /// A directory with `<id>_<name>.sql` migrations, which have been applied to the
+    /// `--bootstrap-db` already.
+    #[clap(long, requires = "bootstrap_db")]
+    pub bootstrap_migrations: Option<String>,
+
     /// Server Log Level
     #[clap(short, long, default_value = "info")]
     pub log_level: LogLevel,
@@ -55,6 +70,66 @@ pub struct ArgsGenerate {
     pub insecure_cookie: bool,
 }
 
+#[derive(Debug, Clone, Parser)]
+pub struct ArgsExport {
+    /// The config file name of a cluster node to get the connection details from
+    #[clap(short, long, default_value = "$HOME/.hiqlite/hiqlite.toml")]
+    pub config_file: String,
+
+    /// The format of the export
+    #[clap(short, long, default_value = "sql")]
+    pub format: DumpFormat,
+
+    /// The directory for the exported files, which will be created if it does not exist
+    #[clap(short, long)]
+    pub dir: String,
+
+    /// Only export the given table. Can be given multiple times. Exports all tables if empty.
+    #[clap(short, long)]
+    pub table: Vec<String>,
+
+    /// Log Level
+    #[clap(short, long, default_value = "info")]
+    pub log_level: LogLevel,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct ArgsImport {
+    /// The config file name of a cluster node to get the connection details from
+    #[clap(short, long, default_value = "$HOME/.hiqlite/hiqlite.toml")]
+    pub config_file: String,
+
+    /// The format of the files
+    #[clap(short, long, default_value = "sql")]
+    pub format: DumpFormat,
+
+    /// Log Level
+    #[clap(short, long, default_value = "info")]
+    pub log_level: LogLevel,
+
+    /// The files to import in the given order. CSV and JSON Lines files must be named after
+    /// the table they are imported into.
+    #[clap(required = true)]
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum DumpFormat {
+    Sql,
+    Csv,
+    Jsonl,
+}
+
+impl From<DumpFormat> for crate::ExportFormat {
+    fn from(value: DumpFormat) -> Self {
+        match value {
+            DumpFormat::Sql => Self::Sql,
+            DumpFormat::Csv => Self::Csv,
+            DumpFormat::Jsonl => Self::JsonLines,
+        }
+    }
+}
+
 #[derive(Debug, Clone, ValueEnum)]
 pub enum LogLevel {
     Debug,
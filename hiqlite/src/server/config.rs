@@ -1,7 +1,7 @@
 use crate::helpers::{read_line_stdin, set_path_access};
 use crate::server::args::{ArgsConfig, ArgsGenerate};
 use crate::server::password;
-use crate::{Error, NodeConfig};
+use crate::{BootstrapDb, Client, Error, NodeConfig};
 use cryptr::{EncKeys, utils};
 use tokio::fs;
 
@@ -19,10 +19,41 @@ pub async fn build_node_config(args: ArgsConfig) -> Result<NodeConfig, Error> {
     if let Some(log) = args.log_statements {
         config.log_statements = log;
     }
+    if let Some(path) = args.bootstrap_db {
+        let bootstrap = BootstrapDb::new(path);
+        config.bootstrap_db = Some(match args.bootstrap_migrations {
+            Some(dir) => bootstrap.with_migrations_dir(&dir)?,
+            None => bootstrap,
+        });
+    }
 
     Ok(config)
 }
 
+/// Builds a remote client for the cluster from the node config at `config_file`.
+pub async fn build_remote_client(config_file: String) -> Result<Client, Error> {
+    let config_path = if config_file == "$HOME/.hiqlite/hiqlite.toml" {
+        default_config_file_path()
+    } else {
+        config_file
+    };
+    let config = NodeConfig::from_toml(&config_path, None, None, None).await?;
+
+    Client::remote(
+        config.nodes.into_iter().map(|n| n.addr_api).collect(),
+        config.tls_api.is_some(),
+        config
+            .tls_api
+            .as_ref()
+            .is_some_and(|tls| tls.danger_tls_no_verify()),
+        config.secret_api,
+        false,
+        None,
+        None,
+    )
+    .await
+}
+
 pub async fn generate(args: ArgsGenerate) -> Result<(), Error> {
     let path = default_config_dir();
     fs::create_dir_all(&path).await?;
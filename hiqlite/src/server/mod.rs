@@ -41,6 +41,37 @@ pub async fn server() -> Result<(), Error> {
             logging::init_logging(&LogLevel::Info, None);
             config::generate(args).await?;
         }
+
+        Args::Export(args) => {
+            logging::init_logging(&args.log_level, None);
+
+            let client = config::build_remote_client(args.config_file).await?;
+            let tables = args.table.iter().map(String::as_str).collect::<Vec<_>>();
+            let summary = client
+                .export(&args.dir, args.format.into(), &tables)
+                .await?;
+
+            for (table, rows) in summary.tables {
+                println!("{table}: {rows} rows");
+            }
+            for file in summary.files {
+                println!("Written: {file}");
+            }
+        }
+
+        Args::Import(args) => {
+            logging::init_logging(&args.log_level, None);
+
+            let client = config::build_remote_client(args.config_file).await?;
+            let format = crate::ExportFormat::from(args.format);
+            for file in args.files {
+                let summary = client.import(&file, format).await?;
+                println!(
+                    "{file}: {} statements in {} transactions",
+                    summary.statements, summary.transactions
+                );
+            }
+        }
     }
 
     Ok(())
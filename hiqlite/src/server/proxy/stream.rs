@@ -1,14 +1,17 @@
 use crate::helpers::{deserialize, serialize};
 use crate::network::api::{
     ApiStreamRequest, ApiStreamRequestPayload, ApiStreamResponse, ApiStreamResponsePayload,
-    WsWriteMsg,
+    WsWriteMsg, stream_change_events, stream_query_chunks, stream_watch_events,
 };
 use crate::network::handshake::HandshakeSecret;
+use crate::query::timeout;
 use crate::server::proxy::handlers::AppStateExt;
 use crate::store::state_machine::sqlite::state_machine::Query;
 use crate::{Client, Error};
 use fastwebsockets::{FragmentCollectorRead, Frame, OpCode, Payload, upgrade};
+use std::collections::HashMap;
 use std::ops::Deref;
+use std::time::Duration;
 use tokio::task;
 use tracing::{debug, error};
 
@@ -35,6 +38,10 @@ pub async fn handle_socket(
     // IMPORTANT: the reader is NOT CANCEL SAFE in v0.8!
     let mut read = FragmentCollectorRead::new(rx);
 
+    let mut query_streams: HashMap<usize, flume::Sender<()>> = HashMap::new();
+    let mut change_subscriptions: HashMap<usize, flume::Sender<()>> = HashMap::new();
+    let mut watches: HashMap<usize, flume::Sender<()>> = HashMap::new();
+
     let handle_write = task::spawn(async move {
         while let Ok(req) = rx_write.recv_async().await {
             match req {
@@ -106,28 +113,171 @@ pub async fn handle_socket(
             }
         };
 
+        match req.payload {
+            ApiStreamRequestPayload::QueryStream(_)
+            | ApiStreamRequestPayload::QueryStreamTimeout(_) => {
+                let (query, client) = match req.payload {
+                    ApiStreamRequestPayload::QueryStream(query) => (query, state.client.clone()),
+                    ApiStreamRequestPayload::QueryStreamTimeout((query, ms)) => (
+                        query,
+                        state.client.with_query_timeout(Duration::from_millis(ms)),
+                    ),
+                    _ => unreachable!(),
+                };
+                query_streams.retain(|_, tx| !tx.is_disconnected());
+
+                let (tx_next, rx_next) = flume::bounded(1);
+                query_streams.insert(req.request_id, tx_next);
+
+                let tx_write = tx_write.clone();
+                task::spawn(async move {
+                    let (tx_chunks, rx_chunks) = flume::bounded(1);
+                    task::spawn(async move {
+                        let mut chunks = match client.query_stream_chunks(query).await {
+                            Ok(chunks) => chunks,
+                            Err(err) => {
+                                let _ = tx_chunks.send_async(Err(err)).await;
+                                return;
+                            }
+                        };
+                        // dropping the `chunks` early will cancel the upstream query as well
+                        loop {
+                            let res = chunks.next().await;
+                            let is_last = !matches!(res, Ok(Some(_)));
+                            if tx_chunks.send_async(res).await.is_err() || is_last {
+                                break;
+                            }
+                        }
+                    });
+
+                    stream_query_chunks(req.request_id, rx_chunks, rx_next, tx_write).await;
+                });
+                continue;
+            }
+            ApiStreamRequestPayload::QueryStreamNext => {
+                if let Some(tx) = query_streams.get(&req.request_id) {
+                    let _ = tx.try_send(());
+                }
+                continue;
+            }
+            ApiStreamRequestPayload::QueryStreamCancel => {
+                query_streams.remove(&req.request_id);
+                continue;
+            }
+            ApiStreamRequestPayload::SubscribeChanges((tables, resume_from)) => {
+                change_subscriptions.retain(|_, tx| !tx.is_disconnected());
+
+                let (tx_cancel, rx_cancel) = flume::bounded(1);
+                change_subscriptions.insert(req.request_id, tx_cancel);
+
+                let client = state.client.clone();
+                let tx_write = tx_write.clone();
+                task::spawn(async move {
+                    let (res, rx) = match client.subscribe_changes_rx(tables, resume_from).await {
+                        Ok(rx) => (Ok(()), Some(rx)),
+                        Err(err) => (Err(err), None),
+                    };
+                    let ack = ApiStreamResponse {
+                        request_id: req.request_id,
+                        result: ApiStreamResponsePayload::SubscribeChanges(res),
+                    };
+                    if tx_write.send_async(WsWriteMsg::Payload(ack)).await.is_err() {
+                        return;
+                    }
+                    let Some(mut rx) = rx else {
+                        return;
+                    };
+
+                    // a lagging subscriber is detected upstream, this only needs to pass events on
+                    let (tx_events, rx_events) = flume::bounded(1);
+                    task::spawn(async move {
+                        // dropping the `rx` early will cancel the upstream subscription as well
+                        while let Some(res) = rx.next().await {
+                            if tx_events.send_async(res).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+
+                    stream_change_events(req.request_id, rx_events, rx_cancel, tx_write).await;
+                });
+                continue;
+            }
+            ApiStreamRequestPayload::SubscribeChangesCancel => {
+                change_subscriptions.remove(&req.request_id);
+                continue;
+            }
+            ApiStreamRequestPayload::Watch((cache_idx, key, resume_from)) => {
+                watches.retain(|_, tx| !tx.is_disconnected());
+
+                let (tx_cancel, rx_cancel) = flume::bounded(1);
+                watches.insert(req.request_id, tx_cancel);
+
+                let client = state.client.clone();
+                let tx_write = tx_write.clone();
+                task::spawn(async move {
+                    let (res, rx) = match client.watch_rx(cache_idx, key, resume_from).await {
+                        Ok(rx) => (Ok(()), Some(rx)),
+                        Err(err) => (Err(err), None),
+                    };
+                    let ack = ApiStreamResponse {
+                        request_id: req.request_id,
+                        result: ApiStreamResponsePayload::Watch(res),
+                    };
+                    if tx_write.send_async(WsWriteMsg::Payload(ack)).await.is_err() {
+                        return;
+                    }
+                    let Some(mut rx) = rx else {
+                        return;
+                    };
+
+                    // a lagging watcher is detected upstream, this only needs to pass events on
+                    let (tx_events, rx_events) = flume::bounded(1);
+                    task::spawn(async move {
+                        // dropping the `rx` early will cancel the upstream watch as well
+                        while let Some(res) = rx.next().await {
+                            if tx_events.send_async(res).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+
+                    stream_watch_events(req.request_id, rx_events, rx_cancel, tx_write).await;
+                });
+                continue;
+            }
+            ApiStreamRequestPayload::WatchCancel => {
+                watches.remove(&req.request_id);
+                continue;
+            }
+            _ => {}
+        }
+
         let state = state.clone();
         let tx_write = tx_write.clone();
         task::spawn(async move {
             let client = &state.client;
             // exchange orig req id for our own to avoid conflicts
             let request_id = req.request_id;
+            let with_log_id = req.payload.wants_log_id();
 
             let res = match req.payload {
-                ApiStreamRequestPayload::Execute(sql) => {
-                    let res = client.execute(sql.sql, sql.params).await;
+                ApiStreamRequestPayload::Execute(sql)
+                | ApiStreamRequestPayload::ExecuteLogId(sql) => {
+                    let res = client.execute_with_key(sql, None, with_log_id).await;
                     ApiStreamResponse {
                         request_id,
-                        result: ApiStreamResponsePayload::Execute(res),
+                        result: ApiStreamResponsePayload::execute(res, with_log_id),
                     }
                 }
 
-                ApiStreamRequestPayload::ExecuteReturning(query) => {
-                    match client.execute_returning_req(query.clone()).await {
-                        Ok(res) => ApiStreamResponse {
-                            request_id,
-                            result: ApiStreamResponsePayload::ExecuteReturning(Ok(res)),
-                        },
+                ApiStreamRequestPayload::ExecuteReturning(query)
+                | ApiStreamRequestPayload::ExecuteReturningLogId(query) => {
+                    let res = match client
+                        .execute_returning_req(query.clone(), with_log_id)
+                        .await
+                    {
+                        Ok(res) => Ok(res),
                         Err(err) => {
                             if client
                                 .was_leader_update_error(
@@ -137,23 +287,21 @@ pub async fn handle_socket(
                                 )
                                 .await
                             {
-                                let res = client.execute_returning_req(query).await;
-                                ApiStreamResponse {
-                                    request_id,
-                                    result: ApiStreamResponsePayload::ExecuteReturning(res),
-                                }
+                                client.execute_returning_req(query, with_log_id).await
                             } else {
-                                ApiStreamResponse {
-                                    request_id,
-                                    result: ApiStreamResponsePayload::ExecuteReturning(Err(err)),
-                                }
+                                Err(err)
                             }
                         }
+                    };
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::execute_returning(res, with_log_id),
                     }
                 }
 
-                ApiStreamRequestPayload::Transaction(queries) => {
-                    let res = match client.txn_execute(queries.clone()).await {
+                ApiStreamRequestPayload::Transaction(queries)
+                | ApiStreamRequestPayload::TransactionLogId(queries) => {
+                    let res = match client.txn_execute(queries.clone(), None, with_log_id).await {
                         Ok(res) => Ok(res),
                         Err(err) => {
                             if client
@@ -164,7 +312,7 @@ pub async fn handle_socket(
                                 )
                                 .await
                             {
-                                client.txn_execute(queries).await
+                                client.txn_execute(queries, None, with_log_id).await
                             } else {
                                 Err(err)
                             }
@@ -172,19 +320,49 @@ pub async fn handle_socket(
                     };
                     ApiStreamResponse {
                         request_id,
-                        result: ApiStreamResponsePayload::Transaction(res),
+                        result: ApiStreamResponsePayload::transaction(res, with_log_id),
+                    }
+                }
+
+                ApiStreamRequestPayload::ExecuteIdempotent((key, sql)) => {
+                    let res = client.execute_with_key(sql, Some(key), with_log_id).await;
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::execute(res, with_log_id),
+                    }
+                }
+
+                ApiStreamRequestPayload::TransactionIdempotent((key, queries)) => {
+                    let res = client.txn_with_key(queries, Some(key), with_log_id).await;
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::transaction(res, with_log_id),
+                    }
+                }
+
+                ApiStreamRequestPayload::ExecuteMany(query) => {
+                    let res = client
+                        .execute_many_with_log_id(query.sql, query.params)
+                        .await;
+                    ApiStreamResponse {
+                        request_id,
+                        result: ApiStreamResponsePayload::ExecuteMany(res),
                     }
                 }
 
                 ApiStreamRequestPayload::QueryConsistent(q) => {
-                    query(client, request_id, q, true).await
+                    query(client, request_id, q, true, None).await
                 }
 
-                ApiStreamRequestPayload::Batch(sql) => {
-                    let res = client.batch(sql).await;
+                ApiStreamRequestPayload::QueryConsistentTimeout((q, ms)) => {
+                    query(client, request_id, q, true, Some(ms)).await
+                }
+
+                ApiStreamRequestPayload::Batch(sql) | ApiStreamRequestPayload::BatchLogId(sql) => {
+                    let res = client.batch_with(sql, with_log_id).await;
                     ApiStreamResponse {
                         request_id,
-                        result: ApiStreamResponsePayload::Batch(res),
+                        result: ApiStreamResponsePayload::batch(res, with_log_id),
                     }
                 }
 
@@ -220,7 +398,13 @@ pub async fn handle_socket(
                     }
                 }
 
-                ApiStreamRequestPayload::Query(q) => query(client, request_id, q, false).await,
+                ApiStreamRequestPayload::Query(q) => {
+                    query(client, request_id, q, false, None).await
+                }
+
+                ApiStreamRequestPayload::QueryTimeout((q, ms)) => {
+                    query(client, request_id, q, false, Some(ms)).await
+                }
 
                 ApiStreamRequestPayload::KV(cache_req) => {
                     let res = client.cache_req_retry(cache_req, false).await;
@@ -253,6 +437,22 @@ pub async fn handle_socket(
                         result: ApiStreamResponsePayload::Notify(res),
                     }
                 }
+
+                ApiStreamRequestPayload::QueryStream(_)
+                | ApiStreamRequestPayload::QueryStreamTimeout(_)
+                | ApiStreamRequestPayload::QueryStreamNext
+                | ApiStreamRequestPayload::QueryStreamCancel => {
+                    unreachable!("query streams are handled in the WebSocket reader loop")
+                }
+
+                ApiStreamRequestPayload::SubscribeChanges(_)
+                | ApiStreamRequestPayload::SubscribeChangesCancel => {
+                    unreachable!("change subscriptions are handled in the WebSocket reader loop")
+                }
+
+                ApiStreamRequestPayload::Watch(_) | ApiStreamRequestPayload::WatchCancel => {
+                    unreachable!("cache watches are handled in the WebSocket reader loop")
+                }
             };
 
             if let Err(err) = tx_write.send_async(WsWriteMsg::Payload(res)).await {
@@ -279,15 +479,20 @@ async fn query(
     request_id: usize,
     query: Query,
     consistent: bool,
+    timeout_ms: Option<u64>,
 ) -> ApiStreamResponse {
-    let res = match client.query_remote_req(query.clone(), consistent).await {
+    let deadline = timeout::deadline(timeout_ms.map(Duration::from_millis));
+    let res = match client
+        .query_remote_req(query.clone(), consistent, deadline)
+        .await
+    {
         Ok(res) => Ok(res),
         Err(err) => {
             if client
                 .was_leader_update_error(&err, &client.inner.leader_db, &client.inner.tx_client_db)
                 .await
             {
-                client.query_remote_req(query, consistent).await
+                client.query_remote_req(query, consistent, deadline).await
             } else {
                 Err(err)
             }
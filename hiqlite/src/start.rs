@@ -17,6 +17,8 @@ use tracing::{debug, info};
 
 #[cfg(feature = "backup")]
 use crate::backup;
+#[cfg(feature = "sqlite")]
+use crate::bootstrap;
 #[cfg(feature = "dashboard")]
 use crate::dashboard;
 
@@ -50,16 +52,34 @@ where
 
     #[cfg(all(feature = "backup", feature = "sqlite"))]
     let backup_applied = backup::restore_backup_start(&node_config).await?;
+    #[cfg(all(feature = "backup", feature = "cache"))]
+    let cache_backup = backup::restore_cache_start(&node_config).await?;
+    #[cfg(all(feature = "backup", feature = "cache"))]
+    let cache_backup_applied = cache_backup.is_some();
+
+    #[cfg(feature = "sqlite")]
+    let bootstrapped = bootstrap::bootstrap_db_start(&node_config).await?;
 
     let raft_config = Arc::new(node_config.raft_config.clone().validate().unwrap());
 
     let _do_reset_metadata = init::check_execute_reset(&node_config.data_dir).await?;
     #[cfg(feature = "sqlite")]
-    let raft_db =
-        store::start_raft_db(&node_config, raft_config.clone(), _do_reset_metadata).await?;
+    let raft_db = store::start_raft_db(
+        &node_config,
+        raft_config.clone(),
+        _do_reset_metadata,
+        bootstrapped,
+    )
+    .await?;
 
     #[cfg(feature = "cache")]
-    let raft_cache = store::start_raft_cache::<C>(&node_config, raft_config.clone()).await?;
+    let raft_cache = store::start_raft_cache::<C>(
+        &node_config,
+        raft_config.clone(),
+        #[cfg(feature = "backup")]
+        cache_backup,
+    )
+    .await?;
 
     let (api_addr, rpc_addr) = {
         let node = node_config
@@ -85,6 +105,8 @@ where
         is_shutting_down: AtomicBool::new(false),
         #[cfg(feature = "backup")]
         backups_dir: format!("{}/state_machine/backups", node_config.data_dir),
+        #[cfg(feature = "backup")]
+        restore: crate::restore::OnlineRestore::new(node_config.wal_size),
         id: node_config.node_id,
         #[cfg(feature = "cache")]
         nodes: node_config.nodes.clone(),
@@ -108,6 +130,10 @@ where
         learner_only: node_config.learner_only,
         #[cfg(feature = "s3")]
         s3_config: node_config.s3_config.clone(),
+        #[cfg(feature = "backup")]
+        backup_targets: node_config
+            .backup_config
+            .targets(node_config.s3_config.as_ref()),
     });
 
     #[cfg(any(feature = "sqlite", feature = "cache"))]
@@ -121,6 +147,10 @@ where
     if backup_applied {
         backup::restore_backup_finish(&state).await;
     }
+    #[cfg(all(feature = "backup", feature = "cache"))]
+    if cache_backup_applied {
+        backup::restore_cache_finish(&state).await;
+    }
 
     let (tx_shutdown, rx_shutdown) = tokio::sync::watch::channel(false);
 
@@ -159,23 +189,27 @@ where
         }));
     };
 
-    let default_routes = Router::new()
-        .nest(
-            "/cluster",
-            Router::new()
-                .route("/add_learner/{raft_type}", post(management::add_learner))
-                .route(
-                    "/become_member/{raft_type}",
-                    post(management::become_member),
-                )
-                .route(
-                    "/membership/{raft_type}",
-                    get(management::get_membership)
-                        .post(management::post_membership)
-                        .delete(management::leave_cluster),
-                )
-                .route("/metrics/{raft_type}", get(management::metrics)),
+    let cluster_routes = Router::new()
+        .route("/add_learner/{raft_type}", post(management::add_learner))
+        .route(
+            "/become_member/{raft_type}",
+            post(management::become_member),
+        )
+        .route(
+            "/membership/{raft_type}",
+            get(management::get_membership)
+                .post(management::post_membership)
+                .delete(management::leave_cluster),
         )
+        .route("/metrics/{raft_type}", get(management::metrics));
+    #[cfg(feature = "backup")]
+    let cluster_routes = cluster_routes.route(
+        "/restore",
+        get(management::get_restore).post(management::post_restore),
+    );
+
+    let default_routes = Router::new()
+        .nest("/cluster", cluster_routes)
         .route("/listen", get(api::listen))
         .route("/stream/{raft_type}", get(api::stream))
         .route("/backup", post(api::post_create_backup))
@@ -291,7 +325,7 @@ where
     let client = Client::new_local(
         state,
         tls_api_client_config,
-        #[cfg(feature = "cache")]
+        #[cfg(any(feature = "cache", feature = "backup"))]
         tls_no_verify,
         #[cfg(feature = "sqlite")]
         tx_client_stream,
@@ -20,7 +20,7 @@ use tracing::info;
 #[cfg(feature = "cache")]
 use crate::{
     app_state::StateRaftCache,
-    store::state_machine::memory::{TypeConfigKV, state_machine::StateMachineMemory},
+    store::state_machine::memory::{TypeConfigKV, eviction, state_machine::StateMachineMemory},
 };
 #[cfg(feature = "sqlite")]
 use crate::{
@@ -42,17 +42,22 @@ pub(crate) async fn start_raft_db(
     node_config: &NodeConfig,
     raft_config: Arc<RaftConfig>,
     do_reset_metadata: bool,
+    bootstrapped: bool,
 ) -> Result<StateRaftDB, Error> {
     // We always want to start stopped and set to `false` as soon as we found out,
     // that we are not pristine node and need cleanup.
     let is_raft_stopped = Arc::new(AtomicBool::new(true));
 
-    let log_store = hiqlite_wal::LogStore::<TypeConfigSqlite>::start(
+    #[allow(unused_mut)]
+    let mut log_store = hiqlite_wal::LogStore::<TypeConfigSqlite>::start(
         logs::logs_dir_db(&node_config.data_dir),
         node_config.wal_sync.clone(),
         node_config.wal_size,
     )
     .await?;
+    #[cfg(feature = "backup")]
+    let archiver = crate::archive::Archiver::new(node_config, &mut log_store).await;
+
     let state_machine_store = StateMachineSqlite::new(
         &node_config.data_dir,
         &node_config.filename_db,
@@ -60,18 +65,31 @@ pub(crate) async fn start_raft_db(
         node_config.log_statements,
         node_config.prepared_statement_cache_capacity,
         node_config.read_pool_size,
-        #[cfg(feature = "s3")]
-        node_config.s3_config.clone(),
+        node_config.sql_functions.clone(),
+        node_config.write_instruction_budget,
+        #[cfg(feature = "backup")]
+        node_config
+            .backup_config
+            .targets(node_config.s3_config.as_ref()),
         do_reset_metadata,
         #[cfg(feature = "backup")]
-        node_config.backup_keep_days_local,
+        node_config
+            .backup_config
+            .retention_local(node_config.backup_keep_days_local),
+        #[cfg(feature = "backup")]
+        node_config.backup_config.verify(),
+        #[cfg(feature = "backup")]
+        node_config.backup_config.differential(),
     )
     .await
     .unwrap();
 
     let is_startup_finished = Arc::new(AtomicBool::new(false));
     let sql_writer = state_machine_store.write_tx.clone();
+    let tx_changes = state_machine_store.tx_changes.clone();
     let read_pool = state_machine_store.read_pool.clone();
+    #[cfg(feature = "backup")]
+    let backup_progress = state_machine_store.backup_progress.clone();
 
     let network = NetworkStreaming {
         node_id: node_config.node_id,
@@ -95,6 +113,11 @@ pub(crate) async fn start_raft_db(
     .await
     .expect("Raft create failed");
 
+    #[cfg(feature = "backup")]
+    if let Some(archiver) = archiver {
+        archiver.spawn(raft.clone(), read_pool.clone());
+    }
+
     init::init_pristine_node_1_db(
         &raft,
         node_config.node_id,
@@ -106,17 +129,27 @@ pub(crate) async fn start_raft_db(
             .as_ref()
             .map(|c| c.danger_tls_no_verify())
             .unwrap_or(false),
+        bootstrapped,
     )
     .await?;
+    if bootstrapped && raft.metrics().borrow().snapshot.is_some() {
+        crate::bootstrap::bootstrap_db_finish(&node_config.data_dir).await?;
+    }
 
     Ok(StateRaftDB {
         raft,
         shutdown_handle,
         sql_writer,
+        tx_changes,
         read_pool,
         log_statements: node_config.log_statements,
+        query_timeout: node_config.query_timeout,
         is_raft_stopped,
         is_startup_finished,
+        leader_contact: Default::default(),
+        has_write_budget: node_config.write_instruction_budget.is_some(),
+        #[cfg(feature = "backup")]
+        backup_progress,
     })
 }
 
@@ -124,6 +157,7 @@ pub(crate) async fn start_raft_db(
 pub(crate) async fn start_raft_cache<C>(
     node_config: &NodeConfig,
     raft_config: Arc<RaftConfig>,
+    #[cfg(feature = "backup")] cache_backup: Option<Vec<u8>>,
 ) -> Result<StateRaftCache, Error>
 where
     C: Debug + CacheVariants,
@@ -136,8 +170,12 @@ where
     let is_startup_finished = Arc::new(AtomicBool::new(false));
 
     let state_machine_store = Arc::new(
-        StateMachineMemory::new::<C>(&node_config.data_dir, !node_config.cache_storage_disk)
-            .await?,
+        StateMachineMemory::new::<C>(
+            &node_config.data_dir,
+            !node_config.cache_storage_disk,
+            &node_config.cache_limits,
+        )
+        .await?,
     );
     let network = NetworkStreaming {
         node_id: node_config.node_id,
@@ -149,7 +187,15 @@ where
         is_raft_stopped: is_raft_stopped.clone(),
     };
 
+    #[cfg(feature = "backup")]
+    if let Some(bytes) = cache_backup {
+        info!("Applying cache backup");
+        state_machine_store.backup_restore(&bytes).await?;
+    }
+
     let tx_caches = state_machine_store.tx_caches.clone();
+    let limited_caches = state_machine_store.limited_caches.clone();
+    let rx_evict = state_machine_store.rx_evict.clone();
     #[cfg(feature = "listen_notify")]
     let tx_notify = state_machine_store.tx_notify.clone();
     #[cfg(feature = "listen_notify_local")]
@@ -158,6 +204,9 @@ where
     #[cfg(feature = "dlock")]
     let tx_dlock = state_machine_store.tx_dlock.clone();
 
+    #[cfg(feature = "backup")]
+    let state_machine = state_machine_store.clone();
+
     let (raft, shutdown_handle) = if node_config.cache_storage_disk {
         let log_store = hiqlite_wal::LogStore::<TypeConfigKV>::start(
             logs::logs_dir_cache(&node_config.data_dir),
@@ -192,6 +241,14 @@ where
         (raft, None)
     };
 
+    eviction::spawn_evictor(
+        raft.clone(),
+        node_config.node_id,
+        tx_caches.clone(),
+        limited_caches,
+        rx_evict,
+    );
+
     init::init_pristine_node_1_cache(
         &raft,
         node_config.cache_storage_disk,
@@ -220,5 +277,7 @@ where
         is_startup_finished,
         shutdown_handle,
         cache_storage_disk: node_config.cache_storage_disk,
+        #[cfg(feature = "backup")]
+        state_machine,
     })
 }
@@ -0,0 +1,182 @@
+use crate::Error;
+use crate::helpers::{deserialize, serialize};
+use crate::store::state_machine::memory::state_machine::{
+    SnapshotKV, SnapshotLocks, SnapshotTTL, SnapshotVersion, StateMachineMemory,
+};
+use openraft::SnapshotMeta;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// The content of a cache backup. Caches are identified by their name instead of their index,
+/// so a backup can still be restored after the cache enum has been changed.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheBackup {
+    caches: Vec<(String, SnapshotKV, SnapshotTTL, SnapshotVersion)>,
+    locks: SnapshotLocks,
+}
+
+impl StateMachineMemory {
+    /// Serializes the content of all caches, counters, TTLs, entry versions and locks into a
+    /// backup.
+    pub(crate) async fn backup_build(&self) -> Result<Vec<u8>, Error> {
+        // make sure no entries are applied in the meantime
+        let _data = self.data.read().await;
+        let (kvs, ttls, locks, versions) = self.snapshot_parts().await;
+
+        let caches = self
+            .cache_names
+            .iter()
+            .zip(kvs.into_iter().zip(ttls).zip(versions))
+            .map(|(name, ((kv, ttl), vers))| (name.to_string(), kv, ttl, vers))
+            .collect();
+
+        Ok(serialize(&CacheBackup { caches, locks })?)
+    }
+
+    /// Replaces the whole content with the given backup. Must only be called on a pristine
+    /// node before the Raft has been started.
+    pub(crate) async fn backup_restore(&self, bytes: &[u8]) -> Result<(), Error> {
+        let backup = deserialize::<CacheBackup>(bytes)?;
+
+        let mut kvs = vec![SnapshotKV::default(); self.cache_names.len()];
+        let mut ttls = vec![SnapshotTTL::default(); self.cache_names.len()];
+        let mut versions = vec![SnapshotVersion::default(); self.cache_names.len()];
+        for (name, kv, ttl, vers) in backup.caches {
+            if let Some(idx) = self.cache_names.iter().position(|n| *n == name) {
+                kvs[idx] = kv;
+                ttls[idx] = ttl;
+                versions[idx] = vers;
+            } else {
+                warn!("Cache {name} from the backup does not exist anymore - skipping it");
+            }
+        }
+
+        // a backup from a node without the `dlock` feature does not contain any locks
+        #[cfg(feature = "dlock")]
+        let locks = if backup.locks.is_empty() {
+            serialize(&std::collections::HashMap::<
+                String,
+                crate::store::state_machine::memory::dlock_handler::LockQueue,
+            >::new())?
+        } else {
+            backup.locks
+        };
+        #[cfg(not(feature = "dlock"))]
+        let locks = backup.locks;
+
+        self.update_state_machine((SnapshotMeta::default(), kvs, ttls, locks, versions))
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CacheVariants;
+    use crate::store::state_machine::memory::eviction::CacheLimits;
+    use crate::store::state_machine::memory::kv_handler::CacheRequestHandler;
+    use tokio::sync::oneshot;
+
+    #[derive(Debug)]
+    enum CacheOld {
+        One,
+        Two,
+    }
+
+    impl CacheVariants for CacheOld {
+        fn hiqlite_cache_index(&self) -> usize {
+            match self {
+                Self::One => 0,
+                Self::Two => 1,
+            }
+        }
+
+        fn hiqlite_cache_variants() -> &'static [(usize, &'static str)] {
+            &[(0, "One"), (1, "Two")]
+        }
+    }
+
+    /// `Two` has been moved to index `0` and `One` has been removed.
+    #[derive(Debug)]
+    enum CacheNew {
+        Two,
+        Three,
+    }
+
+    impl CacheVariants for CacheNew {
+        fn hiqlite_cache_index(&self) -> usize {
+            match self {
+                Self::Two => 0,
+                Self::Three => 1,
+            }
+        }
+
+        fn hiqlite_cache_variants() -> &'static [(usize, &'static str)] {
+            &[(0, "Two"), (1, "Three")]
+        }
+    }
+
+    fn put(sm: &StateMachineMemory, idx: usize, key: &str, value: &[u8]) {
+        sm.tx_caches[idx]
+            .send(CacheRequestHandler::Put((
+                key.to_string(),
+                value.to_vec(),
+                None,
+                1,
+            )))
+            .unwrap();
+    }
+
+    async fn get(sm: &StateMachineMemory, idx: usize, key: &str) -> Option<Vec<u8>> {
+        let (ack, rx) = oneshot::channel();
+        sm.tx_caches[idx]
+            .send(CacheRequestHandler::Get((key.to_string(), ack)))
+            .unwrap();
+        rx.await.unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn backup_restore_maps_caches_by_name() {
+        let base_dir =
+            std::env::temp_dir().join(format!("hiqlite_cache_backup_{}", uuid::Uuid::now_v7()));
+        let base_old = base_dir.join("old");
+        let base_new = base_dir.join("new");
+
+        let sm_old = StateMachineMemory::new::<CacheOld>(
+            base_old.to_str().unwrap(),
+            false,
+            &CacheLimits::default(),
+        )
+        .await
+        .unwrap();
+        put(&sm_old, CacheOld::One.hiqlite_cache_index(), "key", b"one");
+        put(&sm_old, CacheOld::Two.hiqlite_cache_index(), "key", b"two");
+        // the cache handlers work through their queue in order
+        assert!(get(&sm_old, 1, "key").await.is_some());
+        let backup = sm_old.backup_build().await.unwrap();
+
+        let sm_new = StateMachineMemory::new::<CacheNew>(
+            base_new.to_str().unwrap(),
+            false,
+            &CacheLimits::default(),
+        )
+        .await
+        .unwrap();
+        let idx_two = CacheNew::Two.hiqlite_cache_index();
+        let idx_three = CacheNew::Three.hiqlite_cache_index();
+        put(&sm_new, idx_three, "key", b"three");
+        sm_new.backup_restore(&backup).await.unwrap();
+
+        assert_eq!(
+            get(&sm_new, idx_two, "key").await.as_deref(),
+            Some(b"two".as_slice())
+        );
+        // the restore replaces everything, even caches that are not inside the backup
+        assert_eq!(get(&sm_new, idx_three, "key").await, None);
+
+        assert!(sm_new.backup_restore(b"invalid").await.is_err());
+
+        let _ = std::fs::remove_dir_all(base_dir);
+    }
+}
@@ -36,7 +36,7 @@ fn spawn_with_clock(
 const SECONDS_THRESHOLD: i64 = 1_000_000_000_000;
 
 /// Maps a possibly old second-precision expiry to micros.
-fn normalize(exp: i64) -> i64 {
+pub(super) fn normalize(exp: i64) -> i64 {
     if exp < SECONDS_THRESHOLD {
         exp * 1_000_000
     } else {
@@ -68,7 +68,7 @@ async fn ttl_handler(
                     if exp_of.get(&key) == Some(&exp) {
                         exp_of.remove(&key);
                         tx_kv
-                            .send(CacheRequestHandler::Delete(key))
+                            .send(CacheRequestHandler::Expire(key))
                             .expect("kv handler to always be running");
                     }
                     continue;
@@ -170,7 +170,7 @@ mod tests {
         sync(&tx).await;
 
         let mut got = vec![];
-        while let Ok(CacheRequestHandler::Delete(k)) = rx_kv.try_recv() {
+        while let Ok(CacheRequestHandler::Expire(k)) = rx_kv.try_recv() {
             got.push(k);
         }
         got.sort();
@@ -201,7 +201,7 @@ mod tests {
         clock.store(T0 + 61, Ordering::Relaxed);
         sync(&tx).await;
         match rx_kv.try_recv() {
-            Ok(CacheRequestHandler::Delete(k)) => assert_eq!(k, "k".to_string()),
+            Ok(CacheRequestHandler::Expire(k)) => assert_eq!(k, "k".to_string()),
             other => panic!("expected Delete, got {other:?}"),
         }
     }
@@ -251,7 +251,7 @@ mod tests {
         clock.store(T0 + 1_000_001, Ordering::Relaxed);
         sync(&tx).await;
         match rx_kv.try_recv() {
-            Ok(CacheRequestHandler::Delete(k)) => assert_eq!(k, "k".to_string()),
+            Ok(CacheRequestHandler::Expire(k)) => assert_eq!(k, "k".to_string()),
             other => panic!("expected Delete, got {other:?}"),
         }
     }
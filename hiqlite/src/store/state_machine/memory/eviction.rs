@@ -0,0 +1,498 @@
+//! Memory limits for caches.
+//!
+//! Each `kv_handler` tracks the size of its cache and, if it has a `CacheLimit`, the last access
+//! and the hits of each key on this node. When a cache grows beyond its limit, it signals the
+//! evictor task. Only the evictor of the current leader picks the victims by the configured
+//! policy and replicates them as a `CacheRequest::Evict`, so all replicas evict the exact same
+//! keys and stay identical.
+
+use crate::NodeId;
+use crate::store::state_machine::memory::TypeConfigKV;
+use crate::store::state_machine::memory::kv_handler::CacheRequestHandler;
+use crate::store::state_machine::memory::state_machine::CacheRequest;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::{task, time};
+use tracing::{debug, error, warn};
+
+/// The max amount of keys a single eviction log entry contains. Larger evictions are split up.
+const MAX_KEYS_PER_EVICTION: usize = 10_000;
+/// Caches are checked regularly as well, e.g. when this node has just become the leader.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The policy that decides which entries are evicted, when a cache exceeds its `CacheLimit`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvictionPolicy {
+    /// Evicts the least recently used entries first.
+    #[default]
+    Lru,
+    /// Evicts the least frequently used entries first. The hits of all entries are halved
+    /// with each eviction, so formerly hot entries can age out.
+    Lfu,
+}
+
+impl TryFrom<&str> for EvictionPolicy {
+    type Error = crate::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "lru" => Ok(Self::Lru),
+            "lfu" => Ok(Self::Lfu),
+            _ => Err(crate::Error::Config(
+                format!("invalid eviction policy '{value}', expected 'lru' or 'lfu'").into(),
+            )),
+        }
+    }
+}
+
+/// The memory limit for a single cache.
+///
+/// As soon as a cache exceeds one of its limits, the leader evicts entries by the `policy`
+/// until the cache is back at 90% of its limits. Counters are never evicted.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CacheLimit {
+    pub max_entries: Option<u64>,
+    /// The size of all keys and values in bytes.
+    pub max_bytes: Option<u64>,
+    pub policy: EvictionPolicy,
+}
+
+impl CacheLimit {
+    pub fn new(policy: EvictionPolicy) -> Self {
+        Self {
+            max_entries: None,
+            max_bytes: None,
+            policy,
+        }
+    }
+
+    pub fn with_max_entries(mut self, max_entries: u64) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.max_entries.is_none() && self.max_bytes.is_none()
+    }
+}
+
+/// The memory limits of all caches. By default, caches are unbounded.
+///
+/// All nodes should use the same limits, because only the current leader evicts entries.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CacheLimits {
+    /// Applies to all caches without their own limit.
+    pub default: Option<CacheLimit>,
+    /// Limits for single caches by the name of their `CacheVariants` variant.
+    pub caches: HashMap<String, CacheLimit>,
+}
+
+impl CacheLimits {
+    pub fn with_default(mut self, limit: CacheLimit) -> Self {
+        self.default = Some(limit);
+        self
+    }
+
+    pub fn with_cache(mut self, name: impl Into<String>, limit: CacheLimit) -> Self {
+        self.caches.insert(name.into(), limit);
+        self
+    }
+
+    pub(crate) fn get(&self, cache_name: &str) -> Option<&CacheLimit> {
+        self.caches
+            .get(cache_name)
+            .or(self.default.as_ref())
+            .filter(|limit| !limit.is_unlimited())
+    }
+}
+
+/// The current usage of a single cache on this node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub name: String,
+    pub entries: u64,
+    /// The size of all keys and values in bytes.
+    pub bytes: u64,
+    pub max_entries: Option<u64>,
+    pub max_bytes: Option<u64>,
+    /// The amount of evicted entries since this node has started.
+    pub evictions: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Access {
+    tick: u64,
+    hits: u64,
+}
+
+#[derive(Debug)]
+struct Limiter {
+    cache_idx: usize,
+    limit: CacheLimit,
+    tx_evict: flume::Sender<usize>,
+    tick: u64,
+    access: HashMap<String, Access>,
+}
+
+/// Keeps track of the size of a cache and the access of its keys inside the `kv_handler`.
+#[derive(Debug, Default)]
+pub(crate) struct CacheUsage {
+    limiter: Option<Limiter>,
+    bytes: u64,
+    evictions: u64,
+}
+
+#[inline]
+fn entry_size(key: &str, value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
+
+impl CacheUsage {
+    pub(crate) fn new(
+        cache_idx: usize,
+        limit: Option<CacheLimit>,
+        tx_evict: flume::Sender<usize>,
+    ) -> Self {
+        Self {
+            limiter: limit.map(|limit| Limiter {
+                cache_idx,
+                limit,
+                tx_evict,
+                tick: 0,
+                access: HashMap::new(),
+            }),
+            bytes: 0,
+            evictions: 0,
+        }
+    }
+
+    pub(crate) fn accessed(&mut self, key: &str) {
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.tick += 1;
+            let tick = limiter.tick;
+            if let Some(access) = limiter.access.get_mut(key) {
+                access.tick = tick;
+                access.hits = access.hits.saturating_add(1);
+            } else {
+                limiter
+                    .access
+                    .insert(key.to_string(), Access { tick, hits: 1 });
+            }
+        }
+    }
+
+    /// `value` has been inserted for `key`, replacing the `old` value, if it existed.
+    pub(crate) fn inserted(&mut self, key: &str, value: &[u8], old: Option<&[u8]>) {
+        self.bytes += entry_size(key, value);
+        if let Some(old) = old {
+            self.bytes = self.bytes.saturating_sub(entry_size(key, old));
+        }
+        self.accessed(key);
+    }
+
+    pub(crate) fn removed(&mut self, key: &str, value: &[u8]) {
+        self.bytes = self.bytes.saturating_sub(entry_size(key, value));
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.access.remove(key);
+        }
+    }
+
+    pub(crate) fn evicted(&mut self, key: &str, value: &[u8]) {
+        self.removed(key, value);
+        self.evictions += 1;
+    }
+
+    /// Recalculates the usage after the whole `data` has been replaced.
+    pub(crate) fn reset(&mut self, data: &BTreeMap<String, Vec<u8>>) {
+        self.bytes = data.iter().map(|(k, v)| entry_size(k, v)).sum();
+        if let Some(limiter) = self.limiter.as_mut() {
+            limiter.tick = 0;
+            limiter.access.clear();
+        }
+        for key in data.keys() {
+            self.accessed(key);
+        }
+    }
+
+    /// Signals the evictor, if the cache exceeds its limit.
+    pub(crate) fn check(&self, entries: usize) {
+        if let Some(limiter) = &self.limiter
+            && limiter.is_exceeded(entries as u64, self.bytes)
+        {
+            // a full channel means an eviction check is pending anyway
+            let _ = limiter.tx_evict.try_send(limiter.cache_idx);
+        }
+    }
+
+    /// Picks the keys to evict by the configured policy, until the cache is back at 90% of its
+    /// limit. Returns at most `MAX_KEYS_PER_EVICTION` keys.
+    pub(crate) fn eviction_candidates(&mut self, data: &BTreeMap<String, Vec<u8>>) -> Vec<String> {
+        let Some(limiter) = self.limiter.as_mut() else {
+            return Vec::default();
+        };
+        let mut entries = data.len() as u64;
+        let mut bytes = self.bytes;
+        if !limiter.is_exceeded(entries, bytes) {
+            return Vec::default();
+        }
+
+        let target_entries = limiter.limit.max_entries.map(|max| max - max / 10);
+        let target_bytes = limiter.limit.max_bytes.map(|max| max - max / 10);
+
+        let mut order = data
+            .iter()
+            .map(|(key, value)| {
+                let access = limiter.access.get(key).copied().unwrap_or_default();
+                let rank = match limiter.limit.policy {
+                    EvictionPolicy::Lru => (access.tick, 0),
+                    EvictionPolicy::Lfu => (access.hits, access.tick),
+                };
+                (rank, key, value.len())
+            })
+            .collect::<Vec<_>>();
+        order.sort_unstable_by_key(|(rank, _, _)| *rank);
+
+        let mut keys = Vec::new();
+        for (_, key, len) in order {
+            if (target_entries.is_none_or(|target| entries <= target)
+                && target_bytes.is_none_or(|target| bytes <= target))
+                || keys.len() >= MAX_KEYS_PER_EVICTION
+            {
+                break;
+            }
+            entries -= 1;
+            bytes = bytes.saturating_sub((key.len() + len) as u64);
+            keys.push(key.clone());
+        }
+
+        if limiter.limit.policy == EvictionPolicy::Lfu {
+            for access in limiter.access.values_mut() {
+                access.hits /= 2;
+            }
+        }
+
+        keys
+    }
+
+    pub(crate) fn stats(&self, name: &str, entries: usize) -> CacheStats {
+        let limit = self.limiter.as_ref().map(|l| &l.limit);
+        CacheStats {
+            name: name.to_string(),
+            entries: entries as u64,
+            bytes: self.bytes,
+            max_entries: limit.and_then(|l| l.max_entries),
+            max_bytes: limit.and_then(|l| l.max_bytes),
+            evictions: self.evictions,
+        }
+    }
+}
+
+impl Limiter {
+    fn is_exceeded(&self, entries: u64, bytes: u64) -> bool {
+        self.limit.max_entries.is_some_and(|max| entries > max)
+            || self.limit.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+/// Collects the current usage of all caches on this node.
+pub(crate) async fn cache_stats(
+    tx_caches: &[flume::Sender<CacheRequestHandler>],
+) -> Result<Vec<CacheStats>, crate::Error> {
+    let mut stats = Vec::with_capacity(tx_caches.len());
+    for tx in tx_caches {
+        let (ack, rx) = oneshot::channel();
+        tx.send(CacheRequestHandler::Stats(ack))
+            .expect("kv handler to always be running");
+        stats.push(
+            rx.await
+                .map_err(|_| crate::Error::Error("kv handler did not answer".into()))?,
+        );
+    }
+    Ok(stats)
+}
+
+/// Spawns the evictor, which replicates evictions for all caches that exceed their limit, as
+/// long as this node is the leader.
+pub(crate) fn spawn_evictor(
+    raft: openraft::Raft<TypeConfigKV>,
+    node_id: NodeId,
+    tx_caches: Vec<flume::Sender<CacheRequestHandler>>,
+    limited: Vec<usize>,
+    rx_evict: flume::Receiver<usize>,
+) {
+    if limited.is_empty() {
+        return;
+    }
+    task::spawn(evictor(raft, node_id, tx_caches, limited, rx_evict));
+}
+
+async fn evictor(
+    raft: openraft::Raft<TypeConfigKV>,
+    node_id: NodeId,
+    tx_caches: Vec<flume::Sender<CacheRequestHandler>>,
+    limited: Vec<usize>,
+    rx_evict: flume::Receiver<usize>,
+) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+    loop {
+        let caches = tokio::select! {
+            res = rx_evict.recv_async() => match res {
+                Ok(idx) => {
+                    let mut caches = vec![idx];
+                    caches.extend(rx_evict.drain());
+                    caches.sort_unstable();
+                    caches.dedup();
+                    caches
+                }
+                Err(_) => break,
+            },
+            _ = interval.tick() => limited.clone(),
+        };
+
+        {
+            let metrics = raft.metrics();
+            let metrics = metrics.borrow();
+            if metrics.running_state.is_err() {
+                break;
+            }
+            if metrics.current_leader != Some(node_id) {
+                continue;
+            }
+        }
+
+        for cache_idx in caches {
+            if let Err(err) = evict(&raft, &tx_caches[cache_idx], cache_idx).await {
+                warn!("Error evicting entries from cache {cache_idx}: {err}");
+            }
+        }
+    }
+
+    debug!("cache evictor exiting");
+}
+
+async fn evict(
+    raft: &openraft::Raft<TypeConfigKV>,
+    tx_cache: &flume::Sender<CacheRequestHandler>,
+    cache_idx: usize,
+) -> Result<(), crate::Error> {
+    loop {
+        let (ack, rx) = oneshot::channel();
+        tx_cache
+            .send(CacheRequestHandler::EvictionCandidates(ack))
+            .expect("kv handler to always be running");
+        let keys = match rx.await {
+            Ok(keys) => keys,
+            Err(_) => {
+                error!("kv handler did not answer with eviction candidates");
+                return Ok(());
+            }
+        };
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Evicting {} entries from cache {cache_idx}", keys.len());
+        // `client_write` returns after the eviction has been applied on this node, so the next
+        // candidates already reflect it
+        raft.client_write(CacheRequest::Evict { cache_idx, keys })
+            .await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(limit: CacheLimit) -> (CacheUsage, flume::Receiver<usize>) {
+        let (tx, rx) = flume::bounded(1);
+        (CacheUsage::new(3, Some(limit), tx), rx)
+    }
+
+    fn insert(data: &mut BTreeMap<String, Vec<u8>>, usage: &mut CacheUsage, key: &str, len: usize) {
+        let value = vec![0; len];
+        let old = data.insert(key.to_string(), value.clone());
+        usage.inserted(key, &value, old.as_deref());
+        usage.check(data.len());
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let (mut usage, rx) = usage(CacheLimit::new(EvictionPolicy::Lru).with_max_entries(10));
+        let mut data = BTreeMap::new();
+        for i in 0..10 {
+            insert(&mut data, &mut usage, &format!("key_{i}"), 8);
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(usage.eviction_candidates(&data).is_empty());
+
+        usage.accessed("key_0");
+        insert(&mut data, &mut usage, "key_10", 8);
+        assert_eq!(rx.try_recv().unwrap(), 3);
+
+        // 11 entries -> back to 9
+        let keys = usage.eviction_candidates(&data);
+        assert_eq!(keys, vec!["key_1", "key_2"]);
+
+        for key in keys {
+            let value = data.remove(&key).unwrap();
+            usage.evicted(&key, &value);
+        }
+        let stats = usage.stats("test", data.len());
+        assert_eq!(stats.entries, 9);
+        assert_eq!(stats.bytes, 9 * (5 + 8) + 1);
+        assert_eq!(stats.evictions, 2);
+    }
+
+    #[test]
+    fn lfu_evicts_least_frequently_used_by_bytes() {
+        let (mut usage, rx) = usage(CacheLimit::new(EvictionPolicy::Lfu).with_max_bytes(100));
+        let mut data = BTreeMap::new();
+        for key in ["a", "b", "c", "d"] {
+            insert(&mut data, &mut usage, key, 19);
+        }
+        for _ in 0..3 {
+            usage.accessed("a");
+            usage.accessed("b");
+        }
+        usage.accessed("d");
+        assert!(rx.try_recv().is_err());
+
+        // 5 * 20 bytes is still within the limit, a bigger value exceeds it
+        insert(&mut data, &mut usage, "e", 39);
+        assert_eq!(rx.try_recv().unwrap(), 3);
+
+        // 120 bytes -> at most 90 bytes, `c` has the least hits, `e` is older than `d`
+        let keys = usage.eviction_candidates(&data);
+        assert_eq!(keys, vec!["c", "e"]);
+    }
+
+    #[test]
+    fn limits_fall_back_to_default() {
+        let limits = CacheLimits::default()
+            .with_default(CacheLimit::new(EvictionPolicy::Lru).with_max_entries(100))
+            .with_cache(
+                "Sessions",
+                CacheLimit::new(EvictionPolicy::Lfu).with_max_bytes(1024),
+            )
+            .with_cache("Unlimited", CacheLimit::default());
+
+        assert_eq!(limits.get("Users").unwrap().max_entries, Some(100));
+        assert_eq!(limits.get("Sessions").unwrap().policy, EvictionPolicy::Lfu);
+        assert!(limits.get("Unlimited").is_none());
+        assert!(CacheLimits::default().get("Users").is_none());
+        assert_eq!(
+            EvictionPolicy::try_from("LFU").unwrap(),
+            EvictionPolicy::Lfu
+        );
+        assert!(EvictionPolicy::try_from("fifo").is_err());
+    }
+}
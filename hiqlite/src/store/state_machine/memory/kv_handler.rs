@@ -1,11 +1,18 @@
-use crate::NodeId;
 use crate::store::state_machine::memory::TypeConfigKV;
-use crate::store::state_machine::memory::state_machine::StateMachineData;
+use crate::store::state_machine::memory::cache_ttl_handler::normalize;
+use crate::store::state_machine::memory::eviction::{CacheStats, CacheUsage};
+use crate::store::state_machine::memory::state_machine::{
+    CacheBatchOp, SnapshotKV, SnapshotTTL, SnapshotVersion, StateMachineData,
+};
+use crate::store::state_machine::memory::watch::{WatchEvent, WatchKey, Watchers};
+use crate::{Error, NodeId};
+use chrono::Utc;
 use openraft::{Snapshot, StorageError};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt::Debug;
+use std::ops::Bound;
 use std::sync::Arc;
 use std::thread;
 use tokio::sync::{RwLock, oneshot};
@@ -16,18 +23,65 @@ use tracing::{debug, error, info, warn};
 #[allow(clippy::type_complexity)]
 pub enum CacheRequestHandler {
     Get((String, oneshot::Sender<Option<Vec<u8>>>)),
-    GetRemove((String, oneshot::Sender<Option<Vec<u8>>>)),
-    Put((String, Vec<u8>)),
-    Replace((String, Vec<u8>, oneshot::Sender<Option<Vec<u8>>>)),
-    Delete(String),
-    Clear,
+    GetVersioned((String, oneshot::Sender<Option<(Vec<u8>, u64)>>)),
+    GetMany((Vec<String>, oneshot::Sender<Vec<Option<Vec<u8>>>>)),
+    /// `(key, log index, ack)`
+    GetRemove((String, u64, oneshot::Sender<Option<Vec<u8>>>)),
+    /// `(key, value, expires, log index)` - the log index becomes the new version of the entry
+    Put((String, Vec<u8>, Option<i64>, u64)),
+    Replace(
+        (
+            String,
+            Vec<u8>,
+            Option<i64>,
+            u64,
+            oneshot::Sender<Option<Vec<u8>>>,
+        ),
+    ),
+    /// `(key, value, expires, expected version, leader time, version, ack)` -> the value is only
+    /// written, if the current version of the key matches the expected one, or if it does not
+    /// exist for `None`. Entries, which have expired at the leader time, do not exist.
+    PutIfVersion(
+        (
+            String,
+            Vec<u8>,
+            Option<i64>,
+            Option<u64>,
+            i64,
+            u64,
+            oneshot::Sender<bool>,
+        ),
+    ),
+    /// `(key, log index)`
+    Delete((String, u64)),
+    /// Sent by the TTL handler, when an entry has expired.
+    Expire(String),
+    /// `(key, expected version, leader time, log index, ack)`
+    DeleteIfVersion((String, u64, i64, u64, oneshot::Sender<bool>)),
+    Batch((Vec<CacheBatchOp>, u64)),
+    /// `(prefix, limit, cursor, ack)` -> up to `limit` entries after the `cursor` and the next
+    /// cursor, if more entries exist.
+    ScanPrefix(
+        (
+            String,
+            usize,
+            Option<String>,
+            oneshot::Sender<(Vec<(String, Vec<u8>)>, Option<String>)>,
+        ),
+    ),
+    Keys((String, oneshot::Sender<Vec<String>>)),
+    DeletePrefix((String, u64, oneshot::Sender<usize>)),
+    Clear(u64),
     #[cfg(feature = "counters")]
     ClearCounters,
     SnapshotBuildCacheOnly(oneshot::Sender<BTreeMap<String, Vec<u8>>>),
-    SnapshotBuild(oneshot::Sender<(BTreeMap<String, Vec<u8>>, BTreeMap<String, i64>)>),
+    SnapshotBuild(oneshot::Sender<(SnapshotKV, SnapshotVersion)>),
+    /// `(content, expiries, last log index of the snapshot, ack)`
     SnapshotInstall(
         (
-            (BTreeMap<String, Vec<u8>>, BTreeMap<String, i64>),
+            (SnapshotKV, SnapshotVersion),
+            SnapshotTTL,
+            u64,
             oneshot::Sender<()>,
         ),
     ),
@@ -40,16 +94,38 @@ pub enum CacheRequestHandler {
     CounterAdd((String, i64, oneshot::Sender<i64>)),
     #[cfg(feature = "counters")]
     CounterDel(String),
+
+    Evict((Vec<String>, u64)),
+    EvictionCandidates(oneshot::Sender<Vec<String>>),
+    Stats(oneshot::Sender<CacheStats>),
+    /// Registers a watcher, optionally resuming from a log index.
+    Watch(
+        (
+            WatchKey,
+            Option<u64>,
+            flume::Sender<Result<WatchEvent, Error>>,
+            oneshot::Sender<Result<(), Error>>,
+        ),
+    ),
 }
 
-pub fn spawn(cache_name: &'static str) -> flume::Sender<CacheRequestHandler> {
+/// How long the version of an entry is remembered after the local TTL handler has removed it.
+/// A conditional write, which has been proposed before the expiry, may be applied after it and
+/// must still see the entry, just like on all other nodes.
+const EXPIRED_RETAIN_MICROS: i64 = 5 * 60 * 1_000_000;
+
+pub fn spawn(cache_name: &'static str, usage: CacheUsage) -> flume::Sender<CacheRequestHandler> {
     let (tx, rx) = flume::unbounded();
-    task::spawn(kv_handler(cache_name, rx));
+    task::spawn(kv_handler(cache_name, usage, rx));
     tx
 }
 
-#[tracing::instrument(level = "debug", skip(rx))]
-async fn kv_handler(cache_name: &'static str, rx: flume::Receiver<CacheRequestHandler>) {
+#[tracing::instrument(level = "debug", skip(usage, rx))]
+async fn kv_handler(
+    cache_name: &'static str,
+    mut usage: CacheUsage,
+    rx: flume::Receiver<CacheRequestHandler>,
+) {
     info!(
         "Cache {} running on Thread {:?}",
         cache_name,
@@ -57,35 +133,230 @@ async fn kv_handler(cache_name: &'static str, rx: flume::Receiver<CacheRequestHa
     );
 
     let mut data: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    // the raft log index of the last write for each key in `data`
+    let mut versions: SnapshotVersion = BTreeMap::new();
+    // the replicated expiry in micros for each key in `data` with a TTL
+    let mut expires: BTreeMap<String, i64> = BTreeMap::new();
+    // `(version, expiry, removed at)` of entries removed by the local TTL handler
+    let mut expired: BTreeMap<String, (u64, i64, i64)> = BTreeMap::new();
+    let mut expired_order: VecDeque<(i64, String)> = VecDeque::new();
+    let mut watchers = Watchers::default();
+    // the highest log index applied to this cache, which is used for expiry events
+    let mut last_index = 0;
     #[cfg(feature = "counters")]
     let mut counters: BTreeMap<String, i64> = BTreeMap::new();
 
     while let Ok(req) = rx.recv_async().await {
         match req {
             CacheRequestHandler::Get((key, ack)) => {
-                if ack.send(data.get(&key).cloned()).is_err() {
+                let value = data.get(&key).cloned();
+                if value.is_some() {
+                    usage.accessed(&key);
+                }
+                if ack.send(value).is_err() {
                     error!("Error sending back Cache GET request: channel closed");
                 }
             }
-            CacheRequestHandler::GetRemove((key, ack)) => {
-                if ack.send(data.remove(&key)).is_err() {
+            CacheRequestHandler::GetVersioned((key, ack)) => {
+                let value = data.get(&key).map(|v| {
+                    usage.accessed(&key);
+                    (v.clone(), versions.get(&key).copied().unwrap_or_default())
+                });
+                if ack.send(value).is_err() {
+                    error!("Error sending back Cache GET_VERSIONED request: channel closed");
+                }
+            }
+            CacheRequestHandler::GetMany((keys, ack)) => {
+                let values = keys
+                    .iter()
+                    .map(|key| {
+                        let value = data.get(key).cloned();
+                        if value.is_some() {
+                            usage.accessed(key);
+                        }
+                        value
+                    })
+                    .collect();
+                if ack.send(values).is_err() {
+                    error!("Error sending back Cache GET_MANY request: channel closed");
+                }
+            }
+            CacheRequestHandler::GetRemove((key, log_index, ack)) => {
+                last_index = last_index.max(log_index);
+                let value = data.remove(&key);
+                expires.remove(&key);
+                expired.remove(&key);
+                if let Some(v) = &value {
+                    usage.removed(&key, v);
+                    versions.remove(&key);
+                    watchers.delete(&key, log_index);
+                }
+                if ack.send(value).is_err() {
                     error!("Error sending back Cache GET_REMOVE request: channel closed");
                 }
             }
-            CacheRequestHandler::Put((key, value)) => {
+            CacheRequestHandler::Put((key, value, exp, version)) => {
+                last_index = last_index.max(version);
+                usage.inserted(&key, &value, data.get(&key).map(Vec::as_slice));
+                versions.insert(key.clone(), version);
+                set_expiry(&mut expires, &mut expired, &key, exp);
+                watchers.put(&key, &value, version);
                 data.insert(key, value);
+                usage.check(data.len());
             }
-            CacheRequestHandler::Replace((key, value, ack)) => {
-                if ack.send(data.insert(key, value)).is_err() {
+            CacheRequestHandler::Replace((key, value, exp, version, ack)) => {
+                last_index = last_index.max(version);
+                usage.inserted(&key, &value, data.get(&key).map(Vec::as_slice));
+                versions.insert(key.clone(), version);
+                set_expiry(&mut expires, &mut expired, &key, exp);
+                watchers.put(&key, &value, version);
+                let old = data.insert(key, value);
+                usage.check(data.len());
+                if ack.send(old).is_err() {
                     error!("Error sending back Cache REPLACE request: channel closed");
                 }
             }
-            CacheRequestHandler::Delete(key) => {
-                data.remove(&key);
+            CacheRequestHandler::PutIfVersion((key, value, exp, expected, now, version, ack)) => {
+                last_index = last_index.max(version);
+                let current = current_version(&data, &versions, &expires, &expired, &key, now);
+                let matches = current == expected;
+                if matches {
+                    usage.inserted(&key, &value, data.get(&key).map(Vec::as_slice));
+                    versions.insert(key.clone(), version);
+                    set_expiry(&mut expires, &mut expired, &key, exp);
+                    watchers.put(&key, &value, version);
+                    data.insert(key, value);
+                    usage.check(data.len());
+                }
+                if ack.send(matches).is_err() {
+                    error!("Error sending back Cache PUT_IF_VERSION request: channel closed");
+                }
+            }
+            CacheRequestHandler::Delete((key, log_index)) => {
+                last_index = last_index.max(log_index);
+                expires.remove(&key);
+                expired.remove(&key);
+                if let Some(value) = data.remove(&key) {
+                    usage.removed(&key, &value);
+                    versions.remove(&key);
+                    watchers.delete(&key, log_index);
+                }
+            }
+            CacheRequestHandler::Expire(key) => {
+                if let Some(value) = data.remove(&key) {
+                    usage.removed(&key, &value);
+                    let version = versions.remove(&key).unwrap_or_default();
+                    if let Some(exp) = expires.remove(&key) {
+                        let now = Utc::now().timestamp_micros();
+                        expired.insert(key.clone(), (version, exp, now));
+                        expired_order.push_back((now, key.clone()));
+                        while let Some((removed_at, _)) = expired_order.front()
+                            && *removed_at < now - EXPIRED_RETAIN_MICROS
+                        {
+                            let (removed_at, key) = expired_order.pop_front().unwrap();
+                            // the key may have been written and expired again in the meantime
+                            if expired.get(&key).is_some_and(|e| e.2 == removed_at) {
+                                expired.remove(&key);
+                            }
+                        }
+                    }
+                    watchers.expire(&key, last_index);
+                }
+            }
+            CacheRequestHandler::DeleteIfVersion((key, expected, now, log_index, ack)) => {
+                last_index = last_index.max(log_index);
+                let current = current_version(&data, &versions, &expires, &expired, &key, now);
+                let matches = current == Some(expected);
+                if matches {
+                    expires.remove(&key);
+                    expired.remove(&key);
+                    if let Some(value) = data.remove(&key) {
+                        usage.removed(&key, &value);
+                        versions.remove(&key);
+                        watchers.delete(&key, log_index);
+                    }
+                }
+                if ack.send(matches).is_err() {
+                    error!("Error sending back Cache DELETE_IF_VERSION request: channel closed");
+                }
+            }
+            CacheRequestHandler::Batch((ops, version)) => {
+                last_index = last_index.max(version);
+                for op in ops {
+                    match op {
+                        CacheBatchOp::Put {
+                            key,
+                            value,
+                            expires: exp,
+                        } => {
+                            let key = key.into_owned();
+                            usage.inserted(&key, &value, data.get(&key).map(Vec::as_slice));
+                            versions.insert(key.clone(), version);
+                            set_expiry(&mut expires, &mut expired, &key, exp);
+                            watchers.put(&key, &value, version);
+                            data.insert(key, value);
+                        }
+                        CacheBatchOp::Delete { key } => {
+                            expires.remove(key.as_ref());
+                            expired.remove(key.as_ref());
+                            if let Some(value) = data.remove(key.as_ref()) {
+                                usage.removed(&key, &value);
+                                versions.remove(key.as_ref());
+                                watchers.delete(&key, version);
+                            }
+                        }
+                    }
+                }
+                usage.check(data.len());
+            }
+            CacheRequestHandler::ScanPrefix((prefix, limit, cursor, ack)) => {
+                // scans do not count as an access for the eviction, otherwise a single scan
+                // would make all matching entries the most recently used ones
+                let page = scan_prefix(&data, &prefix, limit, cursor);
+                if ack.send(page).is_err() {
+                    error!("Error sending back Cache SCAN_PREFIX request: channel closed");
+                }
+            }
+            CacheRequestHandler::Keys((prefix, ack)) => {
+                let keys = prefix_range(&data, &prefix, None)
+                    .map(|(k, _)| k.clone())
+                    .collect();
+                if ack.send(keys).is_err() {
+                    error!("Error sending back Cache KEYS request: channel closed");
+                }
             }
-            CacheRequestHandler::Clear => {
+            CacheRequestHandler::DeletePrefix((prefix, log_index, ack)) => {
+                last_index = last_index.max(log_index);
+                let keys = prefix_range(&data, &prefix, None)
+                    .map(|(k, _)| k.clone())
+                    .collect::<Vec<_>>();
+                expired.retain(|k, _| !k.starts_with(&prefix));
+                for key in &keys {
+                    expires.remove(key);
+                    if let Some(value) = data.remove(key) {
+                        usage.removed(key, &value);
+                        versions.remove(key);
+                        watchers.delete(key, log_index);
+                    }
+                }
+                if ack.send(keys.len()).is_err() {
+                    error!("Error sending back Cache DELETE_PREFIX request: channel closed");
+                }
+            }
+            CacheRequestHandler::Clear(log_index) => {
                 debug!("Clearing all caches for {cache_name}");
+                last_index = last_index.max(log_index);
+                if !watchers.is_idle() {
+                    for key in data.keys() {
+                        watchers.delete(key, log_index);
+                    }
+                }
                 data.clear();
+                versions.clear();
+                expires.clear();
+                expired.clear();
+                expired_order.clear();
+                usage.reset(&data);
             }
             #[cfg(feature = "counters")]
             CacheRequestHandler::ClearCounters => {
@@ -103,12 +374,26 @@ async fn kv_handler(cache_name: &'static str, rx: flume::Receiver<CacheRequestHa
                 #[cfg(not(feature = "counters"))]
                 let data_counter = BTreeMap::new();
 
-                if ack.send((data.clone(), data_counter)).is_err() {
+                if ack
+                    .send(((data.clone(), data_counter), versions.clone()))
+                    .is_err()
+                {
                     error!("Error sending back SnapshotBuild response");
                 }
             }
-            CacheRequestHandler::SnapshotInstall(((kvs, counts), ack)) => {
+            CacheRequestHandler::SnapshotInstall((((kvs, counts), vers), ttls, log_index, ack)) => {
                 data = kvs;
+                versions = vers;
+                expires = ttls
+                    .into_iter()
+                    .map(|(exp, key)| (key, normalize(exp)))
+                    .collect();
+                expired.clear();
+                expired_order.clear();
+                last_index = log_index;
+                watchers.snapshot_installed(log_index);
+                usage.reset(&data);
+                usage.check(data.len());
                 #[cfg(feature = "counters")]
                 {
                     counters = counts;
@@ -146,12 +431,117 @@ async fn kv_handler(cache_name: &'static str, rx: flume::Receiver<CacheRequestHa
             CacheRequestHandler::CounterDel(key) => {
                 counters.remove(&key);
             }
+
+            CacheRequestHandler::Evict((keys, log_index)) => {
+                last_index = last_index.max(log_index);
+                for key in keys {
+                    expires.remove(&key);
+                    expired.remove(&key);
+                    if let Some(value) = data.remove(&key) {
+                        usage.evicted(&key, &value);
+                        versions.remove(&key);
+                        watchers.delete(&key, log_index);
+                    }
+                }
+            }
+            CacheRequestHandler::EvictionCandidates(ack) => {
+                if ack.send(usage.eviction_candidates(&data)).is_err() {
+                    error!("Error sending back EvictionCandidates response");
+                }
+            }
+            CacheRequestHandler::Stats(ack) => {
+                if ack.send(usage.stats(cache_name, data.len())).is_err() {
+                    error!("Error sending back Stats response");
+                }
+            }
+            CacheRequestHandler::Watch((key, resume_from, tx, ack)) => {
+                let res = watchers.add(key, resume_from, tx, last_index);
+                if ack.send(res).is_err() {
+                    error!("Error sending back Watch response: channel closed");
+                }
+            }
         }
     }
 
     debug!("cache::kv_handler for {cache_name} exiting");
 }
 
+/// Sets or clears the replicated expiry of a freshly written entry.
+fn set_expiry(
+    expires: &mut BTreeMap<String, i64>,
+    expired: &mut BTreeMap<String, (u64, i64, i64)>,
+    key: &str,
+    exp: Option<i64>,
+) {
+    expired.remove(key);
+    match exp {
+        Some(exp) => {
+            expires.insert(key.to_string(), normalize(exp));
+        }
+        None => {
+            expires.remove(key);
+        }
+    }
+}
+
+/// The version of the entry for conditional writes at the leader time `now`. An entry, which has
+/// expired at `now`, does not exist, even if the local TTL handler has not removed it yet. An
+/// entry, which the local TTL handler has removed already, still exists before its expiry.
+fn current_version(
+    data: &BTreeMap<String, Vec<u8>>,
+    versions: &SnapshotVersion,
+    expires: &BTreeMap<String, i64>,
+    expired: &BTreeMap<String, (u64, i64, i64)>,
+    key: &str,
+    now: i64,
+) -> Option<u64> {
+    if data.contains_key(key) {
+        match expires.get(key) {
+            Some(exp) if *exp <= now => None,
+            _ => Some(versions.get(key).copied().unwrap_or_default()),
+        }
+    } else {
+        expired
+            .get(key)
+            .and_then(|(version, exp, _)| (now < *exp).then_some(*version))
+    }
+}
+
+/// All entries starting with `prefix` in key order, beginning after the `cursor` if given.
+fn prefix_range<'a>(
+    data: &'a BTreeMap<String, Vec<u8>>,
+    prefix: &'a str,
+    cursor: Option<String>,
+) -> impl Iterator<Item = (&'a String, &'a Vec<u8>)> {
+    let start = match cursor {
+        Some(cursor) if cursor.as_str() >= prefix => Bound::Excluded(cursor),
+        _ => Bound::Included(prefix.to_string()),
+    };
+    data.range((start, Bound::Unbounded))
+        .take_while(move |(k, _)| k.starts_with(prefix))
+}
+
+#[allow(clippy::type_complexity)]
+fn scan_prefix(
+    data: &BTreeMap<String, Vec<u8>>,
+    prefix: &str,
+    limit: usize,
+    cursor: Option<String>,
+) -> (Vec<(String, Vec<u8>)>, Option<String>) {
+    let mut iter = prefix_range(data, prefix, cursor);
+    let entries = iter
+        .by_ref()
+        .take(limit)
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect::<Vec<_>>();
+    let cursor = if limit > 0 && iter.next().is_some() {
+        entries.last().map(|(k, _)| k.clone())
+    } else {
+        None
+    };
+    (entries, cursor)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -166,8 +556,8 @@ mod tests {
         let (ack, rx) = oneshot::channel();
         let req = match op {
             Op::Get(k) => CacheRequestHandler::Get((k.to_string(), ack)),
-            Op::GetRemove(k) => CacheRequestHandler::GetRemove((k.to_string(), ack)),
-            Op::Replace(k, v) => CacheRequestHandler::Replace((k.to_string(), v, ack)),
+            Op::GetRemove(k) => CacheRequestHandler::GetRemove((k.to_string(), 1, ack)),
+            Op::Replace(k, v) => CacheRequestHandler::Replace((k.to_string(), v, None, 1, ack)),
         };
         tx.send(req).expect("kv handler to be running");
         rx.await.expect("kv handler to always answer")
@@ -175,7 +565,7 @@ mod tests {
 
     #[tokio::test]
     async fn get_remove_and_replace_are_atomic_per_key() {
-        let tx = spawn("test");
+        let tx = spawn("test", CacheUsage::default());
 
         // get_remove on a missing key -> None
         assert_eq!(call(&tx, Op::GetRemove("missing")).await, None);
@@ -188,12 +578,353 @@ mod tests {
         assert_eq!(call(&tx, Op::GetRemove("k")).await, None);
 
         // replace returns the previous value and stores the new one
-        tx.send(CacheRequestHandler::Put(("k".into(), b"v2".to_vec())))
-            .expect("kv handler to be running");
+        tx.send(CacheRequestHandler::Put((
+            "k".into(),
+            b"v2".to_vec(),
+            None,
+            2,
+        )))
+        .expect("kv handler to be running");
         assert_eq!(
             call(&tx, Op::Replace("k", b"v3".to_vec())).await,
             Some(b"v2".to_vec())
         );
         assert_eq!(call(&tx, Op::Get("k")).await, Some(b"v3".to_vec()));
     }
+
+    #[tokio::test]
+    async fn batch_is_applied_in_order() {
+        let tx = spawn("test", CacheUsage::default());
+        tx.send(CacheRequestHandler::Put((
+            "b".into(),
+            b"old".to_vec(),
+            None,
+            1,
+        )))
+        .expect("kv handler to be running");
+
+        tx.send(CacheRequestHandler::Batch((
+            vec![
+                CacheBatchOp::Put {
+                    key: "a".into(),
+                    value: b"a1".to_vec(),
+                    expires: None,
+                },
+                CacheBatchOp::Delete { key: "b".into() },
+                CacheBatchOp::Put {
+                    key: "c".into(),
+                    value: b"c1".to_vec(),
+                    expires: None,
+                },
+                // a later op for the same key wins
+                CacheBatchOp::Put {
+                    key: "a".into(),
+                    value: b"a2".to_vec(),
+                    expires: None,
+                },
+            ],
+            2,
+        )))
+        .expect("kv handler to be running");
+
+        let (ack, rx) = oneshot::channel();
+        let keys = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        tx.send(CacheRequestHandler::GetMany((keys, ack)))
+            .expect("kv handler to be running");
+        assert_eq!(
+            rx.await.expect("kv handler to always answer"),
+            vec![Some(b"a2".to_vec()), None, Some(b"c1".to_vec())]
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_prefix_pages_and_delete_prefix() {
+        let tx = spawn("test", CacheUsage::default());
+        for key in [
+            "session:a:1",
+            "session:a:2",
+            "session:a:3",
+            "session:b:1",
+            "user:a",
+        ] {
+            tx.send(CacheRequestHandler::Put((
+                key.into(),
+                key.as_bytes().to_vec(),
+                None,
+                1,
+            )))
+            .expect("kv handler to be running");
+        }
+
+        let scan = |limit, cursor| {
+            let (ack, rx) = oneshot::channel();
+            tx.send(CacheRequestHandler::ScanPrefix((
+                "session:a:".to_string(),
+                limit,
+                cursor,
+                ack,
+            )))
+            .expect("kv handler to be running");
+            rx
+        };
+        let (entries, cursor) = scan(2, None).await.expect("kv handler to always answer");
+        assert_eq!(
+            entries.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>(),
+            vec!["session:a:1", "session:a:2"]
+        );
+        assert_eq!(cursor.as_deref(), Some("session:a:2"));
+        let (entries, cursor) = scan(2, cursor).await.expect("kv handler to always answer");
+        assert_eq!(
+            entries,
+            vec![("session:a:3".to_string(), b"session:a:3".to_vec())]
+        );
+        assert_eq!(cursor, None);
+        // an exactly filled last page does not return a cursor
+        let (entries, cursor) = scan(3, None).await.expect("kv handler to always answer");
+        assert_eq!(entries.len(), 3);
+        assert_eq!(cursor, None);
+
+        let (ack, rx) = oneshot::channel();
+        tx.send(CacheRequestHandler::DeletePrefix((
+            "session:".to_string(),
+            2,
+            ack,
+        )))
+        .expect("kv handler to be running");
+        assert_eq!(rx.await.expect("kv handler to always answer"), 4);
+
+        let (ack, rx) = oneshot::channel();
+        tx.send(CacheRequestHandler::Keys((String::new(), ack)))
+            .expect("kv handler to be running");
+        assert_eq!(
+            rx.await.expect("kv handler to always answer"),
+            vec!["user:a".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn versions_follow_the_last_write() {
+        let tx = spawn("test", CacheUsage::default());
+
+        let get_versioned = |key: &str| {
+            let (ack, rx) = oneshot::channel();
+            tx.send(CacheRequestHandler::GetVersioned((key.to_string(), ack)))
+                .expect("kv handler to be running");
+            rx
+        };
+        let put_if_version = |key: &str, value: &[u8], expected, version| {
+            let (ack, rx) = oneshot::channel();
+            tx.send(CacheRequestHandler::PutIfVersion((
+                key.to_string(),
+                value.to_vec(),
+                None,
+                expected,
+                0,
+                version,
+                ack,
+            )))
+            .expect("kv handler to be running");
+            rx
+        };
+
+        // put if absent
+        assert!(put_if_version("k", b"v1", None, 3).await.unwrap());
+        assert!(!put_if_version("k", b"v2", None, 4).await.unwrap());
+        assert_eq!(get_versioned("k").await.unwrap(), Some((b"v1".to_vec(), 3)));
+
+        // put if version
+        assert!(!put_if_version("k", b"v2", Some(2), 5).await.unwrap());
+        assert!(put_if_version("k", b"v2", Some(3), 6).await.unwrap());
+        assert_eq!(get_versioned("k").await.unwrap(), Some((b"v2".to_vec(), 6)));
+
+        // unconditional writes bump the version as well
+        tx.send(CacheRequestHandler::Put((
+            "k".into(),
+            b"v3".to_vec(),
+            None,
+            7,
+        )))
+        .expect("kv handler to be running");
+        assert_eq!(get_versioned("k").await.unwrap(), Some((b"v3".to_vec(), 7)));
+
+        let delete_if_version = |expected| {
+            let (ack, rx) = oneshot::channel();
+            tx.send(CacheRequestHandler::DeleteIfVersion((
+                "k".to_string(),
+                expected,
+                0,
+                8,
+                ack,
+            )))
+            .expect("kv handler to be running");
+            rx
+        };
+        assert!(!delete_if_version(6).await.unwrap());
+        assert!(delete_if_version(7).await.unwrap());
+        assert!(!delete_if_version(7).await.unwrap());
+        assert_eq!(get_versioned("k").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn conditional_writes_expire_at_the_leader_time() {
+        let tx = spawn("test", CacheUsage::default());
+        const T0: i64 = 1_786_000_000_000_000;
+        let exp = Some(T0 + 1_000_000);
+
+        let put = |key: &str, version| {
+            tx.send(CacheRequestHandler::Put((
+                key.to_string(),
+                b"v".to_vec(),
+                exp,
+                version,
+            )))
+            .expect("kv handler to be running");
+        };
+        let put_if_version = |key: &str, expected, now, version| {
+            let (ack, rx) = oneshot::channel();
+            tx.send(CacheRequestHandler::PutIfVersion((
+                key.to_string(),
+                b"new".to_vec(),
+                None,
+                expected,
+                now,
+                version,
+                ack,
+            )))
+            .expect("kv handler to be running");
+            rx
+        };
+        let delete_if_version = |key: &str, expected, now, log_index| {
+            let (ack, rx) = oneshot::channel();
+            tx.send(CacheRequestHandler::DeleteIfVersion((
+                key.to_string(),
+                expected,
+                now,
+                log_index,
+                ack,
+            )))
+            .expect("kv handler to be running");
+            rx
+        };
+
+        // proposed after the expiry, but the local TTL handler has not removed it yet
+        put("a", 1);
+        assert!(!put_if_version("a", None, T0, 2).await.unwrap());
+        assert!(put_if_version("a", None, T0 + 1_000_000, 3).await.unwrap());
+
+        // proposed before the expiry, but the local TTL handler has removed it already
+        put("b", 4);
+        tx.send(CacheRequestHandler::Expire("b".into()))
+            .expect("kv handler to be running");
+        assert!(!put_if_version("b", None, T0, 5).await.unwrap());
+        assert!(put_if_version("b", Some(4), T0, 6).await.unwrap());
+        let (ack, rx) = oneshot::channel();
+        tx.send(CacheRequestHandler::GetVersioned(("b".to_string(), ack)))
+            .expect("kv handler to be running");
+        assert_eq!(rx.await.unwrap(), Some((b"new".to_vec(), 6)));
+
+        put("c", 7);
+        tx.send(CacheRequestHandler::Expire("c".into()))
+            .expect("kv handler to be running");
+        assert!(delete_if_version("c", 7, T0, 8).await.unwrap());
+        assert!(!delete_if_version("c", 7, T0, 9).await.unwrap());
+
+        put("d", 10);
+        assert!(
+            !delete_if_version("d", 10, T0 + 2_000_000, 11)
+                .await
+                .unwrap()
+        );
+        assert!(delete_if_version("d", 10, T0, 12).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn watchers_receive_matching_events_in_order() {
+        let tx = spawn("test", CacheUsage::default());
+        let (tx_watch, rx_watch) = flume::unbounded();
+        let (ack, ack_rx) = oneshot::channel();
+        tx.send(CacheRequestHandler::Watch((
+            WatchKey::Prefix("session:".to_string()),
+            None,
+            tx_watch,
+            ack,
+        )))
+        .expect("kv handler to be running");
+        ack_rx.await.unwrap().expect("watcher to be registered");
+
+        tx.send(CacheRequestHandler::Put((
+            "session:1".into(),
+            b"v1".to_vec(),
+            None,
+            1,
+        )))
+        .expect("kv handler to be running");
+        tx.send(CacheRequestHandler::Put((
+            "user:1".into(),
+            b"u1".to_vec(),
+            None,
+            2,
+        )))
+        .expect("kv handler to be running");
+        tx.send(CacheRequestHandler::Delete(("session:1".into(), 3)))
+            .expect("kv handler to be running");
+        // deletes of missing keys do not emit anything
+        tx.send(CacheRequestHandler::Delete(("session:2".into(), 4)))
+            .expect("kv handler to be running");
+        tx.send(CacheRequestHandler::Put((
+            "session:3".into(),
+            b"v3".to_vec(),
+            None,
+            5,
+        )))
+        .expect("kv handler to be running");
+        tx.send(CacheRequestHandler::Expire("session:3".into()))
+            .expect("kv handler to be running");
+
+        let mut events = Vec::with_capacity(4);
+        for _ in 0..4 {
+            events.push(
+                rx_watch
+                    .recv_async()
+                    .await
+                    .expect("watcher to be registered")
+                    .expect("no error event"),
+            );
+        }
+        assert_eq!(
+            events,
+            vec![
+                WatchEvent::Put {
+                    key: "session:1".to_string(),
+                    value: b"v1".to_vec(),
+                    log_index: 1,
+                },
+                WatchEvent::Delete {
+                    key: "session:1".to_string(),
+                    log_index: 3,
+                },
+                WatchEvent::Put {
+                    key: "session:3".to_string(),
+                    value: b"v3".to_vec(),
+                    log_index: 5,
+                },
+                // expiry is not replicated and carries the last applied index
+                WatchEvent::Expire {
+                    key: "session:3".to_string(),
+                    log_index: 5,
+                },
+            ]
+        );
+        assert!(rx_watch.is_empty());
+
+        // dropped watchers are cleaned up on the next event
+        drop(rx_watch);
+        tx.send(CacheRequestHandler::Put((
+            "session:4".into(),
+            b"v4".to_vec(),
+            None,
+            6,
+        )))
+        .expect("kv handler to be running");
+    }
 }
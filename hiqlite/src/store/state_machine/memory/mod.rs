@@ -3,9 +3,13 @@ use crate::Node;
 #[cfg(feature = "in-memory-snapshots")]
 use std::io::Cursor;
 
+#[cfg(feature = "backup")]
+pub(crate) mod backup;
 mod cache_ttl_handler;
+pub(crate) mod eviction;
 pub mod kv_handler;
 pub mod state_machine;
+pub mod watch;
 
 #[cfg(feature = "dlock")]
 pub mod dlock_handler;
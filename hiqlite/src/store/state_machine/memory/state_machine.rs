@@ -1,6 +1,7 @@
 use crate::helpers::{deserialize, serialize, set_path_access};
 use crate::store::StorageResult;
 use crate::store::state_machine::memory::cache_ttl_handler::TtlRequest;
+use crate::store::state_machine::memory::eviction::{CacheLimits, CacheUsage};
 use crate::store::state_machine::memory::kv_handler::CacheRequestHandler;
 use crate::store::state_machine::memory::{TypeConfigKV, cache_ttl_handler, kv_handler};
 use crate::{CacheVariants, Error, Node, NodeId};
@@ -42,10 +43,24 @@ type SnapshotData = fs::File;
 #[cfg(feature = "in-memory-snapshots")]
 type SnapshotData = Cursor<Vec<u8>>;
 
-type SnapshotKVs = Vec<(BTreeMap<String, Vec<u8>>, BTreeMap<String, i64>)>;
-type SnapshotTTLs = Vec<BTreeMap<i64, String>>;
-type SnapshotLocks = Vec<u8>;
-type SnapshotDataContent = (
+/// The values and counters of a single cache.
+pub(super) type SnapshotKV = (BTreeMap<String, Vec<u8>>, BTreeMap<String, i64>);
+pub(super) type SnapshotKVs = Vec<SnapshotKV>;
+pub(super) type SnapshotTTL = BTreeMap<i64, String>;
+pub(super) type SnapshotTTLs = Vec<SnapshotTTL>;
+pub(super) type SnapshotLocks = Vec<u8>;
+/// The raft log index of the last write for each key of a single cache.
+pub(super) type SnapshotVersion = BTreeMap<String, u64>;
+pub(super) type SnapshotVersions = Vec<SnapshotVersion>;
+pub(super) type SnapshotDataContent = (
+    SnapshotMeta<NodeId, Node>,
+    SnapshotKVs,
+    SnapshotTTLs,
+    SnapshotLocks,
+    SnapshotVersions,
+);
+/// The snapshot layout before entry versions have been added.
+type SnapshotDataContentV1 = (
     SnapshotMeta<NodeId, Node>,
     SnapshotKVs,
     SnapshotTTLs,
@@ -123,6 +138,81 @@ pub enum CacheRequest {
         cache_idx: usize,
         key: Cow<'static, str>,
     },
+    /// Only created by the leader, when a cache exceeds its `CacheLimit`.
+    Evict {
+        cache_idx: usize,
+        keys: Vec<String>,
+    },
+    /// Multiple writes to a single cache, which are applied atomically.
+    Batch {
+        cache_idx: usize,
+        ops: Vec<CacheBatchOp>,
+    },
+    GetMany {
+        cache_idx: usize,
+        keys: Vec<String>,
+    },
+    ScanPrefix {
+        cache_idx: usize,
+        prefix: String,
+        limit: usize,
+        cursor: Option<String>,
+    },
+    Keys {
+        cache_idx: usize,
+        prefix: String,
+    },
+    DeletePrefix {
+        cache_idx: usize,
+        prefix: String,
+    },
+    GetVersioned {
+        cache_idx: usize,
+        key: String,
+    },
+    /// Only writes the value if the current version of the key matches `expected`, or if the
+    /// key does not exist for `None`.
+    PutIfVersion {
+        cache_idx: usize,
+        key: Cow<'static, str>,
+        value: Vec<u8>,
+        expires: Option<i64>,
+        expected: Option<u64>,
+        /// The leader time in unix micros, set by `CacheRequest::stamped()`.
+        now: i64,
+    },
+    DeleteIfVersion {
+        cache_idx: usize,
+        key: Cow<'static, str>,
+        expected: u64,
+        /// The leader time in unix micros, set by `CacheRequest::stamped()`.
+        now: i64,
+    },
+}
+
+impl CacheRequest {
+    /// Stamps conditional writes with the current time. This must be done by the leader right
+    /// before proposing it, so all nodes agree on which entries have expired already, no matter
+    /// when their local TTL handler removes them.
+    pub(crate) fn stamped(mut self) -> Self {
+        if let Self::PutIfVersion { now, .. } | Self::DeleteIfVersion { now, .. } = &mut self {
+            *now = Utc::now().timestamp_micros();
+        }
+        self
+    }
+}
+
+/// A single write inside a `CacheRequest::Batch`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CacheBatchOp {
+    Put {
+        key: Cow<'static, str>,
+        value: Vec<u8>,
+        expires: Option<i64>,
+    },
+    Delete {
+        key: Cow<'static, str>,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -134,6 +224,13 @@ pub enum CacheResponse {
     Value(Option<Vec<u8>>),
     #[cfg(feature = "counters")]
     CounterValue(Option<i64>),
+    Values(Vec<Option<Vec<u8>>>),
+    Page((Vec<(String, Vec<u8>)>, Option<String>)),
+    Keys(Vec<String>),
+    Deleted(usize),
+    Versioned(Option<(Vec<u8>, u64)>),
+    /// The new version of a conditional write, or `None` if it has not been written.
+    Version(Option<u64>),
 }
 
 #[derive(Debug, Default)]
@@ -147,8 +244,11 @@ pub struct StateMachineData {
 /// down. If just a single node is restarting, it will re-sync in-memory data from other members.
 #[derive(Debug)]
 pub struct StateMachineMemory {
-    data: RwLock<StateMachineData>,
+    pub(super) data: RwLock<StateMachineData>,
     path_snapshots: String,
+    /// The names of all caches, in the order of their index.
+    #[cfg(feature = "backup")]
+    pub(super) cache_names: Vec<&'static str>,
     /// Whether the cache runs fully in-memory (`cache_storage_disk = false`). Only relevant
     /// with the `in-memory-snapshots` feature, which is the only mode that skips `data_dir`.
     #[cfg(feature = "in-memory-snapshots")]
@@ -161,6 +261,10 @@ pub struct StateMachineMemory {
 
     pub(crate) tx_caches: Vec<flume::Sender<CacheRequestHandler>>,
     tx_ttls: Vec<flume::Sender<TtlRequest>>,
+    /// The indexes of all caches with a `CacheLimit`.
+    pub(crate) limited_caches: Vec<usize>,
+    /// Receives the index of each cache that exceeds its limit.
+    pub(crate) rx_evict: flume::Receiver<usize>,
 
     #[cfg(feature = "listen_notify_local")]
     pub(crate) tx_notify: flume::Sender<NotifyRequest>,
@@ -211,7 +315,11 @@ impl RaftSnapshotBuilder<TypeConfigKV> for Arc<StateMachineMemory> {
 }
 
 impl StateMachineMemory {
-    pub(crate) async fn new<C>(base_path: &str, in_memory_only: bool) -> Result<Self, Error>
+    pub(crate) async fn new<C>(
+        base_path: &str,
+        in_memory_only: bool,
+        limits: &CacheLimits,
+    ) -> Result<Self, Error>
     where
         C: Debug + CacheVariants,
     {
@@ -245,8 +353,15 @@ impl StateMachineMemory {
         let variants = C::hiqlite_cache_variants();
         let mut tx_caches = Vec::with_capacity(variants.len());
         let mut tx_ttls = Vec::with_capacity(variants.len());
-        for (_, name) in variants {
-            let tx_cache = kv_handler::spawn(name);
+        let mut limited_caches = Vec::new();
+        let (tx_evict, rx_evict) = flume::bounded(variants.len().max(1));
+        for (idx, name) in variants {
+            let limit = limits.get(name).cloned();
+            if limit.is_some() {
+                limited_caches.push(*idx);
+            }
+            let usage = CacheUsage::new(*idx, limit, tx_evict.clone());
+            let tx_cache = kv_handler::spawn(name, usage);
             tx_caches.push(tx_cache.clone());
             tx_ttls.push(cache_ttl_handler::spawn(tx_cache));
         }
@@ -260,12 +375,16 @@ impl StateMachineMemory {
         let slf = Self {
             data: RwLock::new(StateMachineData::default()),
             path_snapshots,
+            #[cfg(feature = "backup")]
+            cache_names: variants.iter().map(|(_, name)| *name).collect(),
             #[cfg(feature = "in-memory-snapshots")]
             in_memory_only,
             #[cfg(feature = "in-memory-snapshots")]
             snapshot_mem: RwLock::new(None),
             tx_caches,
             tx_ttls,
+            limited_caches,
+            rx_evict,
             #[cfg(feature = "listen_notify_local")]
             tx_notify,
             #[cfg(feature = "listen_notify_local")]
@@ -298,16 +417,10 @@ impl StateMachineMemory {
         Ok(slf)
     }
 
-    /// Serializes the current cache state (caches, TTLs, locks) into a snapshot blob.
-    /// Shared by the disk-backed (default) and in-memory (`in-memory-snapshots`) paths.
-    async fn build_snapshot_data(
+    /// Collects the current content of all caches, TTLs and locks.
+    pub(super) async fn snapshot_parts(
         &self,
-    ) -> Result<(SnapshotMeta<NodeId, Node>, Vec<u8>), StorageError<NodeId>> {
-        let data = self.data.read().await;
-
-        // TODO should we include notifications in snapshots as well?
-        //  -> unsure if it makes sense or not
-
+    ) -> (SnapshotKVs, SnapshotTTLs, SnapshotLocks, SnapshotVersions) {
         let mut ttls = Vec::with_capacity(self.tx_ttls.len());
         for tx in &self.tx_ttls {
             let (ack, rx) = oneshot::channel();
@@ -320,14 +433,16 @@ impl StateMachineMemory {
         }
 
         let mut caches = Vec::with_capacity(self.tx_caches.len());
+        let mut versions = Vec::with_capacity(self.tx_caches.len());
         for tx in &self.tx_caches {
             let (ack, rx) = oneshot::channel();
             tx.send(CacheRequestHandler::SnapshotBuild(ack))
                 .expect("kv handler to always be running");
-            let snap = rx
+            let (snap, vers) = rx
                 .await
                 .expect("to always receive an answer from kv handler");
             caches.push(snap);
+            versions.push(vers);
         }
 
         #[cfg(feature = "dlock")]
@@ -344,6 +459,20 @@ impl StateMachineMemory {
         #[cfg(not(feature = "dlock"))]
         let locks_bytes: Vec<u8> = Vec::default();
 
+        (caches, ttls, locks_bytes, versions)
+    }
+
+    /// Serializes the current cache state (caches, TTLs, locks) into a snapshot blob.
+    /// Shared by the disk-backed (default) and in-memory (`in-memory-snapshots`) paths.
+    async fn build_snapshot_data(
+        &self,
+    ) -> Result<(SnapshotMeta<NodeId, Node>, Vec<u8>), StorageError<NodeId>> {
+        let data = self.data.read().await;
+
+        // TODO should we include notifications in snapshots as well?
+        //  -> unsure if it makes sense or not
+        let (caches, ttls, locks_bytes, versions) = self.snapshot_parts().await;
+
         let now = Utc::now().timestamp();
         let snapshot_id = if let Some(last) = data.last_applied_log_id {
             format!("{}-{}-{}", now, last.leader_id, last.index)
@@ -357,7 +486,7 @@ impl StateMachineMemory {
             snapshot_id,
         };
 
-        let snap: SnapshotDataContent = (meta.clone(), caches, ttls, locks_bytes);
+        let snap: SnapshotDataContent = (meta.clone(), caches, ttls, locks_bytes, versions);
         let snapshot_bytes =
             serialize(&snap).map_err(|err| StorageIOError::write_state_machine(&err))?;
 
@@ -425,30 +554,36 @@ impl StateMachineMemory {
         meta: &SnapshotMeta<NodeId, Node>,
         bytes: &[u8],
     ) -> Result<(), StorageError<NodeId>> {
-        let (meta_snap, kvs, ttls, locks) = deserialize::<SnapshotDataContent>(bytes)
+        let content = deserialize_snapshot(bytes)
             .map_err(|e| StorageIOError::read_snapshot(Some(meta.signature()), &e))?;
-        debug_assert_eq!(meta.snapshot_id, meta_snap.snapshot_id);
-        debug_assert_eq!(meta.last_log_id, meta_snap.last_log_id);
-        debug_assert_eq!(meta.last_membership, meta_snap.last_membership);
+        debug_assert_eq!(meta.snapshot_id, content.0.snapshot_id);
+        debug_assert_eq!(meta.last_log_id, content.0.last_log_id);
+        debug_assert_eq!(meta.last_membership, content.0.last_membership);
 
-        self.update_state_machine((meta_snap, kvs, ttls, locks))
-            .await;
+        self.update_state_machine(content).await;
 
         Ok(())
     }
 
-    async fn update_state_machine(&self, content: SnapshotDataContent) {
-        let (meta, kvs, ttls, locks) = content;
+    pub(super) async fn update_state_machine(&self, content: SnapshotDataContent) {
+        let (meta, kvs, ttls, locks, mut versions) = content;
+        versions.resize_with(kvs.len(), SnapshotVersion::default);
+        let log_index = meta.last_log_id.map(|id| id.index).unwrap_or_default();
 
         // make sure to hold the metadata lock the whole time
         let mut data = self.data.write().await;
 
-        for (idx, kv_data) in kvs.into_iter().enumerate() {
+        for (idx, kv_data) in kvs.into_iter().zip(versions).enumerate() {
             let (ack, rx) = oneshot::channel();
             self.tx_caches
                 .get(idx)
                 .unwrap()
-                .send(CacheRequestHandler::SnapshotInstall((kv_data, ack)))
+                .send(CacheRequestHandler::SnapshotInstall((
+                    kv_data,
+                    ttls.get(idx).cloned().unwrap_or_default(),
+                    log_index,
+                    ack,
+                )))
                 .expect("kv handler to always be running");
             rx.await
                 .expect("to always receive an answer from the kv handler");
@@ -586,12 +721,24 @@ impl StateMachineMemory {
 
         Ok(Some((
             path,
-            deserialize::<SnapshotDataContent>(&bytes)
-                .map_err(|e| StorageIOError::read_snapshot(None, &e))?,
+            deserialize_snapshot(&bytes).map_err(|e| StorageIOError::read_snapshot(None, &e))?,
         )))
     }
 }
 
+/// Deserializes a snapshot and falls back to the layout before entry versions have been added.
+/// Versions were appended at the very end, so an older snapshot always fails with an unexpected
+/// end instead of being misinterpreted.
+fn deserialize_snapshot(bytes: &[u8]) -> Result<SnapshotDataContent, bincode::error::DecodeError> {
+    match deserialize::<SnapshotDataContent>(bytes) {
+        Ok(content) => Ok(content),
+        Err(err) => match deserialize::<SnapshotDataContentV1>(bytes) {
+            Ok((meta, kvs, ttls, locks)) => Ok((meta, kvs, ttls, locks, Vec::default())),
+            Err(_) => Err(err),
+        },
+    }
+}
+
 impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
     type SnapshotBuilder = Self;
 
@@ -617,6 +764,9 @@ impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
         let mut last_applied_log_id = None;
         for entry in entries {
             last_applied_log_id = Some(entry.log_id);
+            // the version of each written cache entry is the index of its last write, and
+            // watchers receive it with each event
+            let log_index = entry.log_id.index;
 
             // we are using sync sends -> unbounded channels
             let resp_value = match entry.payload {
@@ -652,7 +802,12 @@ impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
                         self.tx_caches
                             .get(cache_idx)
                             .unwrap()
-                            .send(CacheRequestHandler::Put((key.to_string(), value)))
+                            .send(CacheRequestHandler::Put((
+                                key.to_string(),
+                                value,
+                                expires,
+                                log_index,
+                            )))
                             .expect("cache ttl handler to always be running");
 
                         CacheResponse::Ok
@@ -663,7 +818,11 @@ impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
                         self.tx_caches
                             .get(cache_idx)
                             .unwrap()
-                            .send(CacheRequestHandler::GetRemove((key.to_string(), ack)))
+                            .send(CacheRequestHandler::GetRemove((
+                                key.to_string(),
+                                log_index,
+                                ack,
+                            )))
                             .expect("kv handler to always be running");
 
                         // The kv handler runs on its own thread per cache and never takes the
@@ -697,7 +856,13 @@ impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
                         self.tx_caches
                             .get(cache_idx)
                             .unwrap()
-                            .send(CacheRequestHandler::Replace((key.to_string(), value, ack)))
+                            .send(CacheRequestHandler::Replace((
+                                key.to_string(),
+                                value,
+                                expires,
+                                log_index,
+                                ack,
+                            )))
                             .expect("kv handler to always be running");
 
                         CacheResponse::Value(rx.await.expect("kv handler to always answer"))
@@ -707,7 +872,7 @@ impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
                         self.tx_caches
                             .get(cache_idx)
                             .unwrap()
-                            .send(CacheRequestHandler::Delete(key.to_string()))
+                            .send(CacheRequestHandler::Delete((key.to_string(), log_index)))
                             .expect("cache ttl handler to always be running");
 
                         CacheResponse::Ok
@@ -717,7 +882,7 @@ impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
                         self.tx_caches
                             .get(cache_idx)
                             .unwrap()
-                            .send(CacheRequestHandler::Clear)
+                            .send(CacheRequestHandler::Clear(log_index))
                             .expect("cache ttl handler to always be running");
 
                         CacheResponse::Ok
@@ -740,7 +905,7 @@ impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
 
                     CacheRequest::ClearAll => {
                         for tx in &self.tx_caches {
-                            tx.send(CacheRequestHandler::Clear)
+                            tx.send(CacheRequestHandler::Clear(log_index))
                                 .expect("cache ttl handler to always be running");
                             #[cfg(feature = "counters")]
                             tx.send(CacheRequestHandler::ClearCounters)
@@ -882,6 +1047,138 @@ impl RaftStateMachine<TypeConfigKV> for Arc<StateMachineMemory> {
                         #[cfg(not(feature = "counters"))]
                         unreachable!("CounterDel requires the `counters` feature")
                     }
+
+                    CacheRequest::Evict { cache_idx, keys } => {
+                        let tx_ttl = self.tx_ttls.get(cache_idx).unwrap();
+                        for key in &keys {
+                            tx_ttl
+                                .send(TtlRequest::Clear(key.clone()))
+                                .expect("cache ttl handler to always be running");
+                        }
+                        self.tx_caches
+                            .get(cache_idx)
+                            .unwrap()
+                            .send(CacheRequestHandler::Evict((keys, log_index)))
+                            .expect("kv handler to always be running");
+
+                        CacheResponse::Ok
+                    }
+
+                    CacheRequest::Batch { cache_idx, ops } => {
+                        let tx_ttl = self.tx_ttls.get(cache_idx).unwrap();
+                        for op in &ops {
+                            // mirrors `Put`: a re-put without a TTL drops any registered expiry
+                            if let CacheBatchOp::Put { key, expires, .. } = op {
+                                let req = match expires {
+                                    Some(exp) => TtlRequest::Ttl((*exp, key.to_string())),
+                                    None => TtlRequest::Clear(key.to_string()),
+                                };
+                                tx_ttl
+                                    .send(req)
+                                    .expect("cache ttl handler to always be running");
+                            }
+                        }
+
+                        // all ops are sent as a single message, so that no read can ever see a
+                        // partially applied batch
+                        self.tx_caches
+                            .get(cache_idx)
+                            .unwrap()
+                            .send(CacheRequestHandler::Batch((ops, log_index)))
+                            .expect("kv handler to always be running");
+
+                        CacheResponse::Ok
+                    }
+
+                    CacheRequest::GetMany { .. } => {
+                        unreachable!("a CacheRequest::GetMany should never come through the Raft")
+                    }
+
+                    CacheRequest::ScanPrefix { .. } | CacheRequest::Keys { .. } => {
+                        unreachable!(
+                            "a CacheRequest prefix scan should never come through the Raft"
+                        )
+                    }
+
+                    CacheRequest::DeletePrefix { cache_idx, prefix } => {
+                        let (ack, rx) = oneshot::channel();
+                        self.tx_caches
+                            .get(cache_idx)
+                            .unwrap()
+                            .send(CacheRequestHandler::DeletePrefix((prefix, log_index, ack)))
+                            .expect("kv handler to always be running");
+
+                        CacheResponse::Deleted(rx.await.expect("kv handler to always answer"))
+                    }
+
+                    CacheRequest::GetVersioned { .. } => {
+                        unreachable!(
+                            "a CacheRequest::GetVersioned should never come through the Raft"
+                        )
+                    }
+
+                    CacheRequest::PutIfVersion {
+                        cache_idx,
+                        key,
+                        value,
+                        expires,
+                        expected,
+                        now,
+                    } => {
+                        let (ack, rx) = oneshot::channel();
+                        self.tx_caches
+                            .get(cache_idx)
+                            .unwrap()
+                            .send(CacheRequestHandler::PutIfVersion((
+                                key.to_string(),
+                                value,
+                                expires,
+                                expected,
+                                now,
+                                log_index,
+                                ack,
+                            )))
+                            .expect("kv handler to always be running");
+                        let written = rx.await.expect("kv handler to always answer");
+
+                        // the expiry must only change, if the value has actually been written
+                        if written {
+                            let req = match expires {
+                                Some(exp) => TtlRequest::Ttl((exp, key.to_string())),
+                                None => TtlRequest::Clear(key.to_string()),
+                            };
+                            self.tx_ttls
+                                .get(cache_idx)
+                                .unwrap()
+                                .send(req)
+                                .expect("cache ttl handler to always be running");
+                        }
+
+                        CacheResponse::Version(written.then_some(log_index))
+                    }
+
+                    CacheRequest::DeleteIfVersion {
+                        cache_idx,
+                        key,
+                        expected,
+                        now,
+                    } => {
+                        let (ack, rx) = oneshot::channel();
+                        self.tx_caches
+                            .get(cache_idx)
+                            .unwrap()
+                            .send(CacheRequestHandler::DeleteIfVersion((
+                                key.to_string(),
+                                expected,
+                                now,
+                                log_index,
+                                ack,
+                            )))
+                            .expect("kv handler to always be running");
+                        let deleted = rx.await.expect("kv handler to always answer");
+
+                        CacheResponse::Deleted(usize::from(deleted))
+                    }
                 },
 
                 EntryPayload::Membership(mem) => {
@@ -1066,7 +1363,7 @@ mod tests {
         let base = base_dir.to_str().unwrap();
 
         let mut sm = Arc::new(
-            StateMachineMemory::new::<TestCache>(base, true)
+            StateMachineMemory::new::<TestCache>(base, true, &CacheLimits::default())
                 .await
                 .expect("in-memory state machine to start without a data_dir"),
         );
@@ -1107,7 +1404,7 @@ mod tests {
         let base = base_dir.to_str().unwrap();
 
         let sm = Arc::new(
-            StateMachineMemory::new::<TestCache>(base, false)
+            StateMachineMemory::new::<TestCache>(base, false, &CacheLimits::default())
                 .await
                 .expect("state machine to start"),
         );
@@ -1231,5 +1528,103 @@ mod serialized_enum_order {
             }),
             15
         );
+        assert_eq!(
+            idx(&CacheRequest::Evict {
+                cache_idx: 0,
+                keys: vec![]
+            }),
+            16
+        );
+        assert_eq!(
+            idx(&CacheRequest::Batch {
+                cache_idx: 0,
+                ops: vec![]
+            }),
+            17
+        );
+        assert_eq!(
+            idx(&CacheRequest::GetMany {
+                cache_idx: 0,
+                keys: vec![]
+            }),
+            18
+        );
+        assert_eq!(
+            idx(&CacheRequest::ScanPrefix {
+                cache_idx: 0,
+                prefix: String::new(),
+                limit: 0,
+                cursor: None
+            }),
+            19
+        );
+        assert_eq!(
+            idx(&CacheRequest::Keys {
+                cache_idx: 0,
+                prefix: String::new()
+            }),
+            20
+        );
+        assert_eq!(
+            idx(&CacheRequest::DeletePrefix {
+                cache_idx: 0,
+                prefix: String::new()
+            }),
+            21
+        );
+        assert_eq!(
+            idx(&CacheRequest::GetVersioned {
+                cache_idx: 0,
+                key: String::new()
+            }),
+            22
+        );
+        assert_eq!(
+            idx(&CacheRequest::PutIfVersion {
+                cache_idx: 0,
+                key: key(),
+                value: vec![],
+                expires: None,
+                expected: None,
+                now: 0
+            }),
+            23
+        );
+        assert_eq!(
+            idx(&CacheRequest::DeleteIfVersion {
+                cache_idx: 0,
+                key: key(),
+                expected: 0,
+                now: 0
+            }),
+            24
+        );
+    }
+
+    #[test]
+    fn snapshot_without_versions_is_still_readable() {
+        let kvs: SnapshotKVs = vec![(
+            BTreeMap::from([("key".to_string(), b"value".to_vec())]),
+            BTreeMap::new(),
+        )];
+        let legacy: SnapshotDataContentV1 = (SnapshotMeta::default(), kvs.clone(), vec![], vec![]);
+        let bytes = serialize(&legacy).unwrap();
+
+        let (_, kvs_read, _, _, versions) = deserialize_snapshot(&bytes).unwrap();
+        assert_eq!(kvs_read, kvs);
+        assert!(versions.is_empty());
+
+        let current: SnapshotDataContent = (
+            SnapshotMeta::default(),
+            kvs,
+            vec![],
+            vec![],
+            vec![BTreeMap::from([("key".to_string(), 7)])],
+        );
+        let bytes = serialize(&current).unwrap();
+        let (_, _, _, _, versions) = deserialize_snapshot(&bytes).unwrap();
+        assert_eq!(versions[0].get("key"), Some(&7));
+
+        assert!(deserialize_snapshot(b"invalid").is_err());
     }
 }
@@ -0,0 +1,319 @@
+use crate::Error;
+use crate::helpers::{deserialize, event_channel, send_event};
+use crate::store::state_machine::memory::kv_handler::CacheRequestHandler;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// The amount of recent events, which are kept to resume watches.
+const WATCH_HISTORY: usize = 1024;
+
+/// Events are still recorded for this long after the last watcher has gone away, so that it can
+/// resume after a reconnect.
+const WATCH_RESUME_WINDOW: Duration = Duration::from_secs(300);
+
+/// The keys a watch receives events for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatchKey {
+    /// Only this exact key.
+    Exact(String),
+    /// All keys starting with this prefix. An empty prefix matches the whole cache.
+    Prefix(String),
+}
+
+impl WatchKey {
+    #[inline]
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Self::Exact(k) => k == key,
+            Self::Prefix(prefix) => key.starts_with(prefix),
+        }
+    }
+}
+
+/// A change of a watched cache entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WatchEvent {
+    /// The value has been written with the Raft log entry at `log_index`.
+    Put {
+        key: String,
+        value: Vec<u8>,
+        log_index: u64,
+    },
+    /// The value has been deleted or evicted with the Raft log entry at `log_index`.
+    Delete { key: String, log_index: u64 },
+    /// The value has reached its TTL. Expiry does not go through the Raft, which is why
+    /// `log_index` is the last one that has been applied to the cache at that point, which may
+    /// be shared with other events.
+    Expire { key: String, log_index: u64 },
+    /// A snapshot has been installed on the node serving this watch. This replaces the whole
+    /// cache without emitting any events for single entries, so you should re-sync your data.
+    SnapshotInstalled { log_index: u64 },
+}
+
+impl WatchEvent {
+    /// The Raft log index this event belongs to. Log indexes never decrease, but multiple events
+    /// may share the same one. To resume a watch, pass the index of the last event you have seen
+    /// to `Client::watch_from()` and skip the ones with this index you have processed already.
+    pub fn log_index(&self) -> u64 {
+        match self {
+            Self::Put { log_index, .. }
+            | Self::Delete { log_index, .. }
+            | Self::Expire { log_index, .. }
+            | Self::SnapshotInstalled { log_index } => *log_index,
+        }
+    }
+
+    /// The key of the entry, or `None` for a `SnapshotInstalled`.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Self::Put { key, .. } | Self::Delete { key, .. } | Self::Expire { key, .. } => {
+                Some(key)
+            }
+            Self::SnapshotInstalled { .. } => None,
+        }
+    }
+
+    /// Deserializes the value of a `Put`, or returns `None` for all other events.
+    pub fn value<V>(&self) -> Result<Option<V>, Error>
+    where
+        V: for<'a> Deserialize<'a>,
+    {
+        match self {
+            Self::Put { value, .. } => Ok(Some(deserialize(value)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Registers a new watcher for the given keys in the cache behind `tx_cache`. Because the kv
+/// handler processes all messages in order, no write that is applied afterward can be missed.
+///
+/// With `resume_from`, all recorded events with a log index greater or equal are replayed first.
+/// This fails, if the cache cannot guarantee that none of them are missing.
+pub(crate) async fn subscribe(
+    tx_cache: &flume::Sender<CacheRequestHandler>,
+    key: WatchKey,
+    resume_from: Option<u64>,
+) -> Result<flume::Receiver<Result<WatchEvent, Error>>, Error> {
+    let (tx, rx) = event_channel();
+    let (ack, ack_rx) = oneshot::channel();
+    tx_cache
+        .send(CacheRequestHandler::Watch((key, resume_from, tx, ack)))
+        .expect("kv handler to always be running");
+    ack_rx
+        .await
+        .map_err(|err| Error::Error(err.to_string().into()))??;
+    Ok(rx)
+}
+
+struct Watcher {
+    key: WatchKey,
+    tx: flume::Sender<Result<WatchEvent, Error>>,
+}
+
+/// All active watchers of a single cache together with the recent events to resume them. Lives
+/// inside the `kv_handler`, so that events are emitted in the exact order in which entries are
+/// applied.
+#[derive(Default)]
+pub(super) struct Watchers {
+    watchers: Vec<Watcher>,
+    history: VecDeque<WatchEvent>,
+    /// The first log index with completely recorded events, or `None` if nothing is recorded.
+    since: Option<u64>,
+    /// Events up to this log index are not kept anymore.
+    evicted: u64,
+    /// Set as soon as the last watcher has gone away.
+    retain_until: Option<Instant>,
+}
+
+impl Watchers {
+    /// Adds a new watcher. `last_index` is the highest log index, which has been applied to the
+    /// cache already.
+    pub(super) fn add(
+        &mut self,
+        key: WatchKey,
+        resume_from: Option<u64>,
+        tx: flume::Sender<Result<WatchEvent, Error>>,
+        last_index: u64,
+    ) -> Result<(), Error> {
+        self.watchers.retain(|w| !w.tx.is_disconnected());
+
+        let watcher = Watcher { key, tx };
+        if let Some(log_index) = resume_from {
+            if log_index <= self.evicted || self.since.is_none_or(|since| since > log_index) {
+                return Err(Error::BadRequest(
+                    format!(
+                        "Cannot resume cache watch from log index {log_index}: this node does \
+                         not have all events since then - re-sync instead"
+                    )
+                    .into(),
+                ));
+            }
+
+            let replayed = self
+                .history
+                .iter()
+                .filter(|evt| evt.log_index() >= log_index)
+                .all(|evt| watcher.send(evt));
+            if !replayed {
+                // the watcher has received the error already
+                return Ok(());
+            }
+        }
+
+        self.since.get_or_insert(last_index + 1);
+        self.retain_until = None;
+        self.watchers.push(watcher);
+        Ok(())
+    }
+
+    /// Returns `true` if events are neither sent out nor recorded right now.
+    #[inline]
+    pub(super) fn is_idle(&self) -> bool {
+        self.since.is_none()
+    }
+
+    pub(super) fn put(&mut self, key: &str, value: &[u8], log_index: u64) {
+        self.emit(|| WatchEvent::Put {
+            key: key.to_string(),
+            value: value.to_vec(),
+            log_index,
+        });
+    }
+
+    pub(super) fn delete(&mut self, key: &str, log_index: u64) {
+        self.emit(|| WatchEvent::Delete {
+            key: key.to_string(),
+            log_index,
+        });
+    }
+
+    pub(super) fn expire(&mut self, key: &str, log_index: u64) {
+        self.emit(|| WatchEvent::Expire {
+            key: key.to_string(),
+            log_index,
+        });
+    }
+
+    pub(super) fn snapshot_installed(&mut self, log_index: u64) {
+        self.emit(|| WatchEvent::SnapshotInstalled { log_index });
+    }
+
+    /// Sends the event to all matching watchers, removes the ones that have gone away and records
+    /// it until the resume window after the last watcher has passed.
+    fn emit<F>(&mut self, event: F)
+    where
+        F: FnOnce() -> WatchEvent,
+    {
+        if self.is_idle() {
+            return;
+        }
+
+        let event = event();
+        self.watchers.retain(|w| w.send(&event));
+
+        let now = Instant::now();
+        if !self.watchers.is_empty() {
+            self.retain_until = None;
+        } else if self.retain_until.is_none() {
+            self.retain_until = Some(now + WATCH_RESUME_WINDOW);
+        } else if self.retain_until.is_some_and(|until| until < now) {
+            self.since = None;
+            self.retain_until = None;
+            self.history.clear();
+            return;
+        }
+
+        if self.history.len() == WATCH_HISTORY
+            && let Some(oldest) = self.history.pop_front()
+        {
+            self.evicted = oldest.log_index();
+        }
+        self.history.push_back(event);
+    }
+}
+
+impl Watcher {
+    /// Returns `false` if the watcher has gone away or is lagging behind.
+    fn send(&self, event: &WatchEvent) -> bool {
+        if event.key().is_none_or(|k| self.key.matches(k)) {
+            // never blocks the kv handler, a lagging watcher is dropped with an error
+            send_event(&self.tx, Ok(event.clone()), "Cache watcher")
+        } else {
+            !self.tx.is_disconnected()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::EVENT_CHANNEL_CAP;
+
+    #[test]
+    fn lagging_watchers_are_dropped() {
+        let (tx, rx) = event_channel();
+        let mut watchers = Watchers::default();
+        watchers
+            .add(WatchKey::Exact("key".to_string()), None, tx, 0)
+            .unwrap();
+
+        for log_index in 0..EVENT_CHANNEL_CAP as u64 {
+            watchers.put("key", b"value", log_index);
+        }
+        assert!(!watchers.watchers.is_empty());
+        watchers.put("key", b"value", EVENT_CHANNEL_CAP as u64);
+        assert!(watchers.watchers.is_empty());
+
+        let events = rx.drain().collect::<Vec<_>>();
+        assert_eq!(events.len(), EVENT_CHANNEL_CAP + 1);
+        assert!(events[..EVENT_CHANNEL_CAP].iter().all(|e| e.is_ok()));
+        assert!(events.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn watches_match_keys_and_resume_from_a_log_index() {
+        fn indexes(rx: &flume::Receiver<Result<WatchEvent, Error>>) -> Vec<u64> {
+            rx.drain().map(|evt| evt.unwrap().log_index()).collect()
+        }
+
+        let mut watchers = Watchers::default();
+        let (tx_exact, rx_exact) = event_channel();
+        let (tx_prefix, rx_prefix) = event_channel();
+        watchers
+            .add(WatchKey::Exact("key".to_string()), None, tx_exact, 10)
+            .unwrap();
+        watchers
+            .add(WatchKey::Prefix("key".to_string()), None, tx_prefix, 10)
+            .unwrap();
+
+        watchers.put("key", b"1", 11);
+        watchers.put("key_2", b"2", 12);
+        watchers.delete("key", 13);
+        assert_eq!(indexes(&rx_exact), vec![11, 13]);
+        assert_eq!(indexes(&rx_prefix), vec![11, 12, 13]);
+        drop(rx_exact);
+        drop(rx_prefix);
+
+        // events are still recorded after the last watcher has gone away
+        watchers.put("key", b"3", 14);
+        assert!(watchers.watchers.is_empty());
+        assert!(!watchers.is_idle());
+
+        let (tx, rx) = event_channel();
+        watchers
+            .add(WatchKey::Exact("key".to_string()), Some(13), tx, 14)
+            .unwrap();
+        assert_eq!(indexes(&rx), vec![13, 14]);
+
+        // nothing has been recorded before the first watcher
+        let (tx, _rx) = event_channel();
+        assert!(
+            watchers
+                .add(WatchKey::Prefix(String::new()), Some(10), tx, 14)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,752 @@
+use crate::helpers::{event_channel, send_event};
+use crate::query::rows::{ColumnOwned, RowOwned};
+use crate::store::state_machine::sqlite::writer::INTERNAL_TABLES;
+use crate::{Error, NodeId};
+use openraft::LogId;
+use rusqlite::hooks::Action;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::task;
+use tracing::{debug, error, info};
+
+/// The kind of change that happened to a single row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A single changed row.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RowChange {
+    pub table: String,
+    pub op: ChangeOp,
+    pub rowid: i64,
+    /// The values of the row at the end of this log entry, read by the writer before it applies
+    /// the next one. If a row has been changed multiple times inside the same entry, all of its
+    /// changes contain these final values. This is `None` for deletes and for rows, which have
+    /// been removed again later on in the same entry.
+    pub values: Option<RowOwned>,
+}
+
+/// Row changes coming from the SQLite state machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ChangeEvent {
+    /// All changed rows from a single Raft log entry, in the order they have been applied.
+    Rows {
+        log_index: u64,
+        changes: Vec<RowChange>,
+    },
+    /// A snapshot has been installed on the node serving this subscription. This replaces the
+    /// whole database without emitting any row changes, so you should re-sync your data.
+    SnapshotInstalled { log_index: u64 },
+}
+
+impl ChangeEvent {
+    /// The Raft log index this event belongs to. Log indexes are strictly increasing, which you
+    /// can use to resume a subscription after a reconnect.
+    pub fn log_index(&self) -> u64 {
+        match self {
+            Self::Rows { log_index, .. } => *log_index,
+            Self::SnapshotInstalled { log_index } => *log_index,
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub enum ChangesRequest {
+    Publish(ChangeEvent),
+    /// The changes of the log entry with this index could not be read. Every subscriber will
+    /// receive the error and is dropped, because it would miss this entry otherwise.
+    Failed((u64, Error)),
+    Subscribe(
+        (
+            Vec<String>,
+            Option<u64>,
+            flume::Sender<Result<ChangeEvent, Error>>,
+            oneshot::Sender<Result<(), Error>>,
+        ),
+    ),
+}
+
+/// The amount of recent events, which are kept to resume subscriptions.
+const CHANGES_HISTORY: usize = 1024;
+
+/// Tables are still captured for this long after their last subscriber has gone away, so that
+/// it can resume after a reconnect.
+const CHANGES_RESUME_WINDOW: Duration = Duration::from_secs(300);
+
+/// The capture state of a single table, or of all of them.
+#[derive(Debug, Default)]
+struct Capture {
+    /// The first log index with completely captured changes. This is filled in by the writer
+    /// at the end of the first log entry after the capture has started.
+    since: Option<u64>,
+    /// Set as soon as the last subscriber has gone away.
+    retain_until: Option<Instant>,
+}
+
+impl Capture {
+    #[inline]
+    fn covers(&self, log_index: u64) -> bool {
+        self.since.is_some_and(|since| since <= log_index)
+    }
+}
+
+/// The tables changes are captured for. Shared with the writer, so that changes are only
+/// captured when someone is actually interested in them.
+#[derive(Debug, Default)]
+pub struct ChangesFilter {
+    all: Option<Capture>,
+    tables: HashMap<String, Capture>,
+}
+
+impl ChangesFilter {
+    #[inline]
+    fn matches(&self, table: &str) -> bool {
+        self.all.is_some() || self.tables.contains_key(table)
+    }
+
+    fn has_expired(&self, now: Instant) -> bool {
+        self.all
+            .iter()
+            .chain(self.tables.values())
+            .any(|c| c.retain_until.is_some_and(|until| until < now))
+    }
+
+    fn captures(&mut self) -> impl Iterator<Item = &mut Capture> {
+        self.all.iter_mut().chain(self.tables.values_mut())
+    }
+
+    /// Returns `true` if the changes for all of these tables have been captured without any gap
+    /// since the given log index. An empty `tables` means all of them.
+    fn covers(&self, tables: &[String], log_index: u64) -> bool {
+        if self.all.as_ref().is_some_and(|c| c.covers(log_index)) {
+            return true;
+        }
+        !tables.is_empty()
+            && tables
+                .iter()
+                .all(|t| self.tables.get(t).is_some_and(|c| c.covers(log_index)))
+    }
+}
+
+/// Creates the channel to the changes handler.
+#[allow(clippy::type_complexity)]
+pub fn channel() -> (
+    flume::Sender<ChangesRequest>,
+    flume::Receiver<ChangesRequest>,
+    Arc<RwLock<ChangesFilter>>,
+) {
+    let (tx, rx) = flume::bounded(CHANGES_CHANNEL_CAP);
+    (tx, rx, Arc::new(RwLock::new(ChangesFilter::default())))
+}
+
+/// The capacity of the channel to the changes handler. The writer blocks, as soon as the
+/// handler falls behind by this many log entries.
+const CHANGES_CHANNEL_CAP: usize = 1024;
+
+pub fn spawn(rx: flume::Receiver<ChangesRequest>, filter: Arc<RwLock<ChangesFilter>>) {
+    task::spawn(handler(rx, filter));
+}
+
+/// Registers a new subscriber for the given tables. An empty `tables` subscribes to all of them.
+/// Returns as soon as the subscriber is active, so that no changes from writes that have been
+/// started afterward can be missed. A subscriber, which does not keep up with the events, will
+/// receive an error and is dropped.
+///
+/// With `resume_from`, all events with a log index greater or equal are replayed first. This
+/// fails, if this node cannot guarantee that none of them are missing, because they have not
+/// been captured or are not kept anymore.
+pub async fn subscribe(
+    tx_changes: &flume::Sender<ChangesRequest>,
+    tables: Vec<String>,
+    resume_from: Option<u64>,
+) -> Result<flume::Receiver<Result<ChangeEvent, Error>>, Error> {
+    let (tx, rx) = event_channel();
+    let (ack, ack_rx) = oneshot::channel();
+    tx_changes
+        .send_async(ChangesRequest::Subscribe((tables, resume_from, tx, ack)))
+        .await
+        .map_err(|err| Error::Error(err.to_string().into()))?;
+    ack_rx
+        .await
+        .map_err(|err| Error::Error(err.to_string().into()))??;
+    Ok(rx)
+}
+
+struct Subscriber {
+    tables: Vec<String>,
+    /// Events before this log index have not been requested.
+    from: u64,
+    tx: flume::Sender<Result<ChangeEvent, Error>>,
+}
+
+impl Subscriber {
+    /// Returns `false` if the subscriber has gone away or is lagging behind.
+    fn send(&self, event: &ChangeEvent) -> bool {
+        if event.log_index() < self.from {
+            return !self.tx.is_disconnected();
+        }
+
+        let event = match event {
+            ChangeEvent::Rows { log_index, changes } if !self.tables.is_empty() => {
+                let changes = changes
+                    .iter()
+                    .filter(|c| self.tables.contains(&c.table))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if changes.is_empty() {
+                    return !self.tx.is_disconnected();
+                }
+                ChangeEvent::Rows {
+                    log_index: *log_index,
+                    changes,
+                }
+            }
+            event => event.clone(),
+        };
+
+        send_event(&self.tx, Ok(event), "Change subscriber")
+    }
+}
+
+/// The most recent events, which can be replayed to resuming subscribers.
+#[derive(Default)]
+struct History {
+    events: VecDeque<ChangeEvent>,
+    /// Events up to this log index are not kept anymore.
+    evicted: u64,
+}
+
+impl History {
+    fn push(&mut self, event: ChangeEvent) {
+        if self.events.len() == CHANGES_HISTORY
+            && let Some(oldest) = self.events.pop_front()
+        {
+            self.evicted = oldest.log_index();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Forgets all events up to `log_index`, which makes sure, that no subscriber can resume
+    /// across a gap.
+    fn invalidate(&mut self, log_index: u64) {
+        self.events.clear();
+        self.evicted = self.evicted.max(log_index);
+    }
+
+    fn replay(&self, sub: &Subscriber) -> bool {
+        self.events.iter().all(|event| sub.send(event))
+    }
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+async fn handler(rx: flume::Receiver<ChangesRequest>, filter: Arc<RwLock<ChangesFilter>>) {
+    let mut subscribers: Vec<Subscriber> = Vec::new();
+    let mut history = History::default();
+
+    while let Ok(req) = rx.recv_async().await {
+        let event = match req {
+            ChangesRequest::Publish(event) => event,
+            ChangesRequest::Failed((log_index, err)) => {
+                error!(
+                    "Error reading changes for log index {}, dropping all change subscribers: {}",
+                    log_index, err
+                );
+                history.invalidate(log_index);
+                for sub in subscribers.drain(..) {
+                    let _ = sub.tx.try_send(Err(Error::Error(
+                        format!("Reading the changes for log index {log_index} failed: {err}")
+                            .into(),
+                    )));
+                }
+                update_filter(&filter, &subscribers);
+                continue;
+            }
+            ChangesRequest::Subscribe((tables, resume_from, tx, ack)) => {
+                info!(
+                    "New change subscriber for tables {:?} resuming from {:?}",
+                    tables, resume_from
+                );
+
+                if let Some(log_index) = resume_from
+                    && (log_index <= history.evicted
+                        || !filter.read().unwrap().covers(&tables, log_index))
+                {
+                    let _ = ack.send(Err(Error::BadRequest(
+                        format!(
+                            "Cannot resume change subscription from log index {log_index}: \
+                             this node does not have all changes since then - re-sync instead"
+                        )
+                        .into(),
+                    )));
+                    continue;
+                }
+
+                let sub = Subscriber {
+                    tables,
+                    from: resume_from.unwrap_or_default(),
+                    tx,
+                };
+                if resume_from.is_none() || history.replay(&sub) {
+                    subscribers.push(sub);
+                    update_filter(&filter, &subscribers);
+                }
+                let _ = ack.send(Ok(()));
+                continue;
+            }
+        };
+
+        let len = subscribers.len();
+        subscribers.retain(|sub| sub.send(&event));
+        history.push(event);
+
+        if subscribers.len() != len {
+            info!("Removed {} change subscribers", len - subscribers.len());
+            update_filter(&filter, &subscribers);
+        } else if filter.read().unwrap().has_expired(Instant::now()) {
+            update_filter(&filter, &subscribers);
+        }
+    }
+
+    debug!("Changes handler exiting");
+}
+
+/// Adds all tables of the given subscribers to the filter and starts the resume window for the
+/// ones without any subscriber left. Tables are removed after their window has passed.
+fn update_filter(filter: &RwLock<ChangesFilter>, subscribers: &[Subscriber]) {
+    let now = Instant::now();
+    let mut filter = filter.write().unwrap();
+
+    for capture in filter.captures() {
+        if capture.retain_until.is_none() {
+            capture.retain_until = Some(now + CHANGES_RESUME_WINDOW);
+        }
+    }
+    for sub in subscribers {
+        if sub.tables.is_empty() {
+            filter.all.get_or_insert_default().retain_until = None;
+        } else {
+            for table in &sub.tables {
+                filter.tables.entry(table.clone()).or_default().retain_until = None;
+            }
+        }
+    }
+
+    if filter
+        .all
+        .as_ref()
+        .is_some_and(|c| c.retain_until.is_some_and(|until| until < now))
+    {
+        filter.all = None;
+    }
+    filter
+        .tables
+        .retain(|_, c| c.retain_until.is_none_or(|until| until >= now));
+}
+#[derive(Debug)]
+struct CapturedChange {
+    table: String,
+    op: ChangeOp,
+    rowid: i64,
+}
+
+#[derive(Debug, Default)]
+struct CaptureBuf {
+    changes: Vec<CapturedChange>,
+    /// The amount of `changes` at the front, which have been committed already.
+    committed: usize,
+    /// Set during a write budget dry run on the leader.
+    dry_run: bool,
+}
+
+/// Captures row changes on the writer connection via SQLite hooks.
+///
+/// The update hook collects changes while statements are running. They are only marked as
+/// committed by the commit hook and dropped again by the rollback hook, which makes sure
+/// that rolled back transactions never show up as events. Rollbacks of single statements or
+/// savepoints inside a transaction do not fire any hook and must be handled with `mark()` and
+/// `discard_since()` by the writer.
+pub struct ChangeCapture {
+    buf: Arc<Mutex<CaptureBuf>>,
+    filter: Arc<RwLock<ChangesFilter>>,
+    tx: flume::Sender<ChangesRequest>,
+}
+
+impl ChangeCapture {
+    pub fn register(
+        conn: &rusqlite::Connection,
+        filter: Arc<RwLock<ChangesFilter>>,
+        tx: flume::Sender<ChangesRequest>,
+    ) -> Result<Self, Error> {
+        let buf = Arc::new(Mutex::new(CaptureBuf::default()));
+
+        let buf_update = buf.clone();
+        let filter_update = filter.clone();
+        conn.update_hook(Some(
+            move |action: Action, _db: &str, table: &str, rowid: i64| {
+                let op = match action {
+                    Action::SQLITE_INSERT => ChangeOp::Insert,
+                    Action::SQLITE_UPDATE => ChangeOp::Update,
+                    Action::SQLITE_DELETE => ChangeOp::Delete,
+                    _ => return,
+                };
+                if INTERNAL_TABLES.contains(&table) || !filter_update.read().unwrap().matches(table)
+                {
+                    return;
+                }
+
+                let mut buf = buf_update.lock().unwrap();
+                if buf.dry_run {
+                    return;
+                }
+                buf.changes.push(CapturedChange {
+                    table: table.to_string(),
+                    op,
+                    rowid,
+                });
+            },
+        ))?;
+
+        let buf_commit = buf.clone();
+        conn.commit_hook(Some(move || {
+            let mut buf = buf_commit.lock().unwrap();
+            if buf.dry_run {
+                // `true` turns the commit into a rollback
+                return true;
+            }
+            buf.committed = buf.changes.len();
+            // `false` lets the commit continue
+            false
+        }))?;
+
+        let buf_rollback = buf.clone();
+        conn.rollback_hook(Some(move || {
+            let mut buf = buf_rollback.lock().unwrap();
+            let committed = buf.committed;
+            buf.changes.truncate(committed);
+        }))?;
+
+        Ok(Self { buf, filter, tx })
+    }
+
+    /// While set, nothing is captured and every commit is turned into a rollback. A dry run
+    /// can never persist anything this way, even if one of its statements ends the transaction.
+    pub fn set_dry_run(&self, dry_run: bool) {
+        self.buf.lock().unwrap().dry_run = dry_run;
+    }
+
+    /// Returns a marker for all changes captured so far.
+    pub fn mark(&self) -> usize {
+        self.buf.lock().unwrap().changes.len()
+    }
+
+    /// Drops all changes captured after the given `mark()`, even if they have been committed
+    /// in between. This must be called after a failed statement or a rolled back savepoint.
+    pub fn discard_since(&self, mark: usize) {
+        let mut buf = self.buf.lock().unwrap();
+        buf.changes.truncate(mark);
+        buf.committed = buf.committed.min(mark);
+    }
+
+    /// Publishes all committed changes as a single event for the given log entry. This must be
+    /// called at the end of each entry, because the row values are read from `conn` right
+    /// away, before the next entry can modify them.
+    pub fn publish(&self, conn: &rusqlite::Connection, log_id: Option<LogId<NodeId>>) {
+        let committed = {
+            let mut buf = self.buf.lock().unwrap();
+            let committed = mem::take(&mut buf.committed);
+            buf.changes.drain(..committed).collect::<Vec<_>>()
+        };
+        // nothing can have been applied before the log position is known
+        let Some(log_index) = log_id.map(|id| id.index) else {
+            return;
+        };
+        self.start_captures(log_index);
+        if committed.is_empty() {
+            return;
+        }
+
+        let req = match read_changes(conn, committed) {
+            Ok(changes) => ChangesRequest::Publish(ChangeEvent::Rows { log_index, changes }),
+            Err(err) => ChangesRequest::Failed((log_index, err)),
+        };
+        // this channel can never be closed - the handler lives as long as the state machine
+        let _ = self.tx.send(req);
+    }
+
+    /// Tables, which have been added to the filter while this log entry has been applied, are
+    /// completely captured from the next one on.
+    fn start_captures(&self, log_index: u64) {
+        let is_pending = |c: &Capture| c.since.is_none();
+        let pending = {
+            let filter = self.filter.read().unwrap();
+            filter.all.as_ref().is_some_and(is_pending) || filter.tables.values().any(is_pending)
+        };
+        if pending {
+            for capture in self.filter.write().unwrap().captures() {
+                capture.since.get_or_insert(log_index + 1);
+            }
+        }
+    }
+
+    /// Notifies all subscribers, that the whole database has been replaced by a snapshot.
+    pub fn snapshot_installed(&self, log_id: Option<LogId<NodeId>>) {
+        {
+            let mut buf = self.buf.lock().unwrap();
+            buf.changes.clear();
+            buf.committed = 0;
+        }
+
+        let event = ChangeEvent::SnapshotInstalled {
+            log_index: log_id.map(|id| id.index).unwrap_or_default(),
+        };
+        let _ = self.tx.send(ChangesRequest::Publish(event));
+    }
+}
+
+fn read_changes(
+    conn: &rusqlite::Connection,
+    changes: Vec<CapturedChange>,
+) -> Result<Vec<RowChange>, Error> {
+    changes
+        .into_iter()
+        .map(|change| {
+            let values = if change.op == ChangeOp::Delete {
+                None
+            } else {
+                read_row(conn, &change.table, change.rowid)?
+            };
+
+            Ok(RowChange {
+                table: change.table,
+                op: change.op,
+                rowid: change.rowid,
+                values,
+            })
+        })
+        .collect()
+}
+
+fn read_row(
+    conn: &rusqlite::Connection,
+    table: &str,
+    rowid: i64,
+) -> Result<Option<RowOwned>, Error> {
+    let sql = format!(
+        "SELECT * FROM \"{}\" WHERE rowid = $1",
+        table.replace('"', "\"\"")
+    );
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let columns = ColumnOwned::mapping_cols_from_stmt(stmt.columns())?;
+    let mut rows = stmt.query([rowid])?;
+    Ok(rows
+        .next()?
+        .map(|row| RowOwned::from_row_column(row, &columns)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openraft::CommittedLeaderId;
+
+    fn log_id(index: u64) -> Option<LogId<NodeId>> {
+        Some(LogId::new(CommittedLeaderId::new(1, 1), index))
+    }
+
+    #[test]
+    fn only_committed_changes_are_published() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT NOT NULL)",
+            (),
+        )
+        .unwrap();
+        conn.execute("CREATE TABLE other (id INTEGER PRIMARY KEY)", ())
+            .unwrap();
+
+        let (tx, rx, filter) = channel();
+        let capture = ChangeCapture::register(&conn, filter.clone(), tx).unwrap();
+
+        // nothing is captured without a subscriber
+        conn.execute("INSERT INTO test (id, name) VALUES (1, 'a')", ())
+            .unwrap();
+        capture.publish(&conn, log_id(1));
+        assert!(rx.try_recv().is_err());
+
+        filter
+            .write()
+            .unwrap()
+            .tables
+            .insert("test".to_string(), Capture::default());
+
+        conn.execute("INSERT INTO test (id, name) VALUES (2, 'b')", ())
+            .unwrap();
+        conn.execute("UPDATE test SET name = 'c' WHERE id = 2", ())
+            .unwrap();
+        conn.execute("DELETE FROM test WHERE id = 1", ()).unwrap();
+        conn.execute("INSERT INTO other (id) VALUES (1)", ())
+            .unwrap();
+        capture.publish(&conn, log_id(2));
+
+        match rx.try_recv().unwrap() {
+            ChangesRequest::Publish(ChangeEvent::Rows { log_index, changes }) => {
+                assert_eq!(log_index, 2);
+                let ops = changes
+                    .iter()
+                    .map(|c| (c.table.as_str(), c.op, c.rowid))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    ops,
+                    vec![
+                        ("test", ChangeOp::Insert, 2),
+                        ("test", ChangeOp::Update, 2),
+                        ("test", ChangeOp::Delete, 1),
+                    ]
+                );
+
+                let mut values = changes[0].values.clone().unwrap();
+                assert_eq!(values.get::<String>("name"), "c");
+                assert!(changes[2].values.is_none());
+            }
+            _ => panic!("expected a published ChangeEvent::Rows"),
+        }
+
+        // rolled back changes must never be published
+        {
+            let txn = conn.unchecked_transaction().unwrap();
+            txn.execute("INSERT INTO test (id, name) VALUES (3, 'd')", ())
+                .unwrap();
+            txn.rollback().unwrap();
+        }
+        capture.publish(&conn, log_id(3));
+        assert!(rx.try_recv().is_err());
+
+        // inside an open transaction, only the discarded savepoint must be dropped
+        conn.execute_batch("BEGIN").unwrap();
+        conn.execute("INSERT INTO test (id, name) VALUES (4, 'e')", ())
+            .unwrap();
+        let mark = capture.mark();
+        conn.execute_batch("SAVEPOINT inner").unwrap();
+        conn.execute("INSERT INTO test (id, name) VALUES (5, 'f')", ())
+            .unwrap();
+        conn.execute_batch("ROLLBACK TO inner; RELEASE inner")
+            .unwrap();
+        capture.discard_since(mark);
+        // nothing must be published before the outer commit
+        capture.publish(&conn, log_id(4));
+        assert!(rx.try_recv().is_err());
+
+        conn.execute_batch("COMMIT").unwrap();
+        capture.publish(&conn, log_id(4));
+        match rx.try_recv().unwrap() {
+            ChangesRequest::Publish(ChangeEvent::Rows { log_index, changes }) => {
+                assert_eq!(log_index, 4);
+                assert_eq!(changes.len(), 1);
+                assert_eq!(changes[0].rowid, 4);
+            }
+            _ => panic!("expected a published ChangeEvent::Rows"),
+        }
+    }
+
+    #[test]
+    fn lagging_subscribers_are_dropped() {
+        let (tx, rx) = event_channel();
+        let sub = Subscriber {
+            tables: Vec::new(),
+            from: 0,
+            tx,
+        };
+        let event = ChangeEvent::SnapshotInstalled { log_index: 1 };
+
+        for _ in 0..crate::helpers::EVENT_CHANNEL_CAP {
+            assert!(sub.send(&event));
+        }
+        assert!(!sub.send(&event));
+
+        let events = rx.drain().collect::<Vec<_>>();
+        assert_eq!(events.len(), crate::helpers::EVENT_CHANNEL_CAP + 1);
+        assert!(
+            events[..crate::helpers::EVENT_CHANNEL_CAP]
+                .iter()
+                .all(|e| e.is_ok())
+        );
+        assert!(events.last().unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn subscriptions_resume_from_a_log_index() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE test (id INTEGER PRIMARY KEY)", ())
+            .unwrap();
+
+        let (tx, rx, filter) = channel();
+        spawn(rx, filter.clone());
+        let capture = ChangeCapture::register(&conn, filter, tx.clone()).unwrap();
+
+        let rx_live = subscribe(&tx, vec!["test".to_string()], None)
+            .await
+            .unwrap();
+        for id in 1..=3 {
+            conn.execute("INSERT INTO test (id) VALUES ($1)", [id as i64])
+                .unwrap();
+            capture.publish(&conn, log_id(id));
+        }
+        for id in 1..=3 {
+            assert_eq!(rx_live.recv_async().await.unwrap().unwrap().log_index(), id);
+        }
+        drop(rx_live);
+
+        // the subscription started while entry 1 may have been applied already
+        assert!(
+            subscribe(&tx, vec!["test".to_string()], Some(1))
+                .await
+                .is_err()
+        );
+        // the table is still captured after its last subscriber is gone
+        conn.execute("INSERT INTO test (id) VALUES (4)", ())
+            .unwrap();
+        capture.publish(&conn, log_id(4));
+
+        let rx_resumed = subscribe(&tx, vec!["test".to_string()], Some(3))
+            .await
+            .unwrap();
+        for id in 3..=4 {
+            assert_eq!(
+                rx_resumed.recv_async().await.unwrap().unwrap().log_index(),
+                id
+            );
+        }
+        assert!(rx_resumed.is_empty());
+
+        // nothing can be resumed for a table, which has never been captured
+        assert!(
+            subscribe(&tx, vec!["other".to_string()], Some(3))
+                .await
+                .is_err()
+        );
+        assert!(subscribe(&tx, Vec::new(), Some(3)).await.is_err());
+
+        // a failed entry ends all subscriptions and can never be resumed across
+        tx.send_async(ChangesRequest::Failed((5, Error::Sqlite("test".into()))))
+            .await
+            .unwrap();
+        assert!(rx_resumed.recv_async().await.unwrap().is_err());
+        assert!(
+            subscribe(&tx, vec!["test".to_string()], Some(4))
+                .await
+                .is_err()
+        );
+        assert!(
+            subscribe(&tx, vec!["test".to_string()], Some(6))
+                .await
+                .is_ok()
+        );
+    }
+}
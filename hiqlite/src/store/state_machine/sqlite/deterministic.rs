@@ -0,0 +1,454 @@
+use chrono::DateTime;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+/// Built-in SQLite functions, which depend on the current time or randomness. They are replaced
+/// on the writer connection with versions reading from the `WriteStamp` of the current write.
+pub(crate) const STAMPED_FNS: &[&str] = &[
+    "current_date",
+    "current_time",
+    "current_timestamp",
+    "date",
+    "datetime",
+    "julianday",
+    "random",
+    "randomblob",
+    "strftime",
+    "time",
+    "timediff",
+    "unixepoch",
+];
+
+/// `(name, indexes of time-value args, implicit 'now' if fewer args are given)`
+const DATE_FNS: &[(&str, &[usize], Option<usize>)] = &[
+    ("date", &[0], Some(0)),
+    ("datetime", &[0], Some(0)),
+    ("julianday", &[0], Some(0)),
+    ("strftime", &[1], Some(1)),
+    ("time", &[0], Some(0)),
+    ("timediff", &[0, 1], None),
+    ("unixepoch", &[0], Some(0)),
+];
+
+/// Modifiers that depend on the local time zone of a node.
+const FORBIDDEN_MODIFIERS: &[&str] = &["localtime", "utc"];
+
+/// The current time and a random seed, stamped onto a write by the leader at proposal time.
+/// Each node applies the write with the same stamp, which makes functions like `unixepoch()`
+/// or `random()` return the same values on all replicas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteStamp {
+    pub unix_ms: i64,
+    pub seed: u64,
+}
+
+impl WriteStamp {
+    pub fn new() -> Self {
+        let seed = getrandom::u64().unwrap_or_else(|_| {
+            // should never happen, but the nanos are a good enough fallback for a seed
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64
+        });
+
+        Self {
+            unix_ms: chrono::Utc::now().timestamp_millis(),
+            seed,
+        }
+    }
+
+    /// The stamp in the format SQLite uses for `datetime('now')`.
+    fn as_sqlite_datetime(&self) -> String {
+        DateTime::from_timestamp_millis(self.unix_ms)
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M:%S%.3f")
+            .to_string()
+    }
+}
+
+/// Holds the `WriteStamp` of the write currently being applied. It is shared between the state
+/// machine, which sets it, and the replaced functions on the writer connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WriteContext(Arc<Mutex<WriteContextInner>>);
+
+#[derive(Debug, Default)]
+struct WriteContextInner {
+    stamp: Option<WriteStamp>,
+    rng_state: u64,
+    // The stamp and random state of a write budget dry run on the leader. It is kept apart from
+    // the write that is currently being applied, which may have been set in the meantime.
+    dry_run: Option<(WriteStamp, u64)>,
+    // Evaluates the original date / time functions with the stamp instead of 'now'.
+    // It never touches any data and is opened lazily on first use.
+    conn_eval: Option<rusqlite::Connection>,
+}
+
+impl WriteContext {
+    pub(crate) fn set(&self, stamp: Option<WriteStamp>) {
+        let mut inner = self
+            .0
+            .lock()
+            .expect("WriteContext lock to never be poisoned");
+        inner.rng_state = stamp.map(|s| s.seed).unwrap_or_default();
+        inner.stamp = stamp;
+    }
+
+    /// Makes the functions use the given stamp until the dry run is ended with `None`.
+    pub(crate) fn set_dry_run(&self, stamp: Option<WriteStamp>) {
+        self.0
+            .lock()
+            .expect("WriteContext lock to never be poisoned")
+            .dry_run = stamp.map(|s| (s, s.seed));
+    }
+
+    pub(crate) fn stamp(&self) -> Option<WriteStamp> {
+        self.0
+            .lock()
+            .expect("WriteContext lock to never be poisoned")
+            .stamp
+    }
+
+    /// Replaces all `STAMPED_FNS` on the given writer connection.
+    pub(crate) fn register(&self, conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+        let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+        // Only deterministic for the whole write, but never inside a single statement. SQLite
+        // would otherwise evaluate them only once, e.g. for all rows of an `INSERT ... SELECT`.
+        let flags_random = FunctionFlags::SQLITE_UTF8;
+
+        let ctx = self.clone();
+        conn.create_scalar_function("random", 0, flags_random, move |_| {
+            ctx.with_stamp("random", |inner, _| Ok(inner.next_random() as i64))
+        })?;
+
+        let ctx = self.clone();
+        conn.create_scalar_function("randomblob", 1, flags_random, move |c| {
+            let len = c.get::<i64>(0).unwrap_or(1).max(1) as usize;
+            ctx.with_stamp("randomblob", |inner, _| {
+                let mut blob = Vec::with_capacity(len + 8);
+                while blob.len() < len {
+                    blob.extend_from_slice(&inner.next_random().to_le_bytes());
+                }
+                blob.truncate(len);
+                Ok(blob)
+            })
+        })?;
+
+        for (name, fn_date) in [
+            ("current_date", "date"),
+            ("current_time", "time"),
+            ("current_timestamp", "datetime"),
+        ] {
+            let ctx = self.clone();
+            conn.create_scalar_function(name, 0, flags, move |_| {
+                ctx.with_stamp(name, |inner, stamp| {
+                    inner.eval(fn_date, vec![Value::Text(stamp.as_sqlite_datetime())])
+                })
+            })?;
+        }
+
+        for &(name, time_args, implicit_now) in DATE_FNS {
+            let ctx = self.clone();
+            conn.create_scalar_function(name, -1, flags, move |c| {
+                let mut args = (0..c.len())
+                    .map(|i| c.get::<Value>(i))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                check_modifiers(name, &args)?;
+
+                // only touch the stamp if 'now' is actually used
+                let uses_now = implicit_now.is_some_and(|idx| args.len() <= idx)
+                    || time_args
+                        .iter()
+                        .any(|idx| args.get(*idx).is_some_and(is_now));
+                if !uses_now {
+                    return ctx.eval(name, args);
+                }
+
+                ctx.with_stamp(name, |inner, stamp| {
+                    let now = Value::Text(stamp.as_sqlite_datetime());
+                    if let Some(idx) = implicit_now
+                        && args.len() <= idx
+                    {
+                        args.push(now.clone());
+                    }
+                    for idx in time_args {
+                        if args.get(*idx).is_some_and(is_now) {
+                            args[*idx] = now.clone();
+                        }
+                    }
+                    inner.eval(name, args)
+                })
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn with_stamp<F, T>(&self, name: &str, f: F) -> rusqlite::Result<T>
+    where
+        F: FnOnce(&mut WriteContextInner, WriteStamp) -> rusqlite::Result<T>,
+    {
+        let mut inner = self
+            .0
+            .lock()
+            .expect("WriteContext lock to never be poisoned");
+        if let Some((stamp, rng_state)) = inner.dry_run {
+            let rng_state = mem::replace(&mut inner.rng_state, rng_state);
+            let res = f(&mut inner, stamp);
+            let rng_state = mem::replace(&mut inner.rng_state, rng_state);
+            inner.dry_run = Some((stamp, rng_state));
+            return res;
+        }
+        let Some(stamp) = inner.stamp else {
+            return Err(rusqlite::Error::UserFunctionError(
+                format!(
+                    "`{name}()` depends on the current time or randomness and can only be used \
+                    in writes stamped by the leader"
+                )
+                .into(),
+            ));
+        };
+        f(&mut inner, stamp)
+    }
+
+    fn eval(&self, name: &str, args: Vec<Value>) -> rusqlite::Result<Value> {
+        self.0
+            .lock()
+            .expect("WriteContext lock to never be poisoned")
+            .eval(name, args)
+    }
+}
+
+impl WriteContextInner {
+    /// SplitMix64, which is more than enough to create the same sequence on each node.
+    fn next_random(&mut self) -> u64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn eval(&mut self, name: &str, args: Vec<Value>) -> rusqlite::Result<Value> {
+        if self.conn_eval.is_none() {
+            self.conn_eval = Some(rusqlite::Connection::open_in_memory()?);
+        }
+        let conn = self.conn_eval.as_ref().unwrap();
+
+        let placeholders = (1..=args.len())
+            .map(|i| format!("${i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut stmt = conn.prepare_cached(&format!("SELECT {name}({placeholders})"))?;
+        stmt.query_row(rusqlite::params_from_iter(args), |row| row.get(0))
+    }
+}
+
+#[inline]
+fn is_now(value: &Value) -> bool {
+    matches!(value, Value::Text(s) if s.eq_ignore_ascii_case("now"))
+}
+
+fn check_modifiers(name: &str, args: &[Value]) -> rusqlite::Result<()> {
+    for arg in args {
+        if let Value::Text(s) = arg
+            && FORBIDDEN_MODIFIERS
+                .iter()
+                .any(|m| s.trim().eq_ignore_ascii_case(m))
+        {
+            return Err(rusqlite::Error::UserFunctionError(
+                format!(
+                    "the '{s}' modifier for `{name}()` depends on the time zone of each node \
+                    and must never be used for writing"
+                )
+                .into(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conn_with_stamp(stamp: Option<WriteStamp>) -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let ctx = WriteContext::default();
+        ctx.register(&conn).unwrap();
+        ctx.set(stamp);
+        conn
+    }
+
+    fn query(conn: &rusqlite::Connection, sql: &str) -> rusqlite::Result<Value> {
+        conn.query_row(sql, (), |row| row.get(0))
+    }
+
+    #[test]
+    fn stamped_functions_are_deterministic() {
+        // 2024-01-02 03:04:05.678 UTC
+        let stamp = WriteStamp {
+            unix_ms: 1_704_164_645_678,
+            seed: 42,
+        };
+        let conn_1 = conn_with_stamp(Some(stamp));
+        let conn_2 = conn_with_stamp(Some(stamp));
+
+        for sql in [
+            "SELECT random()",
+            "SELECT hex(randomblob(20))",
+            "SELECT random() + random()",
+            "SELECT unixepoch()",
+            "SELECT unixepoch('now', 'subsec')",
+            "SELECT datetime('now', '+1 day')",
+            "SELECT strftime('%s')",
+            "SELECT CURRENT_TIMESTAMP",
+        ] {
+            assert_eq!(query(&conn_1, sql).unwrap(), query(&conn_2, sql).unwrap());
+        }
+
+        assert_eq!(
+            query(&conn_1, "SELECT unixepoch()").unwrap(),
+            Value::Integer(1_704_164_645)
+        );
+        assert_eq!(
+            query(&conn_1, "SELECT datetime('NOW', '+1 day')").unwrap(),
+            Value::Text("2024-01-03 03:04:05".to_string())
+        );
+        assert_eq!(
+            query(&conn_1, "SELECT strftime('%Y-%m-%d %H:%M:%f', 'now')").unwrap(),
+            Value::Text("2024-01-02 03:04:05.678".to_string())
+        );
+        assert_eq!(
+            query(&conn_1, "SELECT CURRENT_DATE").unwrap(),
+            Value::Text("2024-01-02".to_string())
+        );
+        assert_eq!(
+            query(&conn_1, "SELECT timediff('now', '2024-01-01 03:04:05.678')").unwrap(),
+            Value::Text("+0000-00-01 00:00:00.000".to_string())
+        );
+        assert_eq!(
+            query(&conn_1, "SELECT length(randomblob(7))").unwrap(),
+            Value::Integer(7)
+        );
+
+        // defaults in the schema use the stamp as well
+        conn_1
+            .execute_batch(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, created TEXT DEFAULT CURRENT_TIMESTAMP);
+                INSERT INTO t (id) VALUES (1);",
+            )
+            .unwrap();
+        assert_eq!(
+            query(&conn_1, "SELECT created FROM t").unwrap(),
+            Value::Text("2024-01-02 03:04:05".to_string())
+        );
+    }
+
+    #[test]
+    fn random_values_differ_per_row_and_replay() {
+        let stamp = WriteStamp {
+            unix_ms: 1_704_164_645_678,
+            seed: 42,
+        };
+
+        let insert_rows = |conn: &rusqlite::Connection| {
+            conn.execute("CREATE TABLE test (id INTEGER, r INTEGER, b BLOB)", ())
+                .unwrap();
+            conn.execute(
+                r#"
+WITH RECURSIVE ids(id) AS (SELECT 1 UNION ALL SELECT id + 1 FROM ids WHERE id < 5)
+INSERT INTO test (id, r, b) SELECT id, random(), randomblob(8) FROM ids"#,
+                (),
+            )
+            .unwrap();
+
+            let mut stmt = conn.prepare("SELECT r, b FROM test ORDER BY id").unwrap();
+            stmt.query_map((), |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap()
+        };
+
+        let rows = insert_rows(&conn_with_stamp(Some(stamp)));
+        assert_eq!(rows.len(), 5);
+        for (i, row) in rows.iter().enumerate() {
+            for other in &rows[i + 1..] {
+                assert_ne!(row.0, other.0);
+                assert_ne!(row.1, other.1);
+            }
+        }
+
+        // applying the same write again on another node must produce the exact same values
+        assert_eq!(insert_rows(&conn_with_stamp(Some(stamp))), rows);
+    }
+
+    #[test]
+    fn stamped_functions_without_stamp() {
+        let conn = conn_with_stamp(None);
+
+        for sql in [
+            "SELECT random()",
+            "SELECT randomblob(4)",
+            "SELECT unixepoch()",
+            "SELECT date('now')",
+            "SELECT CURRENT_TIME",
+        ] {
+            assert!(query(&conn, sql).is_err(), "{sql}");
+        }
+
+        // functions without 'now' do not need a stamp
+        assert_eq!(
+            query(&conn, "SELECT date('2024-01-31', '+1 day')").unwrap(),
+            Value::Text("2024-02-01".to_string())
+        );
+        assert_eq!(
+            query(&conn, "SELECT unixepoch('1970-01-02')").unwrap(),
+            Value::Integer(86_400)
+        );
+
+        // time zone dependent modifiers are never allowed
+        let conn = conn_with_stamp(Some(WriteStamp::new()));
+        assert!(query(&conn, "SELECT datetime('now', 'localtime')").is_err());
+        assert!(query(&conn, "SELECT datetime('2024-01-01', 'utc')").is_err());
+    }
+
+    #[test]
+    fn dry_runs_keep_the_state_of_the_applied_write() {
+        let stamp = WriteStamp {
+            unix_ms: 1_704_164_645_678,
+            seed: 42,
+        };
+        let conn_1 = conn_with_stamp(Some(stamp));
+        let conn_2 = rusqlite::Connection::open_in_memory().unwrap();
+        let ctx = WriteContext::default();
+        ctx.register(&conn_2).unwrap();
+        ctx.set(Some(stamp));
+
+        assert_eq!(
+            query(&conn_1, "SELECT random()").unwrap(),
+            query(&conn_2, "SELECT random()").unwrap()
+        );
+
+        // a dry run in between must neither use nor advance the state of the write
+        ctx.set_dry_run(Some(WriteStamp {
+            unix_ms: 0,
+            seed: 7,
+        }));
+        assert_eq!(
+            query(&conn_2, "SELECT unixepoch()").unwrap(),
+            Value::Integer(0)
+        );
+        let dry_1 = query(&conn_2, "SELECT random()").unwrap();
+        let dry_2 = query(&conn_2, "SELECT random()").unwrap();
+        assert_ne!(dry_1, dry_2);
+        ctx.set_dry_run(None);
+
+        assert_eq!(
+            query(&conn_1, "SELECT random()").unwrap(),
+            query(&conn_2, "SELECT random()").unwrap()
+        );
+    }
+}
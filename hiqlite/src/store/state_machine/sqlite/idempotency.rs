@@ -0,0 +1,164 @@
+use crate::Error;
+use crate::helpers::deserialize;
+use crate::store::state_machine::sqlite::deterministic::WriteStamp;
+use crate::store::state_machine::sqlite::state_machine::Response;
+use chrono::Utc;
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// How long an idempotency key is remembered after the write it belongs to, in seconds.
+/// Retries after a leader change happen within seconds, so this leaves plenty of headroom.
+pub(crate) const IDEMPOTENCY_KEY_TTL_SECS: i64 = 60 * 60;
+
+/// A client-generated key to make a write idempotent.
+///
+/// If a write with the same key has already been applied, the state machine will return the
+/// original `Response` instead of applying it a second time. Keys expire 1 hour after the write
+/// they belong to has been applied. Expiry is based on the time the leader has stamped the write
+/// with, which makes it deterministic across all nodes, no matter how far the client clock is off.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdempotencyKey {
+    key: String,
+    ts: i64,
+}
+
+impl Default for IdempotencyKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdempotencyKey {
+    /// Generates a new, random key.
+    pub fn new() -> Self {
+        Self::from_key(Uuid::now_v7().to_string())
+    }
+
+    /// Creates a key from your own, application-specific value, e.g. a request id sent by
+    /// your own clients. The key must be unique per write for at least 1 hour.
+    pub fn from_key<K: Into<String>>(key: K) -> Self {
+        Self {
+            key: key.into(),
+            ts: Utc::now().timestamp(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.key
+    }
+
+    /// The time in seconds the write with this key is applied at. Only entries from before
+    /// writes have been stamped by the leader fall back to the creation time of the key.
+    pub(crate) fn applied_at(&self, stamp: Option<WriteStamp>) -> i64 {
+        stamp.map(|s| s.unix_ms / 1000).unwrap_or(self.ts)
+    }
+}
+
+pub(crate) fn create_table(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        r#"
+CREATE TABLE IF NOT EXISTS _idempotency
+(
+    key      TEXT    NOT NULL
+        CONSTRAINT _idempotency_pk
+            PRIMARY KEY,
+    ts       INTEGER NOT NULL,
+    response BLOB    NOT NULL
+)"#,
+        (),
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS _idempotency_ts_index ON _idempotency (ts)",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Removes all expired keys and then looks up the response for the given one.
+///
+/// `now` must be the replicated timestamp of the write and never the local clock, so all nodes
+/// will always agree on which keys still exist.
+pub(crate) fn lookup(
+    conn: &rusqlite::Connection,
+    key: &IdempotencyKey,
+    now: i64,
+) -> Result<Option<Response>, Error> {
+    conn.prepare_cached("DELETE FROM _idempotency WHERE ts < $1")?
+        .execute([now.saturating_sub(IDEMPOTENCY_KEY_TTL_SECS)])?;
+
+    let bytes: Option<Vec<u8>> = conn
+        .prepare_cached("SELECT response FROM _idempotency WHERE key = $1")?
+        .query_row([&key.key], |row| row.get(0))
+        .optional()?;
+
+    match bytes {
+        None => Ok(None),
+        Some(bytes) => deserialize(&bytes)
+            .map(Some)
+            .map_err(|err| Error::Error(err.to_string().into())),
+    }
+}
+
+/// Stores the already serialized `Response` for the given key, applied at `now`.
+pub(crate) fn store(
+    conn: &rusqlite::Connection,
+    key: &IdempotencyKey,
+    now: i64,
+    response: &[u8],
+) -> Result<(), Error> {
+    conn.prepare_cached("REPLACE INTO _idempotency (key, ts, response) VALUES ($1, $2, $3)")?
+        .execute((&key.key, now, response))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::serialize;
+    use crate::store::state_machine::sqlite::state_machine::ResponseExecute;
+
+    #[test]
+    fn keys_are_deduplicated_until_expiry() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+
+        let now = 1_700_000_000;
+        let key = IdempotencyKey::from_key("key1");
+        assert!(lookup(&conn, &key, now).unwrap().is_none());
+
+        store(
+            &conn,
+            &key,
+            now,
+            &serialize(&Response::Execute(ResponseExecute { result: Ok(3) })).unwrap(),
+        )
+        .unwrap();
+        match lookup(&conn, &key, now).unwrap() {
+            Some(Response::Execute(ResponseExecute { result: Ok(3) })) => {}
+            res => panic!("unexpected idempotency lookup result: {res:?}"),
+        }
+
+        // a client clock far in the future must not expire any keys
+        let mut skewed = IdempotencyKey::from_key("key2");
+        skewed.ts = now + 10 * IDEMPOTENCY_KEY_TTL_SECS;
+        assert!(lookup(&conn, &skewed, now).unwrap().is_none());
+        assert!(lookup(&conn, &key, now).unwrap().is_some());
+
+        // a later write only expires the key, once it is older than the TTL
+        let later = IdempotencyKey::from_key("key3");
+        assert!(
+            lookup(&conn, &later, now + IDEMPOTENCY_KEY_TTL_SECS)
+                .unwrap()
+                .is_none()
+        );
+        assert!(lookup(&conn, &key, now).unwrap().is_some());
+
+        assert!(
+            lookup(&conn, &later, now + IDEMPOTENCY_KEY_TTL_SECS + 1)
+                .unwrap()
+                .is_none()
+        );
+        assert!(lookup(&conn, &key, now).unwrap().is_none());
+    }
+}
@@ -2,10 +2,15 @@ use crate::store::state_machine::sqlite::state_machine::QueryWrite;
 use crate::Node;
 use crate::Response;
 
+pub mod changes;
+pub mod deterministic;
+pub mod idempotency;
 pub mod param;
+pub mod restore;
 pub mod snapshot_builder;
 pub mod state_machine;
 pub mod transaction_variable;
+pub mod write_budget;
 pub mod writer;
 
 mod transaction_env;
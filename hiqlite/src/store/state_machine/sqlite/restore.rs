@@ -0,0 +1,235 @@
+//! Online restores replicate a backup through the Raft log.
+//!
+//! The leader splits the backup into chunks, which every node stages inside the `_restore`
+//! table on apply. Staging inside the database itself makes sure that a snapshot taken in the
+//! middle of a restore contains all chunks applied so far. The final `Apply` entry then
+//! replaces the database with the staged backup on all nodes at the same log index.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "backup")]
+use crate::Error;
+#[cfg(feature = "backup")]
+use crate::store::state_machine::sqlite::idempotency;
+#[cfg(feature = "backup")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "backup")]
+use std::io::Write;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RestoreWrite {
+    /// A part of the backup, which is staged until the restore is applied.
+    /// A chunk with `offset == 0` starts a new restore and drops anything staged before.
+    Chunk {
+        id: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Replaces the database with the staged backup, if it is complete.
+    Apply {
+        id: String,
+        size: u64,
+        sha256: Vec<u8>,
+    },
+    /// Drops the staged backup after a failed restore.
+    Abort { id: String },
+}
+
+#[cfg(feature = "backup")]
+pub(crate) fn stage_chunk(
+    conn: &rusqlite::Connection,
+    id: &str,
+    offset: u64,
+    data: &[u8],
+) -> Result<(), Error> {
+    if offset == 0 {
+        conn.execute_batch(
+            r#"
+DROP TABLE IF EXISTS _restore;
+CREATE TABLE _restore
+(
+    offset INTEGER NOT NULL
+        CONSTRAINT _restore_pk
+            PRIMARY KEY,
+    id     TEXT    NOT NULL,
+    data   BLOB    NOT NULL
+)"#,
+        )?;
+    } else if staged_id(conn)?.as_deref() != Some(id) {
+        return Err(Error::Error(
+            format!("Restore {id} has been replaced by another one").into(),
+        ));
+    }
+
+    conn.execute(
+        "REPLACE INTO _restore (offset, id, data) VALUES ($1, $2, $3)",
+        (offset as i64, id, data),
+    )?;
+    Ok(())
+}
+
+/// Writes the staged backup into `path_tmp` and replaces the database with it. The `_metadata`
+/// of the backup is left untouched and must be overwritten by the caller.
+#[cfg(feature = "backup")]
+pub(crate) fn apply(
+    conn: &mut rusqlite::Connection,
+    path_tmp: &str,
+    id: &str,
+    size: u64,
+    sha256: &[u8],
+) -> Result<(), Error> {
+    let res = write_staged(conn, path_tmp, id, size, sha256).and_then(|_| {
+        conn.restore("main", path_tmp, None::<fn(rusqlite::backup::Progress)>)?;
+        // backups from older versions do not contain the idempotency table yet
+        idempotency::create_table(conn)?;
+        Ok(())
+    });
+
+    let _ = std::fs::remove_file(path_tmp);
+    // a backup taken during another restore may contain a staging table as well
+    drop_staged(conn)?;
+
+    res
+}
+
+#[cfg(feature = "backup")]
+pub(crate) fn drop_staged(conn: &rusqlite::Connection) -> Result<(), Error> {
+    conn.execute("DROP TABLE IF EXISTS _restore", ())?;
+    Ok(())
+}
+
+#[cfg(feature = "backup")]
+fn staged_id(conn: &rusqlite::Connection) -> Result<Option<String>, Error> {
+    use rusqlite::OptionalExtension;
+
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_restore'",
+            (),
+            |_| Ok(()),
+        )
+        .optional()?;
+    if exists.is_none() {
+        return Ok(None);
+    }
+
+    Ok(conn
+        .query_row("SELECT id FROM _restore WHERE offset = 0", (), |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
+#[cfg(feature = "backup")]
+fn write_staged(
+    conn: &rusqlite::Connection,
+    path_tmp: &str,
+    id: &str,
+    size: u64,
+    sha256: &[u8],
+) -> Result<(), Error> {
+    if staged_id(conn)?.as_deref() != Some(id) {
+        return Err(Error::Error(
+            format!("No staged backup found for restore {id}").into(),
+        ));
+    }
+
+    let mut file = std::fs::File::create(path_tmp)?;
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+
+    let mut stmt = conn.prepare("SELECT offset, data FROM _restore ORDER BY offset")?;
+    let mut rows = stmt.query(())?;
+    while let Some(row) = rows.next()? {
+        let offset: i64 = row.get(0)?;
+        if offset as u64 != written {
+            return Err(Error::Error(
+                format!("Staged backup for restore {id} is missing data at offset {written}")
+                    .into(),
+            ));
+        }
+
+        let data: Vec<u8> = row.get(1)?;
+        hasher.update(&data);
+        file.write_all(&data)?;
+        written += data.len() as u64;
+    }
+    file.sync_all()?;
+
+    if written != size {
+        return Err(Error::Error(
+            format!("Staged backup for restore {id} has {written} bytes, expected {size}").into(),
+        ));
+    }
+    if hasher.finalize().as_slice() != sha256 {
+        return Err(Error::Error(
+            format!("Checksum mismatch for the staged backup of restore {id}").into(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[cfg(feature = "backup")]
+mod tests {
+    use super::*;
+
+    fn user_count(conn: &rusqlite::Connection) -> i64 {
+        conn.query_row("SELECT count(*) FROM users", (), |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn staged_restore_replaces_database() {
+        let dir = std::env::temp_dir().join(format!("hiqlite_restore_{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path_backup = dir.join("backup.sqlite");
+        let path_tmp = dir.join("restore").to_str().unwrap().to_string();
+
+        let backup = rusqlite::Connection::open(&path_backup).unwrap();
+        backup
+            .execute_batch(
+                "CREATE TABLE users (id INTEGER PRIMARY KEY);
+                INSERT INTO users (id) VALUES (1), (2), (3);",
+            )
+            .unwrap();
+        drop(backup);
+        let bytes = std::fs::read(&path_backup).unwrap();
+        let sha256 = Sha256::digest(&bytes).to_vec();
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE users (id INTEGER PRIMARY KEY);")
+            .unwrap();
+
+        // chunks must belong to the restore that has been started last
+        assert!(stage_chunk(&conn, "a", 1, &bytes[..1]).is_err());
+        let chunk = bytes.len() / 3;
+        for (i, data) in bytes.chunks(chunk).enumerate() {
+            stage_chunk(&conn, "a", (i * chunk) as u64, data).unwrap();
+        }
+        assert!(stage_chunk(&conn, "b", chunk as u64, &bytes[..1]).is_err());
+
+        // an incomplete or modified backup must never be applied
+        let size = bytes.len() as u64;
+        assert!(apply(&mut conn, &path_tmp, "a", size + 1, &sha256).is_err());
+        assert_eq!(user_count(&conn), 0);
+        assert!(staged_id(&conn).unwrap().is_none());
+
+        for (i, data) in bytes.chunks(chunk).enumerate() {
+            stage_chunk(&conn, "a", (i * chunk) as u64, data).unwrap();
+        }
+        assert!(apply(&mut conn, &path_tmp, "a", size, &[0; 32]).is_err());
+        assert_eq!(user_count(&conn), 0);
+
+        for (i, data) in bytes.chunks(chunk).enumerate() {
+            stage_chunk(&conn, "a", (i * chunk) as u64, data).unwrap();
+        }
+        apply(&mut conn, &path_tmp, "a", size, &sha256).unwrap();
+        assert_eq!(user_count(&conn), 3);
+        assert!(staged_id(&conn).unwrap().is_none());
+        assert!(!std::fs::exists(&path_tmp).unwrap());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
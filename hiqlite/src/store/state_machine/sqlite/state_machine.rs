@@ -1,10 +1,15 @@
 #![allow(clippy::upper_case_acronyms)]
 
-use crate::helpers::{deserialize, set_path_access};
+use crate::functions::SqlFunctions;
+use crate::helpers::{deserialize, serialize, set_path_access};
 use crate::migration::Migration;
 use crate::query::rows::RowOwned;
 use crate::store::state_machine::sqlite::TypeConfigSqlite;
+use crate::store::state_machine::sqlite::changes::{self, ChangesRequest};
+use crate::store::state_machine::sqlite::deterministic::{WriteContext, WriteStamp};
+use crate::store::state_machine::sqlite::idempotency::IdempotencyKey;
 use crate::store::state_machine::sqlite::param::Param;
+use crate::store::state_machine::sqlite::restore::RestoreWrite;
 use crate::store::state_machine::sqlite::snapshot_builder::SQLiteSnapshotBuilder;
 use crate::store::state_machine::sqlite::writer::WriterRequest::MetadataRead;
 use crate::store::state_machine::sqlite::writer::{
@@ -17,7 +22,6 @@ use openraft::{
     EntryPayload, LogId, OptionalSend, Snapshot, SnapshotId, SnapshotMeta, StorageError,
     StorageIOError, StoredMembership,
 };
-use rusqlite::functions::FunctionFlags;
 use rusqlite::{OpenFlags, OptionalExtension, ToSql};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
@@ -39,22 +43,6 @@ pub type SqlitePool = deadpool::unmanaged::Pool<rusqlite::Connection>;
 
 pub type Params = Vec<Param>;
 
-/// Non-deterministic SQLite functions that are forbidden on raft write connections,
-/// where every node must apply the identical statement. The dashboard pre-scans
-/// manual queries against this list to return a proper error instead of the panic.
-pub(crate) const FORBIDDEN_NON_DET_FNS: &[&str] = &[
-    "date",
-    "datetime",
-    "julianday",
-    "now",
-    "random",
-    "randomblob",
-    "strftime",
-    "time",
-    "timediff",
-    "unixepoch",
-];
-
 pub struct PathDb(pub String);
 pub struct PathBackups(pub String);
 pub struct PathSnapshots(pub String);
@@ -72,6 +60,44 @@ pub enum QueryWrite {
     #[allow(dead_code)] // only constructed with the `backup` feature
     Backup((NodeId, i64)),
     RTT,
+    Idempotent((IdempotencyKey, Box<QueryWrite>)),
+    ExecuteMany(QueryMany),
+    Stamped((WriteStamp, Box<QueryWrite>)),
+    #[allow(dead_code)] // only constructed with the `backup` feature
+    Restore(RestoreWrite),
+}
+
+impl QueryWrite {
+    /// Wraps the write with the given idempotency key, if any.
+    pub(crate) fn with_idempotency_key(self, key: Option<IdempotencyKey>) -> Self {
+        match key {
+            None => self,
+            Some(key) => Self::Idempotent((key, Box::new(self))),
+        }
+    }
+
+    /// Stamps the write with the current time and a random seed. This must be done by the leader
+    /// right before proposing it, so all nodes apply it with the same values for `unixepoch()`,
+    /// `random()` and friends.
+    pub(crate) fn stamped(self) -> Self {
+        Self::Stamped((WriteStamp::new(), Box::new(self)))
+    }
+
+    /// Checks if a cached idempotent `Response` has been created by the same kind of write.
+    fn is_same_kind(&self, resp: &Response) -> bool {
+        matches!(
+            (self, resp),
+            (Self::Execute(_), Response::Execute(_))
+                | (Self::ExecuteReturning(_), Response::ExecuteReturning(_))
+                | (Self::Transaction(_), Response::Transaction(_))
+                | (Self::Batch(_), Response::Batch(_))
+                | (Self::Migration(_), Response::Migrate(_))
+                | (Self::Backup(_), Response::Backup(_))
+                | (Self::RTT, Response::RTT)
+                | (Self::ExecuteMany(_), Response::ExecuteMany(_))
+                | (Self::Restore(_), Response::Restore(_))
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,6 +106,13 @@ pub struct Query {
     pub params: Params,
 }
 
+/// A single statement, which will be executed once for each of the given `Params`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryMany {
+    pub sql: Cow<'static, str>,
+    pub params: Vec<Params>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
     Empty,
@@ -90,6 +123,28 @@ pub enum Response {
     Migrate(Result<(), Error>),
     Backup(Result<(), Error>),
     RTT,
+    ExecuteMany(Result<Vec<usize>, Error>),
+    Restore(Result<(), Error>),
+}
+
+impl Response {
+    /// Replaces the result with `err` while keeping the kind of the response.
+    fn with_err(self, err: Error) -> Self {
+        match self {
+            Self::Empty => Self::Empty,
+            Self::Execute(_) => Self::Execute(ResponseExecute { result: Err(err) }),
+            Self::ExecuteReturning(_) => {
+                Self::ExecuteReturning(ResponseExecuteReturning { result: Err(err) })
+            }
+            Self::Transaction(_) => Self::Transaction(Err(err)),
+            Self::Batch(_) => Self::Batch(ResponseBatch { result: Err(err) }),
+            Self::Migrate(_) => Self::Migrate(Err(err)),
+            Self::Backup(_) => Self::Backup(Err(err)),
+            Self::RTT => Self::RTT,
+            Self::ExecuteMany(_) => Self::ExecuteMany(Err(err)),
+            Self::Restore(_) => Self::Restore(Err(err)),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -129,11 +184,15 @@ pub struct StateMachineSqlite {
     path_backups: String,
     path_lock_file: String,
 
-    #[cfg(feature = "s3")]
-    s3_config: Option<Arc<crate::s3::S3Config>>,
+    #[cfg(feature = "backup")]
+    backup_targets: Vec<Arc<dyn crate::backup_target::BackupTarget>>,
 
     pub(crate) read_pool: SqlitePool,
     pub(crate) write_tx: flume::Sender<WriterRequest>,
+    pub(crate) tx_changes: flume::Sender<ChangesRequest>,
+    #[cfg(feature = "backup")]
+    pub(crate) backup_progress: Arc<crate::backup_progress::BackupTracker>,
+    write_ctx: WriteContext,
 }
 
 impl StateMachineSqlite {
@@ -145,9 +204,13 @@ impl StateMachineSqlite {
         log_statements: bool,
         prepared_statement_cache_capacity: usize,
         read_pool_size: usize,
-        #[cfg(feature = "s3")] s3_config: Option<Arc<crate::s3::S3Config>>,
+        sql_functions: SqlFunctions,
+        write_instruction_budget: Option<u32>,
+        #[cfg(feature = "backup")] backup_targets: Vec<Arc<dyn crate::backup_target::BackupTarget>>,
         do_reset_metadata: bool,
-        #[cfg(feature = "backup")] local_backup_keep_days: u16,
+        #[cfg(feature = "backup")] local_backup_retention: crate::backup::Retention,
+        #[cfg(feature = "backup")] backup_verify: bool,
+        #[cfg(feature = "backup")] backup_differential: u16,
     ) -> Result<StateMachineSqlite, StorageError<NodeId>> {
         // IMPORTANT: Do NOT change the order of the db exists check!
         // DB recovery will fail otherwise!
@@ -164,16 +227,23 @@ impl StateMachineSqlite {
         Self::check_set_lock_file(&path_lock_file, &path_db, &mut db_exists).await;
 
         // Always start the writer first! -> creates mandatory tables
+        let write_ctx = WriteContext::default();
         let conn = Self::connect(
             path_db.to_string(),
             filename_db.to_string(),
             false,
             prepared_statement_cache_capacity,
+            Some(write_ctx.clone()),
+            sql_functions.clone(),
         )
         .await
         .map_err(|err| StorageError::IO {
             source: StorageIOError::write(&err),
         })?;
+        let (tx_changes, rx_changes, changes_filter) = changes::channel();
+        changes::spawn(rx_changes, changes_filter.clone());
+        #[cfg(feature = "backup")]
+        let backup_progress = Arc::new(crate::backup_progress::BackupTracker::default());
         let write_tx = writer::spawn_writer(
             conn,
             this_node,
@@ -181,7 +251,17 @@ impl StateMachineSqlite {
             log_statements,
             do_reset_metadata,
             #[cfg(feature = "backup")]
-            local_backup_keep_days,
+            local_backup_retention,
+            #[cfg(feature = "backup")]
+            backup_verify,
+            #[cfg(feature = "backup")]
+            backup_differential,
+            #[cfg(feature = "backup")]
+            backup_progress.clone(),
+            tx_changes.clone(),
+            changes_filter,
+            write_ctx.clone(),
+            write_instruction_budget,
         );
 
         let read_pool = Self::connect_read_pool(
@@ -189,6 +269,7 @@ impl StateMachineSqlite {
             filename_db,
             prepared_statement_cache_capacity,
             read_pool_size,
+            sql_functions,
         )
         .await
         .map_err(|err| StorageError::IO {
@@ -202,10 +283,14 @@ impl StateMachineSqlite {
             #[cfg(feature = "backup")]
             path_backups,
             path_lock_file,
-            #[cfg(feature = "s3")]
-            s3_config,
+            #[cfg(feature = "backup")]
+            backup_targets,
             read_pool,
             write_tx,
+            tx_changes,
+            #[cfg(feature = "backup")]
+            backup_progress,
+            write_ctx,
         };
 
         if !db_exists && let Some(snapshot) = slf.read_current_snapshot().await? {
@@ -317,15 +402,18 @@ impl StateMachineSqlite {
         filename_db: String,
         read_only: bool,
         prepared_statement_cache_capacity: usize,
+        write_ctx: Option<WriteContext>,
+        sql_functions: SqlFunctions,
     ) -> Result<rusqlite::Connection, Error> {
         task::spawn_blocking(move || {
             let path_full = format!("{path}/{filename_db}");
             let conn = rusqlite::Connection::open(path_full)?;
 
             Self::apply_pragmas(&conn, read_only, prepared_statement_cache_capacity)?;
-            if !read_only {
-                Self::overwrite_non_det_fns(&conn);
+            if let Some(write_ctx) = write_ctx {
+                write_ctx.register(&conn)?;
             }
+            sql_functions.install(&conn, read_only)?;
 
             Ok(conn)
         })
@@ -337,6 +425,7 @@ impl StateMachineSqlite {
         filename_db: &str,
         prepared_statement_cache_capacity: usize,
         pool_size: usize,
+        sql_functions: SqlFunctions,
     ) -> Result<SqlitePool, Error> {
         let path_full = format!("{path}/{filename_db}");
 
@@ -347,6 +436,8 @@ impl StateMachineSqlite {
                 filename_db.to_string(),
                 true,
                 prepared_statement_cache_capacity,
+                None,
+                sql_functions.clone(),
             )
             .await;
             while conn.is_err() {
@@ -356,6 +447,8 @@ impl StateMachineSqlite {
                     filename_db.to_string(),
                     true,
                     prepared_statement_cache_capacity,
+                    None,
+                    sql_functions.clone(),
                 )
                 .await;
             }
@@ -420,27 +513,6 @@ impl StateMachineSqlite {
         Ok(())
     }
 
-    fn overwrite_non_det_fns(conn: &rusqlite::Connection) {
-        // Overwrite the non-deterministic functions with a panicking guard: using one on
-        // the write path would diverge the cluster, so it fails loudly.
-        // No query-string scan: the guard only runs when a statement actually calls the
-        // function, so queries that never use them pay nothing.
-        for &name in FORBIDDEN_NON_DET_FNS {
-            conn.create_scalar_function(
-                name,
-                -1,
-                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
-                move |_| -> rusqlite::Result<String> {
-                    panic!(
-                        "forbidden usage of `{name}()` - non-deterministic functions must never be \
-                        used for writing connections in a Raft cluster"
-                    )
-                },
-            )
-            .expect("Cannot register forbidden function");
-        }
-    }
-
     async fn update_state_machine_(
         &mut self,
         snapshot_path: String,
@@ -514,11 +586,18 @@ impl StateMachineSqlite {
         let filename_db = id.to_string();
 
         // open a DB connection to read out the metadata
-        let conn = Self::connect(db_path, filename_db, false, 2)
-            .await
-            .map_err(|err| StorageError::IO {
-                source: StorageIOError::write(&err),
-            })?;
+        let conn = Self::connect(
+            db_path,
+            filename_db,
+            false,
+            2,
+            None,
+            SqlFunctions::default(),
+        )
+        .await
+        .map_err(|err| StorageError::IO {
+            source: StorageIOError::write(&err),
+        })?;
 
         // let path_snapshot_clone = path_snapshot.clone();
         let path_dbg = path_snapshot.clone();
@@ -579,6 +658,267 @@ impl StateMachineSqlite {
 
         Ok(Some(snapshot))
     }
+
+    async fn apply_write(
+        &self,
+        write_tx: &flume::Sender<WriterRequest>,
+        write: QueryWrite,
+        last_applied_log_id: Option<LogId<NodeId>>,
+    ) -> Response {
+        match write {
+            QueryWrite::Execute(Query { sql, params }) => {
+                let (tx, rx) = oneshot::channel();
+                let query = writer::Query::Execute(writer::SqlExecute {
+                    sql,
+                    params,
+                    last_applied_log_id,
+                    tx,
+                });
+
+                write_tx
+                    .send_async(WriterRequest::Query(query))
+                    .await
+                    .expect("sql writer to always be listening");
+
+                let result = rx.await.expect("to always get a response from sql writer");
+                Response::Execute(ResponseExecute { result })
+            }
+
+            QueryWrite::ExecuteReturning(Query { sql, params }) => {
+                let (tx, rx) = oneshot::channel();
+                let query = writer::Query::ExecuteReturning(writer::SqlExecuteReturning {
+                    sql,
+                    params,
+                    last_applied_log_id,
+                    tx,
+                });
+
+                write_tx
+                    .send_async(WriterRequest::Query(query))
+                    .await
+                    .expect("sql writer to always be listening");
+
+                let result = rx.await.expect("to always get a response from sql writer");
+                Response::ExecuteReturning(ResponseExecuteReturning { result })
+            }
+
+            QueryWrite::Transaction(queries) => {
+                let (tx, rx) = oneshot::channel();
+                let req = WriterRequest::Query(writer::Query::Transaction(SqlTransaction {
+                    queries,
+                    last_applied_log_id,
+                    tx,
+                }));
+
+                write_tx
+                    .send_async(req)
+                    .await
+                    .expect("sql writer to always be listening");
+
+                let result = rx.await.expect("to always get a response from sql writer");
+                Response::Transaction(result)
+            }
+
+            QueryWrite::Batch(sql) => {
+                let (tx, rx) = oneshot::channel();
+                let req = WriterRequest::Query(writer::Query::Batch(SqlBatch {
+                    sql,
+                    last_applied_log_id,
+                    tx,
+                }));
+
+                write_tx
+                    .send_async(req)
+                    .await
+                    .expect("sql writer to always be listening");
+
+                let result = rx.await.expect("to always get a response from sql writer");
+                Response::Batch(ResponseBatch { result })
+            }
+
+            QueryWrite::Backup((node_id, ts)) => {
+                #[cfg(feature = "backup")]
+                {
+                    let (ack, rx) = oneshot::channel();
+                    let req = WriterRequest::Backup(writer::BackupRequest {
+                        node_id,
+                        target_folder: self.path_backups.clone(),
+                        ts,
+                        #[cfg(feature = "backup")]
+                        targets: self.backup_targets.clone(),
+                        last_applied_log_id,
+                        ack,
+                    });
+
+                    write_tx
+                        .send_async(req)
+                        .await
+                        .expect("sql writer to always be listening");
+
+                    let result = rx.await.expect("to always get a response from sql writer");
+                    Response::Backup(result)
+                }
+                #[cfg(not(feature = "backup"))]
+                unreachable!("Backup requires the `backup` feature")
+            }
+
+            QueryWrite::Migration(migrations) => {
+                let (tx, rx) = oneshot::channel();
+                let req = WriterRequest::Migrate(writer::Migrate {
+                    migrations,
+                    last_applied_log_id,
+                    tx,
+                });
+
+                write_tx
+                    .send_async(req)
+                    .await
+                    .expect("sql writer to always be listening");
+
+                let result = rx.await.expect("to always get a response from sql writer");
+                Response::Migrate(result)
+            }
+
+            QueryWrite::RTT => {
+                let (ack, rx) = oneshot::channel();
+                let req = WriterRequest::RTT(writer::RTTRequest {
+                    last_applied_log_id,
+                    ack,
+                });
+
+                write_tx
+                    .send_async(req)
+                    .await
+                    .expect("sql writer to always be listening");
+
+                rx.await.expect("to always get a response from sql writer");
+                Response::RTT
+            }
+
+            QueryWrite::ExecuteMany(QueryMany { sql, params }) => {
+                let (tx, rx) = oneshot::channel();
+                let req =
+                    WriterRequest::Query(writer::Query::ExecuteMany(writer::SqlExecuteMany {
+                        sql,
+                        params,
+                        last_applied_log_id,
+                        tx,
+                    }));
+
+                write_tx
+                    .send_async(req)
+                    .await
+                    .expect("sql writer to always be listening");
+
+                let result = rx.await.expect("to always get a response from sql writer");
+                Response::ExecuteMany(result)
+            }
+
+            QueryWrite::Idempotent((key, write)) => {
+                let now = key.applied_at(self.write_ctx.stamp());
+                let (tx_exclusive, rx_exclusive) = flume::bounded(1);
+                let (ack, rx) = oneshot::channel();
+                let req = WriterRequest::IdempotencyLookup(writer::IdempotencyLookupRequest {
+                    key: key.clone(),
+                    now,
+                    last_applied_log_id,
+                    rx_exclusive,
+                    ack,
+                });
+
+                write_tx
+                    .send_async(req)
+                    .await
+                    .expect("sql writer to always be listening");
+
+                if let Some(resp) = rx.await.expect("to always get a response from sql writer") {
+                    if write.is_same_kind(&resp) {
+                        return resp;
+                    }
+                    // The writer has committed the lookup already and does not wait for
+                    // `tx_exclusive` in this case.
+                    warn!(
+                        "Idempotency key '{}' has been re-used for a different kind of write - \
+                        applying it as a new write",
+                        key.as_str()
+                    );
+                    let resp =
+                        Box::pin(self.apply_write(write_tx, *write, last_applied_log_id)).await;
+                    return match self.store_idempotency_key(write_tx, key, now, &resp).await {
+                        Ok(()) => resp,
+                        Err(err) => resp.with_err(err),
+                    };
+                }
+
+                // The writer now only listens to `tx_exclusive` until the response is stored.
+                let resp =
+                    Box::pin(self.apply_write(&tx_exclusive, *write, last_applied_log_id)).await;
+                // If the commit fails, the write has been rolled back and must not report success.
+                match self
+                    .store_idempotency_key(&tx_exclusive, key, now, &resp)
+                    .await
+                {
+                    Ok(()) => resp,
+                    Err(err) => resp.with_err(err),
+                }
+            }
+
+            QueryWrite::Restore(write) => {
+                #[cfg(feature = "backup")]
+                {
+                    let (ack, rx) = oneshot::channel();
+                    let req = WriterRequest::Restore(writer::RestoreRequest {
+                        write,
+                        path_tmp: format!("{}/restore", self.path_snapshots),
+                        last_applied_log_id,
+                        ack,
+                    });
+
+                    write_tx
+                        .send_async(req)
+                        .await
+                        .expect("sql writer to always be listening");
+
+                    let result = rx.await.expect("to always get a response from sql writer");
+                    Response::Restore(result)
+                }
+                #[cfg(not(feature = "backup"))]
+                unreachable!("Restore requires the `backup` feature")
+            }
+
+            QueryWrite::Stamped((stamp, write)) => {
+                // The writer only ever works on the write we are currently awaiting, which makes
+                // it safe to simply set the context before and reset it afterward.
+                self.write_ctx.set(Some(stamp));
+                let resp = Box::pin(self.apply_write(write_tx, *write, last_applied_log_id)).await;
+                self.write_ctx.set(None);
+                resp
+            }
+        }
+    }
+
+    async fn store_idempotency_key(
+        &self,
+        write_tx: &flume::Sender<WriterRequest>,
+        key: IdempotencyKey,
+        now: i64,
+        resp: &Response,
+    ) -> Result<(), Error> {
+        let (ack, rx) = oneshot::channel();
+        let req = WriterRequest::IdempotencyStore(writer::IdempotencyStoreRequest {
+            key,
+            now,
+            response: serialize(resp).expect("Response to always serialize"),
+            ack,
+        });
+
+        write_tx
+            .send_async(req)
+            .await
+            .expect("sql writer to always be listening");
+
+        rx.await.expect("to always get a response from sql writer")
+    }
 }
 
 impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
@@ -626,133 +966,9 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
                 // TODO we probably need to update the log id in writer in case of ::Empty?
                 EntryPayload::Blank => Response::Empty,
 
-                EntryPayload::Normal(QueryWrite::Execute(Query { sql, params })) => {
-                    let (tx, rx) = oneshot::channel();
-                    let query = writer::Query::Execute(writer::SqlExecute {
-                        sql,
-                        params,
-                        last_applied_log_id,
-                        tx,
-                    });
-
-                    self.write_tx
-                        .send_async(WriterRequest::Query(query))
-                        .await
-                        .expect("sql writer to always be listening");
-
-                    let result = rx.await.expect("to always get a response from sql writer");
-                    Response::Execute(ResponseExecute { result })
-                }
-
-                EntryPayload::Normal(QueryWrite::ExecuteReturning(Query { sql, params })) => {
-                    let (tx, rx) = oneshot::channel();
-                    let query = writer::Query::ExecuteReturning(writer::SqlExecuteReturning {
-                        sql,
-                        params,
-                        last_applied_log_id,
-                        tx,
-                    });
-
-                    self.write_tx
-                        .send_async(WriterRequest::Query(query))
-                        .await
-                        .expect("sql writer to always be listening");
-
-                    let result = rx.await.expect("to always get a response from sql writer");
-                    Response::ExecuteReturning(ResponseExecuteReturning { result })
-                }
-
-                EntryPayload::Normal(QueryWrite::Transaction(queries)) => {
-                    let (tx, rx) = oneshot::channel();
-                    let req = WriterRequest::Query(writer::Query::Transaction(SqlTransaction {
-                        queries,
-                        last_applied_log_id,
-                        tx,
-                    }));
-
-                    self.write_tx
-                        .send_async(req)
-                        .await
-                        .expect("sql writer to always be listening");
-
-                    let result = rx.await.expect("to always get a response from sql writer");
-                    Response::Transaction(result)
-                }
-
-                EntryPayload::Normal(QueryWrite::Batch(sql)) => {
-                    let (tx, rx) = oneshot::channel();
-                    let req = WriterRequest::Query(writer::Query::Batch(SqlBatch {
-                        sql,
-                        last_applied_log_id,
-                        tx,
-                    }));
-
-                    self.write_tx
-                        .send_async(req)
-                        .await
-                        .expect("sql writer to always be listening");
-
-                    let result = rx.await.expect("to always get a response from sql writer");
-                    Response::Batch(ResponseBatch { result })
-                }
-
-                EntryPayload::Normal(QueryWrite::Backup((node_id, ts))) => {
-                    #[cfg(feature = "backup")]
-                    {
-                        let (ack, rx) = oneshot::channel();
-                        let req = WriterRequest::Backup(writer::BackupRequest {
-                            node_id,
-                            target_folder: self.path_backups.clone(),
-                            ts,
-                            #[cfg(feature = "s3")]
-                            s3_config: self.s3_config.clone(),
-                            last_applied_log_id,
-                            ack,
-                        });
-
-                        self.write_tx
-                            .send_async(req)
-                            .await
-                            .expect("sql writer to always be listening");
-
-                        let result = rx.await.expect("to always get a response from sql writer");
-                        Response::Backup(result)
-                    }
-                    #[cfg(not(feature = "backup"))]
-                    unreachable!("Backup requires the `backup` feature")
-                }
-
-                EntryPayload::Normal(QueryWrite::Migration(migrations)) => {
-                    let (tx, rx) = oneshot::channel();
-                    let req = WriterRequest::Migrate(writer::Migrate {
-                        migrations,
-                        last_applied_log_id,
-                        tx,
-                    });
-
-                    self.write_tx
-                        .send_async(req)
+                EntryPayload::Normal(write) => {
+                    self.apply_write(&self.write_tx, write, last_applied_log_id)
                         .await
-                        .expect("sql writer to always be listening");
-
-                    let result = rx.await.expect("to always get a response from sql writer");
-                    Response::Migrate(result)
-                }
-
-                EntryPayload::Normal(QueryWrite::RTT) => {
-                    let (ack, rx) = oneshot::channel();
-                    let req = WriterRequest::RTT(writer::RTTRequest {
-                        last_applied_log_id,
-                        ack,
-                    });
-
-                    self.write_tx
-                        .send_async(req)
-                        .await
-                        .expect("sql writer to always be listening");
-
-                    rx.await.expect("to always get a response from sql writer");
-                    Response::RTT
                 }
 
                 EntryPayload::Membership(mem) => {
@@ -849,42 +1065,6 @@ impl RaftStateMachine<TypeConfigSqlite> for StateMachineSqlite {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn forbidden_functions_panic_on_purpose_and_fail_the_statement() {
-        let conn = rusqlite::Connection::open_in_memory().unwrap();
-        StateMachineSqlite::overwrite_non_det_fns(&conn);
-
-        // The registered closures `panic!` on purpose; rusqlite turns that into a
-        // statement error at the FFI boundary, so the query fails.
-        let err = conn
-            .query_row("SELECT now()", (), |row| row.get::<_, String>(0))
-            .unwrap_err();
-        assert!(err.to_string().contains("unwinding panic"));
-
-        // the connection stays usable afterwards
-        let one: i64 = conn.query_row("SELECT 1", (), |row| row.get(0)).unwrap();
-        assert_eq!(one, 1);
-
-        // every registered function panics, not just `now()` - "unwinding panic" proves
-        // the call reached our closure, not a missing function
-        for name in FORBIDDEN_NON_DET_FNS {
-            let err = conn
-                .query_row(&format!("SELECT {name}()"), (), |row| {
-                    row.get::<_, String>(0)
-                })
-                .unwrap_err();
-            assert!(
-                err.to_string().contains("unwinding panic"),
-                "{name} did not panic: {err}"
-            );
-        }
-    }
-}
-
 #[cfg(test)]
 mod serialized_enum_order {
     use super::*;
@@ -907,5 +1087,32 @@ mod serialized_enum_order {
         assert_eq!(idx(&QueryWrite::Migration(vec![])), 4);
         assert_eq!(idx(&QueryWrite::Backup((0, 0))), 5);
         assert_eq!(idx(&QueryWrite::RTT), 6);
+        assert_eq!(
+            idx(&QueryWrite::Idempotent((
+                IdempotencyKey::new(),
+                Box::new(QueryWrite::RTT)
+            ))),
+            7
+        );
+        assert_eq!(
+            idx(&QueryWrite::ExecuteMany(QueryMany {
+                sql: Cow::Owned(String::new()),
+                params: vec![],
+            })),
+            8
+        );
+        assert_eq!(
+            idx(&QueryWrite::Stamped((
+                WriteStamp::new(),
+                Box::new(QueryWrite::RTT)
+            ))),
+            9
+        );
+        assert_eq!(
+            idx(&QueryWrite::Restore(RestoreWrite::Abort {
+                id: String::new()
+            })),
+            10
+        );
     }
 }
@@ -1,6 +1,6 @@
 use std::{borrow::Cow, collections::{hash_map::Entry, HashMap}};
 
-use rusqlite::{types::Value, Transaction};
+use rusqlite::{types::Value, Connection};
 
 use crate::Error;
 
@@ -22,12 +22,12 @@ impl TransactionEnv {
 }
 
 /// A context for looking up Param variables
-pub struct TransactionParamContext<'a, 't> {
-    pub txn: &'a Transaction<'t>,
+pub struct TransactionParamContext<'a> {
+    pub txn: &'a Connection,
     pub env: &'a mut TransactionEnv,
 }
 
-impl TransactionParamContext<'_, '_> {
+impl TransactionParamContext<'_> {
     pub fn lookup_statement_output_indexed(&mut self, statement_index: usize, column_index: usize) -> Result<Value, Cow<'static, str>> {
         let executed_stmt = ObservableStatement::by_index(&self.env.observable_stmts, statement_index)?;
         Ok(executed_stmt.get_first_row_value(column_index)?.clone())
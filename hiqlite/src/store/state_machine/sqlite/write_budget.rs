@@ -0,0 +1,346 @@
+use crate::Error;
+use crate::store::state_machine::sqlite::changes::ChangeCapture;
+use crate::store::state_machine::sqlite::deterministic::{WriteContext, WriteStamp};
+use crate::store::state_machine::sqlite::state_machine::{Query, QueryMany, QueryWrite};
+use crate::store::state_machine::sqlite::writer::{self, WriterRequest};
+use rusqlite::StatementStatus;
+use rusqlite::hooks::{AuthAction, AuthContext, Authorization};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::oneshot;
+use tokio::task;
+
+/// Limits the amount of SQLite VM instructions a single statement of a client write may
+/// execute.
+///
+/// SQLite counts the VM instructions for each statement on its own and calls the progress
+/// handler each time this counter reaches a multiple of the configured amount. Using the budget
+/// itself as the interval aborts each statement exactly when it has used up its budget.
+///
+/// The count depends on the query plan, which depends on the statistics of each database and
+/// the SQLite version of each node. Aborting a statement while applying a Raft log could apply
+/// the same write on one node and reject it on another one. The budget is therefore only
+/// evaluated by the leader in a dry run on its writer connection, before the write is proposed.
+/// A write exceeding it never makes it into the Raft log, and all logs are applied without any
+/// limit.
+#[derive(Debug)]
+pub(crate) struct WriteBudget {
+    instructions: Option<i32>,
+    write_ctx: WriteContext,
+    exceeded: Arc<AtomicBool>,
+    /// The `last_applied_log_id` of the writer from before the dry run, if one is running.
+    dry_run: Option<Option<openraft::LogId<crate::NodeId>>>,
+}
+
+impl WriteBudget {
+    pub(crate) fn new(instructions: Option<u32>, write_ctx: WriteContext) -> Self {
+        Self {
+            // validated by `NodeConfig::is_valid()`
+            instructions: instructions.map(|i| i.clamp(1, i32::MAX as u32) as i32),
+            write_ctx,
+            exceeded: Arc::new(AtomicBool::new(false)),
+            dry_run: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_dry_run(&self) -> bool {
+        self.dry_run.is_some()
+    }
+
+    /// Starts a dry run inside a transaction. Nothing it does can ever be committed, and it must
+    /// be ended with `end_dry_run()` before the writer accepts any other request.
+    pub(crate) fn begin_dry_run(
+        &mut self,
+        conn: &rusqlite::Connection,
+        changes: &ChangeCapture,
+        stamp: Option<WriteStamp>,
+        last_applied_log_id: Option<openraft::LogId<crate::NodeId>>,
+    ) -> rusqlite::Result<()> {
+        conn.execute_batch("BEGIN")?;
+        changes.set_dry_run(true);
+        self.write_ctx.set_dry_run(stamp);
+        // connection settings would survive the rollback
+        conn.authorizer(Some(|ctx: AuthContext<'_>| match ctx.action {
+            AuthAction::Pragma { .. } | AuthAction::Attach { .. } | AuthAction::Detach { .. } => {
+                Authorization::Deny
+            }
+            _ => Authorization::Allow,
+        }))?;
+
+        self.exceeded.store(false, Ordering::Relaxed);
+        if let Some(instructions) = self.instructions {
+            let exceeded = self.exceeded.clone();
+            // returning `true` interrupts the statement with `SQLITE_INTERRUPT`
+            conn.progress_handler(
+                instructions,
+                Some(move || {
+                    exceeded.store(true, Ordering::Relaxed);
+                    true
+                }),
+            )?;
+        }
+        self.dry_run = Some(last_applied_log_id);
+
+        Ok(())
+    }
+
+    /// Rolls back everything the dry run did. Returns the `last_applied_log_id` from before and
+    /// if any statement exceeded the budget.
+    pub(crate) fn end_dry_run(
+        &mut self,
+        conn: &rusqlite::Connection,
+        changes: &ChangeCapture,
+    ) -> rusqlite::Result<(Option<openraft::LogId<crate::NodeId>>, bool)> {
+        let last_applied_log_id = self.dry_run.take().flatten();
+
+        conn.progress_handler(0, None::<fn() -> bool>)?;
+        conn.authorizer(None::<fn(AuthContext<'_>) -> Authorization>)?;
+        // an interrupted statement rolls back the whole transaction already
+        if !conn.is_autocommit() {
+            conn.execute_batch("ROLLBACK")?;
+        }
+        self.write_ctx.set_dry_run(None);
+        changes.set_dry_run(false);
+
+        Ok((last_applied_log_id, self.exceeded.load(Ordering::Relaxed)))
+    }
+}
+
+/// SQLite keeps counting VM instructions across executions of the same statement, and the
+/// progress handler fires on multiples of the budget for that count. The statement cache has
+/// a different state for each dry run, so each statement must start with a fresh count before
+/// it executes.
+#[inline]
+pub(crate) fn reset_count(stmt: &rusqlite::Statement<'_>) {
+    stmt.reset_status(StatementStatus::VmStep);
+}
+
+/// Runs a client write in a dry run on the writer of this node, which must be the leader.
+/// Returns an error if any of its statements exceeded the `write_instruction_budget`.
+pub(crate) async fn check(
+    write_tx: &flume::Sender<WriterRequest>,
+    write: &QueryWrite,
+) -> Result<(), Error> {
+    let (stamp, write) = match write {
+        QueryWrite::Stamped((stamp, write)) => (Some(*stamp), write.as_ref()),
+        write => (None, write),
+    };
+    let write = match write {
+        QueryWrite::Idempotent((_, write)) => write.as_ref(),
+        write => write,
+    };
+    // migrations, backups and other internal writes are never limited
+    if !matches!(
+        write,
+        QueryWrite::Execute(_)
+            | QueryWrite::ExecuteReturning(_)
+            | QueryWrite::Transaction(_)
+            | QueryWrite::Batch(_)
+            | QueryWrite::ExecuteMany(_)
+    ) {
+        return Ok(());
+    }
+
+    // The writer sends back each response and expects it to be received. The dry run must
+    // never be cancelled halfway, even if the client gives up on the write.
+    let write_tx = write_tx.clone();
+    let write = write.clone();
+    let exceeded = task::spawn(async move { dry_run(&write_tx, stamp, write).await })
+        .await
+        .map_err(|err| Error::Error(err.to_string().into()))?;
+
+    if exceeded {
+        Err(Error::Sqlite(
+            "statement exceeded the 'write_instruction_budget' and the write has been rejected"
+                .into(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+async fn dry_run(
+    write_tx: &flume::Sender<WriterRequest>,
+    stamp: Option<WriteStamp>,
+    write: QueryWrite,
+) -> bool {
+    let (tx_exclusive, rx_exclusive) = flume::bounded(1);
+    let (ack, rx) = oneshot::channel();
+    write_tx
+        .send_async(WriterRequest::DryRun(writer::DryRunRequest {
+            stamp,
+            rx_exclusive,
+            ack,
+        }))
+        .await
+        .expect("sql writer to always be listening");
+    rx.await.expect("to always get a response from sql writer");
+
+    // The results do not matter. Any error other than the budget will show up again when the
+    // write is applied.
+    match write {
+        QueryWrite::Execute(Query { sql, params }) => {
+            let (tx, rx) = oneshot::channel();
+            send(
+                &tx_exclusive,
+                writer::Query::Execute(writer::SqlExecute {
+                    sql,
+                    params,
+                    last_applied_log_id: None,
+                    tx,
+                }),
+            )
+            .await;
+            let _ = rx.await;
+        }
+        QueryWrite::ExecuteReturning(Query { sql, params }) => {
+            let (tx, rx) = oneshot::channel();
+            send(
+                &tx_exclusive,
+                writer::Query::ExecuteReturning(writer::SqlExecuteReturning {
+                    sql,
+                    params,
+                    last_applied_log_id: None,
+                    tx,
+                }),
+            )
+            .await;
+            let _ = rx.await;
+        }
+        QueryWrite::Transaction(queries) => {
+            let (tx, rx) = oneshot::channel();
+            send(
+                &tx_exclusive,
+                writer::Query::Transaction(writer::SqlTransaction {
+                    queries,
+                    last_applied_log_id: None,
+                    tx,
+                }),
+            )
+            .await;
+            let _ = rx.await;
+        }
+        QueryWrite::Batch(sql) => {
+            let (tx, rx) = oneshot::channel();
+            send(
+                &tx_exclusive,
+                writer::Query::Batch(writer::SqlBatch {
+                    sql,
+                    last_applied_log_id: None,
+                    tx,
+                }),
+            )
+            .await;
+            let _ = rx.await;
+        }
+        QueryWrite::ExecuteMany(QueryMany { sql, params }) => {
+            let (tx, rx) = oneshot::channel();
+            send(
+                &tx_exclusive,
+                writer::Query::ExecuteMany(writer::SqlExecuteMany {
+                    sql,
+                    params,
+                    last_applied_log_id: None,
+                    tx,
+                }),
+            )
+            .await;
+            let _ = rx.await;
+        }
+        _ => unreachable!("checked by `check()`"),
+    }
+
+    let (ack, rx) = oneshot::channel();
+    tx_exclusive
+        .send_async(WriterRequest::DryRunEnd(ack))
+        .await
+        .expect("sql writer to always be listening");
+    rx.await.expect("to always get a response from sql writer")
+}
+
+#[inline]
+async fn send(tx_exclusive: &flume::Sender<WriterRequest>, query: writer::Query) {
+    tx_exclusive
+        .send_async(WriterRequest::Query(query))
+        .await
+        .expect("sql writer to always be listening");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::state_machine::sqlite::changes;
+
+    const SQL_COUNT: &str = "WITH RECURSIVE cnt(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM cnt \
+        WHERE x < $1) SELECT count(*) FROM cnt";
+
+    fn count(conn: &rusqlite::Connection, limit: i64) -> Result<i64, Error> {
+        // cached statements keep their VM step counter across executions
+        let mut stmt = conn.prepare_cached(SQL_COUNT)?;
+        reset_count(&stmt);
+        Ok(stmt.query_row([limit], |row| row.get(0))?)
+    }
+
+    #[test]
+    fn dry_runs_detect_expensive_statements() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let (tx, _rx, filter) = changes::channel();
+        let changes = ChangeCapture::register(&conn, filter, tx).unwrap();
+        let mut budget = WriteBudget::new(Some(100_000), WriteContext::default());
+
+        budget.begin_dry_run(&conn, &changes, None, None).unwrap();
+        assert_eq!(count(&conn, 10).unwrap(), 10);
+        assert!(!budget.end_dry_run(&conn, &changes).unwrap().1);
+
+        budget.begin_dry_run(&conn, &changes, None, None).unwrap();
+        assert!(count(&conn, 1_000_000).is_err());
+        assert!(budget.end_dry_run(&conn, &changes).unwrap().1);
+
+        // the budget applies to each execution on its own
+        budget.begin_dry_run(&conn, &changes, None, None).unwrap();
+        for _ in 0..100 {
+            assert_eq!(count(&conn, 1_000).unwrap(), 1_000);
+        }
+        assert!(!budget.end_dry_run(&conn, &changes).unwrap().1);
+
+        // outside a dry run, nothing is ever aborted
+        assert_eq!(count(&conn, 1_000_000).unwrap(), 1_000_000);
+
+        // without a budget, nothing is ever exceeded
+        let mut budget = WriteBudget::new(None, WriteContext::default());
+        budget.begin_dry_run(&conn, &changes, None, None).unwrap();
+        assert_eq!(count(&conn, 1_000_000).unwrap(), 1_000_000);
+        assert!(!budget.end_dry_run(&conn, &changes).unwrap().1);
+    }
+
+    #[test]
+    fn dry_runs_never_persist_anything() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        let (tx, _rx, filter) = changes::channel();
+        let changes = ChangeCapture::register(&conn, filter, tx).unwrap();
+        let mut budget = WriteBudget::new(Some(100_000), WriteContext::default());
+
+        budget.begin_dry_run(&conn, &changes, None, None).unwrap();
+        conn.execute("INSERT INTO t (id) VALUES (1)", ()).unwrap();
+        // a commit turns into a rollback, and so does each following statement on its own
+        assert!(conn.execute_batch("COMMIT").is_err());
+        assert!(conn.execute("INSERT INTO t (id) VALUES (2)", ()).is_err());
+        assert!(
+            conn.execute_batch("PRAGMA recursive_triggers = ON")
+                .is_err()
+        );
+        budget.end_dry_run(&conn, &changes).unwrap();
+
+        let rows: i64 = conn
+            .query_row("SELECT count(*) FROM t", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+        let recursive_triggers: i64 = conn
+            .query_row("PRAGMA recursive_triggers", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(recursive_triggers, 0);
+    }
+}
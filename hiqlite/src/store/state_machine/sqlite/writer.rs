@@ -1,7 +1,14 @@
+#[cfg(feature = "backup")]
+use crate::backup_progress::ProgressKind;
 use crate::helpers::{deserialize, serialize};
 use crate::migration::Migration;
 use crate::query::rows::{ColumnOwned, RowOwned, ValueOwned};
 use crate::store::logs;
+use crate::store::state_machine::sqlite::changes::{ChangeCapture, ChangesFilter, ChangesRequest};
+use crate::store::state_machine::sqlite::deterministic::{WriteContext, WriteStamp};
+use crate::store::state_machine::sqlite::idempotency::{self, IdempotencyKey};
+#[cfg(feature = "backup")]
+use crate::store::state_machine::sqlite::restore::{self, RestoreWrite};
 use crate::store::state_machine::sqlite::state_machine;
 use crate::store::state_machine::sqlite::state_machine::{
     Params, StateMachineData, StateMachineSqlite, StoredSnapshot,
@@ -9,6 +16,7 @@ use crate::store::state_machine::sqlite::state_machine::{
 use crate::store::state_machine::sqlite::transaction_env::{
     TransactionEnv, TransactionParamContext,
 };
+use crate::store::state_machine::sqlite::write_budget::{self, WriteBudget};
 use crate::{AppliedMigration, Error, Node, NodeId, Param};
 use chrono::Utc;
 use flume::RecvError;
@@ -21,6 +29,7 @@ use rusqlite::{Batch, CachedStatement, Rows, Transaction};
 use std::borrow::Cow;
 use std::default::Default;
 use std::ops::Sub;
+use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 use thread_priority::ThreadPriority;
@@ -29,6 +38,11 @@ use tokio::{fs, runtime, task, time};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+/// Hiqlite internal tables, which are created by the writer. They never show up in exports or
+/// change events and must not exist inside a database to bootstrap from.
+pub(crate) const INTERNAL_TABLES: [&str; 4] =
+    ["_idempotency", "_metadata", "_migrations", "_restore"];
+
 #[derive(Debug)]
 pub enum WriterRequest {
     Query(Query),
@@ -41,6 +55,12 @@ pub enum WriterRequest {
     Shutdown(oneshot::Sender<()>),
     #[allow(clippy::upper_case_acronyms)]
     RTT(RTTRequest),
+    IdempotencyLookup(IdempotencyLookupRequest),
+    IdempotencyStore(IdempotencyStoreRequest),
+    DryRun(DryRunRequest),
+    DryRunEnd(oneshot::Sender<bool>),
+    #[cfg(feature = "backup")]
+    Restore(RestoreRequest),
 }
 
 #[derive(Debug)]
@@ -49,6 +69,7 @@ pub enum Query {
     ExecuteReturning(SqlExecuteReturning),
     Transaction(SqlTransaction),
     Batch(SqlBatch),
+    ExecuteMany(SqlExecuteMany),
 }
 
 #[derive(Debug)]
@@ -81,6 +102,14 @@ pub struct SqlBatch {
     pub tx: oneshot::Sender<Result<Vec<Result<usize, Error>>, Error>>,
 }
 
+#[derive(Debug)]
+pub struct SqlExecuteMany {
+    pub sql: Cow<'static, str>,
+    pub params: Vec<Params>,
+    pub last_applied_log_id: Option<LogId<NodeId>>,
+    pub tx: oneshot::Sender<Result<Vec<usize>, Error>>,
+}
+
 #[derive(Debug)]
 pub struct Migrate {
     pub migrations: Vec<Migration>,
@@ -119,8 +148,8 @@ pub struct BackupRequest {
     pub node_id: NodeId,
     pub target_folder: String,
     pub ts: i64,
-    #[cfg(feature = "s3")]
-    pub s3_config: Option<std::sync::Arc<crate::s3::S3Config>>,
+    #[cfg(feature = "backup")]
+    pub targets: Vec<std::sync::Arc<dyn crate::backup_target::BackupTarget>>,
     pub last_applied_log_id: Option<LogId<NodeId>>,
     pub ack: oneshot::Sender<Result<(), Error>>,
 }
@@ -131,14 +160,58 @@ pub struct RTTRequest {
     pub ack: oneshot::Sender<()>,
 }
 
+#[derive(Debug)]
+pub struct IdempotencyLookupRequest {
+    pub key: IdempotencyKey,
+    pub now: i64,
+    pub last_applied_log_id: Option<LogId<NodeId>>,
+    /// If the key does not exist yet, the writer keeps the transaction open and only accepts
+    /// requests from this channel until the matching `IdempotencyStore`.
+    pub rx_exclusive: flume::Receiver<WriterRequest>,
+    pub ack: oneshot::Sender<Option<state_machine::Response>>,
+}
+
+#[derive(Debug)]
+pub struct IdempotencyStoreRequest {
+    pub key: IdempotencyKey,
+    pub now: i64,
+    pub response: Vec<u8>,
+    pub ack: oneshot::Sender<Result<(), Error>>,
+}
+
+#[derive(Debug)]
+pub struct DryRunRequest {
+    pub stamp: Option<WriteStamp>,
+    /// The writer only accepts requests from this channel until the matching `DryRunEnd`.
+    pub rx_exclusive: flume::Receiver<WriterRequest>,
+    pub ack: oneshot::Sender<()>,
+}
+
+#[cfg(feature = "backup")]
+#[derive(Debug)]
+pub struct RestoreRequest {
+    pub write: RestoreWrite,
+    pub path_tmp: String,
+    pub last_applied_log_id: Option<LogId<NodeId>>,
+    pub ack: oneshot::Sender<Result<(), Error>>,
+}
+
 #[allow(clippy::blocks_in_conditions)]
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_writer(
     mut conn: rusqlite::Connection,
     this_node: NodeId,
     path_lock_file: String,
     log_statements: bool,
     do_reset_metadata: bool,
-    #[cfg(feature = "backup")] local_backup_keep_days: u16,
+    #[cfg(feature = "backup")] local_backup_retention: crate::backup::Retention,
+    #[cfg(feature = "backup")] backup_verify: bool,
+    #[cfg(feature = "backup")] backup_differential: u16,
+    #[cfg(feature = "backup")] backup_progress: Arc<crate::backup_progress::BackupTracker>,
+    tx_changes: flume::Sender<ChangesRequest>,
+    changes_filter: Arc<RwLock<ChangesFilter>>,
+    write_ctx: WriteContext,
+    write_instruction_budget: Option<u32>,
 ) -> flume::Sender<WriterRequest> {
     let (tx, rx) = flume::bounded::<WriterRequest>(1);
 
@@ -150,6 +223,7 @@ pub fn spawn_writer(
         let mut sm_data = StateMachineData::default();
         let mut ts_last_backup = None;
         let mut shutdown_ack: Option<oneshot::Sender<()>> = None;
+        let mut budget = WriteBudget::new(write_instruction_budget, write_ctx);
 
         // TODO should we maybe save a backup task handle in case of shutdown overlap?
 
@@ -172,8 +246,48 @@ CREATE TABLE IF NOT EXISTS _metadata
             (),
         )
         .expect("_metadata table creation to always succeed");
+        idempotency::create_table(&conn).expect("_idempotency table creation to always succeed");
+
+        // captures row changes for `Client::subscribe_changes()`
+        let changes = ChangeCapture::register(&conn, changes_filter, tx_changes)
+            .expect("SQLite hooks registration to always succeed");
+
+        // An idempotent write keeps a transaction open from the key lookup until its response
+        // has been stored, and a dry run until it has finished. No other request, like a
+        // snapshot, must ever see it in between.
+        let mut rx_exclusive: Option<flume::Receiver<WriterRequest>> = None;
+
+        'main: loop {
+            let req = if let Some(rx_ex) = &rx_exclusive {
+                match rx_ex.recv() {
+                    Ok(req) => req,
+                    Err(_) => {
+                        rx_exclusive = None;
+                        if budget.is_dry_run() {
+                            error!("Write budget dry run has been aborted - rolling it back");
+                            let (last_applied_log_id, _) = budget
+                                .end_dry_run(&conn, &changes)
+                                .expect("Dry run rollback to never fail");
+                            sm_data.last_applied_log_id = last_applied_log_id;
+                            continue;
+                        }
+
+                        error!("Idempotent write has been aborted - rolling back its transaction");
+                        if !conn.is_autocommit()
+                            && let Err(err) = conn.execute_batch("ROLLBACK")
+                        {
+                            error!("Error during txn rollback: {:?}", err);
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                match rx.recv() {
+                    Ok(req) => req,
+                    Err(_) => break,
+                }
+            };
 
-        'main: while let Ok(req) = rx.recv() {
             match req {
                 WriterRequest::Query(query) => match query {
                     Query::Execute(q) => {
@@ -219,7 +333,14 @@ CREATE TABLE IF NOT EXISTS _metadata
                                 continue;
                             }
 
-                            stmt.raw_execute().map_err(Error::from)
+                            write_budget::reset_count(&stmt);
+                            let mark = changes.mark();
+                            let res = stmt.raw_execute().map_err(Error::from);
+                            if res.is_err() {
+                                // a failed statement inside a transaction does not fire a hook
+                                changes.discard_since(mark);
+                            }
+                            res
                         };
 
                         q.tx.send(res).expect("oneshot tx to never be dropped");
@@ -278,6 +399,8 @@ CREATE TABLE IF NOT EXISTS _metadata
                                 continue;
                             }
 
+                            write_budget::reset_count(&stmt);
+                            let mark = changes.mark();
                             let mut rows = stmt.raw_query();
                             let mut res = Vec::new();
                             loop {
@@ -289,7 +412,8 @@ CREATE TABLE IF NOT EXISTS _metadata
                                         break;
                                     }
                                     Err(err) => {
-                                        res.push(Err(Error::Sqlite(err.to_string().into())));
+                                        changes.discard_since(mark);
+                                        res.push(Err(Error::from(err)));
                                     }
                                 }
                             }
@@ -303,7 +427,11 @@ CREATE TABLE IF NOT EXISTS _metadata
                     Query::Transaction(req) => {
                         sm_data.last_applied_log_id = req.last_applied_log_id;
 
-                        let txn = match conn.transaction() {
+                        // A savepoint makes it possible to discard exactly the captured changes
+                        // of this transaction on rollback. It works nested inside the transaction
+                        // of an idempotent write as well.
+                        let mark = changes.mark();
+                        let mut txn = match conn.savepoint() {
                             Ok(txn) => txn,
                             Err(err) => {
                                 error!("Opening database transaction: {err:?}");
@@ -367,6 +495,7 @@ CREATE TABLE IF NOT EXISTS _metadata
                             }
 
                             let column_count = stmt.column_count();
+                            write_budget::reset_count(&stmt);
 
                             if column_count > 0 {
                                 // the statement is potentially "observable", because it returns columns.
@@ -441,6 +570,8 @@ CREATE TABLE IF NOT EXISTS _metadata
                             if let Err(e) = txn.rollback() {
                                 error!("Error during txn rollback: {:?}", e);
                             }
+                            drop(txn);
+                            changes.discard_since(mark);
                             req.tx.send(Err(err)).expect("oneshot tx to never be dropped");
                         } else {
                             match txn.commit() {
@@ -450,6 +581,7 @@ CREATE TABLE IF NOT EXISTS _metadata
                                         .expect("oneshot tx to never be dropped");
                                 }
                                 Err(err) => {
+                                    changes.discard_since(mark);
                                     req.tx
                                         .send(Err(Error::Transaction(err.to_string().into())))
                                         .expect("oneshot tx to never be dropped");
@@ -490,6 +622,25 @@ CREATE TABLE IF NOT EXISTS _metadata
                             req.tx.send(Ok(res)).expect("oneshot tx to never be dropped");
                         }
                     }
+
+                    Query::ExecuteMany(req) => {
+                        sm_data.last_applied_log_id = req.last_applied_log_id;
+
+                        if log_statements {
+                            info!(
+                                "Query::ExecuteMany:\n{}\n{} parameter sets",
+                                req.sql,
+                                req.params.len()
+                            );
+                        }
+
+                        let mark = changes.mark();
+                        let res = execute_many(&mut conn, &req.sql, req.params);
+                        if res.is_err() {
+                            changes.discard_since(mark);
+                        }
+                        req.tx.send(res).expect("oneshot tx to never be dropped");
+                    }
                 },
 
                 WriterRequest::Migrate(req) => {
@@ -515,6 +666,25 @@ CREATE TABLE IF NOT EXISTS _metadata
                     sm_data.last_snapshot_id = Some(snapshot_id.to_string());
                     persist_metadata(&conn, &sm_data).expect("Metadata persist to never fail");
 
+                    // `VACUUM INTO` cannot report any progress, only the total amount of pages
+                    #[cfg(feature = "backup")]
+                    {
+                        backup_progress.start(ProgressKind::Snapshot, &snapshot_id.to_string());
+                        if let Ok(pagecount) =
+                            conn.query_row("PRAGMA page_count", (), |row| row.get::<_, i32>(0))
+                        {
+                            backup_progress.pages(
+                                ProgressKind::Snapshot,
+                                Progress {
+                                    remaining: pagecount,
+                                    pagecount,
+                                },
+                            );
+                        }
+                    }
+                    #[cfg(feature = "backup")]
+                    let path_snapshot = path.clone();
+
                     match create_snapshot(
                         &conn,
                         // snapshot_id,
@@ -523,6 +693,14 @@ CREATE TABLE IF NOT EXISTS _metadata
                         // sm_data.last_membership.clone(),
                     ) {
                         Ok(_) => {
+                            #[cfg(feature = "backup")]
+                            {
+                                let size = std::fs::metadata(&path_snapshot)
+                                    .map(|m| m.len())
+                                    .unwrap_or_default();
+                                backup_progress.finish(ProgressKind::Snapshot, None, size);
+                            }
+
                             if let Err(err) = conn.execute("PRAGMA optimize", []) {
                                 error!("Error during 'PRAGMA optimize': {}", err);
                             }
@@ -533,6 +711,8 @@ CREATE TABLE IF NOT EXISTS _metadata
                         }
                         Err(err) => {
                             error!("Error creating new snapshot: {:?}", err);
+                            #[cfg(feature = "backup")]
+                            backup_progress.fail(ProgressKind::Snapshot, err.to_string());
                             ack.send(Err(StorageError::IO {
                                 source: StorageIOError::write(&err),
                             }))
@@ -558,6 +738,10 @@ CREATE TABLE IF NOT EXISTS _metadata
                         continue;
                     }
 
+                    // snapshots from older versions do not contain the idempotency table yet
+                    idempotency::create_table(&conn)
+                        .expect("_idempotency table creation to always succeed");
+
                     if let Err(err) = conn.execute("PRAGMA optimize", []) {
                         error!("Error during 'PRAGMA optimize': {}", err);
                     }
@@ -577,6 +761,7 @@ CREATE TABLE IF NOT EXISTS _metadata
                             Ok(metadata)
                         })
                         .expect("Metadata query to always succeed");
+                    changes.snapshot_installed(sm_data.last_applied_log_id);
 
                     ack.send(Ok(())).expect("snapshot install listener to always exist");
                 }
@@ -615,6 +800,19 @@ CREATE TABLE IF NOT EXISTS _metadata
                 WriterRequest::Backup(req) => {
                     sm_data.last_applied_log_id = req.last_applied_log_id;
 
+                    // must happen before the duplicate check below to stay deterministic
+                    #[cfg(feature = "backup")]
+                    let archive_base = {
+                        let index = req.last_applied_log_id.map(|id| id.index).unwrap_or_default();
+                        let lineage =
+                            crate::archive::lineage_init(&conn, req.node_id, req.ts, index)
+                                .expect("Archive lineage init to never fail");
+                        crate::archive::ArchivePosition {
+                            lineage: lineage.lineage,
+                            index,
+                        }
+                    };
+
                     // TODO include a TS in the req to skip backups if they are replayed after
                     // a restart
                     let now = Utc::now();
@@ -628,21 +826,46 @@ CREATE TABLE IF NOT EXISTS _metadata
                         continue;
                     }
 
-                    info!("VACUUMing the database");
-                    let start = Instant::now();
-                    match conn.execute("VACUUM", ()) {
-                        Ok(_) => {
-                            info!("VACUUM finished after {} ms", start.elapsed().as_millis());
+                    #[cfg(feature = "backup")]
+                    let next = crate::backup_diff::next_backup(
+                        &req.target_folder,
+                        req.node_id,
+                        req.ts,
+                        backup_differential,
+                    );
+                    #[cfg(feature = "backup")]
+                    if next == crate::backup_diff::NextBackup::Exists {
+                        info!(
+                            "Backup {} is older than the current differential chain - ignoring it",
+                            req.ts
+                        );
+                        req.ack.send(Ok(()));
+                        continue;
+                    }
+
+                    // differentials compare the page layout with the previous backup, which
+                    // would be rewritten completely by a VACUUM
+                    #[cfg(feature = "backup")]
+                    let vacuum = next != crate::backup_diff::NextBackup::Differential;
+                    #[cfg(not(feature = "backup"))]
+                    let vacuum = true;
+                    if vacuum {
+                        info!("VACUUMing the database");
+                        let start = Instant::now();
+                        match conn.execute("VACUUM", ()) {
+                            Ok(_) => {
+                                info!("VACUUM finished after {} ms", start.elapsed().as_millis());
+                            }
+                            Err(err) => error!("Error during VACUUM: {}", err),
                         }
-                        Err(err) => error!("Error during VACUUM: {}", err),
                     }
 
                     // only the current leader should push the backup
-                    #[cfg(feature = "s3")]
-                    let s3_config = if this_node == req.node_id {
-                        req.s3_config
+                    #[cfg(feature = "backup")]
+                    let targets = if this_node == req.node_id {
+                        req.targets
                     } else {
-                        None
+                        Vec::new()
                     };
 
                     if let Err(err) = create_backup(
@@ -650,8 +873,16 @@ CREATE TABLE IF NOT EXISTS _metadata
                         req.node_id,
                         req.ts,
                         req.target_folder.clone(),
-                        #[cfg(feature = "s3")]
-                        s3_config,
+                        #[cfg(feature = "backup")]
+                        &archive_base,
+                        #[cfg(feature = "backup")]
+                        backup_verify,
+                        #[cfg(feature = "backup")]
+                        next,
+                        #[cfg(feature = "backup")]
+                        targets,
+                        #[cfg(feature = "backup")]
+                        &backup_progress,
                         #[cfg(feature = "s3")]
                         &rt,
                     ) {
@@ -664,7 +895,7 @@ CREATE TABLE IF NOT EXISTS _metadata
                     rt.spawn(async move {
                         if let Err(err) = crate::backup::backup_local_cleanup(
                             req.target_folder,
-                            local_backup_keep_days,
+                            local_backup_retention,
                         )
                         .await
                         {
@@ -685,11 +916,124 @@ CREATE TABLE IF NOT EXISTS _metadata
                     req.ack.send(()).expect("rtt ack listener to always exist");
                 }
 
+                WriterRequest::IdempotencyLookup(req) => {
+                    // The key, the write and its response are stored in a single transaction.
+                    // Otherwise, a crash in between would apply the write again on restart.
+                    conn.execute_batch("BEGIN IMMEDIATE")
+                        .expect("Idempotency transaction to always begin");
+
+                    // An error here would make nodes disagree about applying the write or not.
+                    let resp = idempotency::lookup(&conn, &req.key, req.now)
+                        .expect("Idempotency lookup to never fail");
+                    if resp.is_some() {
+                        // the wrapped write will not be applied and cannot update the log id
+                        sm_data.last_applied_log_id = req.last_applied_log_id;
+                        conn.execute_batch("COMMIT")
+                            .expect("Idempotency lookup commit to never fail");
+                    } else {
+                        rx_exclusive = Some(req.rx_exclusive);
+                    }
+                    req.ack
+                        .send(resp)
+                        .expect("idempotency ack listener to always exist");
+                }
+
+                WriterRequest::IdempotencyStore(req) => {
+                    rx_exclusive = None;
+                    idempotency::store(&conn, &req.key, req.now, &req.response)
+                        .expect("Idempotency store to never fail");
+                    // An interrupted write may have rolled back the whole transaction already.
+                    let res = if conn.is_autocommit() {
+                        Ok(())
+                    } else {
+                        conn.execute_batch("COMMIT").map_err(Error::from)
+                    };
+                    if let Err(err) = &res {
+                        // e.g. violated deferred foreign keys, which is the same on each node
+                        error!("Error committing idempotent write: {:?}", err);
+                        if let Err(err) = conn.execute_batch("ROLLBACK") {
+                            error!("Error during txn rollback: {:?}", err);
+                        }
+                    }
+                    req.ack
+                        .send(res)
+                        .expect("idempotency ack listener to always exist");
+                }
+
+                WriterRequest::DryRun(req) => {
+                    budget
+                        .begin_dry_run(&conn, &changes, req.stamp, sm_data.last_applied_log_id)
+                        .expect("Dry run to always begin");
+                    rx_exclusive = Some(req.rx_exclusive);
+                    req.ack
+                        .send(())
+                        .expect("dry run ack listener to always exist");
+                }
+
+                WriterRequest::DryRunEnd(ack) => {
+                    rx_exclusive = None;
+                    let (last_applied_log_id, exceeded) = budget
+                        .end_dry_run(&conn, &changes)
+                        .expect("Dry run rollback to never fail");
+                    sm_data.last_applied_log_id = last_applied_log_id;
+                    ack.send(exceeded)
+                        .expect("dry run ack listener to always exist");
+                }
+
+                #[cfg(feature = "backup")]
+                WriterRequest::Restore(req) => {
+                    sm_data.last_applied_log_id = req.last_applied_log_id;
+
+                    let res = match req.write {
+                        RestoreWrite::Chunk { id, offset, data } => {
+                            restore::stage_chunk(&conn, &id, offset, &data)
+                        }
+                        RestoreWrite::Apply { id, size, sha256 } => {
+                            let start = Instant::now();
+                            info!("Restoring the database from the staged backup of {id}");
+
+                            let res = restore::apply(&mut conn, &req.path_tmp, &id, size, &sha256);
+                            if res.is_ok() {
+                                // The restored `_metadata` belongs to the backup, while the Raft
+                                // continues where it is right now.
+                                persist_metadata(&conn, &sm_data)
+                                    .expect("Metadata persist to never fail");
+                                crate::archive::restore_reset(&conn)
+                                    .expect("Archive metadata reset to never fail");
+
+                                if let Err(err) = conn.execute("PRAGMA optimize", []) {
+                                    error!("Error during 'PRAGMA optimize': {}", err);
+                                }
+                                changes.snapshot_installed(sm_data.last_applied_log_id);
+
+                                info!(
+                                    "Database restore finished after {} ms",
+                                    start.elapsed().as_millis()
+                                );
+                            }
+                            res
+                        }
+                        RestoreWrite::Abort { id } => {
+                            warn!("Dropping the staged backup of the failed restore {id}");
+                            restore::drop_staged(&conn)
+                        }
+                    };
+
+                    if let Err(err) = &res {
+                        error!("Error during database restore: {err}");
+                    }
+                    req.ack.send(res).expect("restore ack listener to always exist");
+                }
+
                 WriterRequest::Shutdown(ack) => {
                     shutdown_ack = Some(ack);
                     break;
                 }
             }
+
+            // changes are only captured after a commit, so this is a no-op for any request
+            // that did not modify user tables
+            changes.publish(&conn, sm_data.last_applied_log_id);
         }
 
         warn!("SQL writer is shutting down");
@@ -713,7 +1057,7 @@ CREATE TABLE IF NOT EXISTS _metadata
 }
 
 #[inline]
-fn persist_metadata(
+pub(crate) fn persist_metadata(
     conn: &rusqlite::Connection,
     metadata: &StateMachineData,
 ) -> Result<(), rusqlite::Error> {
@@ -753,6 +1097,55 @@ Got:      {}
     }
 }
 
+/// Executes the same statement for each of the given `params` inside a single transaction,
+/// while the statement is only prepared once.
+fn execute_many(
+    conn: &mut rusqlite::Connection,
+    sql: &str,
+    params: Vec<Params>,
+) -> Result<Vec<usize>, Error> {
+    // a savepoint works inside the transaction of an idempotent write or a dry run as well
+    let txn = conn.savepoint()?;
+
+    let results = {
+        let mut stmt = txn
+            .prepare_cached(sql)
+            .map_err(|err| Error::PrepareStatement(err.to_string().into()))?;
+
+        let mut results = Vec::with_capacity(params.len());
+        for params in params {
+            // The statement keeps the bindings from the previous execution. A set with missing
+            // params would silently re-use old values without this check.
+            if params.len() != stmt.parameter_count() {
+                return Err(Error::QueryParams(
+                    format!(
+                        "expected {} params, got {}",
+                        stmt.parameter_count(),
+                        params.len()
+                    )
+                    .into(),
+                ));
+            }
+
+            let mut idx = 1;
+            #[allow(clippy::explicit_counter_loop)]
+            for param in params {
+                stmt.raw_bind_parameter(idx, param.into_sql())
+                    .map_err(|err| Error::QueryParams(err.to_string().into()))?;
+                idx += 1;
+            }
+
+            write_budget::reset_count(&stmt);
+            results.push(stmt.raw_execute()?);
+        }
+        results
+    };
+
+    // an early return above drops the transaction, which rolls it back
+    txn.commit()?;
+    Ok(results)
+}
+
 #[inline]
 fn create_snapshot(conn: &rusqlite::Connection, path: String) -> Result<(), Error> {
     // vacuum into a temp file and move it into place, so a crash can never leave a
@@ -768,77 +1161,144 @@ fn create_snapshot(conn: &rusqlite::Connection, path: String) -> Result<(), Erro
     Ok(())
 }
 
-#[cfg(feature = "s3")]
-const S3_MAX_RETRIES: u64 = 10;
-
+#[allow(clippy::too_many_arguments)]
 fn create_backup(
     conn: &rusqlite::Connection,
     node_id: NodeId,
     ts: i64,
     target_folder: String,
-    #[cfg(feature = "s3")] s3_config: Option<std::sync::Arc<crate::s3::S3Config>>,
+    #[cfg(feature = "backup")] archive_base: &crate::archive::ArchivePosition,
+    #[cfg(feature = "backup")] verify: bool,
+    #[cfg(feature = "backup")] next: crate::backup_diff::NextBackup,
+    #[cfg(feature = "backup")] targets: Vec<
+        std::sync::Arc<dyn crate::backup_target::BackupTarget>,
+    >,
+    #[cfg(feature = "backup")] progress: &Arc<crate::backup_progress::BackupTracker>,
     #[cfg(feature = "s3")] rt: &runtime::Handle,
 ) -> Result<(), Error> {
     // - build target db file name with node id and timestamp
-    // - vacuum into target file
-    // - connect to vacuumed db and reset metadata
-    // - if we have an s3 target, encrypt and push it
-
+    // - copy the database page by page into the target file, or vacuum into it
+    // - connect to the copy and reset metadata
+    // - remember the log archive position and the row counts of the source
+    // - move it into place, or only keep the changed pages for a differential
+    // - if configured, verify the backup in the background
+    // - encrypt and push it to all targets
+
+    #[cfg(feature = "backup")]
+    let file = if next == crate::backup_diff::NextBackup::Differential {
+        format!("backup_node_{node_id}_{ts}.diff")
+    } else {
+        format!("backup_node_{node_id}_{ts}.sqlite")
+    };
+    #[cfg(not(feature = "backup"))]
     let file = format!("backup_node_{node_id}_{ts}.sqlite");
     let path_full = format!("{target_folder}/{file}");
     info!("Creating database backup into {path_full}");
+    #[cfg(feature = "backup")]
+    progress.start(ProgressKind::Backup, &file);
 
-    // vacuum into a temp file and move it into place, so a crash mid-backup can never leave
-    // a partial file under the final backup name (restore would pick it up as valid)
+    // create the backup in a temp file and move it into place, so a crash mid-backup can never
+    // leave a partial file under the final backup name (restore would pick it up as valid)
     let path_temp = format!("{path_full}~");
-    if let Err(err) = conn.execute(&format!("VACUUM main INTO '{path_temp}'"), ()) {
-        let _ = std::fs::remove_file(&path_temp);
-        return Err(err.into());
-    }
-    if let Err(err) = std::fs::rename(&path_temp, &path_full) {
+    let res = (|| {
+        // the database has just been VACUUMed for all full backups
+        #[cfg(feature = "backup")]
+        crate::backup_diff::copy_pages(conn, &path_temp, |p| {
+            progress.pages(ProgressKind::Backup, p)
+        })?;
+        #[cfg(not(feature = "backup"))]
+        conn.execute(&format!("VACUUM main INTO '{path_temp}'"), ())?;
+
+        // connect to the backup and reset metadata
+        // make sure connection is dropped before starting encrypt + push
+        let conn_bkp = rusqlite::Connection::open(&path_temp)?;
+        persist_metadata(&conn_bkp, &StateMachineData::default());
+        #[cfg(feature = "backup")]
+        {
+            crate::archive::backup_set_base(&conn_bkp, archive_base)?;
+            crate::backup::backup_set_row_counts(conn, &conn_bkp)?;
+        }
+        Ok::<(), Error>(())
+    })();
+    if let Err(err) = res {
         let _ = std::fs::remove_file(&path_temp);
-        return Err(Error::Error(
-            format!("rename backup into place: {err}").into(),
-        ));
+        #[cfg(feature = "backup")]
+        {
+            crate::backup_diff::chain_reset(&target_folder);
+            progress.fail(ProgressKind::Backup, err.to_string());
+        }
+        return Err(err);
     }
 
-    // connect to the backup and reset metadata
-    // make sure connection is dropped before starting encrypt + push
-    {
-        let conn_bkp = rusqlite::Connection::open(&path_full)?;
-        persist_metadata(&conn_bkp, &StateMachineData::default());
+    #[cfg(feature = "backup")]
+    if next == crate::backup_diff::NextBackup::Differential {
+        if let Err(err) =
+            crate::backup_diff::create_differential(&target_folder, ts, &file, &path_temp)
+        {
+            let _ = std::fs::remove_file(&path_temp);
+            crate::backup_diff::chain_reset(&target_folder);
+            progress.fail(ProgressKind::Backup, err.to_string());
+            return Err(err);
+        }
+    } else {
+        if let Err(err) = rename_backup(&path_temp, &path_full) {
+            progress.fail(ProgressKind::Backup, err.to_string());
+            return Err(err);
+        }
+        if next == crate::backup_diff::NextBackup::Plain {
+            crate::backup_diff::chain_reset(&target_folder);
+        } else if let Err(err) =
+            crate::backup_diff::chain_start(&target_folder, node_id, ts, &file, &path_full)
+        {
+            // the full backup itself is fine, only the next one will be a full one again
+            error!("Error starting differential backup chain: {err}");
+            crate::backup_diff::chain_reset(&target_folder);
+        }
     }
+    #[cfg(not(feature = "backup"))]
+    rename_backup(&path_temp, &path_full)?;
 
     info!("Database backup finished");
+    #[cfg(feature = "backup")]
+    let size = std::fs::metadata(&path_full).map(|m| m.len()).unwrap_or_default();
+    #[cfg(feature = "backup")]
+    progress.copied(ProgressKind::Backup, size, targets.len());
+
+    #[cfg(feature = "backup")]
+    if verify {
+        let file = file.clone();
+        let target_folder = target_folder.clone();
+        rt.spawn(async move {
+            if let Err(err) = crate::backup::verify_local(&target_folder, &file).await {
+                error!("Error verifying backup {file}: {err}");
+            }
+        });
+    }
 
-    #[cfg(feature = "s3")]
-    if let Some(s3) = s3_config {
+    #[cfg(feature = "backup")]
+    for target in targets {
+        let file = file.clone();
+        let path_full = path_full.clone();
+        let target_folder = target_folder.clone();
+        let progress = progress.clone();
         rt.spawn(async move {
-            info!("Background task for database encryption and S3 backup task has been started");
-
-            // The backup was already acked; retry the upload so a failure is not just a log line.
-            let mut attempt = 0;
-            loop {
-                attempt += 1;
-                match s3.push(&path_full, &file).await {
-                    Ok(_) => {
-                        info!("Push backup to S3 has been finished");
-                        break;
-                    }
-                    Err(err) if attempt < S3_MAX_RETRIES => {
-                        error!(
-                            "Error pushing Backup to S3 (attempt {attempt}/{S3_MAX_RETRIES}): {err} - retrying"
-                        );
-                        time::sleep(Duration::from_secs(5 * attempt)).await;
-                    }
-                    Err(err) => {
-                        error!(
-                            "Error pushing Backup to S3 after {attempt} attempts: {err} - \
-                            the backup exists locally but not offsite"
-                        );
-                        break;
-                    }
-                }
+            let name = target.name();
+            info!(
+                "Background task for database encryption and backup push to {name} has been started"
+            );
+
+            let is_pushed =
+                crate::backup::push_backup(target.as_ref(), &path_full, &file, Some(&progress))
+                    .await;
+            let err = (!is_pushed).then(|| format!("Push to {name} failed"));
+            progress.pushed(&file, size, err);
+
+            if is_pushed
+                && verify
+                && let Err(err) =
+                    crate::backup::verify_target(target.as_ref(), &target_folder, &file).await
+            {
+                error!("Error verifying backup {file} on {name}: {err}");
             }
         });
     }
@@ -846,6 +1306,16 @@ fn create_backup(
     Ok(())
 }
 
+fn rename_backup(path_temp: &str, path_full: &str) -> Result<(), Error> {
+    if let Err(err) = std::fs::rename(path_temp, path_full) {
+        let _ = std::fs::remove_file(path_temp);
+        return Err(Error::Error(
+            format!("rename backup into place: {err}").into(),
+        ));
+    }
+    Ok(())
+}
+
 fn migrate(
     conn: &mut rusqlite::Connection,
     mut migrations: Vec<Migration>,
@@ -885,7 +1355,7 @@ fn migrate(
 }
 
 #[inline]
-fn create_migrations_table(conn: &rusqlite::Connection) -> Result<(), Error> {
+pub(crate) fn create_migrations_table(conn: &rusqlite::Connection) -> Result<(), Error> {
     conn.execute(
         r#"
     CREATE TABLE IF NOT EXISTS _migrations
@@ -1054,6 +1524,41 @@ mod tests {
         assert_eq!(ts, 42);
     }
 
+    #[test]
+    fn execute_many_runs_in_one_transaction() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE test (id INTEGER NOT NULL PRIMARY KEY)", ())
+            .unwrap();
+        let sql = "INSERT INTO test (id) VALUES ($1)";
+
+        let res = execute_many(
+            &mut conn,
+            sql,
+            vec![
+                vec![Param::from(1)],
+                vec![Param::from(2)],
+                vec![Param::from(3)],
+            ],
+        )
+        .unwrap();
+        assert_eq!(res, vec![1, 1, 1]);
+
+        // a single failing set must roll back all others
+        let res = execute_many(
+            &mut conn,
+            sql,
+            vec![vec![Param::from(4)], vec![Param::from(1)]],
+        );
+        assert!(res.is_err());
+        let res = execute_many(&mut conn, sql, vec![vec![Param::from(5)], vec![]]);
+        assert!(res.is_err());
+
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM test", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+
     #[test]
     fn migration_validation_gap_panics() {
         let conn = rusqlite::Connection::open_in_memory().unwrap();
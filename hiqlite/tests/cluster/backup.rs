@@ -1,17 +1,38 @@
-use crate::{TEST_DATA_DIR, log};
+use crate::start::BACKUP_TARGET_DIR;
+use crate::{Cache, TEST_DATA_DIR, log};
+use futures_util::StreamExt;
 use hiqlite::macros::params;
-use hiqlite::{Client, Error};
+use hiqlite::{BackupState, Client, Error};
 use std::time::Duration;
 use tokio::{fs, time};
 
 pub static BACKUP_PATH_FILE: &str = "tests/data_test/backup.sqlite";
+pub static BACKUP_CACHE_PATH_FILE: &str = "tests/data_test/backup.cache";
+
+const CACHE_KEY: &str = "cache backup key";
+const CACHE_VALUE: &str = "cache backup value";
 
 pub async fn test_backup(client_1: &Client) -> Result<(), Error> {
     log("Creating backup request via client_1");
-    client_1.backup().await?;
+    let mut rx = client_1.backup_with_progress().await?;
 
-    // these test backups are tiny and very quick -> async background task
-    time::sleep(Duration::from_millis(100)).await;
+    log("Wait for the backup to finish on node 1");
+    let progress = time::timeout(
+        Duration::from_secs(30),
+        rx.wait_for(|p| p.as_ref().is_some_and(|p| p.state.is_done())),
+    )
+    .await
+    .expect("backup to finish")
+    .unwrap()
+    .clone()
+    .unwrap();
+    assert_eq!(progress.state, BackupState::Finished, "{progress:?}");
+    assert!(progress.pages_total > 0);
+    assert_eq!(progress.pages_copied, progress.pages_total);
+    assert_eq!(progress.bytes_uploaded, progress.bytes_total);
+    let last_backup = client_1.backup_metrics()?.last_backup.unwrap();
+    assert_eq!(last_backup.name, progress.name);
+    assert!(last_backup.size > 0);
 
     log("Find backup DB");
 
@@ -21,6 +42,42 @@ pub async fn test_backup(client_1: &Client) -> Result<(), Error> {
     let leader = metrics.current_leader.unwrap();
     let path = find_backup_file(leader).await;
 
+    log("Verify the backup");
+    let name = path.rsplit_once('/').unwrap().1;
+    let verification = client_1.backup_verify(name).await?;
+    assert!(verification.is_ok(), "{verification:?}");
+    let listing = client_1.backup_list_local().await?;
+    let backup = listing.iter().find(|b| b.name == name).unwrap();
+    assert_eq!(backup.verification.as_ref(), Some(&verification));
+
+    log("Check the encrypted copy on the backup target");
+    let target = format!("file:{BACKUP_TARGET_DIR}");
+    assert_eq!(client_1.backup_targets()?, vec![target.clone()]);
+    let mut verified = false;
+    for _ in 0..50 {
+        let listing = client_1.backup_list_target(&target).await?;
+        if let Some(backup) = listing.iter().find(|b| b.name == name)
+            && let Some(verification) = &backup.verification
+        {
+            assert!(verification.is_ok(), "{verification:?}");
+            verified = true;
+            break;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(verified, "backup {name} has not been pushed to {target}");
+
+    // the copy is encrypted, but can be streamed in plain
+    let encrypted = fs::read(format!("{BACKUP_TARGET_DIR}/{name}")).await?;
+    let plain = fs::read(&path).await?;
+    assert_ne!(encrypted, plain);
+    let mut stream = client_1.backup_target_stream(&target, name.to_string())?;
+    let mut streamed = Vec::with_capacity(plain.len());
+    while let Some(chunk) = stream.next().await {
+        streamed.extend(chunk.map_err(|err| Error::Error(err.to_string().into()))?);
+    }
+    assert_eq!(streamed, plain);
+
     // copy the file into a 2nd location for later restore from file testing
     fs::copy(&path, BACKUP_PATH_FILE).await?;
 
@@ -68,14 +125,65 @@ pub async fn test_backup_restore_prerequisites(client: &Client) -> Result<(), Er
     Ok(())
 }
 
+pub async fn test_backup_cache(client_1: &Client) -> Result<(), Error> {
+    log("Put a value into the cache before the cache backup");
+    client_1
+        .put(Cache::Two, CACHE_KEY, &CACHE_VALUE.to_string(), None)
+        .await?;
+    // the local state machine may lag behind the leader a tiny bit
+    time::sleep(Duration::from_millis(100)).await;
+
+    log("Creating cache backup via client_1");
+    let name = client_1.backup_cache().await?;
+    assert!(name.starts_with("backup_cache_node_1_"), "{name}");
+    assert!(name.ends_with(".cache"), "{name}");
+
+    let listing = client_1.backup_list_local().await?;
+    let backup = listing.iter().find(|b| b.name == name).unwrap();
+    assert!(backup.verification.is_none());
+
+    log("Check the encrypted copy of the cache backup on the backup target");
+    let target = format!("file:{BACKUP_TARGET_DIR}");
+    let mut pushed = false;
+    for _ in 0..50 {
+        let listing = client_1.backup_list_target(&target).await?;
+        if listing.iter().any(|b| b.name == name) {
+            pushed = true;
+            break;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(
+        pushed,
+        "cache backup {name} has not been pushed to {target}"
+    );
+
+    // copy the file into a 2nd location for the restore during the next startup
+    let path = format!("{TEST_DATA_DIR}/node_1/state_machine/backups/{name}");
+    fs::copy(&path, BACKUP_CACHE_PATH_FILE).await?;
+
+    log("Delete the cache value, which must come back with the restore");
+    client_1.delete(Cache::Two, CACHE_KEY).await?;
+
+    Ok(())
+}
+
+pub async fn test_cache_is_restored(client: &Client) -> Result<(), Error> {
+    let value: Option<String> = client.get(Cache::Two, CACHE_KEY).await?;
+    assert_eq!(value.as_deref(), Some(CACHE_VALUE));
+    Ok(())
+}
+
 pub async fn find_backup_file(node_id: u64) -> String {
     let path_base = format!("{}/node_{}/state_machine/backups", TEST_DATA_DIR, node_id);
     let mut ls = fs::read_dir(&path_base).await.unwrap();
 
-    if let Some(file) = ls.next_entry().await.unwrap() {
+    while let Some(file) = ls.next_entry().await.unwrap() {
         let file_name = file.file_name();
         let name = file_name.to_str().unwrap();
-        return format!("{}/{}", path_base, name);
+        if name.starts_with("backup_node_") && name.ends_with(".sqlite") {
+            return format!("{}/{}", path_base, name);
+        }
     }
     panic!("Backup folder is empty when it should not be");
 }
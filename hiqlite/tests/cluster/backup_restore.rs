@@ -1,15 +1,26 @@
-use crate::backup::BACKUP_PATH_FILE;
+use crate::backup::{BACKUP_CACHE_PATH_FILE, BACKUP_PATH_FILE};
 use crate::execute_query::TestData;
 use crate::start::build_config;
 use crate::{Cache, backup, log};
 use hiqlite::macros::params;
-use hiqlite::{Client, Error, start_node_with_cache};
+use hiqlite::{BackupSource, Client, Error, RestoreState, RestoreTarget, start_node_with_cache};
 use std::env;
-use tokio::task;
+use std::time::Duration;
+use tokio::{task, time};
 
 pub async fn start_test_cluster_with_backup(
     from_fs: bool,
+    with_cache: bool,
 ) -> Result<(Client, Client, Client), Error> {
+    if with_cache {
+        unsafe {
+            env::set_var(
+                "HQL_BACKUP_RESTORE_CACHE",
+                format!("file:{}", BACKUP_CACHE_PATH_FILE),
+            );
+        }
+    }
+
     if from_fs {
         unsafe {
             env::set_var("HQL_BACKUP_SKIP_VALIDATION", "true");
@@ -39,6 +50,9 @@ pub async fn start_test_cluster_with_backup(
     unsafe {
         env::remove_var("HQL_BACKUP_RESTORE");
     }
+    unsafe {
+        env::remove_var("HQL_BACKUP_RESTORE_CACHE");
+    }
 
     Ok((client_1, client_2, client_3))
 }
@@ -68,3 +82,54 @@ pub async fn test_db_is_healthy_after_restore(client: &Client) -> Result<(), Err
 
     Ok(())
 }
+
+pub async fn test_online_restore(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Creating a backup for the online restore");
+    client_1.backup().await?;
+    time::sleep(Duration::from_millis(100)).await;
+
+    let leader = client_1.metrics_db().await?.current_leader.unwrap();
+    let path = backup::find_backup_file(leader).await;
+
+    log("Change current database as preparation for the online restore");
+    backup::test_backup_restore_prerequisites(client_1).await?;
+
+    // the restore must be forwarded to the leader
+    let mut follower = None;
+    for client in [client_1, client_2, client_3] {
+        if client.metrics_db().await?.id != leader {
+            follower = Some(client);
+            break;
+        }
+    }
+    let follower = follower.unwrap();
+
+    log("Point-in-time recovery is only possible during startup");
+    let res = follower
+        .restore_backup(BackupSource::PointInTime(RestoreTarget::Latest))
+        .await;
+    assert!(res.is_err());
+
+    log(format!("Restoring {path} online via a follower"));
+    let progress = follower.restore_backup(BackupSource::File(path)).await?;
+    assert_eq!(progress.state, RestoreState::Finished);
+    assert!(progress.bytes_total > 0);
+    assert_eq!(progress.bytes_replicated, progress.bytes_total);
+
+    let latest = client_1.restore_progress().await?.unwrap();
+    assert_eq!(latest.id, progress.id);
+
+    // followers apply the restore in the background
+    time::sleep(Duration::from_millis(500)).await;
+
+    log("Make sure databases are correctly restored online");
+    test_db_is_healthy_after_restore(client_1).await?;
+    test_db_is_healthy_after_restore(client_2).await?;
+    test_db_is_healthy_after_restore(client_3).await?;
+
+    Ok(())
+}
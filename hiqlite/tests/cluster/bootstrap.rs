@@ -0,0 +1,157 @@
+use crate::{Cache, log, start};
+use hiqlite::macros::params;
+use hiqlite::{BootstrapDb, Client, Node, NodeConfig, start_node_with_cache};
+use std::time::Duration;
+use tokio::{fs, task, time};
+
+const TEST_DATA_DIR_BOOTSTRAP: &str = "tests/data_test_bootstrap";
+const ROWS: i64 = 2000;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bootstrap_cluster_from_sqlite_file() {
+    let _ = fs::remove_dir_all(TEST_DATA_DIR_BOOTSTRAP).await;
+    fs::create_dir_all(format!("{TEST_DATA_DIR_BOOTSTRAP}/migrations"))
+        .await
+        .unwrap();
+
+    log("Creating a standalone SQLite database to bootstrap from");
+    let path_src = format!("{TEST_DATA_DIR_BOOTSTRAP}/standalone.db");
+    let sql_init = "CREATE TABLE standalone (id INTEGER PRIMARY KEY, data TEXT NOT NULL);";
+    {
+        let conn = rusqlite::Connection::open(&path_src).unwrap();
+        conn.execute_batch(sql_init).unwrap();
+        let mut stmt = conn
+            .prepare("INSERT INTO standalone (id, data) VALUES ($1, $2)")
+            .unwrap();
+        for id in 1..=ROWS {
+            stmt.execute((id, format!("row {id}"))).unwrap();
+        }
+    }
+    fs::write(
+        format!("{TEST_DATA_DIR_BOOTSTRAP}/migrations/1_init.sql"),
+        sql_init,
+    )
+    .await
+    .unwrap();
+
+    log("Simulate a crash of node 1 during a previous bootstrap");
+    let dir_node_1 = format!("{TEST_DATA_DIR_BOOTSTRAP}/node_1");
+    fs::create_dir_all(format!("{dir_node_1}/state_machine/db"))
+        .await
+        .unwrap();
+    fs::write(
+        format!("{dir_node_1}/state_machine/db/hiqlite.db"),
+        b"incomplete",
+    )
+    .await
+    .unwrap();
+    fs::write(
+        format!("{dir_node_1}/bootstrap_pending"),
+        path_src.as_bytes(),
+    )
+    .await
+    .unwrap();
+
+    let nodes = bootstrap_nodes();
+    let handle_client_1 = task::spawn(start_node_with_cache::<Cache>(
+        build_bootstrap_config(1, nodes.clone(), &path_src).await,
+    ));
+    let handle_client_2 = task::spawn(start_node_with_cache::<Cache>(
+        build_bootstrap_config(2, nodes.clone(), &path_src).await,
+    ));
+    let handle_client_3 = task::spawn(start_node_with_cache::<Cache>(
+        build_bootstrap_config(3, nodes, &path_src).await,
+    ));
+
+    let client_1 = handle_client_1.await.unwrap().unwrap();
+    let client_2 = handle_client_2.await.unwrap().unwrap();
+    let client_3 = handle_client_3.await.unwrap().unwrap();
+    start::wait_for_healthy_cluster(&client_1, &client_2, &client_3)
+        .await
+        .unwrap();
+
+    log("Make sure all nodes have received the bootstrapped database");
+    for client in [&client_1, &client_2, &client_3] {
+        assert_bootstrapped(client).await;
+    }
+    assert!(
+        !fs::try_exists(format!("{dir_node_1}/bootstrap_pending"))
+            .await
+            .unwrap()
+    );
+
+    log("Make sure the bootstrapped cluster accepts writes");
+    client_1
+        .execute(
+            "INSERT INTO standalone (id, data) VALUES ($1, $2)",
+            params!(ROWS + 1, "after bootstrap"),
+        )
+        .await
+        .unwrap();
+    time::sleep(Duration::from_millis(100)).await;
+    for client in [&client_1, &client_2, &client_3] {
+        let count: i64 = client
+            .query_raw_one("SELECT COUNT(*) AS count FROM standalone", params!())
+            .await
+            .unwrap()
+            .get("count");
+        assert_eq!(count, ROWS + 1);
+    }
+
+    for client in [client_1, client_2, client_3] {
+        if let Err(err) = client.shutdown().await {
+            log(format!("bootstrap test shutdown error: {err}"));
+        }
+    }
+    let _ = fs::remove_dir_all(TEST_DATA_DIR_BOOTSTRAP).await;
+}
+
+fn bootstrap_nodes() -> Vec<Node> {
+    vec![
+        Node {
+            id: 1,
+            addr_raft: "127.0.0.1:38001".to_string(),
+            addr_api: "127.0.0.1:37001".to_string(),
+        },
+        Node {
+            id: 2,
+            addr_raft: "127.0.0.1:38002".to_string(),
+            addr_api: "127.0.0.1:37002".to_string(),
+        },
+        Node {
+            id: 3,
+            addr_raft: "127.0.0.1:38003".to_string(),
+            addr_api: "127.0.0.1:37003".to_string(),
+        },
+    ]
+}
+
+async fn build_bootstrap_config(node_id: u64, nodes: Vec<Node>, path_src: &str) -> NodeConfig {
+    let mut config = start::build_config_with_nodes(node_id, nodes, TEST_DATA_DIR_BOOTSTRAP).await;
+    // only a pristine node 1 is using it, the others must ignore it
+    config.bootstrap_db = Some(
+        BootstrapDb::new(path_src)
+            .with_migrations_dir(&format!("{TEST_DATA_DIR_BOOTSTRAP}/migrations"))
+            .unwrap(),
+    );
+    config
+}
+
+async fn assert_bootstrapped(client: &Client) {
+    let mut row = client
+        .query_raw_one(
+            "SELECT COUNT(*) AS count, MAX(data) AS max FROM standalone",
+            params!(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<i64>("count"), ROWS);
+    assert_eq!(row.get::<String>("max"), "row 999");
+
+    let mut row = client
+        .query_raw_one("SELECT id, name FROM _migrations", params!())
+        .await
+        .unwrap();
+    assert_eq!(row.get::<i64>("id"), 1);
+    assert_eq!(row.get::<String>("name"), "init");
+}
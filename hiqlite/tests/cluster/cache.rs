@@ -1,5 +1,6 @@
-use crate::{log, Cache};
-use hiqlite::{Client, Error};
+use crate::{Cache, log};
+use futures_util::{Stream, StreamExt};
+use hiqlite::{CacheBatch, CachePage, Client, Error, WatchEvent, WatchKey};
 use std::string::ToString;
 use std::time::Duration;
 use tokio::time;
@@ -177,7 +178,9 @@ pub async fn test_cache(
     assert!(v.is_none());
 
     log("Test replace");
-    let v: Option<String> = client_1.replace(Cache::One, KEY, &VALUE.to_string(), None).await?;
+    let v: Option<String> = client_1
+        .replace(Cache::One, KEY, &VALUE.to_string(), None)
+        .await?;
     assert!(v.is_none());
     let v: Option<String> = client_1
         .replace(Cache::One, KEY, &VALUE_2.to_string(), None)
@@ -194,7 +197,9 @@ pub async fn test_cache(
     client_1
         .put(Cache::One, KEY, &VALUE.to_string(), Some(1))
         .await?;
-    let v: Option<String> = client_1.replace(Cache::One, KEY, &VALUE_2.to_string(), None).await?;
+    let v: Option<String> = client_1
+        .replace(Cache::One, KEY, &VALUE_2.to_string(), None)
+        .await?;
     assert_eq!(v.as_deref(), Some(VALUE));
     time::sleep(Duration::from_millis(1500)).await;
     let v: String = client_1.get(Cache::One, KEY).await?.unwrap();
@@ -205,7 +210,9 @@ pub async fn test_cache(
     client_1
         .put(Cache::One, KEY, &VALUE.to_string(), None)
         .await?;
-    let v: Option<String> = client_1.replace(Cache::One, KEY, &VALUE_2.to_string(), Some(1)).await?;
+    let v: Option<String> = client_1
+        .replace(Cache::One, KEY, &VALUE_2.to_string(), Some(1))
+        .await?;
     assert_eq!(v.as_deref(), Some(VALUE));
     time::sleep(Duration::from_millis(1500)).await;
     let v: Option<String> = client_1.get(Cache::One, KEY).await?;
@@ -232,6 +239,344 @@ pub async fn test_cache(
     // restore the value the later health checks expect in `Cache::One`
     insert_test_value_cache(client_1).await?;
 
+    test_multi_key(client_1, client_2, client_3).await?;
+    test_prefix(client_1, client_2, client_3).await?;
+    test_versioned(client_1, client_2, client_3).await?;
+    test_watch(client_1, client_2, client_3).await?;
+    test_eviction(client_1, client_2, client_3).await?;
+
+    Ok(())
+}
+
+async fn test_multi_key(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Test mput / mget / mdelete");
+    let keys = ["multi_1", "multi_2", "multi_3"];
+    client_2
+        .mput(
+            Cache::Two,
+            [
+                (keys[0], "one".to_string(), None),
+                (keys[1], "two".to_string(), Some(1)),
+                (keys[2], "three".to_string(), None),
+            ],
+        )
+        .await?;
+    time::sleep(Duration::from_millis(100)).await;
+    for client in [client_1, client_2, client_3] {
+        let values: Vec<Option<String>> = client
+            .mget(Cache::Two, [keys[0], keys[1], keys[2], "missing"])
+            .await?;
+        assert_eq!(
+            values,
+            vec![
+                Some("one".to_string()),
+                Some("two".to_string()),
+                Some("three".to_string()),
+                None,
+            ]
+        );
+    }
+
+    // the TTL applies to each key on its own
+    time::sleep(Duration::from_millis(1500)).await;
+    let values: Vec<Option<String>> = client_3.mget(Cache::Two, keys).await?;
+    assert_eq!(
+        values,
+        vec![Some("one".to_string()), None, Some("three".to_string())]
+    );
+
+    client_3.mdelete(Cache::Two, [keys[0], keys[2]]).await?;
+    time::sleep(Duration::from_millis(100)).await;
+    for client in [client_1, client_2, client_3] {
+        let values: Vec<Option<String>> = client.mget(Cache::Two, keys).await?;
+        assert!(values.iter().all(Option::is_none));
+    }
+
+    log("Test mixed cache_batch");
+    client_1
+        .put(Cache::Two, keys[0], &"old".to_string(), None)
+        .await?;
+    let batch = CacheBatch::new()
+        .put(keys[1], &"new".to_string(), None)
+        .delete(keys[0])
+        .put(keys[2], &"first".to_string(), None)
+        .put(keys[2], &"second".to_string(), None);
+    assert_eq!(batch.len(), 4);
+    client_1.cache_batch(Cache::Two, batch).await?;
+    time::sleep(Duration::from_millis(100)).await;
+    for client in [client_1, client_2, client_3] {
+        let values: Vec<Option<String>> = client.mget(Cache::Two, keys).await?;
+        assert_eq!(
+            values,
+            vec![None, Some("new".to_string()), Some("second".to_string())]
+        );
+    }
+    client_1.mdelete(Cache::Two, keys).await?;
+
+    Ok(())
+}
+
+async fn test_prefix(client_1: &Client, client_2: &Client, client_3: &Client) -> Result<(), Error> {
+    log("Test scan_prefix / keys / delete_prefix");
+    client_1
+        .mput(
+            Cache::Two,
+            [
+                ("session:user_1:a", 1i64, None),
+                ("session:user_1:b", 2, None),
+                ("session:user_1:c", 3, None),
+                ("session:user_2:a", 4, None),
+            ],
+        )
+        .await?;
+    time::sleep(Duration::from_millis(100)).await;
+
+    for client in [client_1, client_2, client_3] {
+        let keys = client.keys(Cache::Two, "session:user_1:").await?;
+        assert_eq!(
+            keys,
+            vec!["session:user_1:a", "session:user_1:b", "session:user_1:c"]
+        );
+
+        let mut cursor = None;
+        let mut values = Vec::new();
+        loop {
+            let page: CachePage<i64> = client
+                .scan_prefix(Cache::Two, "session:user_1:", 2, cursor)
+                .await?;
+            assert!(page.entries.len() <= 2);
+            values.extend(page.entries.into_iter().map(|(_, v)| v));
+            if page.cursor.is_none() {
+                break;
+            }
+            cursor = page.cursor;
+        }
+        assert_eq!(values, vec![1, 2, 3]);
+
+        let res = client
+            .scan_prefix::<_, _, i64>(Cache::Two, "session:user_1:", 0, None)
+            .await;
+        assert!(matches!(res, Err(Error::BadRequest(_))));
+    }
+
+    // invalidate a whole user at once
+    let deleted = client_2
+        .delete_prefix(Cache::Two, "session:user_1:")
+        .await?;
+    assert_eq!(deleted, 3);
+    time::sleep(Duration::from_millis(100)).await;
+    for client in [client_1, client_2, client_3] {
+        let keys = client.keys(Cache::Two, "session:").await?;
+        assert_eq!(keys, vec!["session:user_2:a"]);
+    }
+    client_1.delete_prefix(Cache::Two, "session:").await?;
+
+    Ok(())
+}
+
+async fn test_versioned(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Test versioned cache entries with compare-and-swap");
+    let key = "versioned";
+    let v1 = client_1
+        .put_if_absent(Cache::Two, key, &0i64, None)
+        .await?
+        .expect("key to not exist yet");
+    assert!(
+        client_2
+            .put_if_absent(Cache::Two, key, &1i64, None)
+            .await?
+            .is_none()
+    );
+    time::sleep(Duration::from_millis(100)).await;
+    for client in [client_1, client_2, client_3] {
+        let res: Option<(i64, u64)> = client.get_versioned(Cache::Two, key).await?;
+        assert_eq!(res, Some((0, v1)));
+    }
+
+    // only a single one of concurrent updates based on the same version can win
+    let (r1, r2, r3) = tokio::join!(
+        client_1.put_if_version(Cache::Two, key, &1i64, v1, None),
+        client_2.put_if_version(Cache::Two, key, &2i64, v1, None),
+        client_3.put_if_version(Cache::Two, key, &3i64, v1, None),
+    );
+    let winners = [r1?, r2?, r3?].into_iter().flatten().collect::<Vec<u64>>();
+    assert_eq!(winners.len(), 1);
+    let v2 = winners[0];
+    assert!(v2 > v1);
+
+    // unconditional writes bump the version as well
+    client_3.put(Cache::Two, key, &10i64, None).await?;
+    time::sleep(Duration::from_millis(100)).await;
+    let (value, v3): (i64, u64) = client_2.get_versioned(Cache::Two, key).await?.unwrap();
+    assert_eq!(value, 10);
+    assert!(v3 > v2);
+
+    assert!(!client_1.delete_if_version(Cache::Two, key, v2).await?);
+    assert!(client_1.delete_if_version(Cache::Two, key, v3).await?);
+    time::sleep(Duration::from_millis(100)).await;
+    for client in [client_1, client_2, client_3] {
+        let res: Option<(i64, u64)> = client.get_versioned(Cache::Two, key).await?;
+        assert!(res.is_none());
+    }
+
+    Ok(())
+}
+
+async fn test_watch(client_1: &Client, client_2: &Client, client_3: &Client) -> Result<(), Error> {
+    log("Test key and prefix watches on all nodes");
+    // a single key on one node, a prefix on the others
+    let mut watch_1 = client_1.watch_key(Cache::Two, "watch:a").await?.boxed();
+    let mut watch_2 = client_2.watch(Cache::Two, "watch:").await?.boxed();
+    let mut watch_3 = client_3.watch(Cache::Two, "watch:").await?.boxed();
+
+    let v1 = client_1
+        .put_if_absent(Cache::Two, "watch:a", &1i64, None)
+        .await?
+        .unwrap();
+    client_2.put(Cache::Two, "not_watched", &1i64, None).await?;
+    client_3.put(Cache::Two, "watch:b", &2i64, Some(1)).await?;
+    client_1.delete(Cache::Two, "watch:a").await?;
+
+    for watch in [&mut watch_1, &mut watch_2, &mut watch_3] {
+        let event = next_watch_event(watch).await?;
+        assert_eq!(event.key(), Some("watch:a"));
+        assert_eq!(event.log_index(), v1);
+        assert_eq!(event.value::<i64>()?, Some(1));
+    }
+
+    for watch in [&mut watch_2, &mut watch_3] {
+        let event = next_watch_event(watch).await?;
+        assert_eq!(event.key(), Some("watch:b"));
+        assert_eq!(event.value::<i64>()?, Some(2));
+    }
+
+    let mut log_index = 0;
+    for watch in [&mut watch_1, &mut watch_2, &mut watch_3] {
+        match next_watch_event(watch).await? {
+            WatchEvent::Delete {
+                key,
+                log_index: idx,
+            } => {
+                assert_eq!(key, "watch:a");
+                assert!(idx > v1);
+                log_index = idx;
+            }
+            evt => panic!("unexpected watch event: {evt:?}"),
+        }
+    }
+
+    // expiry happens on each node on its own
+    for watch in [&mut watch_2, &mut watch_3] {
+        match next_watch_event(watch).await? {
+            WatchEvent::Expire {
+                key,
+                log_index: idx,
+            } => {
+                assert_eq!(key, "watch:b");
+                assert!(idx >= log_index);
+            }
+            evt => panic!("unexpected watch event: {evt:?}"),
+        }
+    }
+
+    log("Test that key watches ignore keys with the same prefix");
+    client_2.put(Cache::Two, "watch:ab", &3i64, None).await?;
+    client_2.put(Cache::Two, "watch:a", &4i64, None).await?;
+    for watch in [&mut watch_2, &mut watch_3] {
+        assert_eq!(next_watch_event(watch).await?.key(), Some("watch:ab"));
+        assert_eq!(next_watch_event(watch).await?.key(), Some("watch:a"));
+    }
+    let event = next_watch_event(&mut watch_1).await?;
+    assert_eq!(event.key(), Some("watch:a"));
+    assert_eq!(event.value::<i64>()?, Some(4));
+
+    log("Test resuming a watch from a log index");
+    drop(watch_1);
+    let mut watch_1 = client_1
+        .watch_from(Cache::Two, WatchKey::Exact("watch:a".to_string()), v1)
+        .await?;
+    let replayed = [
+        next_watch_event(&mut watch_1).await?,
+        next_watch_event(&mut watch_1).await?,
+        next_watch_event(&mut watch_1).await?,
+    ];
+    assert_eq!(replayed[0].log_index(), v1);
+    assert_eq!(replayed[1].log_index(), log_index);
+    assert_eq!(replayed[2].value::<i64>()?, Some(4));
+    // nothing has been recorded before the first watch
+    assert!(
+        client_1
+            .watch_from(Cache::Two, WatchKey::Prefix(String::new()), 1)
+            .await
+            .is_err()
+    );
+
+    client_2.delete(Cache::Two, "watch:a").await?;
+    client_2.delete(Cache::Two, "watch:ab").await?;
+
+    Ok(())
+}
+
+pub async fn next_watch_event<S>(watch: &mut S) -> Result<WatchEvent, Error>
+where
+    S: Stream<Item = Result<WatchEvent, Error>> + Unpin,
+{
+    time::timeout(Duration::from_secs(10), watch.next())
+        .await
+        .expect("timeout waiting for a watch event")
+        .expect("watch to never end")
+}
+
+async fn test_eviction(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Test cache eviction: `Cache::Three` is limited to 10 entries");
+    for i in 0..20 {
+        client_1
+            .put(Cache::Three, format!("evict_{i}"), &i, None)
+            .await?;
+    }
+
+    // evictions are replicated asynchronously after the leader noticed the exceeded limit
+    let mut snapshots = Vec::with_capacity(3);
+    for _ in 0..50 {
+        time::sleep(Duration::from_millis(100)).await;
+        snapshots.clear();
+        for client in [client_1, client_2, client_3] {
+            snapshots.push(client.get_snapshot::<_, i32>(Cache::Three).await?);
+        }
+        if snapshots.iter().all(|s| s.len() <= 10) && snapshots[0] == snapshots[1] {
+            break;
+        }
+    }
+    for snapshot in &snapshots {
+        assert!(snapshot.len() <= 10);
+        // the least recently used entries are evicted first
+        assert_eq!(snapshot.get("evict_19"), Some(&19));
+        assert!(!snapshot.contains_key("evict_0"));
+    }
+    assert_eq!(snapshots[0], snapshots[1]);
+    assert_eq!(snapshots[0], snapshots[2]);
+
+    for client in [client_1, client_2, client_3] {
+        let stats = client.cache_stats().await?;
+        let stats = stats.iter().find(|s| s.name == "Three").unwrap();
+        assert_eq!(stats.max_entries, Some(10));
+        assert_eq!(stats.entries, snapshots[0].len() as u64);
+        assert_eq!(stats.evictions, 20 - stats.entries);
+    }
+    client_1.clear_cache(Cache::Three).await?;
+
     Ok(())
 }
 
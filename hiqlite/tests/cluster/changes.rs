@@ -0,0 +1,169 @@
+use crate::log;
+use chrono::Utc;
+use futures_util::{Stream, StreamExt};
+use hiqlite::macros::params;
+use hiqlite::{ChangeEvent, ChangeOp, Client, Error};
+use std::time::Duration;
+use tokio::time;
+
+pub async fn test_subscribe_changes(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Subscribe to row changes on all nodes");
+    let mut changes_1 = client_1.subscribe_changes(vec!["test_2"]).await?;
+    let mut changes_2 = client_2.subscribe_changes(vec!["test_2"]).await?;
+    // an empty list subscribes to all tables
+    let mut changes_3 = client_3.subscribe_changes(Vec::<&str>::new()).await?;
+
+    let now = Utc::now().timestamp();
+    let (_, log_id) = client_1
+        .execute_with_log_id(
+            "INSERT INTO test_2 (id, ts, description) VALUES ($1, $2, $3)",
+            params!(1000, now, "change 1"),
+        )
+        .await?;
+
+    for changes in [&mut changes_1, &mut changes_2, &mut changes_3] {
+        match next_event(changes).await? {
+            ChangeEvent::Rows { log_index, changes } => {
+                assert_eq!(log_index, log_id.index);
+                assert_eq!(changes.len(), 1);
+                assert_eq!(changes[0].table, "test_2");
+                assert_eq!(changes[0].op, ChangeOp::Insert);
+                assert_eq!(changes[0].rowid, 1000);
+                let mut values = changes[0].values.clone().unwrap();
+                assert_eq!(values.get::<String>("description"), "change 1");
+            }
+            evt => panic!("unexpected change event: {evt:?}"),
+        }
+    }
+
+    log("All rows of a transaction must be published as one event");
+    let (_, log_id) = client_2
+        .txn_with_log_id([
+            (
+                "UPDATE test_2 SET description = $1 WHERE id = $2",
+                params!("change 2", 1000),
+            ),
+            (
+                "INSERT INTO test_2 (id, ts, description) VALUES ($1, $2, $3)",
+                params!(1001, now, "change 3"),
+            ),
+            ("DELETE FROM test_2 WHERE id = $1", params!(1001)),
+        ])
+        .await?;
+
+    for changes in [&mut changes_1, &mut changes_2, &mut changes_3] {
+        match next_event(changes).await? {
+            ChangeEvent::Rows { log_index, changes } => {
+                assert_eq!(log_index, log_id.index);
+                let ops = changes.iter().map(|c| (c.op, c.rowid)).collect::<Vec<_>>();
+                assert_eq!(
+                    ops,
+                    vec![
+                        (ChangeOp::Update, 1000),
+                        (ChangeOp::Insert, 1001),
+                        (ChangeOp::Delete, 1001)
+                    ]
+                );
+                let mut values = changes[0].values.clone().unwrap();
+                assert_eq!(values.get::<String>("description"), "change 2");
+                // the row does not exist anymore at the end of the entry
+                assert!(changes[1].values.is_none());
+                assert!(changes[2].values.is_none());
+            }
+            evt => panic!("unexpected change event: {evt:?}"),
+        }
+    }
+
+    log("Rolled back transactions and other tables must not show up");
+    let res = client_3
+        .txn([
+            (
+                "UPDATE test_2 SET description = $1 WHERE id = $2",
+                params!("rolled back", 1000),
+            ),
+            (
+                "INSERT INTO test_2 (id, ts, description) VALUES ($1, $2, $3)",
+                params!(1000, now, "unique key constraint"),
+            ),
+        ])
+        .await;
+    assert!(res.is_err());
+
+    // `test` is only observed by the subscriber for all tables
+    let (_, log_id_other) = client_1
+        .txn_with_log_id([
+            (
+                "INSERT INTO test (id, ts, description) VALUES ($1, $2, $3)",
+                params!(1000, now, "other table"),
+            ),
+            ("DELETE FROM test WHERE id = $1", params!(1000)),
+        ])
+        .await?;
+    match next_event(&mut changes_3).await? {
+        ChangeEvent::Rows { log_index, changes } => {
+            assert_eq!(log_index, log_id_other.index);
+            assert_eq!(changes.len(), 2);
+            assert!(changes.iter().all(|c| c.table == "test"));
+        }
+        evt => panic!("unexpected change event: {evt:?}"),
+    }
+
+    let (_, log_id) = client_3
+        .execute_with_log_id("DELETE FROM test_2 WHERE id = $1", params!(1000))
+        .await?;
+
+    for changes in [&mut changes_1, &mut changes_2, &mut changes_3] {
+        match next_event(changes).await? {
+            ChangeEvent::Rows { log_index, changes } => {
+                assert_eq!(log_index, log_id.index);
+                assert_eq!(changes.len(), 1);
+                assert_eq!(changes[0].op, ChangeOp::Delete);
+                assert_eq!(changes[0].rowid, 1000);
+                assert!(changes[0].values.is_none());
+            }
+            evt => panic!("unexpected change event: {evt:?}"),
+        }
+    }
+
+    log("Change subscriptions must resume from a log index");
+    drop(changes_1);
+    let (_, log_id_resume) = client_2
+        .execute_with_log_id(
+            "INSERT INTO test_2 (id, ts, description) VALUES ($1, $2, $3)",
+            params!(1002, now, "change 4"),
+        )
+        .await?;
+    let mut changes_1 = client_1
+        .subscribe_changes_from(["test_2"], log_id.index)
+        .await?;
+    for index in [log_id.index, log_id_resume.index] {
+        assert_eq!(next_event(&mut changes_1).await?.log_index(), index);
+    }
+    // nothing has been captured before the first subscription
+    assert!(
+        client_1
+            .subscribe_changes_from(["test_2"], 1)
+            .await
+            .is_err()
+    );
+
+    client_1
+        .execute("DELETE FROM test_2 WHERE id = $1", params!(1002))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn next_event<S>(changes: &mut S) -> Result<ChangeEvent, Error>
+where
+    S: Stream<Item = Result<ChangeEvent, Error>> + Unpin,
+{
+    time::timeout(Duration::from_secs(10), changes.next())
+        .await
+        .expect("timeout waiting for a change event")
+        .expect("change subscription to never end")
+}
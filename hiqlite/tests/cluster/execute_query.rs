@@ -1,7 +1,8 @@
 use crate::log;
 use chrono::Utc;
+use futures_util::{StreamExt, TryStreamExt};
 use hiqlite::macros::params;
-use hiqlite::{Client, Error};
+use hiqlite::{Client, Error, IdempotencyKey, ReadConsistency, Staleness};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::time;
@@ -217,5 +218,516 @@ pub async fn test_execute_query(
     assert_eq!(row.ts, data.ts);
     assert_eq!(row.description, data.description);
 
+    log("Read your own writes on another node with 'query_as_after()'");
+    let (rows_affected, log_id) = client_2
+        .execute_with_log_id(
+            "INSERT INTO test VALUES ($1, $2, $3)",
+            params!(9, Utc::now().timestamp(), "Read your writes"),
+        )
+        .await?;
+    assert_eq!(rows_affected, 1);
+    // no sleep on purpose -> client 3 must wait for its local replica
+    let res: Vec<TestData> = client_3
+        .query_as_after(&log_id, "SELECT * FROM test WHERE id = $1", params!(9))
+        .await?;
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].description.as_deref(), Some("Read your writes"));
+
+    let (res, log_id) = client_3
+        .txn_with_log_id([("DELETE FROM test WHERE id = $1", params!(9))])
+        .await?;
+    assert_eq!(*res[0].as_ref().unwrap(), 1);
+    let res: Vec<TestData> = client_1
+        .query_map_after(&log_id, "SELECT * FROM test WHERE id = $1", params!(9))
+        .await?;
+    assert!(res.is_empty());
+
+    log("Query with all read consistency levels from all clients");
+    for client in [client_1, client_2, client_3] {
+        for consistency in [
+            ReadConsistency::Local,
+            ReadConsistency::BoundedStaleness(Staleness::Time(Duration::from_secs(5))),
+            ReadConsistency::BoundedStaleness(Staleness::LogLag(0)),
+            ReadConsistency::Leader,
+            ReadConsistency::Linearizable,
+        ] {
+            let res: Vec<TestData> = client
+                .query_map_with(consistency, "SELECT * FROM test WHERE id = $1", params!(3))
+                .await?;
+            assert_eq!(res.len(), 1, "{consistency:?}");
+            assert_eq!(res[0].id, 3);
+
+            let rows = client
+                .query_raw_with(consistency, "SELECT * FROM test WHERE id = $1", params!(3))
+                .await?;
+            assert_eq!(rows.len(), 1, "{consistency:?}");
+
+            let res: TestData = client
+                .query_map_one_with(consistency, "SELECT * FROM test WHERE id = $1", params!(3))
+                .await?;
+            assert_eq!(res.id, 3);
+            let res: Option<TestData> = client
+                .query_map_optional_with(
+                    consistency,
+                    "SELECT * FROM test WHERE id = $1",
+                    params!(9),
+                )
+                .await?;
+            assert!(res.is_none(), "{consistency:?}");
+
+            let res: Vec<TestData> = client
+                .query_as_with(consistency, "SELECT * FROM test WHERE id = $1", params!(3))
+                .await?;
+            assert_eq!(res.len(), 1, "{consistency:?}");
+            let res: TestData = client
+                .query_as_one_with(consistency, "SELECT * FROM test WHERE id = $1", params!(3))
+                .await?;
+            assert_eq!(res.id, 3);
+            let res: Option<TestData> = client
+                .query_as_optional_with(consistency, "SELECT * FROM test WHERE id = $1", params!(9))
+                .await?;
+            assert!(res.is_none(), "{consistency:?}");
+
+            let mut row = client
+                .query_raw_one_with(consistency, "SELECT * FROM test WHERE id = $1", params!(3))
+                .await?;
+            assert_eq!(row.get::<i64>("id"), 3);
+        }
+    }
+
+    test_idempotent_writes(client_1, client_2, client_3).await?;
+    test_execute_many(client_1, client_2, client_3).await?;
+    test_query_stream(client_1, client_2).await?;
+    test_custom_functions(client_1, client_2, client_3).await?;
+    test_stamped_functions(client_1, client_2, client_3).await?;
+    test_query_timeouts(client_1, client_2, client_3).await?;
+    test_write_budget(client_1, client_2, client_3).await?;
+
+    Ok(())
+}
+
+async fn test_idempotent_writes(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Retried idempotent writes must only be applied once");
+    let key = IdempotencyKey::new();
+    for client in [client_2, client_2, client_1] {
+        // without the key, the retries would fail with a unique key constraint error
+        let rows_affected = client
+            .execute_idempotent(
+                key.clone(),
+                "INSERT INTO test VALUES ($1, $2, $3)",
+                params!(20, 0, "Idempotent"),
+            )
+            .await?;
+        assert_eq!(rows_affected, 1);
+    }
+
+    let key = IdempotencyKey::from_key("test-idempotent-txn");
+    for client in [client_3, client_3, client_1, client_2] {
+        let res = client
+            .txn_idempotent(
+                key.clone(),
+                [("UPDATE test SET ts = ts + 1 WHERE id = $1", params!(20))],
+            )
+            .await?;
+        assert_eq!(*res[0].as_ref().unwrap(), 1);
+    }
+
+    let res: Vec<TestData> = client_1
+        .query_map_with(
+            ReadConsistency::Linearizable,
+            "SELECT * FROM test WHERE id = $1",
+            params!(20),
+        )
+        .await?;
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].ts, 1);
+
+    // a new key is a new write
+    let rows_affected = client_2
+        .execute_idempotent(
+            IdempotencyKey::new(),
+            "DELETE FROM test WHERE id = $1",
+            params!(20),
+        )
+        .await?;
+    assert_eq!(rows_affected, 1);
+
+    log("Idempotent writes must fail if their commit fails");
+    client_1
+        .execute(
+            "CREATE TABLE idem_parent (id INTEGER PRIMARY KEY)",
+            params!(),
+        )
+        .await?;
+    client_1
+        .execute(
+            r#"CREATE TABLE idem_child (
+                id INTEGER PRIMARY KEY,
+                parent_id INTEGER NOT NULL
+                    REFERENCES idem_parent (id) DEFERRABLE INITIALLY DEFERRED
+            )"#,
+            params!(),
+        )
+        .await?;
+    let key = IdempotencyKey::new();
+    for client in [client_1, client_3] {
+        // the deferred foreign key is only checked on commit
+        let res = client
+            .execute_idempotent(
+                key.clone(),
+                "INSERT INTO idem_child VALUES ($1, $2)",
+                params!(1, 1),
+            )
+            .await;
+        assert!(res.is_err(), "{res:?}");
+    }
+    let rows = client_2
+        .query_raw_with(
+            ReadConsistency::Linearizable,
+            "SELECT * FROM idem_child",
+            params!(),
+        )
+        .await?;
+    assert!(rows.is_empty());
+
+    Ok(())
+}
+
+async fn test_execute_many(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Bulk insert with 'execute_many()' from all clients");
+    // keep each log entry below the tiny `wal_size` of the test cluster
+    let sql = "INSERT INTO test VALUES ($1, $2, $3)";
+    for (i, client) in [client_1, client_2, client_3].into_iter().enumerate() {
+        let start = 30_000 + i as i64 * 100;
+        let params = (start..start + 100)
+            .map(|id| params!(id, id, format!("Bulk {id}")))
+            .collect::<Vec<_>>();
+        let (res, log_id) = client.execute_many_with_log_id(sql, params).await?;
+        assert_eq!(res.len(), 100);
+        assert!(res.iter().all(|rows_affected| *rows_affected == 1));
+
+        let res: Vec<TestData> = client
+            .query_map_after(
+                &log_id,
+                "SELECT * FROM test WHERE id >= $1 AND id < $2",
+                params!(start, start + 100),
+            )
+            .await?;
+        assert_eq!(res.len(), 100);
+    }
+
+    log("A single failing 'execute_many()' set must roll back all others");
+    let res = client_2
+        .execute_many(
+            sql,
+            vec![params!(30_300, 0, "ok"), params!(30_000, 0, "dup")],
+        )
+        .await;
+    assert!(res.is_err());
+    let res: Vec<TestData> = client_1
+        .query_map_with(
+            ReadConsistency::Linearizable,
+            "SELECT * FROM test WHERE id = $1",
+            params!(30_300),
+        )
+        .await?;
+    assert!(res.is_empty());
+
+    let res = client_3
+        .execute_many(
+            "UPDATE test SET ts = 0 WHERE id >= $1 AND id < $2",
+            vec![params!(30_000, 30_100), params!(30_100, 30_300)],
+        )
+        .await?;
+    assert_eq!(res, vec![100, 200]);
+
+    client_1
+        .execute("DELETE FROM test WHERE id >= $1", params!(30_000))
+        .await?;
+
+    Ok(())
+}
+
+async fn test_query_stream(client_1: &Client, client_2: &Client) -> Result<(), Error> {
+    log("Insert test data for query streams");
+    // more rows than a single stream chunk
+    let rows_affected = client_1
+        .execute(
+            r#"INSERT INTO test
+            WITH RECURSIVE ids(x) AS (SELECT 10000 UNION ALL SELECT x + 1 FROM ids WHERE x < 10599)
+            SELECT x, 0, NULL FROM ids"#,
+            params!(),
+        )
+        .await?;
+    assert_eq!(rows_affected, 600);
+    time::sleep(Duration::from_millis(500)).await;
+
+    log("Stream all rows with 'query_stream()'");
+    let mut rows = client_2
+        .query_stream(
+            "SELECT * FROM test WHERE id >= $1 ORDER BY id",
+            params!(10000),
+        )
+        .await?;
+    let mut expected = 10000;
+    while let Some(row) = rows.next().await {
+        let id: i64 = row?.get("id");
+        assert_eq!(id, expected);
+        expected += 1;
+    }
+    assert_eq!(expected, 10600);
+
+    log("Stream mapped rows with 'query_stream_map()'");
+    let res: Vec<TestData> = client_1
+        .query_stream_map("SELECT * FROM test WHERE id >= $1", params!(10000))
+        .await?
+        .try_collect()
+        .await?;
+    assert_eq!(res.len(), 600);
+
+    log("Make sure a dropped stream does not block the read pool");
+    for _ in 0..10 {
+        let mut rows = client_1
+            .query_stream("SELECT * FROM test", params!())
+            .await?;
+        assert!(rows.next().await.is_some());
+    }
+
+    log("Make sure query stream errors are returned");
+    let mut rows = client_1
+        .query_stream("SELECT * FROM does_not_exist", params!())
+        .await?;
+    assert!(rows.next().await.unwrap().is_err());
+    assert!(rows.next().await.is_none());
+
+    client_1
+        .execute("DELETE FROM test WHERE id >= $1", params!(10000))
+        .await?;
+
+    Ok(())
+}
+
+async fn test_custom_functions(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Use deterministic custom SQL functions for writing");
+    let (_, log_id) = client_2
+        .execute_with_log_id(
+            "INSERT INTO test VALUES ($1, test_double($2), $3)",
+            params!(40, 21, "Custom fn"),
+        )
+        .await?;
+
+    for client in [client_1, client_2, client_3] {
+        let res: Vec<TestData> = client
+            .query_map_after(&log_id, "SELECT * FROM test WHERE id = $1", params!(40))
+            .await?;
+        assert_eq!(res[0].ts, 42);
+
+        let mut rows = client
+            .query_raw(
+                "SELECT test_double(ts) AS doubled FROM test WHERE id = $1",
+                params!(40),
+            )
+            .await?;
+        assert_eq!(rows[0].get::<i64>("doubled"), 84);
+    }
+
+    log("Non-deterministic custom SQL functions must only work for reads");
+    for (node_id, client) in [client_1, client_2, client_3].into_iter().enumerate() {
+        let mut rows = client
+            .query_raw("SELECT test_node_id() AS node_id", params!())
+            .await?;
+        assert_eq!(rows[0].get::<i64>("node_id"), node_id as i64 + 1);
+    }
+
+    let res = client_3
+        .execute(
+            "UPDATE test SET ts = test_node_id() WHERE id = $1",
+            params!(40),
+        )
+        .await;
+    assert!(res.is_err());
+
+    client_1
+        .execute("DELETE FROM test WHERE id = $1", params!(40))
+        .await?;
+
+    Ok(())
+}
+
+async fn test_stamped_functions(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Time and random functions in writes must be the same on all nodes");
+    let now = Utc::now().timestamp();
+    let (_, log_id) = client_3
+        .execute_with_log_id(
+            "INSERT INTO test VALUES ($1, unixepoch(), hex(randomblob(16)) || random())",
+            params!(41),
+        )
+        .await?;
+
+    let mut first: Option<TestData> = None;
+    for client in [client_1, client_2, client_3] {
+        let mut res: Vec<TestData> = client
+            .query_map_after(&log_id, "SELECT * FROM test WHERE id = $1", params!(41))
+            .await?;
+        let data = res.remove(0);
+        assert!((data.ts - now).abs() < 60);
+        if let Some(first) = &first {
+            assert_eq!(first, &data);
+        } else {
+            first = Some(data);
+        }
+    }
+
+    client_1
+        .execute("DELETE FROM test WHERE id = $1", params!(41))
+        .await?;
+
+    Ok(())
+}
+
+/// Never finishes on its own and would block a read connection forever.
+pub const SQL_SLOW: &str = "WITH RECURSIVE cnt(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM cnt) \
+    SELECT count(*) FROM cnt";
+
+const SQL_STREAM_ROWS: &str = "WITH RECURSIVE ids(x) AS \
+    (SELECT 1 UNION ALL SELECT x + 1 FROM ids WHERE x < 600) SELECT x FROM ids";
+
+async fn test_query_timeouts(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Slow local and remote queries must be aborted after their timeout");
+    for client in [client_1, client_2, client_3] {
+        let client = client.with_query_timeout(Duration::from_millis(200));
+
+        let start = time::Instant::now();
+        let res = client.query_raw(SQL_SLOW, params!()).await;
+        assert!(matches!(res, Err(Error::Timeout(_))), "{res:?}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // on client 2 and 3, this runs on the leader with the deadline of the client
+        let start = time::Instant::now();
+        let res = client.query_consistent(SQL_SLOW, params!()).await;
+        assert!(matches!(res, Err(Error::Timeout(_))), "{res:?}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // the timeout applies to each chunk of a stream
+        let start = time::Instant::now();
+        let mut rows = client.query_stream(SQL_SLOW, params!()).await?;
+        let res = rows.next().await.unwrap();
+        assert!(matches!(res, Err(Error::Timeout(_))), "{res:?}");
+        assert!(rows.next().await.is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // ... so that a slow consumer does not run into it
+        let mut rows = client.query_stream(SQL_STREAM_ROWS, params!()).await?;
+        let mut count = 0;
+        while let Some(row) = rows.next().await {
+            row?;
+            count += 1;
+            if count % 256 == 0 {
+                time::sleep(Duration::from_millis(300)).await;
+            }
+        }
+        assert_eq!(count, 600);
+
+        // the connections must be usable again
+        let res: Vec<TestData> = client.query_as("SELECT * FROM test", params!()).await?;
+        assert!(!res.is_empty());
+        let res = client
+            .query_consistent("SELECT count(*) FROM test", params!())
+            .await?;
+        assert_eq!(res.len(), 1);
+    }
+
+    Ok(())
+}
+
+const SQL_RUNAWAY: &str = "INSERT INTO test (id, ts, description) \
+    SELECT 42, (WITH RECURSIVE cnt(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM cnt) \
+    SELECT count(*) FROM cnt), 'runaway'";
+
+async fn test_write_budget(
+    client_1: &Client,
+    client_2: &Client,
+    client_3: &Client,
+) -> Result<(), Error> {
+    log("Writes exceeding the 'write_instruction_budget' must be rejected by the leader");
+    let err = client_2.execute(SQL_RUNAWAY, params!()).await.unwrap_err();
+    assert!(
+        err.to_string().contains("write_instruction_budget"),
+        "{err}"
+    );
+
+    let res = client_3
+        .txn([
+            (
+                "INSERT INTO test (id, ts, description) VALUES ($1, $2, $3)",
+                params!(43, 1, "rolled back"),
+            ),
+            (SQL_RUNAWAY, params!()),
+        ])
+        .await;
+    assert!(res.is_err(), "{res:?}");
+
+    // the statements in front of the expensive one must not be persisted by the dry run
+    let res = client_1
+        .batch(format!(
+            "INSERT INTO test (id, ts, description) VALUES (45, 1, 'dry run'); {SQL_RUNAWAY};"
+        ))
+        .await;
+    assert!(res.is_err(), "{res:?}");
+
+    // the budget applies to each statement on its own
+    let (_, log_id) = client_1
+        .txn_with_log_id([
+            (
+                "INSERT INTO test (id, ts, description) SELECT 44, count(*), 'fits' FROM \
+                (WITH RECURSIVE cnt(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM cnt \
+                WHERE x < 10000) SELECT x FROM cnt)",
+                params!(),
+            ),
+            (
+                "UPDATE test SET description = $1 WHERE id = $2",
+                params!("fits too", 44),
+            ),
+        ])
+        .await?;
+
+    for client in [client_1, client_2, client_3] {
+        let res: Vec<TestData> = client
+            .query_map_after(
+                &log_id,
+                "SELECT * FROM test WHERE id IN ($1, $2, $3, $4)",
+                params!(42, 43, 44, 45),
+            )
+            .await?;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].id, 44);
+        assert_eq!(res[0].ts, 10_000);
+        assert_eq!(res[0].description.as_deref(), Some("fits too"));
+    }
+
+    client_1
+        .execute("DELETE FROM test WHERE id = $1", params!(44))
+        .await?;
+
     Ok(())
 }
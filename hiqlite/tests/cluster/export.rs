@@ -0,0 +1,133 @@
+use crate::{TEST_DATA_DIR, log};
+use hiqlite::macros::params;
+use hiqlite::{Client, Error, ExportFormat};
+use std::time::Duration;
+use tokio::time;
+
+#[derive(Debug, PartialEq)]
+struct ExportRow {
+    id: i64,
+    name: Option<String>,
+    score: Option<f64>,
+    data: Option<Vec<u8>>,
+}
+
+impl From<&mut hiqlite::Row<'_>> for ExportRow {
+    fn from(row: &mut hiqlite::Row<'_>) -> Self {
+        Self {
+            id: row.get("id"),
+            name: row.get("name"),
+            score: row.get("score"),
+            data: row.get("data"),
+        }
+    }
+}
+
+async fn rows(client: &Client, table: &str) -> Result<Vec<ExportRow>, Error> {
+    client
+        .query_map(format!("SELECT * FROM {table} ORDER BY id"), params!())
+        .await
+}
+
+pub async fn test_export_import(client_1: &Client, client_2: &Client) -> Result<(), Error> {
+    log("Creating tables for the export");
+    client_1
+        .batch(
+            r#"
+            CREATE TABLE export_sql (
+                id INTEGER PRIMARY KEY,
+                name TEXT,
+                score REAL,
+                data BLOB
+            );
+            CREATE INDEX idx_export_sql_name ON export_sql (name);
+            INSERT INTO export_sql VALUES
+                (1, 'it''s; -- not a comment', 1.0, X'00FF10'),
+                (2, NULL, NULL, NULL),
+                (3, '', -0.5, X'');
+
+            CREATE TABLE export_files (
+                id INTEGER PRIMARY KEY,
+                name TEXT,
+                score REAL,
+                data BLOB
+            );
+            INSERT INTO export_files VALUES
+                (1, 'with "quotes", commas
+and a new line', 13.37, NULL),
+                (2, NULL, NULL, NULL),
+                (3, '', 42.0, NULL);
+            "#,
+        )
+        .await?;
+    let expected_sql = rows(client_1, "export_sql").await?;
+    let expected_files = rows(client_1, "export_files").await?;
+    assert_eq!(expected_sql.len(), 3);
+    assert_eq!(expected_files.len(), 3);
+
+    log("Export into a SQL dump and import it again");
+    let dir = format!("{TEST_DATA_DIR}/export");
+    // exports are independent of the node they are running on
+    // -> race condition when we read too fast
+    time::sleep(Duration::from_millis(100)).await;
+    let summary = client_2
+        .export(&format!("{dir}/sql"), ExportFormat::Sql, &["export_sql"])
+        .await?;
+    assert_eq!(summary.tables, vec![("export_sql".to_string(), 3)]);
+    assert_eq!(summary.files, vec![format!("{dir}/sql/dump.sql")]);
+
+    client_1.execute("DROP TABLE export_sql", params!()).await?;
+    let summary = client_1
+        .import(&format!("{dir}/sql/dump.sql"), ExportFormat::Sql)
+        .await?;
+    // CREATE TABLE + 3 rows + CREATE INDEX
+    assert_eq!(summary.statements, 5);
+    assert_eq!(summary.transactions, 1);
+    assert_eq!(rows(client_1, "export_sql").await?, expected_sql);
+
+    let mut index = client_1
+        .query_raw_one(
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'export_sql'",
+            params!(),
+        )
+        .await?;
+    assert_eq!(index.get::<String>("name"), "idx_export_sql_name");
+
+    log("Export into CSV and JSON Lines and import them again");
+    for format in [ExportFormat::Csv, ExportFormat::JsonLines] {
+        let summary = client_1.export(&dir, format, &["export_files"]).await?;
+        assert_eq!(summary.tables, vec![("export_files".to_string(), 3)]);
+        let path = format!("{dir}/export_files.{}", format.extension());
+        assert_eq!(summary.files, vec![path.clone()]);
+
+        client_1
+            .execute("DELETE FROM export_files", params!())
+            .await?;
+        let summary = client_2.import(&path, format).await?;
+        assert_eq!(summary.statements, 3);
+
+        // race condition when we read too fast
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(rows(client_1, "export_files").await?, expected_files);
+    }
+
+    log("Make sure invalid exports fail");
+    assert!(
+        client_1
+            .export(&dir, ExportFormat::Sql, &["does_not_exist"])
+            .await
+            .is_err()
+    );
+    assert!(
+        client_1
+            .export(&dir, ExportFormat::Sql, &["_metadata"])
+            .await
+            .is_err()
+    );
+
+    client_1
+        .batch("DROP TABLE export_sql; DROP TABLE export_files;")
+        .await?;
+
+    Ok(())
+}
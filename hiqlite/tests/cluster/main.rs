@@ -14,10 +14,13 @@ use tracing_subscriber::{EnvFilter, Layer};
 mod backup;
 mod backup_restore;
 mod batch;
+mod bootstrap;
 mod cache;
+mod changes;
 mod check;
 mod dlock;
 mod execute_query;
+mod export;
 mod learner_only;
 mod listen_notify;
 mod migration;
@@ -99,6 +102,14 @@ async fn exec_tests() -> Result<(), Error> {
     batch::test_batch(&client_1, &client_2, &client_3).await?;
     log("Batch tests finished");
 
+    log("Starting export and import tests");
+    export::test_export_import(&client_1, &client_2).await?;
+    log("Export and import tests finished");
+
+    log("Starting change subscription tests");
+    changes::test_subscribe_changes(&client_1, &client_2, &client_3).await?;
+    log("Change subscription tests finished");
+
     log("Starting SQL type conversion tests");
     type_conversions::test_type_conversions(&client_1).await?;
     log("SQL type conversion tests finished");
@@ -177,7 +188,7 @@ async fn exec_tests() -> Result<(), Error> {
         "Trying to start the cluster again after shutdown with restore from {restore_from} backup"
     ));
     let (client_1, client_2, client_3) =
-        backup_restore::start_test_cluster_with_backup(restore_from == "file").await?;
+        backup_restore::start_test_cluster_with_backup(restore_from == "file", false).await?;
 
     log("Cluster has been started again");
 
@@ -190,6 +201,10 @@ async fn exec_tests() -> Result<(), Error> {
     backup::test_backup_restore_prerequisites(&client_1).await?;
     log("Current database has been changed");
 
+    log("Starting cache backup tests");
+    backup::test_backup_cache(&client_1).await?;
+    log("Cache backup tests finished");
+
     log("Shutting down nodes");
     join_all([
         client_1.shutdown(),
@@ -202,11 +217,16 @@ async fn exec_tests() -> Result<(), Error> {
 
     log("Trying to start the cluster again after shutdown with restore from local file backup");
     let (client_1, client_2, client_3) =
-        backup_restore::start_test_cluster_with_backup(true).await?;
+        backup_restore::start_test_cluster_with_backup(true, true).await?;
     log("Cluster has been started again");
 
     start::wait_for_healthy_cluster(&client_1, &client_2, &client_3).await?;
     log("Cluster is healthy again");
+
+    log("Make sure the cache has been restored");
+    backup::test_cache_is_restored(&client_1).await?;
+    backup::test_cache_is_restored(&client_2).await?;
+    backup::test_cache_is_restored(&client_3).await?;
     // ENV: test local file backup restore
 
     cache::insert_test_value_cache(&client_1).await?;
@@ -218,6 +238,10 @@ async fn exec_tests() -> Result<(), Error> {
     backup_restore::test_db_is_healthy_after_restore(&client_2).await?;
     backup_restore::test_db_is_healthy_after_restore(&client_3).await?;
 
+    log("Test online restore");
+    backup_restore::test_online_restore(&client_1, &client_2, &client_3).await?;
+    log("Online restore tests finished");
+
     // we need to wait a bit until all backup nodes have created a new snapshot
     time::sleep(Duration::from_millis(1000)).await;
 
@@ -1,9 +1,13 @@
-use crate::execute_query::TestData;
+use crate::execute_query::{SQL_SLOW, TestData};
 use crate::start::SECRET_API;
-use crate::{Cache, check, log, start};
+use crate::{Cache, cache, changes, check, log, start};
 use chrono::Utc;
+use futures_util::StreamExt;
 use hiqlite::macros::params;
-use hiqlite::{Client, Error, Lock};
+use hiqlite::{
+    CachePage, ChangeEvent, ChangeOp, Client, Error, IdempotencyKey, Lock, ReadConsistency,
+    WatchEvent, WatchKey,
+};
 use std::time::Duration;
 use tokio::{task, time};
 
@@ -52,6 +56,19 @@ pub async fn test_remote_only_client() -> Result<(), Error> {
     let res = client_2.listen::<TestData>().await?;
     assert_eq!(res, msg);
 
+    log("Slow queries from remote clients must be aborted on the server");
+    let client_timeout = client_1.with_query_timeout(Duration::from_millis(200));
+    let start = time::Instant::now();
+    let res = client_timeout.query_raw(SQL_SLOW, params!()).await;
+    assert!(matches!(res, Err(Error::Timeout(_))), "{res:?}");
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let start = time::Instant::now();
+    let mut rows = client_timeout.query_stream(SQL_SLOW, params!()).await?;
+    let res = rows.next().await.unwrap();
+    assert!(matches!(res, Err(Error::Timeout(_))), "{res:?}");
+    assert!(start.elapsed() < Duration::from_secs(5));
+
     test_get_remove_atomicity(&client_1).await?;
     test_mixed_claim_atomicity(&client_1).await?;
 
@@ -106,7 +123,10 @@ async fn test_mixed_claim_atomicity(client: &Client) -> Result<(), Error> {
             .filter(|v| v.as_deref() == Some("atomic"))
             .count()
             + usize::from(r?.as_deref() == Some("atomic"));
-        assert!(claims <= 1, "the original value must be claimed at most once");
+        assert!(
+            claims <= 1,
+            "the original value must be claimed at most once"
+        );
 
         let v: Option<String> = client.get(Cache::One, key).await?;
         assert!(
@@ -146,6 +166,18 @@ async fn check_client(client: &Client, id: u64) -> Result<(), Error> {
     assert_eq!(res.ts, data.ts);
     assert_eq!(res.description, data.description);
 
+    // rows read on the leader are deserialized for `query_as` as well
+    for consistency in [ReadConsistency::Local, ReadConsistency::Linearizable] {
+        let res: TestData = client
+            .query_as_one_with(
+                consistency,
+                "SELECT * FROM test WHERE id = $1",
+                params!(data.id),
+            )
+            .await?;
+        assert_eq!(res, data);
+    }
+
     let rows_affected = client
         .execute("DELETE FROM test WHERE id = $1", params!(data.id))
         .await?;
@@ -157,6 +189,42 @@ async fn check_client(client: &Client, id: u64) -> Result<(), Error> {
         .ok();
     assert!(res.is_none());
 
+    // query stream
+    let rows_affected = client
+        .execute(
+            r#"INSERT INTO test
+            WITH RECURSIVE ids(x) AS (SELECT 10000 UNION ALL SELECT x + 1 FROM ids WHERE x < 10599)
+            SELECT x, 0, NULL FROM ids"#,
+            params!(),
+        )
+        .await?;
+    assert_eq!(rows_affected, 600);
+
+    let mut rows = client
+        .query_stream(
+            "SELECT * FROM test WHERE id >= $1 ORDER BY id",
+            params!(10000),
+        )
+        .await?;
+    let mut count = 0;
+    while let Some(row) = rows.next().await {
+        let id: i64 = row?.get("id");
+        assert_eq!(id, 10000 + count);
+        count += 1;
+    }
+    assert_eq!(count, 600);
+
+    // an early dropped stream must not affect following requests
+    let mut rows = client
+        .query_stream("SELECT * FROM test WHERE id >= $1", params!(10000))
+        .await?;
+    assert!(rows.next().await.is_some());
+    drop(rows);
+
+    client
+        .execute("DELETE FROM test WHERE id >= $1", params!(10000))
+        .await?;
+
     // transaction
 
     let sql = "INSERT INTO test VALUES ($1, $2, $3)";
@@ -215,6 +283,93 @@ async fn check_client(client: &Client, id: u64) -> Result<(), Error> {
         .await?;
     assert_eq!(data.len(), 0);
 
+    // the same writes, but with the `LogId` they have been applied with
+    let (results, log_id) = client
+        .txn_with_log_id([(sql, params!(1001, now, "Transaction with LogId"))])
+        .await?;
+    assert!(results[0].is_ok());
+    let (rows, log_id_returning) = client
+        .execute_returning_with_log_id("DELETE FROM test WHERE id = $1 RETURNING id", params!(1001))
+        .await?;
+    assert_eq!(rows.len(), 1);
+    assert!(log_id_returning.index > log_id.index);
+    let (results, log_id_batch) = client
+        .batch_with_log_id("DELETE FROM test WHERE id = 1001;")
+        .await?;
+    assert_eq!(*results[0].as_ref().unwrap(), 0);
+    assert!(log_id_batch.index > log_id_returning.index);
+
+    // idempotent writes
+    let key = IdempotencyKey::new();
+    for _ in 0..2 {
+        let rows_affected = client
+            .execute_idempotent(
+                key.clone(),
+                "INSERT INTO test VALUES ($1, $2, $3)",
+                params!(1003, now, "Idempotent remote"),
+            )
+            .await?;
+        assert_eq!(rows_affected, 1);
+    }
+    let key = IdempotencyKey::new();
+    for _ in 0..2 {
+        let results = client
+            .txn_idempotent(
+                key.clone(),
+                [("DELETE FROM test WHERE id = $1", params!(1003))],
+            )
+            .await?;
+        assert_eq!(*results[0].as_ref().unwrap(), 1);
+    }
+
+    // bulk execute
+    let params = (2000..2100)
+        .map(|id| params!(id, now, "Bulk remote"))
+        .collect::<Vec<_>>();
+    let res = client
+        .execute_many("INSERT INTO test VALUES ($1, $2, $3)", params)
+        .await?;
+    assert_eq!(res.len(), 100);
+    let rows_affected = client
+        .execute(
+            "DELETE FROM test WHERE id >= $1 AND id < $2",
+            params!(2000, 2100),
+        )
+        .await?;
+    assert_eq!(rows_affected, 100);
+
+    // change subscriptions
+    let mut changes = client.subscribe_changes(["test_2"]).await?;
+    let (_, log_id) = client
+        .execute_with_log_id(
+            "INSERT INTO test_2 VALUES ($1, $2, $3)",
+            params!(2000, now, "Change remote"),
+        )
+        .await?;
+    match changes::next_event(&mut changes).await? {
+        ChangeEvent::Rows { log_index, changes } => {
+            assert_eq!(log_index, log_id.index);
+            assert_eq!(changes.len(), 1);
+            assert_eq!(changes[0].op, ChangeOp::Insert);
+            assert_eq!(changes[0].rowid, 2000);
+            let mut values = changes[0].values.clone().unwrap();
+            assert_eq!(values.get::<String>("description"), "Change remote");
+        }
+        evt => panic!("unexpected change event: {evt:?}"),
+    }
+    drop(changes);
+    let (_, log_id) = client
+        .execute_with_log_id("DELETE FROM test_2 WHERE id = $1", params!(2000))
+        .await?;
+    let mut changes = client
+        .subscribe_changes_from(["test_2"], log_id.index)
+        .await?;
+    assert_eq!(
+        changes::next_event(&mut changes).await?.log_index(),
+        log_id.index
+    );
+    drop(changes);
+
     log(format!("Test remote client {} cache", id));
     let key = "remote_key";
     let value = "remote Value";
@@ -230,6 +385,73 @@ async fn check_client(client: &Client, id: u64) -> Result<(), Error> {
     let v: Option<String> = client.get(Cache::One, key).await?;
     assert!(v.is_none());
 
+    let prefix = format!("remote_{id}:");
+    client
+        .mput(
+            Cache::One,
+            (0..3i64).map(|i| (format!("{prefix}{i}"), i, None)),
+        )
+        .await?;
+    let page: CachePage<i64> = client.scan_prefix(Cache::One, &prefix, 2, None).await?;
+    assert_eq!(page.entries.len(), 2);
+    let page: CachePage<i64> = client
+        .scan_prefix(Cache::One, &prefix, 2, page.cursor)
+        .await?;
+    assert_eq!(page.entries, vec![(format!("{prefix}2"), 2)]);
+    assert!(page.cursor.is_none());
+    assert_eq!(client.delete_prefix(Cache::One, prefix.as_str()).await?, 3);
+    assert!(client.keys(Cache::One, prefix).await?.is_empty());
+
+    let key = format!("remote_versioned_{id}");
+    let version = client
+        .put_if_absent(Cache::One, key.clone(), &1i64, None)
+        .await?
+        .unwrap();
+    let (value, current): (i64, u64) = client
+        .get_versioned(Cache::One, key.clone())
+        .await?
+        .unwrap();
+    assert_eq!((value, current), (1, version));
+    assert!(
+        client
+            .put_if_version(Cache::One, key.clone(), &2i64, version + 1, None)
+            .await?
+            .is_none()
+    );
+    assert!(client.delete_if_version(Cache::One, key, version).await?);
+
+    let key = format!("remote_watch_{id}");
+    let mut watch = client.watch(Cache::One, key.clone()).await?;
+    let version = client
+        .put_if_absent(Cache::One, key.clone(), &1i64, None)
+        .await?
+        .unwrap();
+    client.delete(Cache::One, key.clone()).await?;
+    match cache::next_watch_event(&mut watch).await? {
+        WatchEvent::Put { log_index, .. } => assert_eq!(log_index, version),
+        evt => panic!("unexpected watch event: {evt:?}"),
+    }
+    match cache::next_watch_event(&mut watch).await? {
+        WatchEvent::Delete { key: k, log_index } => {
+            assert_eq!(k, key);
+            assert!(log_index > version);
+        }
+        evt => panic!("unexpected watch event: {evt:?}"),
+    }
+    drop(watch);
+    let mut watch = client
+        .watch_from(Cache::One, WatchKey::Exact(key.clone()), version)
+        .await?;
+    assert_eq!(
+        cache::next_watch_event(&mut watch).await?.log_index(),
+        version
+    );
+    assert_eq!(
+        cache::next_watch_event(&mut watch).await?.key(),
+        Some(key.as_str())
+    );
+    drop(watch);
+
     log(format!("Test remote client {} locks", id));
     let lock = client.lock("remote").await?;
 
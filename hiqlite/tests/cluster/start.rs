@@ -1,9 +1,15 @@
 use crate::{Cache, TEST_DATA_DIR, log};
-use hiqlite::{Client, Error, Node, NodeConfig, start_node_with_cache};
+use hiqlite::functions::SqlFunctions;
+use hiqlite::{
+    CacheLimit, CacheLimits, Client, Error, EvictionPolicy, LocalTarget, Node, NodeConfig,
+    start_node_with_cache,
+};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::{fs, task, time};
 
 pub const SECRET_API: &str = "qweqweqweqweqweqwe";
+pub const BACKUP_TARGET_DIR: &str = "tests/data_test/backup_target";
 
 pub async fn start_test_cluster() -> Result<(Client, Client, Client), Error> {
     let handle_client_1 = task::spawn(start_node_with_cache::<Cache>(build_config(1).await));
@@ -83,7 +89,24 @@ pub async fn build_config_with_nodes(
     config.secret_api = SECRET_API.to_string();
 
     config.backup_config = Default::default();
+    // all nodes share the same target, like a mounted network path
+    config.backup_config = config
+        .backup_config
+        .with_verify(true)
+        .with_differential(3)
+        .with_target(Arc::new(LocalTarget::new(BACKUP_TARGET_DIR)));
     config.cache_storage_disk = false;
+    config.cache_limits = CacheLimits::default().with_cache(
+        "Three",
+        CacheLimit::new(EvictionPolicy::Lru).with_max_entries(10),
+    );
+
+    // generous enough for all regular test writes
+    config.write_instruction_budget = Some(10_000_000);
+
+    config.sql_functions = SqlFunctions::default()
+        .scalar("test_double", 1, true, |ctx| Ok(ctx.get::<i64>(0)? * 2))
+        .scalar("test_node_id", 0, false, move |_| Ok(node_id as i64));
 
     config
 }